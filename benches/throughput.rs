@@ -0,0 +1,124 @@
+use std::{hint::black_box, io::Cursor, path::Path};
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use packling::{
+    encryption::{decrypt, encrypt},
+    flow_pack::{PackOptions, pack},
+    flow_unpack::{UnpackOptions, unpack},
+    shared::{Verbosity, calc_pak_crc32},
+};
+
+
+/// Any key works, since nothing checks it when packing or unpacking.
+const BENCH_KEY: [u8; 16] = *b"packling testing";
+
+/// Data sizes to measure per-blob operations at: smaller than, equal to
+/// and larger than one encryption chunk.
+const DATA_SIZES: [usize; 4] = [0x400, 0x2000, 0x1_0000, 0x10_0000];
+
+
+/// Somewhat compressible test data (a repeating pattern with some
+/// variation).
+fn test_data(size: usize) -> Vec<u8> {
+    (0..size).map(|i| ((i % 251) ^ (i / 4096)) as u8).collect()
+}
+
+
+fn bench_encryption(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encryption");
+    for size in DATA_SIZES {
+        let data = test_data(size);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("encrypt", size), &data, |b, data| {
+            b.iter_batched_ref(|| data.clone(), |data| encrypt(b"bench.bin", &BENCH_KEY, data), criterion::BatchSize::LargeInput);
+        });
+        group.bench_with_input(BenchmarkId::new("decrypt", size), &data, |b, data| {
+            b.iter_batched_ref(|| data.clone(), |data| decrypt(b"bench.bin", &BENCH_KEY, data), criterion::BatchSize::LargeInput);
+        });
+    }
+    group.finish();
+}
+
+
+fn bench_lz4(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lz4");
+    for size in DATA_SIZES {
+        let data = test_data(size);
+        let compressed = lz4_flex::block::compress(&data);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("compress", size), &data, |b, data| {
+            b.iter(|| lz4_flex::block::compress(black_box(data)));
+        });
+        group.bench_with_input(BenchmarkId::new("decompress", size), &compressed, |b, compressed| {
+            b.iter(|| lz4_flex::block::decompress(black_box(compressed), size).unwrap());
+        });
+    }
+    group.finish();
+}
+
+
+fn bench_crc(c: &mut Criterion) {
+    let mut group = c.benchmark_group("crc");
+    for size in DATA_SIZES {
+        let data = test_data(size);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("crc32", size), &data, |b, data| {
+            b.iter(|| crc32fast::hash(black_box(data)));
+        });
+        group.bench_with_input(BenchmarkId::new("pak_jamcrc32", size), &data, |b, data| {
+            b.iter(|| calc_pak_crc32(&mut Cursor::new(black_box(data)), size as u64).unwrap());
+        });
+    }
+    group.finish();
+}
+
+
+/// Write a synthetic extracted folder: 64 files of various sizes in a
+/// few subfolders, 8 MiB in total.
+fn write_synthetic_tree(folder: &Path) -> u64 {
+    let mut total_size = 0;
+    for i in 0..64_usize {
+        let size = 0x1000 << (i % 6);
+        let path = folder.join(format!("folder_{}/file_{i}.bin", i % 4));
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, test_data(size)).unwrap();
+        total_size += size as u64;
+    }
+    total_size
+}
+
+
+fn bench_pack_unpack(c: &mut Criterion) {
+    let temp = tempfile::tempdir().unwrap();
+    let input_folder = temp.path().join("input");
+    let pak_file = temp.path().join("bench.pak");
+    let output_folder = temp.path().join("output");
+    let total_size = write_synthetic_tree(&input_folder);
+
+    let mut group = c.benchmark_group("pak");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(total_size));
+
+    for compress_files in [false, true] {
+        let suffix = if compress_files { "compressed" } else { "uncompressed" };
+
+        group.bench_function(format!("pack_{suffix}"), |b| {
+            b.iter(|| pack(&input_folder, &pak_file, &BENCH_KEY, &PackOptions { force: true, compress_files, ..PackOptions::default() }, Verbosity::Quiet).unwrap());
+        });
+
+        pack(&input_folder, &pak_file, &BENCH_KEY, &PackOptions { force: true, compress_files, ..PackOptions::default() }, Verbosity::Quiet).unwrap();
+        let options = UnpackOptions {
+            force: true,
+            ..UnpackOptions::default()
+        };
+        group.bench_function(format!("unpack_{suffix}"), |b| {
+            b.iter(|| unpack(&pak_file, &output_folder, &BENCH_KEY, &options, Verbosity::Quiet).unwrap());
+        });
+    }
+
+    group.finish();
+}
+
+
+criterion_group!(benches, bench_encryption, bench_lz4, bench_crc, bench_pack_unpack);
+criterion_main!(benches);
@@ -0,0 +1,21 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+use packling::archive::PakArchive;
+
+// Any key works for exercising the parsers, since nothing checks it
+const DUMMY_KEY: [u8; 16] = *b"packling fuzzing";
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(mut archive) = PakArchive::new(Cursor::new(data), &DUMMY_KEY) else {
+        return;
+    };
+
+    let assets: Vec<_> = archive.assets().map_while(Result::ok).collect();
+    for asset in &assets {
+        let _ = archive.read_asset_head(asset, 16);
+        let _ = archive.read_asset(asset);
+    }
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use binrw::BinRead;
+use libfuzzer_sys::fuzz_target;
+use packling::shared::PakAssets;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = PakAssets::read(&mut Cursor::new(data));
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use binrw::BinRead;
+use libfuzzer_sys::fuzz_target;
+use packling::shared::PakHeader;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = PakHeader::read(&mut Cursor::new(data));
+});
@@ -0,0 +1,28 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use packling::{
+    flow_unpack::{UnpackOptions, unpack},
+    shared::Verbosity,
+};
+
+// Any key works for exercising the parsers, since nothing checks it
+const DUMMY_KEY: [u8; 16] = *b"packling fuzzing";
+
+fuzz_target!(|data: &[u8]| {
+    let folder = std::env::temp_dir().join(format!("packling-fuzz-{}", std::process::id()));
+    let input_file = folder.join("input.pak");
+    let output_folder = folder.join("output");
+
+    std::fs::create_dir_all(&folder).expect("couldn't create temporary folder");
+    std::fs::write(&input_file, data).expect("couldn't write temporary input file");
+
+    let options = UnpackOptions {
+        force: true,
+        keep_going: true,
+        ..UnpackOptions::default()
+    };
+    let _ = unpack(&input_file, &output_folder, &DUMMY_KEY, &options, Verbosity::Silent);
+
+    std::fs::remove_dir_all(&folder).ok();
+});
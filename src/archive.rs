@@ -0,0 +1,490 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock, PoisonError},
+};
+
+use anyhow::{Context, bail};
+use binrw::{BinRead, BinReaderExt};
+
+use crate::{
+    cache::AssetCache,
+    encryption::{XXTEA_CHUNK_SIZE, decrypt, decrypt_chunk},
+    key::{KeyRef, OwnedKey, fingerprint},
+    shared::{
+        ASSETS_LIST_NAME,
+        FILE_VERSION,
+        PAK_HEADER_SIZE,
+        PakAsset,
+        PakHeader,
+        decompress_lz4,
+    },
+};
+
+
+/// The decrypted and decompressed assets-list blob of a .pak file,
+/// with entries only parsed when they're needed.
+pub struct AssetsList {
+    data: Vec<u8>,
+    count: u32,
+    /// The position of each entry in `data`, by asset name. Only built
+    /// the first time an asset is looked up by name.
+    index: OnceLock<HashMap<Vec<u8>, usize>>,
+}
+
+
+impl AssetsList {
+    /// Wrap an assets-list blob, without parsing any of the entries yet.
+    pub fn new(data: Vec<u8>) -> anyhow::Result<Self> {
+        let Some(count_bytes) = data.first_chunk() else {
+            bail!("assets list is too short ({} bytes)", data.len());
+        };
+        Ok(Self {
+            count: u32::from_le_bytes(*count_bytes),
+            data,
+            index: OnceLock::new(),
+        })
+    }
+
+    /// The number of entries (according to the count at the start of
+    /// the blob).
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    /// Whether there are no entries.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Iterate over the entries, parsing each one as it's reached.
+    /// Iteration stops after the first entry that fails to parse.
+    pub fn iter(&self) -> AssetsIter<'_> {
+        AssetsIter {
+            data: &self.data,
+            position: 4,
+            remaining: self.count,
+        }
+    }
+
+    /// Find an entry by name. If more than one has the same name, the
+    /// first one is found.
+    pub fn find(&self, name: &[u8]) -> anyhow::Result<Option<PakAsset>> {
+        if self.index.get().is_none() {
+            let mut index = HashMap::new();
+            let mut iter = self.iter();
+            loop {
+                let position = iter.position;
+                let Some(asset) = iter.next() else {
+                    break;
+                };
+                index.entry(asset?.name).or_insert(position);
+            }
+            // (if another thread got here first, it built the same
+            // index)
+            let _ = self.index.set(index);
+        }
+
+        let Some(&position) = self.index.get().and_then(|index| index.get(name)) else {
+            return Ok(None);
+        };
+        Ok(Some(PakAsset::read(&mut Cursor::new(&self.data[position..]))?))
+    }
+
+    /// Find an entry by name, which has to exist.
+    fn get(&self, name: &str) -> anyhow::Result<PakAsset> {
+        match self.find(name.as_bytes())? {
+            Some(asset) => Ok(asset),
+            None => bail!("asset {name:?} not found"),
+        }
+    }
+}
+
+
+/// Iterator over the entries in an `AssetsList`.
+pub struct AssetsIter<'a> {
+    data: &'a [u8],
+    position: usize,
+    remaining: u32,
+}
+
+
+impl Iterator for AssetsIter<'_> {
+    type Item = anyhow::Result<PakAsset>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let mut cursor = Cursor::new(&self.data[self.position.min(self.data.len())..]);
+        match PakAsset::read(&mut cursor) {
+            Ok(asset) => {
+                self.position += usize::try_from(cursor.position()).expect("cursor position should fit in a usize");
+                Some(Ok(asset))
+            },
+            Err(e) => {
+                self.remaining = 0;
+                Some(Err(e.into()))
+            },
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.remaining as usize))
+    }
+}
+
+
+/// An opened .pak file, with its header and assets list already read.
+///
+/// Works with both encrypted and decrypted .pak files.
+pub struct PakArchive<R> {
+    reader: R,
+    file_size: u64,
+    key: OwnedKey,
+    cache: Option<AssetCache>,
+    assets_list: AssetsList,
+    pub header: PakHeader,
+    pub encrypted: bool,
+}
+
+
+impl PakArchive<BufReader<File>> {
+    /// Open the .pak file at `path`.
+    pub fn open(path: &Path, key: KeyRef) -> anyhow::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?), key)
+    }
+}
+
+
+impl<R: Read + Seek> PakArchive<R> {
+    /// Read the header and assets list from `reader`.
+    pub fn new(mut reader: R, key: KeyRef) -> anyhow::Result<Self> {
+        let file_size = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        let header = PakHeader::read(&mut reader)?;
+
+        if header.version != FILE_VERSION {
+            bail!("unknown PAK version: {}", header.version);
+        }
+
+        // Same heuristic as `check_is_encrypted()`
+        let num_files: u32 = reader.read_le()?;
+        let encrypted = num_files > 0x000f_ffff;
+
+        let assets_list = AssetsList::new(read_assets_list(&mut reader, &header, key, encrypted)?);
+        let assets_list = if encrypted {
+            // (garbage here usually means the key is wrong)
+            assets_list.with_context(|| format!("couldn't read the assets list (decrypted with key {})", fingerprint(key)))?
+        } else {
+            assets_list?
+        };
+
+        Ok(Self {
+            reader,
+            file_size,
+            key: Box::new(*key),
+            cache: None,
+            assets_list,
+            header,
+            encrypted,
+        })
+    }
+
+    /// Keep up to `budget` bytes of recently read asset data (decrypted
+    /// and decompressed) in memory, so that reading the same assets
+    /// again with `read_asset()` is faster.
+    pub fn with_cache(mut self, budget: usize) -> Self {
+        self.cache = Some(AssetCache::new(budget));
+        self
+    }
+
+    /// The absolute offset of the start of the asset data (which asset
+    /// offsets are relative to).
+    pub fn data_start_offset(&self) -> u64 {
+        data_start_offset(&self.header)
+    }
+
+    /// The absolute offset of an asset's data.
+    pub fn asset_offset(&self, asset: &PakAsset) -> u64 {
+        self.data_start_offset() + u64::from(asset.offset)
+    }
+
+    /// Iterate over the entries in the assets list, parsing them on
+    /// demand.
+    pub fn assets(&self) -> AssetsIter<'_> {
+        self.assets_list.iter()
+    }
+
+    /// The number of entries in the assets list.
+    pub fn asset_count(&self) -> usize {
+        self.assets_list.len()
+    }
+
+    /// Find an asset by name.
+    pub fn find_asset(&self, name: &str) -> anyhow::Result<PakAsset> {
+        self.assets_list.get(name)
+    }
+
+    /// Access the underlying reader.
+    pub fn reader_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Read an asset's data exactly as stored in the file (encrypted,
+    /// unless this is a decrypted .pak, and possibly compressed).
+    pub fn read_asset_raw(&mut self, asset: &PakAsset) -> anyhow::Result<Vec<u8>> {
+        let offset = self.asset_offset(asset);
+        read_stored(&mut self.reader, asset, offset, self.file_size)
+    }
+
+    /// Read an asset's data, decrypted but still possibly compressed.
+    pub fn read_asset_decrypted(&mut self, asset: &PakAsset) -> anyhow::Result<Vec<u8>> {
+        let mut data = self.read_asset_raw(asset)?;
+        decrypt_stored(asset, &self.key, self.encrypted, &mut data);
+        Ok(data)
+    }
+
+    /// Read up to the first `size` bytes of an asset's data, decrypted
+    /// and decompressed. For uncompressed assets, this avoids reading
+    /// more than the first encryption chunk if possible.
+    pub fn read_asset_head(&mut self, asset: &PakAsset, size: usize) -> anyhow::Result<Vec<u8>> {
+        if asset.size_compressed != asset.size_decompressed || size > XXTEA_CHUNK_SIZE {
+            // No way to decompress just part of an LZ4 block
+            let mut data = self.read_asset(asset)?;
+            data.truncate(size);
+            return Ok(data);
+        }
+
+        check_asset_in_bounds(asset, self.asset_offset(asset), self.file_size)?;
+        let chunk_size = usize::try_from(asset.size_compressed)?.min(XXTEA_CHUNK_SIZE);
+        self.reader.seek(SeekFrom::Start(self.asset_offset(asset)))?;
+        let mut data = vec![0; chunk_size];
+        self.reader.read_exact(&mut data)?;
+        if self.encrypted {
+            decrypt_chunk(&asset.name, &self.key, asset.size_compressed, 0, &mut data);
+        }
+        data.truncate(size);
+        Ok(data)
+    }
+
+    /// Read an asset's data, decrypted and decompressed.
+    pub fn read_asset(&mut self, asset: &PakAsset) -> anyhow::Result<Vec<u8>> {
+        if let Some(data) = self.cache.as_mut().and_then(|c| c.get(asset.offset, &asset.name)) {
+            return Ok(data);
+        }
+
+        let stored = self.read_asset_raw(asset)?;
+        let data = decode_stored(asset, &self.key, self.encrypted, stored)?;
+
+        if let Some(cache) = &mut self.cache {
+            cache.insert(asset.offset, &asset.name, &data);
+        }
+        Ok(data)
+    }
+}
+
+
+/// A .pak file opened for concurrent reads. Unlike `PakArchive`, this
+/// is `Send + Sync`, and all of its reading methods take `&self`.
+///
+/// Each read borrows a file handle from a pool, opening another one if
+/// they're all in use, so reads from multiple threads don't block each
+/// other. Otherwise, assets are read and decoded the same way (by the
+/// same functions) as in `PakArchive`.
+pub struct SharedPakArchive {
+    path: PathBuf,
+    handles: Mutex<Vec<File>>,
+    file_size: u64,
+    key: OwnedKey,
+    cache: Option<Mutex<AssetCache>>,
+    assets_list: AssetsList,
+    pub header: PakHeader,
+    pub encrypted: bool,
+}
+
+
+impl SharedPakArchive {
+    /// Open the .pak file at `path`.
+    pub fn open(path: &Path, key: KeyRef) -> anyhow::Result<Self> {
+        let PakArchive { reader, file_size, key, assets_list, header, encrypted, .. } = PakArchive::open(path, key)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            // (every read seeks first, so the position doesn't matter)
+            handles: Mutex::new(vec![reader.into_inner()]),
+            file_size,
+            key,
+            cache: None,
+            assets_list,
+            header,
+            encrypted,
+        })
+    }
+
+    /// Keep up to `budget` bytes of recently read asset data (decrypted
+    /// and decompressed) in memory, shared between all threads, so that
+    /// reading the same assets again with `read_asset()` is faster.
+    pub fn with_cache(mut self, budget: usize) -> Self {
+        self.cache = Some(Mutex::new(AssetCache::new(budget)));
+        self
+    }
+
+    /// The absolute offset of the start of the asset data (which asset
+    /// offsets are relative to).
+    pub fn data_start_offset(&self) -> u64 {
+        data_start_offset(&self.header)
+    }
+
+    /// The absolute offset of an asset's data.
+    pub fn asset_offset(&self, asset: &PakAsset) -> u64 {
+        self.data_start_offset() + u64::from(asset.offset)
+    }
+
+    /// Iterate over the entries in the assets list, parsing them on
+    /// demand.
+    pub fn assets(&self) -> AssetsIter<'_> {
+        self.assets_list.iter()
+    }
+
+    /// The number of entries in the assets list.
+    pub fn asset_count(&self) -> usize {
+        self.assets_list.len()
+    }
+
+    /// Find an asset by name.
+    pub fn find_asset(&self, name: &str) -> anyhow::Result<PakAsset> {
+        self.assets_list.get(name)
+    }
+
+    /// Run `f` with a file handle from the pool.
+    fn with_handle<T>(&self, f: impl FnOnce(&mut File) -> anyhow::Result<T>) -> anyhow::Result<T> {
+        // (the pool is just a list of handles, so it can't be left in
+        // an inconsistent state by a panic)
+        let handle = self.handles.lock().unwrap_or_else(PoisonError::into_inner).pop();
+        let mut handle = match handle {
+            Some(handle) => handle,
+            None => File::open(&self.path)?,
+        };
+
+        let result = f(&mut handle);
+
+        self.handles.lock().unwrap_or_else(PoisonError::into_inner).push(handle);
+        result
+    }
+
+    /// Read an asset's data exactly as stored in the file (encrypted,
+    /// unless this is a decrypted .pak, and possibly compressed).
+    pub fn read_asset_raw(&self, asset: &PakAsset) -> anyhow::Result<Vec<u8>> {
+        self.with_handle(|file| read_stored(file, asset, self.asset_offset(asset), self.file_size))
+    }
+
+    /// Read an asset's data, decrypted but still possibly compressed.
+    pub fn read_asset_decrypted(&self, asset: &PakAsset) -> anyhow::Result<Vec<u8>> {
+        let mut data = self.read_asset_raw(asset)?;
+        decrypt_stored(asset, &self.key, self.encrypted, &mut data);
+        Ok(data)
+    }
+
+    /// Read an asset's data, decrypted and decompressed.
+    pub fn read_asset(&self, asset: &PakAsset) -> anyhow::Result<Vec<u8>> {
+        if let Some(cache) = &self.cache
+            && let Some(data) = cache.lock().unwrap_or_else(PoisonError::into_inner).get(asset.offset, &asset.name)
+        {
+            return Ok(data);
+        }
+
+        let stored = self.read_asset_raw(asset)?;
+        let data = decode_stored(asset, &self.key, self.encrypted, stored)?;
+
+        if let Some(cache) = &self.cache {
+            cache.lock().unwrap_or_else(PoisonError::into_inner).insert(asset.offset, &asset.name, &data);
+        }
+        Ok(data)
+    }
+}
+
+
+/// The absolute offset of the start of the asset data (which asset
+/// offsets are relative to) in a .pak with this header.
+fn data_start_offset(header: &PakHeader) -> u64 {
+    PAK_HEADER_SIZE as u64 + u64::from(header.assets_list_size_compressed)
+}
+
+
+/// Read an asset's data (at absolute offset `offset`) exactly as stored
+/// in a file of size `file_size`, checking that it's all there before
+/// allocating space for it.
+fn read_stored<R: Read + Seek>(reader: &mut R, asset: &PakAsset, offset: u64, file_size: u64) -> anyhow::Result<Vec<u8>> {
+    check_asset_in_bounds(asset, offset, file_size)?;
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut data = vec![0; asset.size_compressed.try_into()?];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+
+/// Decrypt an asset's stored data in place, unless it's from a
+/// decrypted .pak.
+fn decrypt_stored(asset: &PakAsset, key: KeyRef, encrypted: bool, data: &mut [u8]) {
+    if encrypted {
+        decrypt(&asset.name, key, data);
+    }
+}
+
+
+/// Decrypt and decompress an asset's stored data.
+fn decode_stored(asset: &PakAsset, key: KeyRef, encrypted: bool, mut data: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    decrypt_stored(asset, key, encrypted, &mut data);
+    if asset.size_compressed != asset.size_decompressed {
+        data = decompress_lz4(&data, asset.size_decompressed)?;
+    }
+    Ok(data)
+}
+
+
+/// Check that an asset's data (at absolute offset `offset`) doesn't
+/// extend past the end of a file of size `file_size`.
+fn check_asset_in_bounds(asset: &PakAsset, offset: u64, file_size: u64) -> anyhow::Result<()> {
+    let asset_end = offset + u64::from(asset.size_compressed);
+    if asset_end > file_size {
+        bail!(
+            "asset {:?} extends past the end of the file ({asset_end:#x} > {file_size:#x})",
+            String::from_utf8_lossy(&asset.name),
+        );
+    }
+    Ok(())
+}
+
+
+/// Read the assets-list blob following the header, and decrypt (if
+/// `encrypted`) and decompress it.
+pub fn read_assets_list<R: Read + Seek>(
+    reader: &mut R,
+    header: &PakHeader,
+    key: KeyRef,
+    encrypted: bool,
+) -> anyhow::Result<Vec<u8>> {
+    let file_size = reader.seek(SeekFrom::End(0))?;
+    if PAK_HEADER_SIZE as u64 + u64::from(header.assets_list_size_compressed) > file_size {
+        bail!("assets list extends past the end of the file");
+    }
+
+    reader.seek(SeekFrom::Start(PAK_HEADER_SIZE as u64))?;
+    let mut data = vec![0; header.assets_list_size_compressed.try_into()?];
+    reader.read_exact(&mut data)?;
+
+    if encrypted {
+        decrypt(ASSETS_LIST_NAME, key, &mut data);
+    }
+
+    if header.assets_list_size_compressed != header.assets_list_size_decompressed {
+        data = decompress_lz4(&data, header.assets_list_size_decompressed)?;
+    }
+
+    Ok(data)
+}
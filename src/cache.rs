@@ -0,0 +1,135 @@
+use std::collections::{BTreeMap, HashMap};
+
+
+/// A least-recently-used cache of decoded asset data, keyed by asset
+/// offset and name (an assets list can have more than one asset with
+/// the same name), that holds at most a fixed number of bytes.
+pub struct AssetCache {
+    budget: usize,
+    used: usize,
+    /// Incremented on every access, to order entries by age.
+    clock: u64,
+    entries: HashMap<CacheKey, CacheEntry>,
+    /// Keys of the cached assets, by the time they were last used.
+    by_last_use: BTreeMap<u64, CacheKey>,
+}
+
+
+/// An asset's offset (relative to the start of the asset data) and name.
+type CacheKey = (u32, Vec<u8>);
+
+
+struct CacheEntry {
+    data: Vec<u8>,
+    last_use: u64,
+}
+
+
+impl AssetCache {
+    /// Create an empty cache holding at most `budget` bytes of data.
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            used: 0,
+            clock: 0,
+            entries: HashMap::new(),
+            by_last_use: BTreeMap::new(),
+        }
+    }
+
+    /// Total size of the data currently cached.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    /// Get a copy of the cached data for an asset, if there is any.
+    pub fn get(&mut self, offset: u32, name: &[u8]) -> Option<Vec<u8>> {
+        self.clock += 1;
+        let key = (offset, name.to_vec());
+        let entry = self.entries.get_mut(&key)?;
+        self.by_last_use.remove(&entry.last_use);
+        self.by_last_use.insert(self.clock, key);
+        entry.last_use = self.clock;
+        Some(entry.data.clone())
+    }
+
+    /// Cache the data for an asset, evicting the least recently used
+    /// entries as needed to stay within the budget. Data larger than
+    /// the whole budget isn't cached.
+    pub fn insert(&mut self, offset: u32, name: &[u8], data: &[u8]) {
+        if data.len() > self.budget {
+            return;
+        }
+
+        let key = (offset, name.to_vec());
+        self.remove(&key);
+        while self.used + data.len() > self.budget {
+            let Some((_, oldest)) = self.by_last_use.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&oldest) {
+                self.used -= entry.data.len();
+            }
+        }
+
+        self.clock += 1;
+        self.used += data.len();
+        self.by_last_use.insert(self.clock, key.clone());
+        self.entries.insert(key, CacheEntry {
+            data: data.to_vec(),
+            last_use: self.clock,
+        });
+    }
+
+    /// Remove an asset from the cache, if it's in it.
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.by_last_use.remove(&entry.last_use);
+            self.used -= entry.data.len();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_asset_cache() {
+        let mut cache = AssetCache::new(10);
+        cache.insert(0, b"a", &[1; 4]);
+        cache.insert(0, b"b", &[2; 4]);
+        assert_eq!(cache.used(), 8);
+
+        // "a" is now more recently used than "b", so "b" gets evicted
+        assert_eq!(cache.get(0, b"a"), Some(vec![1; 4]));
+        cache.insert(0, b"c", &[3; 4]);
+        assert_eq!(cache.get(0, b"b"), None);
+        assert_eq!(cache.get(0, b"a"), Some(vec![1; 4]));
+        assert_eq!(cache.get(0, b"c"), Some(vec![3; 4]));
+        assert_eq!(cache.used(), 8);
+
+        // Replacing an entry doesn't count it twice
+        cache.insert(0, b"c", &[4; 6]);
+        assert_eq!(cache.get(0, b"c"), Some(vec![4; 6]));
+        assert_eq!(cache.used(), 10);
+
+        // Too big to cache at all
+        cache.insert(0, b"d", &[5; 11]);
+        assert_eq!(cache.get(0, b"d"), None);
+        assert_eq!(cache.used(), 10);
+    }
+
+    #[test]
+    fn test_asset_cache_duplicate_names() {
+        // (assets with the same name at different offsets are different
+        // assets)
+        let mut cache = AssetCache::new(10);
+        cache.insert(0, b"a", &[1; 4]);
+        cache.insert(4, b"a", &[2; 4]);
+        assert_eq!(cache.get(0, b"a"), Some(vec![1; 4]));
+        assert_eq!(cache.get(4, b"a"), Some(vec![2; 4]));
+        assert_eq!(cache.get(8, b"a"), None);
+    }
+}
@@ -1,3 +1,16 @@
+//! The XXTEA encryption used for assets and the assets list.
+//!
+//! Each blob of data is encrypted in chunks of `XXTEA_CHUNK_SIZE` bytes,
+//! each with its own key from `generate_key()`: the fixed key (see
+//! `key::get_key()`), masked with a hash of the blob's name (the asset
+//! name, or `shared::ASSETS_LIST_NAME` for the assets list), the blob's
+//! length and the chunk's offset. Only whole 4-byte words are encrypted,
+//! so the last 1-3 bytes of a blob whose length isn't a multiple of 4
+//! are left as they are, and a chunk of less than 8 bytes isn't
+//! encrypted at all. So a blob of up to 7 bytes is stored entirely as
+//! plaintext, and a larger one can end in up to 7 bytes of it (see
+//! `plaintext_tail_len()`).
+
 use std::io::{Read, Seek, SeekFrom};
 
 use crate::key::KeyRef;
@@ -5,7 +18,7 @@ use crate::key::KeyRef;
 
 /// Size in bytes of encryption/decryption chunks. Each chunk uses a
 /// different XXTEA key.
-const XXTEA_CHUNK_SIZE: usize = 0x2000;
+pub const XXTEA_CHUNK_SIZE: usize = 0x2000;
 
 
 /// Generate an XXTEA key using the PAK file key generation algorithm.
@@ -14,7 +27,19 @@ const XXTEA_CHUNK_SIZE: usize = 0x2000;
 /// - `length`: the full length of the data blob
 /// - `chunk_offset`: the offset of the 0x2000-byte chunk of encrypted
 ///   data (each chunk is encrypted with a different key)
-fn generate_key(name: &[u8], length: u32, chunk_offset: u32, fixed_key: KeyRef) -> Box<[u8]> {
+///
+/// `encrypt()` and `decrypt()` do this for each chunk, so this is only
+/// needed to encrypt or decrypt chunks some other way.
+///
+/// ```
+/// use packling::encryption::generate_key;
+///
+/// let fixed_key = [0xff; 16];
+/// let first_chunk_key = generate_key(b"a.txt", 0x3000, 0, &fixed_key);
+/// assert_eq!(first_chunk_key.len(), 16);
+/// assert_ne!(generate_key(b"a.txt", 0x3000, 0x2000, &fixed_key), first_chunk_key);
+/// ```
+pub fn generate_key(name: &[u8], length: u32, chunk_offset: u32, fixed_key: KeyRef) -> Box<[u8]> {
     let mut key = Box::new(*fixed_key);
 
     let mask = length ^ chunk_offset ^ djb2::Djb2a::hash_bytes(name).as_u32();
@@ -34,6 +59,21 @@ fn generate_key(name: &[u8], length: u32, chunk_offset: u32, fixed_key: KeyRef)
 /// Encrypt a blob of PAK data in-place.
 ///
 /// `name` is a string that's used as part of key generation.
+///
+/// ```
+/// use packling::encryption::{decrypt, encrypt};
+///
+/// let key = [0x42; 16];
+/// let mut data = b"Hello, world!".to_vec();
+/// encrypt(b"greeting.txt", &key, &mut data);
+/// assert_ne!(&data[..12], b"Hello, world");
+/// // (13 bytes isn't a whole number of words, so the last one is left
+/// // as it is)
+/// assert_eq!(data[12], b'!');
+///
+/// decrypt(b"greeting.txt", &key, &mut data);
+/// assert_eq!(data, b"Hello, world!");
+/// ```
 pub fn encrypt(name: &[u8], key: KeyRef, data: &mut [u8]) {
     let data_len = data.len();
 
@@ -60,38 +100,92 @@ pub fn encrypt(name: &[u8], key: KeyRef, data: &mut [u8]) {
 }
 
 
-/// Decrypt a blob of PAK data in-place.
+/// The number of bytes at the end of a blob of `data_len` bytes that
+/// `encrypt` leaves unencrypted. This is the whole blob for blobs of 7
+/// bytes or less, and otherwise the last `data_len % 4` bytes (or the
+/// whole last chunk, if it's 7 bytes or less).
 ///
-/// `name` is a string that's used as part of key generation.
-pub fn decrypt(name: &[u8], key: KeyRef, data: &mut [u8]) {
-    let data_len = data.len();
+/// ```
+/// use packling::encryption::plaintext_tail_len;
+///
+/// assert_eq!(plaintext_tail_len(4), 4);
+/// assert_eq!(plaintext_tail_len(7), 7);
+/// assert_eq!(plaintext_tail_len(13), 1);
+/// assert_eq!(plaintext_tail_len(0x2000 + 3), 3);
+/// assert_eq!(plaintext_tail_len(0x2000 + 6), 6);
+/// ```
+pub fn plaintext_tail_len(data_len: usize) -> usize {
+    if data_len == 0 {
+        return 0;
+    }
 
-    for chunk_start in (0..data.len()).step_by(XXTEA_CHUNK_SIZE) {
-        // Note: if the data length isn't a multiple of 4, the last few
-        // bytes are just unencrypted
-        let chunk_size = (data.len() - chunk_start).min(XXTEA_CHUNK_SIZE) & !3;
+    let last_chunk_len = data_len - (data_len - 1) / XXTEA_CHUNK_SIZE * XXTEA_CHUNK_SIZE;
+    let encrypted_len = last_chunk_len & !3;
+    if encrypted_len <= 4 {
+        last_chunk_len
+    } else {
+        last_chunk_len - encrypted_len
+    }
+}
 
-        if chunk_size <= 4 {
-            // "< 4" would make more sense, but in practice, 4-byte
-            // files are unencrypted (see
-            // punch_out_prd/art/wwiseaudio/FE_Music.txt and
-            // Transitions_FrontEnd.txt), so this is correct
-            return;
-        }
 
-        let chunk = &mut data[chunk_start..(chunk_start + chunk_size)];
+/// Decrypt a blob of PAK data in-place. This is the inverse of
+/// `encrypt()`, so it has to be given the same name and the whole blob.
+///
+/// `name` is a string that's used as part of key generation.
+pub fn decrypt(name: &[u8], key: KeyRef, data: &mut [u8]) {
+    #[allow(clippy::cast_possible_truncation)]
+    let data_len = data.len() as u32;
 
+    for (i, chunk) in data.chunks_mut(XXTEA_CHUNK_SIZE).enumerate() {
         #[allow(clippy::cast_possible_truncation)]
-        let key = generate_key(name, data_len as u32, chunk_start as u32, key);
+        let chunk_start = (i * XXTEA_CHUNK_SIZE) as u32;
+        decrypt_chunk(name, key, data_len, chunk_start, chunk);
+    }
+}
+
 
-        xxtea_nostd::decrypt(&key, chunk);
+/// Decrypt a single chunk of a larger blob of PAK data in-place.
+///
+/// `length` is the full length of the blob, and `chunk_offset` is the
+/// offset of `chunk` within it (a multiple of the chunk size). `chunk`
+/// must extend to the end of the chunk or the end of the blob,
+/// whichever comes first.
+pub fn decrypt_chunk(name: &[u8], key: KeyRef, length: u32, chunk_offset: u32, chunk: &mut [u8]) {
+    // Note: if the data length isn't a multiple of 4, the last few
+    // bytes are just unencrypted
+    let chunk_size = chunk.len() & !3;
+
+    if chunk_size <= 4 {
+        // (see encrypt())
+        return;
     }
+
+    let key = generate_key(name, length, chunk_offset, key);
+
+    xxtea_nostd::decrypt(&key, &mut chunk[..chunk_size]);
 }
 
 
 /// Read a blob of encrypted data from a reader, and decrypt it.
 ///
 /// `name` is a string that's used as part of key generation.
+///
+/// ```
+/// use std::io::Cursor;
+///
+/// use packling::encryption::{decrypt_from_reader, encrypt};
+///
+/// let key = [0x42; 16];
+/// let mut blob = b"0123456789abcdef".to_vec();
+/// encrypt(b"digits.txt", &key, &mut blob);
+///
+/// let mut file = b"HEADER".to_vec();
+/// file.extend_from_slice(&blob);
+/// let data = decrypt_from_reader(&mut Cursor::new(file), b"digits.txt", 6, 16, &key)?;
+/// assert_eq!(&*data, b"0123456789abcdef");
+/// # Ok::<(), anyhow::Error>(())
+/// ```
 pub fn decrypt_from_reader<R: Read + Seek>(
     reader: &mut R,
     name: &[u8],
@@ -110,6 +204,7 @@ pub fn decrypt_from_reader<R: Read + Seek>(
 
 
 #[cfg(test)]
+#[allow(clippy::byte_char_slices)]
 mod tests {
     use super::*;
 
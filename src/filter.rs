@@ -0,0 +1,156 @@
+use anyhow::bail;
+use regex::Regex;
+
+use crate::shared::PakAsset;
+
+
+/// Convert a glob pattern to an equivalent anchored regex.
+///
+/// `*` and `?` don't match `/`, but `**` does. Patterns without any `/`
+/// are matched against the last path component only, so that `*.lua`
+/// finds Lua files in any folder.
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from(if glob.contains('/') { "^" } else { "(^|/)" });
+
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            },
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                // Character classes are passed through as-is, apart
+                // from "!" negation
+                regex.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    regex.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == '\\' {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                    if c == ']' {
+                        break;
+                    }
+                }
+            },
+            _ => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    regex.push('$');
+    regex
+}
+
+
+/// Criteria for selecting a subset of the assets in a .pak.
+#[derive(Default)]
+pub struct AssetFilter {
+    name: Option<Regex>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+}
+
+
+impl AssetFilter {
+    /// Only accept assets with names matching a glob pattern (see
+    /// `glob_to_regex()`).
+    pub fn with_glob(mut self, glob: &str) -> anyhow::Result<Self> {
+        self.name = Some(Regex::new(&glob_to_regex(glob))?);
+        Ok(self)
+    }
+
+    /// Only accept assets with names matching a regex (unanchored).
+    pub fn with_regex(mut self, regex: &str) -> anyhow::Result<Self> {
+        self.name = Some(Regex::new(regex)?);
+        Ok(self)
+    }
+
+    /// Only accept assets whose decompressed size is within a range
+    /// (inclusive, and unbounded on either side if `None`).
+    pub fn with_size_range(mut self, min_size: Option<u64>, max_size: Option<u64>) -> anyhow::Result<Self> {
+        if let (Some(min), Some(max)) = (min_size, max_size)
+            && min > max
+        {
+            bail!("the minimum size ({min}) is larger than the maximum size ({max})");
+        }
+        self.min_size = min_size;
+        self.max_size = max_size;
+        Ok(self)
+    }
+
+    /// Check whether an asset is selected by this filter.
+    pub fn matches(&self, asset: &PakAsset) -> bool {
+        let size = u64::from(asset.size_decompressed);
+        if self.min_size.is_some_and(|min| size < min) || self.max_size.is_some_and(|max| size > max) {
+            return false;
+        }
+        self.matches_name(&asset.name)
+    }
+
+    /// Check whether an asset name is selected by this filter, ignoring
+    /// any criteria other than the name.
+    pub fn matches_name(&self, asset_name: &[u8]) -> bool {
+        if let Some(name) = &self.name
+            && !name.is_match(&String::from_utf8_lossy(asset_name))
+        {
+            return false;
+        }
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_glob(glob: &str, name: &str, expected: bool) {
+        let regex = Regex::new(&glob_to_regex(glob)).unwrap();
+        assert_eq!(regex.is_match(name), expected, "{glob:?} vs {name:?}");
+    }
+
+    #[test]
+    fn test_glob_to_regex() {
+        assert_glob("*.lua", "main.lua", true);
+        assert_glob("*.lua", "scripts/main.lua", true);
+        assert_glob("*.lua", "main.lua.bak", false);
+        assert_glob("scripts/*.lua", "scripts/main.lua", true);
+        assert_glob("scripts/*.lua", "scripts/sub/main.lua", false);
+        assert_glob("scripts/**.lua", "scripts/sub/main.lua", true);
+        assert_glob("art/??.bin", "art/ab.bin", true);
+        assert_glob("art/??.bin", "art/abc.bin", false);
+        assert_glob("[!a]*.txt", "b.txt", true);
+        assert_glob("[!a]*.txt", "a.txt", false);
+        assert_glob("a+b.txt", "a+b.txt", true);
+        assert_glob("a+b.txt", "aab.txt", false);
+    }
+
+    #[test]
+    fn test_size_range() {
+        let asset = |size| PakAsset {
+            name: b"a.bin".to_vec(),
+            size_decompressed: size,
+            size_compressed: size / 2,
+            offset: 0,
+            plaintext_crc32: 0,
+            ciphertext_crc32: 0,
+        };
+        let filter = AssetFilter::default().with_size_range(Some(0x10), Some(0x20)).unwrap();
+        assert!(!filter.matches(&asset(0x0f)));
+        assert!(filter.matches(&asset(0x10)));
+        assert!(filter.matches(&asset(0x20)));
+        assert!(!filter.matches(&asset(0x21)));
+
+        // (the decompressed size, not the stored size)
+        let filter = AssetFilter::default().with_size_range(None, Some(0x10)).unwrap();
+        assert!(!filter.matches(&asset(0x18)));
+
+        assert!(AssetFilter::default().with_size_range(Some(2), Some(1)).is_err());
+    }
+}
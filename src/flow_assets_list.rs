@@ -0,0 +1,311 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Cursor, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use anyhow::bail;
+use binrw::BinWrite;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    archive::PakArchive,
+    encryption::encrypt,
+    key::KeyRef,
+    shared::{
+        ASSETS_LIST_NAME,
+        EXTENDED_FILE_VERSION,
+        PakAsset,
+        PakAssets,
+        PakHeader,
+        calc_field_0x0c,
+        calc_field_0x10,
+        calc_pak_crc32_file,
+    },
+};
+
+
+/// Formats to export an assets list in.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default, ValueEnum)]
+#[clap(rename_all = "kebab_case")]
+pub enum AssetsListFormat {
+    /// JSON, as used by the other machine-readable output.
+    #[default]
+    Json,
+    /// TOML, like pack manifests.
+    Toml,
+}
+
+
+impl AssetsListFormat {
+    /// Guess the format of a file from its extension (JSON, unless it's
+    /// ".toml").
+    pub fn from_path(path: &Path) -> Self {
+        if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("toml")) {
+            AssetsListFormat::Toml
+        } else {
+            AssetsListFormat::Json
+        }
+    }
+}
+
+
+/// The header and assets list of a .pak file, in an editable form.
+///
+/// The fields marked as derived are only there for reference:
+/// `import()` ignores them, and recalculates them from everything else.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AssetsListDump {
+    pub version: u32,
+    pub unk0c: u8,
+    pub timestamp: i64,
+
+    /// Whole-file JAMCRC32 (derived).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crc32: Option<u32>,
+    /// Size of the assets list as stored (derived).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assets_list_size: Option<u32>,
+    /// CRC32 of the assets list before encryption (derived).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assets_list_plaintext_crc32: Option<u32>,
+    /// CRC32 of the assets list as stored (derived).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assets_list_ciphertext_crc32: Option<u32>,
+
+    #[serde(default, rename = "asset")]
+    pub assets: Vec<AssetsListDumpEntry>,
+}
+
+
+/// A single entry in an `AssetsListDump`.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AssetsListDumpEntry {
+    pub name: String,
+    pub size_decompressed: u32,
+    pub size_compressed: u32,
+    /// Offset from the start of the asset data.
+    pub offset: u32,
+    pub plaintext_crc32: u32,
+    pub ciphertext_crc32: u32,
+
+    /// Offset from the start of the file (derived).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub absolute_offset: Option<u64>,
+    /// Field 0x0c (derived).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field_0c: Option<u32>,
+    /// Field 0x10 (derived).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field_10: Option<u32>,
+}
+
+
+/// Dump the header and assets list of a .pak file, to `output_file` or
+/// stdout.
+pub fn export(
+    input_file: &Path,
+    key: KeyRef,
+    format: AssetsListFormat,
+    output_file: Option<&Path>,
+) -> anyhow::Result<()> {
+    let archive = PakArchive::open(input_file, key)?;
+    let header = &archive.header;
+
+    let mut assets = Vec::with_capacity(archive.asset_count());
+    for asset in archive.assets() {
+        let asset = asset?;
+        // (a lossy conversion would change the name when importing)
+        let Ok(name) = String::from_utf8(asset.name.clone()) else {
+            bail!("asset name {:?} isn't valid UTF-8, so can't be exported", String::from_utf8_lossy(&asset.name));
+        };
+        assets.push(AssetsListDumpEntry {
+            name,
+            size_decompressed: asset.size_decompressed,
+            size_compressed: asset.size_compressed,
+            offset: asset.offset,
+            plaintext_crc32: asset.plaintext_crc32,
+            ciphertext_crc32: asset.ciphertext_crc32,
+            absolute_offset: Some(archive.asset_offset(&asset)),
+            field_0c: Some(calc_field_0x0c(&asset.name, asset.size_compressed)),
+            field_10: Some(calc_field_0x10(&asset.name, asset.size_compressed)),
+        });
+    }
+
+    let dump = AssetsListDump {
+        version: header.version,
+        unk0c: header.unk0c,
+        timestamp: header.timestamp,
+        crc32: Some(header.crc32),
+        assets_list_size: Some(header.assets_list_size_compressed),
+        assets_list_plaintext_crc32: Some(header.plaintext_crc32),
+        assets_list_ciphertext_crc32: Some(header.ciphertext_crc32),
+        assets,
+    };
+
+    let text = match format {
+        AssetsListFormat::Json => serde_json::to_string_pretty(&dump)? + "\n",
+        AssetsListFormat::Toml => toml::to_string_pretty(&dump)?,
+    };
+    match output_file {
+        Some(path) => std::fs::write(path, text)?,
+        None => print!("{text}"),
+    }
+
+    Ok(())
+}
+
+
+/// Load an assets-list dump, in the format its extension implies.
+fn load_dump(path: &Path) -> anyhow::Result<AssetsListDump> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => bail!("couldn't read {path:?}: {e}"),
+    };
+    let dump = match AssetsListFormat::from_path(path) {
+        AssetsListFormat::Json => serde_json::from_str(&contents).map_err(anyhow::Error::from),
+        AssetsListFormat::Toml => toml::from_str(&contents).map_err(anyhow::Error::from),
+    };
+    match dump {
+        Ok(dump) => Ok(dump),
+        Err(e) => bail!("couldn't parse {path:?}: {e}"),
+    }
+}
+
+
+/// Rebuild a .pak file's header and assets list from a (probably
+/// edited) dump, keeping its asset data exactly as it is, and write the
+/// result to `output_file`.
+///
+/// Nothing stops the dump from describing assets that don't match the
+/// data (that's the point), but assets that extend past the end of it
+/// are warned about.
+pub fn import(
+    input_file: &Path,
+    dump_file: &Path,
+    output_file: &Path,
+    key: KeyRef,
+    force: bool,
+) -> anyhow::Result<()> {
+    if output_file.canonicalize().ok() == Some(input_file.canonicalize()?) {
+        bail!("the output file can't be the input .pak (its asset data is copied from there)");
+    }
+    if !force && output_file.is_file() {
+        bail!("output file exists (use -f to force)");
+    }
+
+    let dump = load_dump(dump_file)?;
+    if dump.version == EXTENDED_FILE_VERSION {
+        // (whose assets list has 64-bit offsets, which this doesn't write)
+        bail!("version {EXTENDED_FILE_VERSION} (extended offsets) isn't supported");
+    }
+    let mut archive = PakArchive::open(input_file, key)?;
+    let data_start = archive.data_start_offset();
+    let data_len = std::fs::metadata(input_file)?.len().saturating_sub(data_start);
+
+    for entry in &dump.assets {
+        let end = u64::from(entry.offset) + u64::from(entry.size_compressed);
+        if end > data_len {
+            eprintln!(
+                "warning: {} extends past the end of the asset data ({end:#x} > {data_len:#x})",
+                entry.name,
+            );
+        }
+    }
+
+    let assets_list: Vec<PakAsset> = dump.assets.iter().map(|entry| PakAsset {
+        name: entry.name.as_bytes().to_vec(),
+        size_decompressed: entry.size_decompressed,
+        size_compressed: entry.size_compressed,
+        offset: entry.offset,
+        plaintext_crc32: entry.plaintext_crc32,
+        ciphertext_crc32: entry.ciphertext_crc32,
+    }).collect();
+    let asset_count = assets_list.len();
+
+    let mut assets_list_cursor = Cursor::new(Vec::new());
+    (PakAssets {contents: assets_list}).write(&mut assets_list_cursor)?;
+    let mut assets_list_buf = assets_list_cursor.into_inner();
+
+    // (stored the same way as in the input .pak, but never compressed)
+    let plaintext_crc32 = crc32fast::hash(&assets_list_buf);
+    if archive.encrypted {
+        encrypt(ASSETS_LIST_NAME, key, &mut assets_list_buf);
+    }
+    let ciphertext_crc32 = crc32fast::hash(&assets_list_buf);
+
+    let mut header = PakHeader {
+        version: dump.version,
+        crc32: 0,
+        unk0c: dump.unk0c,
+        timestamp: dump.timestamp,
+        assets_list_size_decompressed: u32::try_from(assets_list_buf.len())?,
+        assets_list_size_compressed: u32::try_from(assets_list_buf.len())?,
+        plaintext_crc32,
+        ciphertext_crc32,
+    };
+
+    let mut writer = BufWriter::new(File::create(output_file)?);
+    header.write(&mut writer)?;
+    writer.write_all(&assets_list_buf)?;
+
+    let reader = archive.reader_mut();
+    reader.seek(SeekFrom::Start(data_start))?;
+    std::io::copy(reader, &mut writer)?;
+    writer.flush()?;
+    drop(writer);
+
+    // Now that everything else is in place, fill in the whole-file CRC32
+    header.crc32 = calc_pak_crc32_file(output_file)?;
+    let mut writer = BufWriter::new(File::options().write(true).open(output_file)?);
+    header.write(&mut writer)?;
+    writer.flush()?;
+
+    println!("Wrote {} ({asset_count} asset(s))", output_file.display());
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dump_round_trip() {
+        let dump = AssetsListDump {
+            version: 1,
+            unk0c: 1,
+            timestamp: 1_700_000_000,
+            crc32: Some(0x1234_5678),
+            assets_list_size: None,
+            assets_list_plaintext_crc32: None,
+            assets_list_ciphertext_crc32: None,
+            assets: vec![AssetsListDumpEntry {
+                name: "levels/1-1.bin".to_owned(),
+                size_decompressed: 0x100,
+                size_compressed: 0x80,
+                offset: 0,
+                plaintext_crc32: 0xaaaa_aaaa,
+                ciphertext_crc32: 0xbbbb_bbbb,
+                absolute_offset: None,
+                field_0c: Some(0),
+                field_10: None,
+            }],
+        };
+
+        let json: AssetsListDump = serde_json::from_str(&serde_json::to_string(&dump).unwrap()).unwrap();
+        let toml: AssetsListDump = toml::from_str(&toml::to_string_pretty(&dump).unwrap()).unwrap();
+        for parsed in [json, toml] {
+            assert_eq!(parsed.crc32, Some(0x1234_5678));
+            assert_eq!(parsed.assets.len(), 1);
+            assert_eq!(parsed.assets[0].name, "levels/1-1.bin");
+            assert_eq!(parsed.assets[0].size_compressed, 0x80);
+            assert_eq!(parsed.assets[0].field_0c, Some(0));
+            assert_eq!(parsed.assets[0].field_10, None);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    archive::PakArchive,
+    encryption::decrypt,
+    key::KeyRef,
+    shared::decompress_lz4,
+};
+
+
+/// Time spent and bytes processed in one phase of extraction.
+#[derive(Default)]
+struct Phase {
+    time: Duration,
+    bytes: u64,
+}
+
+
+impl Phase {
+    /// Run `f`, adding its running time and `bytes` to the totals.
+    fn measure<T>(&mut self, bytes: u64, f: impl FnOnce() -> anyhow::Result<T>) -> anyhow::Result<T> {
+        let start = Instant::now();
+        let result = f()?;
+        self.time += start.elapsed();
+        self.bytes += bytes;
+        Ok(result)
+    }
+
+    /// Print the totals for this phase, as one row of the table.
+    #[allow(clippy::cast_precision_loss)]
+    fn print(&self, name: &str, total_time: Duration) {
+        let seconds = self.time.as_secs_f64();
+        let throughput = if seconds > 0.0 {
+            format!("{:.1} MB/s", self.bytes as f64 / seconds / 1_000_000.0)
+        } else {
+            "-".to_owned()
+        };
+        let share = if total_time.is_zero() {
+            0.0
+        } else {
+            seconds / total_time.as_secs_f64() * 100.0
+        };
+        println!("{name:<12} {seconds:>10.3} s {share:>6.1}% {:>14} {throughput:>14}", self.bytes);
+    }
+}
+
+
+/// Extract every asset in a .pak in memory, timing each phase (reading,
+/// checksumming, decrypting, decompressing and optionally writing to
+/// `scratch_folder`) separately, and print a breakdown.
+pub fn bench(input_file: &Path, key: KeyRef, scratch_folder: Option<&Path>) -> anyhow::Result<()> {
+    let mut open = Phase::default();
+    let mut read = Phase::default();
+    let mut crc = Phase::default();
+    let mut decryption = Phase::default();
+    let mut decompression = Phase::default();
+    let mut write = Phase::default();
+
+    let mut archive = open.measure(0, || PakArchive::open(input_file, key))?;
+    let assets = archive.assets().collect::<anyhow::Result<Vec<_>>>()?;
+
+    let scratch_folder = scratch_folder.map(|f| f.join(format!("packling-bench-{}", std::process::id())));
+    if let Some(folder) = &scratch_folder {
+        std::fs::create_dir_all(folder)?;
+    }
+
+    for (i, asset) in assets.iter().enumerate() {
+        let size_compressed = u64::from(asset.size_compressed);
+        let size_decompressed = u64::from(asset.size_decompressed);
+
+        let mut data = read.measure(size_compressed, || archive.read_asset_raw(asset))?;
+        crc.measure(size_compressed, || Ok(crc32fast::hash(&data)))?;
+        if archive.encrypted {
+            decryption.measure(size_compressed, || {
+                decrypt(&asset.name, key, &mut data);
+                Ok(())
+            })?;
+        }
+        if asset.size_compressed != asset.size_decompressed {
+            data = decompression.measure(size_decompressed, || decompress_lz4(&data, asset.size_decompressed))?;
+        }
+        if let Some(folder) = &scratch_folder {
+            // (flat numbered names, so that creating folders isn't
+            // part of the measurement)
+            write.measure(size_decompressed, || Ok(std::fs::write(folder.join(format!("{i}.bin")), &data)?))?;
+        }
+    }
+
+    if let Some(folder) = &scratch_folder {
+        std::fs::remove_dir_all(folder)?;
+    }
+
+    let total_time = open.time + read.time + crc.time + decryption.time + decompression.time + write.time;
+
+    println!("{} assets", assets.len());
+    println!("{:<12} {:>12} {:>7} {:>14} {:>14}", "phase", "time", "share", "bytes", "throughput");
+    open.print("open", total_time);
+    read.print("read", total_time);
+    crc.print("crc32", total_time);
+    if archive.encrypted {
+        decryption.print("decrypt", total_time);
+    }
+    decompression.print("decompress", total_time);
+    if scratch_folder.is_some() {
+        write.print("write", total_time);
+    }
+    println!("{:<12} {:>10.3} s", "total", total_time.as_secs_f64());
+    println!();
+    println!("Note: if the .pak was read recently, it may be cached in memory, making \"read\" faster than the storage really is.");
+
+    Ok(())
+}
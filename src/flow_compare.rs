@@ -0,0 +1,198 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use anyhow::{Context, bail};
+use binrw::BinRead;
+
+use crate::shared::{PAK_HEADER_SIZE, PakHeader, Verbosity};
+
+
+/// A byte range where two files differ, with the CRC32 of each file's
+/// bytes in it.
+#[derive(Clone, Eq, PartialEq, Debug)]
+struct DifferingRange {
+    start: u64,
+    end: u64,
+    old_crc32: u32,
+    new_crc32: u32,
+}
+
+
+/// Find the byte ranges where two files differ, comparing them a block
+/// at a time. Ranges never span any of `boundaries`, so that each is
+/// within a single region of the file. (If one file is longer, the rest
+/// of it counts as differing.)
+fn differing_ranges<R1: Read, R2: Read>(
+    old: &mut R1,
+    new: &mut R2,
+    boundaries: &[u64],
+    block_size: u64,
+) -> anyhow::Result<Vec<DifferingRange>> {
+    let mut ranges: Vec<(u64, u64, crc32fast::Hasher, crc32fast::Hasher)> = Vec::new();
+    let mut old_block = Vec::new();
+    let mut new_block = Vec::new();
+    let mut position = 0;
+
+    loop {
+        let next_boundary = boundaries.iter().copied().filter(|&b| b > position).min().unwrap_or(u64::MAX);
+        let len = block_size.min(next_boundary - position);
+
+        old_block.clear();
+        new_block.clear();
+        old.by_ref().take(len).read_to_end(&mut old_block)?;
+        new.by_ref().take(len).read_to_end(&mut new_block)?;
+        if old_block.is_empty() && new_block.is_empty() {
+            break;
+        }
+        let block_end = position + u64::try_from(old_block.len().max(new_block.len()))?;
+
+        if old_block != new_block {
+            match ranges.last_mut() {
+                Some(range) if range.1 == position && !boundaries.contains(&position) => range.1 = block_end,
+                _ => ranges.push((position, block_end, crc32fast::Hasher::new(), crc32fast::Hasher::new())),
+            }
+            let range = ranges.last_mut().unwrap();
+            range.2.update(&old_block);
+            range.3.update(&new_block);
+        }
+
+        position = block_end;
+    }
+
+    Ok(ranges.into_iter().map(|(start, end, old_hasher, new_hasher)| DifferingRange {
+        start,
+        end,
+        old_crc32: old_hasher.finalize(),
+        new_crc32: new_hasher.finalize(),
+    }).collect())
+}
+
+
+/// Read the header of a .pak file, without checking anything but the
+/// magic.
+fn read_header(path: &Path) -> anyhow::Result<PakHeader> {
+    PakHeader::read(&mut BufReader::new(File::open(path)?))
+        .with_context(|| format!("{} isn't a .pak file", path.display()))
+}
+
+
+/// Compare two .pak files without the key: their (unencrypted) header
+/// fields, their sizes, and which byte ranges differ, with the CRC32 of
+/// each file's bytes in each. This can show that two downloads of an
+/// encrypted .pak are identical, or roughly where they differ (by
+/// region, and in steps of `block_size` bytes).
+///
+/// Differences are an error, so that scripts can check the result.
+pub fn compare(old_file: &Path, new_file: &Path, block_size: u64, verbosity: Verbosity) -> anyhow::Result<()> {
+    if block_size == 0 {
+        bail!("the block size can't be 0");
+    }
+
+    let old_header = read_header(old_file)?;
+    let new_header = read_header(new_file)?;
+    let old_size = std::fs::metadata(old_file)?.len();
+    let new_size = std::fs::metadata(new_file)?.len();
+
+    let header_fields = [
+        ("version", u64::from(old_header.version), u64::from(new_header.version)),
+        ("whole-file CRC32", old_header.crc32.into(), new_header.crc32.into()),
+        ("field 0x0c", old_header.unk0c.into(), new_header.unk0c.into()),
+        ("timestamp", old_header.timestamp as u64, new_header.timestamp as u64),
+        ("assets list size", old_header.assets_list_size_compressed.into(), new_header.assets_list_size_compressed.into()),
+        ("assets list size (decompressed)", old_header.assets_list_size_decompressed.into(), new_header.assets_list_size_decompressed.into()),
+        ("assets list plaintext CRC32", old_header.plaintext_crc32.into(), new_header.plaintext_crc32.into()),
+        ("assets list ciphertext CRC32", old_header.ciphertext_crc32.into(), new_header.ciphertext_crc32.into()),
+    ];
+    let differing_fields: Vec<_> = header_fields.iter().filter(|(_, old, new)| old != new).collect();
+
+    let old_data_start = PAK_HEADER_SIZE as u64 + u64::from(old_header.assets_list_size_compressed);
+    let ranges = differing_ranges(
+        &mut BufReader::new(File::open(old_file)?),
+        &mut BufReader::new(File::open(new_file)?),
+        &[PAK_HEADER_SIZE as u64, old_data_start],
+        block_size,
+    )?;
+
+    if ranges.is_empty() {
+        if verbosity >= Verbosity::Normal {
+            println!("{} and {} are identical ({old_size} bytes)", old_file.display(), new_file.display());
+        }
+        return Ok(());
+    }
+
+    if !differing_fields.is_empty() {
+        println!("Header fields that differ ({}):", differing_fields.len());
+        for (name, old, new) in differing_fields {
+            println!("  {name}: {old:#x} -> {new:#x}");
+        }
+    }
+    if old_size != new_size {
+        println!("File sizes: {old_size:#x} -> {new_size:#x} bytes");
+    }
+
+    println!("Byte ranges that differ ({}):", ranges.len());
+    for range in &ranges {
+        let region = if range.start < PAK_HEADER_SIZE as u64 {
+            "header"
+        } else if range.start < old_data_start {
+            "assets list"
+        } else {
+            "asset data"
+        };
+        println!(
+            "  {:#x}..{:#x} ({region}): CRC32 {:08x} -> {:08x}",
+            range.start,
+            range.end,
+            range.old_crc32,
+            range.new_crc32,
+        );
+    }
+
+    // (the assets list has every asset's ciphertext CRC32 and location)
+    if old_header.assets_list_size_compressed == new_header.assets_list_size_compressed
+        && old_header.ciphertext_crc32 == new_header.ciphertext_crc32
+        && ranges.iter().all(|range| range.start >= old_data_start)
+    {
+        println!("The assets lists are identical, so the differing asset data is either unused or corrupted");
+    }
+
+    bail!("{} and {} differ", old_file.display(), new_file.display());
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u64, end: u64, old: &[u8], new: &[u8]) -> DifferingRange {
+        DifferingRange {start, end, old_crc32: crc32fast::hash(old), new_crc32: crc32fast::hash(new)}
+    }
+
+    #[test]
+    fn test_differing_ranges() {
+        let old = [0_u8; 0x20];
+        let mut new = old;
+        new[0x05] = 1;
+        new[0x09] = 2;
+        new[0x11] = 3;
+
+        // Adjacent differing blocks are merged, but not across a boundary
+        let ranges = differing_ranges(&mut &old[..], &mut &new[..], &[0x08], 4).unwrap();
+        assert_eq!(ranges, [
+            range(0x04, 0x08, &old[0x04..0x08], &new[0x04..0x08]),
+            range(0x08, 0x0c, &old[0x08..0x0c], &new[0x08..0x0c]),
+            range(0x10, 0x14, &old[0x10..0x14], &new[0x10..0x14]),
+        ]);
+        let ranges = differing_ranges(&mut &old[..], &mut &new[..], &[], 4).unwrap();
+        assert_eq!(ranges[0], range(0x04, 0x0c, &old[0x04..0x0c], &new[0x04..0x0c]));
+
+        // The rest of a longer file differs
+        let ranges = differing_ranges(&mut &old[..], &mut &old[..0x1a], &[], 8).unwrap();
+        assert_eq!(ranges, [range(0x18, 0x20, &old[0x18..0x20], &old[0x18..0x1a])]);
+
+        assert!(differing_ranges(&mut &old[..], &mut &old[..], &[0x08], 4).unwrap().is_empty());
+    }
+}
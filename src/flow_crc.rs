@@ -0,0 +1,121 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use anyhow::bail;
+
+use crate::{
+    encryption::encrypt,
+    flow_pack::compress_asset,
+    jamcrc32::Jamcrc32Hasher,
+    key::KeyRef,
+    shared::{PAK_CRC32_START_OFFSET, calc_field_0x0c, calc_field_0x10},
+};
+
+
+/// Initial value to use for a CRC32 calculation.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum CrcInit {
+    Value(u32),
+    /// The size of the whole file (truncated to 32 bits), as used for
+    /// the whole-file JAMCRC32 in `PakHeader`.
+    FileSize,
+}
+
+
+/// Options for `print_crc()`.
+pub struct CrcOptions {
+    /// Calculate a JAMCRC32 instead of a regular CRC32.
+    pub jam: bool,
+    pub init: CrcInit,
+    /// Offset in the file to start at.
+    pub start: u64,
+}
+
+
+impl CrcOptions {
+    /// The options that reproduce the whole-file checksum stored in the
+    /// header of a .pak file.
+    pub fn pak_header() -> Self {
+        Self {
+            jam: true,
+            init: CrcInit::FileSize,
+            start: PAK_CRC32_START_OFFSET as u64,
+        }
+    }
+}
+
+
+/// Print the CRC32 or JAMCRC32 of a file.
+pub fn print_crc(input_file: &Path, options: &CrcOptions) -> anyhow::Result<()> {
+    let file = File::open(input_file)?;
+    let file_size = file.metadata()?.len();
+    if options.start > file_size {
+        bail!("start offset {:#x} is past the end of the file ({file_size:#x} bytes)", options.start);
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    let init = match options.init {
+        CrcInit::Value(value) => value,
+        CrcInit::FileSize => file_size as u32,
+    };
+
+    let mut reader = BufReader::new(file);
+    reader.seek(SeekFrom::Start(options.start))?;
+
+    let crc = if options.jam {
+        let mut hasher = Jamcrc32Hasher::new_with_initial(init);
+        update_from_reader(&mut reader, |data| hasher.update(data))?;
+        hasher.finalize()
+    } else {
+        let mut hasher = crc32fast::Hasher::new_with_initial(init);
+        update_from_reader(&mut reader, |data| hasher.update(data))?;
+        hasher.finalize()
+    };
+
+    println!("{crc:#010x}");
+
+    Ok(())
+}
+
+
+/// Feed everything remaining in `reader` to `update`, in pieces.
+fn update_from_reader<R: Read>(reader: &mut R, mut update: impl FnMut(&[u8])) -> anyhow::Result<()> {
+    let mut buffer = vec![0; 8 * 1024];
+    loop {
+        let amount_read = reader.read(&mut buffer)?;
+        if amount_read == 0 {
+            return Ok(());
+        }
+        update(&buffer[..amount_read]);
+    }
+}
+
+
+/// Print the `PakAsset` fields (other than the offset) that a file
+/// would have if it were packed into a .pak as `asset_name`.
+pub fn print_asset_fields(input_file: &Path, asset_name: &str, key: KeyRef, compress: bool) -> anyhow::Result<()> {
+    let asset_name = asset_name.as_bytes();
+
+    let mut data = std::fs::read(input_file)?;
+    let size_decompressed = u32::try_from(data.len())?;
+    if compress {
+        data = compress_asset(data);
+    }
+    let size_compressed = u32::try_from(data.len())?;
+
+    let plaintext_crc32 = crc32fast::hash(&data);
+    encrypt(asset_name, key, &mut data);
+    let ciphertext_crc32 = crc32fast::hash(&data);
+
+    println!("Size (decompressed): {size_decompressed:#x}");
+    println!("Size (compressed):   {size_compressed:#x}");
+    println!("Field 0x0C:          {:#010x}", calc_field_0x0c(asset_name, size_compressed));
+    println!("Field 0x10:          {:#010x}", calc_field_0x10(asset_name, size_compressed));
+    println!("Plaintext CRC32:     {plaintext_crc32:#010x}");
+    println!("Ciphertext CRC32:    {ciphertext_crc32:#010x}");
+
+    Ok(())
+}
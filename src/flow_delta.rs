@@ -0,0 +1,802 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use anyhow::bail;
+use binrw::{BinRead, BinWrite, binrw};
+use clap::ValueEnum;
+
+use crate::{
+    archive::PakArchive,
+    encryption::XXTEA_CHUNK_SIZE,
+    flow_verify::verify,
+    key::KeyRef,
+    shared::{PakAsset, Verbosity, hash_file_section},
+};
+
+
+/// The current version of the delta patch format.
+pub const DELTA_VERSION: u32 = 1;
+
+/// The largest file an IPS patch can describe (its offsets are 24-bit).
+const IPS_MAX_FILE_SIZE: u64 = 0xff_ffff;
+
+/// The largest IPS record.
+const IPS_MAX_RECORD_SIZE: u64 = 0xffff;
+
+/// An IPS record can't start at this offset, since it'd read as "EOF".
+const IPS_EOF_OFFSET: u64 = 0x45_4f46;
+
+
+/// Formats to write a patch in.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default, ValueEnum)]
+#[clap(rename_all = "kebab_case")]
+pub enum PatchFormat {
+    /// packling's own format, which `packling apply` can use.
+    #[default]
+    Packling,
+    /// BPS, as used for ROM hacks, which most patchers support.
+    Bps,
+    /// IPS, which is even more widely supported, but limited to files
+    /// under 16 MiB.
+    Ips,
+}
+
+
+/// Header of a delta patch file (which is followed by `DeltaOp`s, up to
+/// `DeltaOp::End`). The sizes and plain CRC32s of the old and new files
+/// let a patch check that it's being applied to the right file, and
+/// that the result is right.
+#[binrw]
+#[brw(little, magic = b"PKDL")]
+pub struct DeltaHeader {
+    pub version: u32,
+    pub old_size: u64,
+    pub old_crc32: u32,
+    pub new_size: u64,
+    pub new_crc32: u32,
+}
+
+
+/// A single step in building the new file from the old one.
+#[binrw]
+#[brw(little)]
+#[derive(Debug, PartialEq, Eq)]
+pub enum DeltaOp {
+    /// Append `len` bytes from `offset` in the old file.
+    #[brw(magic = 0_u8)]
+    Copy { offset: u64, len: u64 },
+    /// Append the `len` bytes that follow this in the patch.
+    #[brw(magic = 1_u8)]
+    Insert { len: u64 },
+    /// The end of the patch.
+    #[brw(magic = 2_u8)]
+    End,
+}
+
+
+/// A list of `DeltaOp`s that builds the new file from the old one,
+/// with adjacent copies and inserts merged into single ops. Inserted
+/// data always comes from the same offset in the new file, so it's read
+/// from there when the patch is written.
+#[derive(Default)]
+struct DeltaPlan {
+    ops: Vec<DeltaOp>,
+    copied: u64,
+    inserted: u64,
+}
+
+
+impl DeltaPlan {
+    fn copy(&mut self, offset: u64, len: u64) {
+        if let Some(DeltaOp::Copy {offset: last_offset, len: last_len}) = self.ops.last_mut()
+            && *last_offset + *last_len == offset
+        {
+            *last_len += len;
+        } else {
+            self.ops.push(DeltaOp::Copy {offset, len});
+        }
+        self.copied += len;
+    }
+
+    fn insert(&mut self, len: u64) {
+        if let Some(DeltaOp::Insert {len: last_len}) = self.ops.last_mut() {
+            *last_len += len;
+        } else {
+            self.ops.push(DeltaOp::Insert {len});
+        }
+        self.inserted += len;
+    }
+}
+
+
+/// Copy `len` bytes from `offset` in `reader` to `writer`.
+fn copy_range<R: Read + Seek, W: Write>(reader: &mut R, offset: u64, len: u64, writer: &mut W) -> anyhow::Result<()> {
+    reader.seek(SeekFrom::Start(offset))?;
+    if std::io::copy(&mut reader.by_ref().take(len), writer)? != len {
+        bail!("couldn't read {len:#x} bytes from {offset:#x}");
+    }
+    Ok(())
+}
+
+
+/// Work out how to build `new_archive` (`new_size` bytes long) from
+/// `old_archive`, as described for `create_delta()`.
+fn plan_delta<R: Read + Seek>(
+    old_archive: &mut PakArchive<R>,
+    old_size: u64,
+    new_archive: &mut PakArchive<R>,
+    new_size: u64,
+) -> anyhow::Result<DeltaPlan> {
+    let mut old_assets: HashMap<Vec<u8>, PakAsset> = HashMap::new();
+    for asset in old_archive.assets() {
+        let asset = asset?;
+        old_assets.entry(asset.name.clone()).or_insert(asset);
+    }
+
+    let mut new_assets = new_archive.assets().collect::<anyhow::Result<Vec<_>>>()?;
+    new_assets.sort_by_key(|asset| asset.offset);
+
+    let mut plan = DeltaPlan::default();
+    let data_start = new_archive.data_start_offset();
+    plan.insert(data_start);
+    let mut position = data_start;
+
+    for asset in &new_assets {
+        let start = new_archive.asset_offset(asset);
+        let end = start + u64::from(asset.size_compressed);
+        if end <= position || end > new_size {
+            // (overlapping an earlier asset entirely, or past the end of
+            // the file, so whatever's there is covered below)
+            continue;
+        }
+        if start > position {
+            plan.insert(start - position);
+            position = start;
+        }
+
+        let old = old_assets.get(&asset.name)
+            .filter(|old| old.size_compressed == asset.size_compressed)
+            .filter(|old| old_archive.asset_offset(old) + u64::from(old.size_compressed) <= old_size);
+        let Some(old) = old else {
+            plan.insert(end - position);
+            position = end;
+            continue;
+        };
+        let data = new_archive.read_asset_raw(asset)?;
+        let old_data = old_archive.read_asset_raw(old)?;
+
+        let mut relative = usize::try_from(position - start)?;
+        while relative < data.len() {
+            let chunk_end = ((relative / XXTEA_CHUNK_SIZE + 1) * XXTEA_CHUNK_SIZE).min(data.len());
+            let chunk_len = u64::try_from(chunk_end - relative)?;
+            if old_data[relative..chunk_end] == data[relative..chunk_end] {
+                plan.copy(old_archive.asset_offset(old) + u64::try_from(relative)?, chunk_len);
+            } else {
+                plan.insert(chunk_len);
+            }
+            relative = chunk_end;
+        }
+        position = end;
+    }
+
+    if position < new_size {
+        plan.insert(new_size - position);
+    }
+
+    Ok(plan)
+}
+
+
+/// Create a patch that turns `old_file` into `new_file`, exactly.
+///
+/// This works at the level of encryption chunks within assets: each
+/// asset in the new .pak that's also in the old one, with the same name
+/// and stored size (and so, the same encryption key), is compared to it
+/// a chunk at a time, and only the chunks that differ are included in
+/// the patch. Everything else is copied from the old .pak, so unchanged
+/// assets cost almost nothing. The header, assets list, and anything
+/// else outside of matching assets are included in full.
+///
+/// BPS and IPS patches are made from the same comparison, for use with
+/// other patchers. (IPS can only overwrite data in place, so it's much
+/// less compact if assets have moved.)
+pub fn create_delta(
+    old_file: &Path,
+    new_file: &Path,
+    patch_file: &Path,
+    key: KeyRef,
+    format: PatchFormat,
+    force: bool,
+    verbosity: Verbosity,
+) -> anyhow::Result<()> {
+    let mut old_archive = PakArchive::open(old_file, key)?;
+    let mut new_archive = PakArchive::open(new_file, key)?;
+    let old_size = std::fs::metadata(old_file)?.len();
+    let new_size = std::fs::metadata(new_file)?.len();
+
+    if format == PatchFormat::Ips && new_size > IPS_MAX_FILE_SIZE {
+        bail!("the new .pak is too large for an IPS patch (use BPS instead)");
+    }
+
+    let plan = plan_delta(&mut old_archive, old_size, &mut new_archive, new_size)?;
+
+    let f = File::options()
+        .write(true)
+        .truncate(true)
+        .create(force)
+        .create_new(!force)
+        .open(patch_file);
+    if let Err(ref e) = f
+        && e.kind() == ErrorKind::AlreadyExists
+    {
+        bail!("output file exists (use -f to force)");
+    }
+    let mut writer = BufWriter::new(f?);
+    let mut new_reader = BufReader::new(File::open(new_file)?);
+    let old_crc32 = hash_file_section(old_file, 0, old_size)?.finalize();
+    let new_crc32 = hash_file_section(new_file, 0, new_size)?.finalize();
+
+    match format {
+        PatchFormat::Packling => {
+            DeltaHeader {version: DELTA_VERSION, old_size, old_crc32, new_size, new_crc32}.write(&mut writer)?;
+            let mut position = 0;
+            for op in &plan.ops {
+                op.write(&mut writer)?;
+                match *op {
+                    DeltaOp::Copy {len, ..} => position += len,
+                    DeltaOp::Insert {len} => {
+                        copy_range(&mut new_reader, position, len, &mut writer)?;
+                        position += len;
+                    },
+                    DeltaOp::End => {},
+                }
+            }
+            DeltaOp::End.write(&mut writer)?;
+        },
+        PatchFormat::Bps => {
+            writer.write_all(b"BPS1")?;
+            write_bps_number(&mut writer, old_size)?;
+            write_bps_number(&mut writer, new_size)?;
+            write_bps_number(&mut writer, 0)?;  // (metadata size)
+            let mut position = 0;
+            let mut source_position = 0;
+            for op in &plan.ops {
+                match *op {
+                    DeltaOp::Copy {offset, len} if offset == position => {
+                        write_bps_number(&mut writer, ((len - 1) << 2) | BPS_SOURCE_READ)?;
+                        position += len;
+                    },
+                    DeltaOp::Copy {offset, len} => {
+                        write_bps_number(&mut writer, ((len - 1) << 2) | BPS_SOURCE_COPY)?;
+                        if offset >= source_position {
+                            write_bps_number(&mut writer, (offset - source_position) << 1)?;
+                        } else {
+                            write_bps_number(&mut writer, ((source_position - offset) << 1) | 1)?;
+                        }
+                        source_position = offset + len;
+                        position += len;
+                    },
+                    DeltaOp::Insert {len} => {
+                        write_bps_number(&mut writer, ((len - 1) << 2) | BPS_TARGET_READ)?;
+                        copy_range(&mut new_reader, position, len, &mut writer)?;
+                        position += len;
+                    },
+                    DeltaOp::End => {},
+                }
+            }
+            writer.write_all(&old_crc32.to_le_bytes())?;
+            writer.write_all(&new_crc32.to_le_bytes())?;
+            writer.flush()?;
+            let patch_crc32 = hash_file_section(patch_file, 0, writer.stream_position()?)?.finalize();
+            writer.write_all(&patch_crc32.to_le_bytes())?;
+        },
+        PatchFormat::Ips => {
+            writer.write_all(b"PATCH")?;
+            let mut position = 0;
+            for op in &plan.ops {
+                match *op {
+                    // (already there, since the patch is applied in place)
+                    DeltaOp::Copy {offset, len} if offset == position => position += len,
+                    DeltaOp::Copy {len, ..} | DeltaOp::Insert {len} => {
+                        write_ips_records(&mut new_reader, position, len, &mut writer)?;
+                        position += len;
+                    },
+                    DeltaOp::End => {},
+                }
+            }
+            writer.write_all(b"EOF")?;
+            if new_size < old_size {
+                writer.write_all(&new_size.to_be_bytes()[5..])?;
+            }
+        },
+    }
+
+    writer.flush()?;
+    let patch_size = writer.stream_position()?;
+
+    if verbosity >= Verbosity::Quiet {
+        println!(
+            "Created a {patch_size}-byte patch ({} bytes copied from the old .pak, {} bytes included)",
+            plan.copied,
+            plan.inserted,
+        );
+    }
+
+    Ok(())
+}
+
+
+/// BPS action: copy from the same offset in the old file.
+const BPS_SOURCE_READ: u64 = 0;
+/// BPS action: copy from the patch.
+const BPS_TARGET_READ: u64 = 1;
+/// BPS action: copy from a relative offset in the old file.
+const BPS_SOURCE_COPY: u64 = 2;
+/// BPS action: copy from a relative offset earlier in the new file.
+const BPS_TARGET_COPY: u64 = 3;
+
+
+/// Write a number in BPS's variable-length encoding.
+fn write_bps_number<W: Write>(writer: &mut W, mut value: u64) -> anyhow::Result<()> {
+    loop {
+        let low = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            writer.write_all(&[0x80 | low])?;
+            return Ok(());
+        }
+        writer.write_all(&[low])?;
+        value -= 1;
+    }
+}
+
+
+/// Read a number in BPS's variable-length encoding.
+fn read_bps_number<R: Read>(reader: &mut R) -> anyhow::Result<u64> {
+    let mut value: u64 = 0;
+    let mut shift: u64 = 1;
+    loop {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        value = u64::from(byte[0] & 0x7f).checked_mul(shift)
+            .and_then(|part| value.checked_add(part))
+            .ok_or_else(|| anyhow::anyhow!("patch is corrupt (number too large)"))?;
+        if byte[0] & 0x80 != 0 {
+            return Ok(value);
+        }
+        shift = shift.checked_shl(7).filter(|&shift| shift != 0)
+            .ok_or_else(|| anyhow::anyhow!("patch is corrupt (number too large)"))?;
+        value = value.checked_add(shift)
+            .ok_or_else(|| anyhow::anyhow!("patch is corrupt (number too large)"))?;
+    }
+}
+
+
+/// Write IPS records that set bytes `start..start + len` of the output
+/// to those in the new file.
+fn write_ips_records<R: Read + Seek, W: Write>(new_reader: &mut R, start: u64, len: u64, writer: &mut W) -> anyhow::Result<()> {
+    let end = start + len;
+    let mut position = start;
+    while position < end {
+        let mut record_start = position;
+        if record_start == IPS_EOF_OFFSET {
+            // (start a byte earlier instead, rewriting it as-is)
+            record_start -= 1;
+        }
+        let record_end = (record_start + IPS_MAX_RECORD_SIZE).min(end);
+        writer.write_all(&record_start.to_be_bytes()[5..])?;
+        writer.write_all(&u16::try_from(record_end - record_start)?.to_be_bytes())?;
+        copy_range(new_reader, record_start, record_end - record_start, writer)?;
+        position = record_end;
+    }
+    Ok(())
+}
+
+
+/// Apply a patch to `old_file`, writing the result to `new_file`. This
+/// can be a patch created by `create_delta()` in any format, or any
+/// other BPS or IPS patch.
+///
+/// The old file is checked against the size and CRC32 recorded in the
+/// patch first, and the new file afterward (it's deleted if it doesn't
+/// match). IPS patches don't record these, so those checks are skipped.
+/// Then all of the new .pak's own CRC32s are checked, as with `packling
+/// verify`, and it's deleted if any are wrong.
+pub fn apply_delta(
+    old_file: &Path,
+    patch_file: &Path,
+    new_file: &Path,
+    key: KeyRef,
+    force: bool,
+    verbosity: Verbosity,
+) -> anyhow::Result<()> {
+    let mut patch = BufReader::new(File::open(patch_file)?);
+    let magic = patch.fill_buf()?;
+    let format = if magic.starts_with(b"PKDL") {
+        PatchFormat::Packling
+    } else if magic.starts_with(b"BPS1") {
+        PatchFormat::Bps
+    } else if magic.starts_with(b"PATCH") {
+        PatchFormat::Ips
+    } else {
+        bail!("{patch_file:?} isn't a packling, BPS or IPS patch");
+    };
+
+    let old_size = std::fs::metadata(old_file)?.len();
+    let patch_size = std::fs::metadata(patch_file)?.len();
+    let old_crc32 = || -> anyhow::Result<u32> { Ok(hash_file_section(old_file, 0, old_size)?.finalize()) };
+
+    // Check everything that can be checked before creating the output
+    let expected = match format {
+        PatchFormat::Packling => {
+            let header = match DeltaHeader::read(&mut patch) {
+                Ok(header) => header,
+                Err(e) => bail!("{patch_file:?} isn't a valid packling delta patch ({e})"),
+            };
+            if header.version != DELTA_VERSION {
+                bail!("unsupported delta patch version {} (expected {DELTA_VERSION})", header.version);
+            }
+            if old_size != header.old_size || old_crc32()? != header.old_crc32 {
+                bail!("{old_file:?} isn't the file this patch was made for (or it's been modified since)");
+            }
+            Some((header.new_size, header.new_crc32))
+        },
+        PatchFormat::Bps => {
+            if patch_size < 4 + 3 + 12 {
+                bail!("patch is truncated");
+            }
+            patch.seek(SeekFrom::Start(patch_size - 12))?;
+            let mut footer = [0; 12];
+            patch.read_exact(&mut footer)?;
+            let [source_crc32, target_crc32, patch_crc32] = [0, 4, 8]
+                .map(|i| u32::from_le_bytes(footer[i..i + 4].try_into().unwrap()));
+            if hash_file_section(patch_file, 0, patch_size - 4)?.finalize() != patch_crc32 {
+                bail!("patch is corrupt (CRC32 mismatch)");
+            }
+
+            patch.seek(SeekFrom::Start(4))?;
+            let source_size = read_bps_number(&mut patch)?;
+            let target_size = read_bps_number(&mut patch)?;
+            let metadata_size = read_bps_number(&mut patch)?;
+            patch.seek_relative(i64::try_from(metadata_size)?)?;
+            if old_size != source_size || old_crc32()? != source_crc32 {
+                bail!("{old_file:?} isn't the file this patch was made for (or it's been modified since)");
+            }
+            Some((target_size, target_crc32))
+        },
+        PatchFormat::Ips => {
+            patch.seek(SeekFrom::Start(5))?;
+            None
+        },
+    };
+
+    let f = File::options()
+        .write(true)
+        .truncate(true)
+        .create(force)
+        .create_new(!force)
+        .open(new_file);
+    if let Err(ref e) = f
+        && e.kind() == ErrorKind::AlreadyExists
+    {
+        bail!("output file exists (use -f to force)");
+    }
+    let mut old = BufReader::new(File::open(old_file)?);
+
+    let result = match format {
+        PatchFormat::Packling => apply_packling(&mut patch, &mut old, old_size, BufWriter::new(f?)),
+        PatchFormat::Bps => apply_bps(&mut patch, patch_size - 12, &mut old, old_size, new_file, BufWriter::new(f?)),
+        PatchFormat::Ips => apply_ips(&mut patch, &mut old, f?),
+    };
+    if let Err(e) = result {
+        std::fs::remove_file(new_file)?;
+        return Err(e.context("couldn't apply the patch (the output file has been deleted)"));
+    }
+
+    if let Some((expected_size, expected_crc32)) = expected {
+        let new_size = std::fs::metadata(new_file)?.len();
+        if new_size != expected_size || hash_file_section(new_file, 0, new_size)?.finalize() != expected_crc32 {
+            std::fs::remove_file(new_file)?;
+            bail!("the patched file doesn't match the one the patch was made from (the output file has been deleted)");
+        }
+    }
+
+    if let Err(e) = verify(new_file, key, None, verbosity) {
+        std::fs::remove_file(new_file)?;
+        return Err(e.context("the patched .pak is damaged (the output file has been deleted)"));
+    }
+
+    Ok(())
+}
+
+
+/// Execute the `DeltaOp`s in a packling delta patch (after its header).
+fn apply_packling<P: Read + Seek, R: Read + Seek, W: Write>(
+    patch: &mut P,
+    old: &mut R,
+    old_size: u64,
+    mut writer: W,
+) -> anyhow::Result<()> {
+    loop {
+        match DeltaOp::read(patch)? {
+            DeltaOp::Copy {offset, len} => {
+                if offset.checked_add(len).is_none_or(|end| end > old_size) {
+                    bail!("patch is corrupt (copies {len:#x} bytes from {offset:#x}, past the end of the old file)");
+                }
+                copy_range(old, offset, len, &mut writer)?;
+            },
+            DeltaOp::Insert {len} => {
+                if std::io::copy(&mut patch.by_ref().take(len), &mut writer)? != len {
+                    bail!("patch is truncated");
+                }
+            },
+            DeltaOp::End => break,
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+
+/// Execute the actions in a BPS patch, from after its header up to
+/// `actions_end` (the start of its footer).
+fn apply_bps<P: Read + Seek, R: Read + Seek>(
+    patch: &mut P,
+    actions_end: u64,
+    old: &mut R,
+    old_size: u64,
+    new_file: &Path,
+    mut writer: BufWriter<File>,
+) -> anyhow::Result<()> {
+    let mut position: u64 = 0;
+    let mut source_position: u64 = 0;
+    let mut target_position: u64 = 0;
+    let mut target_reader = File::open(new_file)?;
+
+    let check_source = |offset: u64, len: u64| {
+        if offset.checked_add(len).is_none_or(|end| end > old_size) {
+            bail!("patch is corrupt (copies {len:#x} bytes from {offset:#x}, past the end of the old file)");
+        }
+        Ok(())
+    };
+    let relative = |base: u64, encoded: u64| {
+        let distance = encoded >> 1;
+        let offset = if encoded & 1 == 0 { base.checked_add(distance) } else { base.checked_sub(distance) };
+        offset.ok_or_else(|| anyhow::anyhow!("patch is corrupt (bad relative offset)"))
+    };
+
+    while patch.stream_position()? < actions_end {
+        let action = read_bps_number(patch)?;
+        let len = (action >> 2) + 1;
+        match action & 3 {
+            BPS_SOURCE_READ => {
+                check_source(position, len)?;
+                copy_range(old, position, len, &mut writer)?;
+            },
+            BPS_TARGET_READ => {
+                if std::io::copy(&mut patch.by_ref().take(len), &mut writer)? != len {
+                    bail!("patch is truncated");
+                }
+            },
+            BPS_SOURCE_COPY => {
+                source_position = relative(source_position, read_bps_number(patch)?)?;
+                check_source(source_position, len)?;
+                copy_range(old, source_position, len, &mut writer)?;
+                source_position += len;
+            },
+            BPS_TARGET_COPY => {
+                // (this may overlap the data it's creating, repeating it)
+                target_position = relative(target_position, read_bps_number(patch)?)?;
+                if target_position >= position {
+                    bail!("patch is corrupt (copies from {target_position:#x}, past the end of the new file so far)");
+                }
+                writer.flush()?;
+                let mut pattern = Vec::new();
+                copy_range(&mut target_reader, target_position, len.min(position - target_position), &mut pattern)?;
+                let data: Vec<u8> = pattern.iter().copied().cycle().take(usize::try_from(len)?).collect();
+                writer.write_all(&data)?;
+                target_position += len;
+            },
+            _ => unreachable!(),
+        }
+        position += len;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+
+/// Apply the records in an IPS patch (after its "PATCH" magic), on top
+/// of a copy of the old file.
+fn apply_ips<P: Read, R: Read + Seek>(patch: &mut P, old: &mut R, mut output: File) -> anyhow::Result<()> {
+    old.seek(SeekFrom::Start(0))?;
+    std::io::copy(old, &mut output)?;
+
+    let read_u24 = |patch: &mut P| -> std::io::Result<u64> {
+        let mut bytes = [0; 8];
+        patch.read_exact(&mut bytes[5..])?;
+        Ok(u64::from_be_bytes(bytes))
+    };
+
+    loop {
+        let offset = read_u24(patch)?;
+        if offset == IPS_EOF_OFFSET {
+            break;
+        }
+        let mut size = [0; 2];
+        patch.read_exact(&mut size)?;
+        let size = u16::from_be_bytes(size);
+
+        output.seek(SeekFrom::Start(offset))?;
+        if size == 0 {
+            // (run-length encoded)
+            let mut rle = [0; 3];
+            patch.read_exact(&mut rle)?;
+            let count = usize::from(u16::from_be_bytes([rle[0], rle[1]]));
+            output.write_all(&vec![rle[2]; count])?;
+        } else if std::io::copy(&mut patch.by_ref().take(u64::from(size)), &mut output)? != u64::from(size) {
+            bail!("patch is truncated");
+        }
+    }
+
+    // (an optional extension: the size to truncate the file to)
+    match read_u24(patch) {
+        Ok(size) => output.set_len(size)?,
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => {},
+        Err(e) => return Err(e.into()),
+    }
+
+    output.flush()?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow_pack::{PackOptions, pack};
+
+    const TEST_KEY: [u8; 16] = *b"packling testing";
+
+    /// Pack `files` into `pak_file`, via a folder next to it.
+    fn pack_files(pak_file: &Path, files: &[(&str, &[u8])]) {
+        let folder = pak_file.with_extension("");
+        std::fs::create_dir(&folder).unwrap();
+        for (name, data) in files {
+            std::fs::write(folder.join(name), data).unwrap();
+        }
+        pack(&folder, pak_file, &TEST_KEY, &PackOptions::default(), Verbosity::Silent).unwrap();
+    }
+
+    #[test]
+    fn test_plan_delta() {
+        let temp = tempfile::tempdir().unwrap();
+        let old_file = temp.path().join("old.pak");
+        let new_file = temp.path().join("new.pak");
+
+        // (one changed byte, in the second chunk of b.bin)
+        let data: Vec<u8> = (0..0x4005).map(|i| (i * 7) as u8).collect();
+        let mut new_data = data.clone();
+        new_data[0x2001] ^= 0xff;
+        pack_files(&old_file, &[("a.bin", &[1; 0x20]), ("b.bin", &data)]);
+        pack_files(&new_file, &[("a.bin", &[1; 0x20]), ("b.bin", &new_data)]);
+
+        let mut old_archive = PakArchive::open(&old_file, &TEST_KEY).unwrap();
+        let mut new_archive = PakArchive::open(&new_file, &TEST_KEY).unwrap();
+        let old_size = std::fs::metadata(&old_file).unwrap().len();
+        let new_size = std::fs::metadata(&new_file).unwrap().len();
+        let plan = plan_delta(&mut old_archive, old_size, &mut new_archive, new_size).unwrap();
+
+        // Everything but the header, assets list and changed chunk comes
+        // from the old .pak, in as few copies as possible
+        let data_start = new_archive.data_start_offset();
+        assert_eq!(plan.ops, [
+            DeltaOp::Insert {len: data_start},
+            DeltaOp::Copy {offset: data_start, len: 0x20 + 0x2000},
+            DeltaOp::Insert {len: 0x2000},
+            DeltaOp::Copy {offset: data_start + 0x20 + 0x4000, len: 5},
+        ]);
+        assert_eq!(plan.copied, 0x20 + 0x2000 + 5);
+        assert_eq!(plan.inserted, new_size - plan.copied);
+    }
+
+    /// Create a patch from `old_file` to `new_file`, apply it, and check
+    /// that the result is identical to `new_file`.
+    fn assert_delta_round_trip(old_file: &Path, new_file: &Path, format: PatchFormat) {
+        let patch_file = old_file.with_file_name("patch.bin");
+        let patched_file = old_file.with_file_name("patched.pak");
+        create_delta(old_file, new_file, &patch_file, &TEST_KEY, format, true, Verbosity::Silent).unwrap();
+        apply_delta(old_file, &patch_file, &patched_file, &TEST_KEY, true, Verbosity::Silent).unwrap();
+        assert_eq!(std::fs::read(&patched_file).unwrap(), std::fs::read(new_file).unwrap());
+    }
+
+    #[test]
+    fn test_delta_round_trip() {
+        let temp = tempfile::tempdir().unwrap();
+        let old_file = temp.path().join("old.pak");
+        let new_file = temp.path().join("new.pak");
+
+        let data: Vec<u8> = (0..0x4005).map(|i| (i * 7) as u8).collect();
+        let mut new_data = data.clone();
+        new_data[0x10] ^= 0xff;
+        pack_files(&old_file, &[("a.bin", &[1; 0x20]), ("b.bin", &data), ("c.bin", &[2; 0x30])]);
+        pack_files(&new_file, &[("a.bin", &[3; 0x40]), ("b.bin", &new_data), ("d.bin", &[4; 0x10])]);
+
+        assert_delta_round_trip(&old_file, &new_file, PatchFormat::Packling);
+    }
+
+    #[test]
+    fn test_bps_ips_round_trip() {
+        let temp = tempfile::tempdir().unwrap();
+        let small_file = temp.path().join("small.pak");
+        let large_file = temp.path().join("large.pak");
+
+        let data: Vec<u8> = (0..0x4005).map(|i| (i * 7) as u8).collect();
+        pack_files(&small_file, &[("a.bin", &[1; 0x20])]);
+        pack_files(&large_file, &[("a.bin", &[1; 0x20]), ("b.bin", &data)]);
+
+        // (extending the file, then truncating it)
+        for format in [PatchFormat::Bps, PatchFormat::Ips] {
+            assert_delta_round_trip(&small_file, &large_file, format);
+            assert_delta_round_trip(&large_file, &small_file, format);
+        }
+    }
+
+    #[test]
+    fn test_ips_eof_offset() {
+        let temp = tempfile::tempdir().unwrap();
+        let output_file = temp.path().join("output.bin");
+
+        let old = vec![0; usize::try_from(IPS_EOF_OFFSET).unwrap() + 0x10];
+        let mut new = old.clone();
+        new[usize::try_from(IPS_EOF_OFFSET).unwrap()..].fill(0xff);
+
+        // A record can't start at "EOF", so it starts a byte earlier
+        let mut patch = Vec::new();
+        write_ips_records(&mut std::io::Cursor::new(&new), IPS_EOF_OFFSET, 0x10, &mut patch).unwrap();
+        assert_eq!(patch[..5], [0x45, 0x4f, 0x45, 0x00, 0x11]);
+        patch.extend_from_slice(b"EOF");
+
+        let output = File::options().read(true).write(true).create_new(true).open(&output_file).unwrap();
+        apply_ips(&mut patch.as_slice(), &mut std::io::Cursor::new(&old), output).unwrap();
+        assert!(std::fs::read(&output_file).unwrap() == new);
+    }
+
+    #[test]
+    fn test_apply_delta_damaged() {
+        let temp = tempfile::tempdir().unwrap();
+        let old_file = temp.path().join("old.pak");
+        let new_file = temp.path().join("new.pak");
+        let patch_file = temp.path().join("patch.bin");
+        let patched_file = temp.path().join("patched.pak");
+
+        pack_files(&old_file, &[("a.bin", &[1; 0x20])]);
+        pack_files(&new_file, &[("a.bin", &[2; 0x20])]);
+
+        // A patch faithfully recreating a .pak with a bad asset CRC32
+        let mut new = std::fs::read(&new_file).unwrap();
+        *new.last_mut().unwrap() ^= 0xff;
+        std::fs::write(&new_file, new).unwrap();
+        create_delta(&old_file, &new_file, &patch_file, &TEST_KEY, PatchFormat::Packling, false, Verbosity::Silent).unwrap();
+
+        assert!(apply_delta(&old_file, &patch_file, &patched_file, &TEST_KEY, false, Verbosity::Silent).is_err());
+        assert!(!patched_file.exists());
+    }
+
+    #[test]
+    fn test_bps_number() {
+        for value in [0, 1, 0x7f, 0x80, 0x4080, 0xffff_ffff, u64::MAX >> 1] {
+            let mut data = Vec::new();
+            write_bps_number(&mut data, value).unwrap();
+            assert_eq!(read_bps_number(&mut data.as_slice()).unwrap(), value);
+        }
+        let mut data = Vec::new();
+        write_bps_number(&mut data, 0x80).unwrap();
+        assert_eq!(data, [0x00, 0x80]);
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{
+    archive::PakArchive,
+    key::KeyRef,
+};
+
+
+/// Formats to print a diff in.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default, ValueEnum)]
+#[clap(rename_all = "kebab_case")]
+pub enum DiffFormat {
+    /// One line per added ("+"), removed ("-") or changed ("M") asset.
+    #[default]
+    Text,
+    /// A machine-readable JSON report.
+    Json,
+    /// Markdown tables, e.g. for a mod's release notes.
+    Markdown,
+}
+
+
+/// An asset's contents (decrypted and decompressed), as compared by
+/// `diff()`.
+#[derive(Serialize, Clone, Eq, PartialEq, Debug)]
+pub struct DiffAsset {
+    pub name: String,
+    pub size: u32,
+    pub crc32: u32,
+}
+
+
+/// An asset that's in both .pak files, but with different contents.
+#[derive(Serialize, Clone, Eq, PartialEq, Debug)]
+pub struct ChangedAsset {
+    pub name: String,
+    pub old_size: u32,
+    pub new_size: u32,
+    pub old_crc32: u32,
+    pub new_crc32: u32,
+}
+
+
+/// The differences between the assets in two .pak files.
+#[derive(Serialize, Default, Debug)]
+pub struct PakDiff {
+    /// Assets only in the new .pak, in its order.
+    pub added: Vec<DiffAsset>,
+    /// Assets only in the old .pak, in its order.
+    pub removed: Vec<DiffAsset>,
+    /// Assets in both, with different contents, in the new .pak's
+    /// order.
+    pub changed: Vec<ChangedAsset>,
+    /// The number of assets in both, with the same contents.
+    pub unchanged: usize,
+}
+
+
+/// Read the name, size and CRC32 of every asset in a .pak, in order.
+fn read_contents(input_file: &Path, key: KeyRef) -> anyhow::Result<Vec<DiffAsset>> {
+    let mut archive = PakArchive::open(input_file, key)?;
+    let assets = archive.assets().collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut contents = Vec::with_capacity(assets.len());
+    for asset in &assets {
+        let data = archive.read_asset(asset)?;
+        contents.push(DiffAsset {
+            name: String::from_utf8_lossy(&asset.name).into_owned(),
+            size: asset.size_decompressed,
+            crc32: crc32fast::hash(&data),
+        });
+    }
+    Ok(contents)
+}
+
+
+/// Compare the assets in two .pak files by name and contents (after
+/// decryption and decompression, so an asset that's only been
+/// compressed differently doesn't count as changed).
+pub fn diff(old_file: &Path, new_file: &Path, key: KeyRef) -> anyhow::Result<PakDiff> {
+    let old_contents = read_contents(old_file, key)?;
+    let new_contents = read_contents(new_file, key)?;
+
+    let old_by_name: HashMap<&str, &DiffAsset> = old_contents.iter().map(|a| (a.name.as_str(), a)).collect();
+    let new_by_name: HashMap<&str, &DiffAsset> = new_contents.iter().map(|a| (a.name.as_str(), a)).collect();
+
+    let mut diff = PakDiff::default();
+    for new in &new_contents {
+        match old_by_name.get(new.name.as_str()) {
+            None => diff.added.push(new.clone()),
+            Some(old) if old.size == new.size && old.crc32 == new.crc32 => diff.unchanged += 1,
+            Some(old) => diff.changed.push(ChangedAsset {
+                name: new.name.clone(),
+                old_size: old.size,
+                new_size: new.size,
+                old_crc32: old.crc32,
+                new_crc32: new.crc32,
+            }),
+        }
+    }
+    diff.removed = old_contents.iter()
+        .filter(|old| !new_by_name.contains_key(old.name.as_str()))
+        .cloned()
+        .collect();
+
+    Ok(diff)
+}
+
+
+/// Print the differences between the assets in two .pak files.
+pub fn print_diff(old_file: &Path, new_file: &Path, key: KeyRef, format: DiffFormat) -> anyhow::Result<()> {
+    let diff = diff(old_file, new_file, key)?;
+
+    match format {
+        DiffFormat::Text => {
+            for asset in &diff.added {
+                println!("+ {}", asset.name);
+            }
+            for asset in &diff.removed {
+                println!("- {}", asset.name);
+            }
+            for asset in &diff.changed {
+                println!("M {} ({:#x} -> {:#x} bytes)", asset.name, asset.old_size, asset.new_size);
+            }
+            println!(
+                "{} added, {} removed, {} changed, {} unchanged",
+                diff.added.len(),
+                diff.removed.len(),
+                diff.changed.len(),
+                diff.unchanged,
+            );
+        },
+        DiffFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&diff)?);
+        },
+        DiffFormat::Markdown => {
+            print!("{}", diff_markdown(&diff));
+        },
+    }
+
+    Ok(())
+}
+
+
+/// Escape text for a Markdown table cell (which, even inside a code
+/// span, can't contain a bare "|").
+fn markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+
+/// Render a diff as Markdown tables.
+fn diff_markdown(diff: &PakDiff) -> String {
+    let mut lines = vec![format!(
+        "**{} added, {} removed, {} changed**, {} unchanged",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.changed.len(),
+        diff.unchanged,
+    )];
+
+    for (heading, assets) in [("Added", &diff.added), ("Removed", &diff.removed)] {
+        if assets.is_empty() {
+            continue;
+        }
+        lines.push(String::new());
+        lines.push(format!("### {heading}"));
+        lines.push(String::new());
+        lines.push("| Asset | Size | CRC32 |".to_owned());
+        lines.push("| --- | ---: | --- |".to_owned());
+        for asset in assets {
+            lines.push(format!("| `{}` | {} | `{:08x}` |", markdown_cell(&asset.name), asset.size, asset.crc32));
+        }
+    }
+
+    if !diff.changed.is_empty() {
+        lines.push(String::new());
+        lines.push("### Changed".to_owned());
+        lines.push(String::new());
+        lines.push("| Asset | Old size | New size | Old CRC32 | New CRC32 |".to_owned());
+        lines.push("| --- | ---: | ---: | --- | --- |".to_owned());
+        for asset in &diff.changed {
+            lines.push(format!(
+                "| `{}` | {} | {} | `{:08x}` | `{:08x}` |",
+                markdown_cell(&asset.name),
+                asset.old_size,
+                asset.new_size,
+                asset.old_crc32,
+                asset.new_crc32,
+            ));
+        }
+    }
+
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_markdown() {
+        let diff = PakDiff {
+            added: vec![DiffAsset {name: "a|b.bin".to_owned(), size: 16, crc32: 0x1234}],
+            removed: Vec::new(),
+            changed: vec![ChangedAsset {
+                name: "c.bin".to_owned(),
+                old_size: 1,
+                new_size: 2,
+                old_crc32: 0xaaaa_aaaa,
+                new_crc32: 0xbbbb_bbbb,
+            }],
+            unchanged: 3,
+        };
+        assert_eq!(diff_markdown(&diff), "\
+**1 added, 0 removed, 1 changed**, 3 unchanged
+
+### Added
+
+| Asset | Size | CRC32 |
+| --- | ---: | --- |
+| `a\\|b.bin` | 16 | `00001234` |
+
+### Changed
+
+| Asset | Old size | New size | Old CRC32 | New CRC32 |
+| --- | ---: | ---: | --- | --- |
+| `c.bin` | 1 | 2 | `aaaaaaaa` | `bbbbbbbb` |
+");
+    }
+}
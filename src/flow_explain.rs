@@ -0,0 +1,155 @@
+use std::path::Path;
+
+use crate::{
+    archive::PakArchive,
+    encryption::{XXTEA_CHUNK_SIZE, plaintext_tail_len},
+    key::KeyRef,
+    shared::{
+        ASSETS_LIST_NAME_HASH,
+        FILE_VERSION,
+        PAK_CRC32_START_OFFSET,
+        PAK_HEADER_SIZE,
+        TimeZone,
+        calc_field_0x0c,
+        calc_field_0x10,
+        calc_pak_crc32_file,
+        format_timestamp,
+    },
+};
+
+
+/// Describe a CRC check result.
+pub(crate) fn check(stored: u32, calculated: u32) -> String {
+    if stored == calculated {
+        "matches".to_owned()
+    } else {
+        format!("MISMATCH, calculated {calculated:#010x}")
+    }
+}
+
+
+/// Walk through the structure of a .pak file, explaining what each part
+/// means, along with derived and validated values. Up to `max_entries`
+/// assets-list entries are described in detail.
+pub fn explain(input_file: &Path, key: KeyRef, max_entries: usize, timezone: TimeZone) -> anyhow::Result<()> {
+    let mut archive = PakArchive::open(input_file, key)?;
+    let file_size = std::fs::metadata(input_file)?.len();
+    let header = &archive.header;
+    let data_start = archive.data_start_offset();
+
+    println!("{} is {file_size:#x} bytes, in three parts: header, assets list, and asset data.", input_file.display());
+    println!();
+
+    println!("HEADER (0x00-{:#x}, never encrypted)", PAK_HEADER_SIZE - 1);
+    println!("  0x00  Magic \"KCAP\", identifying a Lingcod .pak file.");
+    println!(
+        "  0x04  Version {}. {}",
+        header.version,
+        if header.version == FILE_VERSION { "This is the only version found in released games." } else { "This isn't the usual version!" },
+    );
+    if archive.encrypted {
+        let calculated = calc_pak_crc32_file(input_file)?;
+        println!(
+            "  0x08  Whole-file JAMCRC32 {:#010x}, over bytes {PAK_CRC32_START_OFFSET:#x} to the end, starting from the file size instead of the usual 0xffffffff ({}).",
+            header.crc32,
+            check(header.crc32, calculated),
+        );
+    } else {
+        println!(
+            "  0x08  Whole-file JAMCRC32 {:#010x}. This .pak has been decrypted, so it was calculated over the encrypted data, and can't be checked.",
+            header.crc32,
+        );
+    }
+    println!("  0x0c  {}: always 1 in known files; changing it has no known effect.", header.unk0c);
+    println!("  0x0d  Timestamp {}: when the .pak was created (7-byte signed Unix time).", format_timestamp(header.timestamp, timezone)?);
+    println!(
+        "  0x14  Assets list size, decompressed: {:#x} bytes.",
+        header.assets_list_size_decompressed,
+    );
+    println!(
+        "  0x18  Assets list size, as stored: {:#x} bytes. {}",
+        header.assets_list_size_compressed,
+        if header.assets_list_size_compressed == header.assets_list_size_decompressed {
+            "The sizes are equal, so the list isn't compressed."
+        } else {
+            "The sizes differ, so the list is LZ4-compressed."
+        },
+    );
+    println!(
+        "  0x1c  Derived: djb2a(\"header\") ^ stored assets list size = {ASSETS_LIST_NAME_HASH:#010x} ^ {:#x} = {:#010x}.",
+        header.assets_list_size_compressed,
+        ASSETS_LIST_NAME_HASH ^ header.assets_list_size_compressed,
+    );
+    println!("  0x20  CRC32 of the assets list after decryption: {:#010x}.", header.plaintext_crc32);
+    println!("  0x24  CRC32 of the assets list as stored: {:#010x}.", header.ciphertext_crc32);
+    println!();
+
+    println!("ASSETS LIST ({PAK_HEADER_SIZE:#x}-{:#x})", data_start.saturating_sub(1));
+    if archive.encrypted {
+        println!("  XXTEA-encrypted in {XXTEA_CHUNK_SIZE:#x}-byte chunks, with keys derived from the name \"header\", its size, and each chunk's offset.");
+    } else {
+        println!("  Decrypted (in the game's files, it's XXTEA-encrypted).");
+    }
+    println!(
+        "  {} entries, each a u32 name length, the name, and seven u32 fields: decompressed size, stored size, offset, two derived fields, and two CRC32s.",
+        archive.asset_count(),
+    );
+    println!();
+
+    println!("ASSET DATA ({data_start:#x}-{:#x})", file_size.saturating_sub(1));
+    println!("  Each asset is encrypted like the assets list, but with its own name as part of the key. Offsets in the assets list are relative to {data_start:#x}.");
+
+    let assets = archive.assets().take(max_entries).collect::<anyhow::Result<Vec<_>>>()?;
+    for (i, asset) in assets.iter().enumerate() {
+        let name = String::from_utf8_lossy(&asset.name);
+        let size = usize::try_from(asset.size_compressed)?;
+        let tail_len = plaintext_tail_len(size);
+
+        println!();
+        println!("  Entry #{i}: {name}");
+        println!(
+            "    Sizes: {:#x} bytes stored, {:#x} decompressed ({}).",
+            asset.size_compressed,
+            asset.size_decompressed,
+            if asset.size_compressed == asset.size_decompressed { "not compressed" } else { "LZ4-compressed" },
+        );
+        println!("    Offset: {:#x}, so it starts at {:#x} in the file.", asset.offset, archive.asset_offset(asset));
+        println!(
+            "    Field 0x0c (derived): {}, since it's 2 for assets of 0xa00000 bytes or more, or .alf files, and 0 otherwise.",
+            calc_field_0x0c(&asset.name, asset.size_compressed),
+        );
+        println!(
+            "    Field 0x10 (derived): djb2a(name) ^ stored size (or 0 for empty assets) = {:#010x}.",
+            calc_field_0x10(&asset.name, asset.size_compressed),
+        );
+
+        let ciphertext_status = match archive.read_asset_raw(asset) {
+            Ok(data) => check(asset.ciphertext_crc32, crc32fast::hash(&data)),
+            Err(e) => format!("can't be read: {e}"),
+        };
+        println!(
+            "    CRC32s: {:#010x} decrypted, {:#010x} as stored ({ciphertext_status}).",
+            asset.plaintext_crc32,
+            asset.ciphertext_crc32,
+        );
+        println!(
+            "    Encryption: {} chunk(s){}.",
+            size.div_ceil(XXTEA_CHUNK_SIZE),
+            if size > 0 && tail_len == size {
+                " in principle, but it's too small, so it isn't encrypted at all".to_owned()
+            } else if tail_len > 0 {
+                format!(", with the last {tail_len} byte(s) left unencrypted")
+            } else {
+                String::new()
+            },
+        );
+    }
+
+    let remaining = archive.asset_count().saturating_sub(assets.len());
+    if remaining > 0 {
+        println!();
+        println!("  ...and {remaining} more (use --entries to see more, or the list and map subcommands to see them all).");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use anyhow::bail;
+
+use crate::{
+    archive::PakArchive,
+    encryption::decrypt,
+    key::KeyRef,
+};
+
+
+/// Write extracted data to `output`, or to stdout if there isn't one.
+fn write_output(data: &[u8], output: Option<&Path>) -> anyhow::Result<()> {
+    if let Some(output) = output {
+        std::fs::write(output, data)?;
+    } else {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(data)?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+
+/// Extract the asset at position `index` in the assets list (counting
+/// from 0), decrypted and decompressed.
+///
+/// Only the entries up to `index` need to be intact, so this can still
+/// get at the assets near the start of a damaged assets list.
+pub fn extract_by_index(input_file: &Path, key: KeyRef, index: usize, output: Option<&Path>) -> anyhow::Result<()> {
+    let mut archive = PakArchive::open(input_file, key)?;
+    let count = archive.asset_count();
+    let Some(asset) = archive.assets().nth(index) else {
+        bail!("asset index {index} is out of range (there are {count} asset(s))");
+    };
+    let asset = asset?;
+
+    let data = archive.read_asset(&asset)?;
+    write_output(&data, output)
+}
+
+
+/// Extract `len` bytes starting at absolute file offset `offset`,
+/// without looking at the header or assets list at all.
+///
+/// If `name` is given, the bytes are decrypted as if they were a
+/// complete asset with that name (the name and size are both part of
+/// the key). Otherwise, they're written exactly as stored. Either way,
+/// the result isn't decompressed, since its decompressed size isn't
+/// known.
+pub fn extract_raw_range(
+    input_file: &Path,
+    key: KeyRef,
+    offset: u64,
+    len: u64,
+    name: Option<&str>,
+    output: Option<&Path>,
+) -> anyhow::Result<()> {
+    let mut file = File::open(input_file)?;
+    let file_size = file.metadata()?.len();
+    if offset.checked_add(len).is_none_or(|end| end > file_size) {
+        bail!("range {offset:#x}:{len:#x} extends past the end of the file ({file_size:#x} bytes)");
+    }
+    if u32::try_from(len).is_err() {
+        bail!("range length {len:#x} is too large to be a single asset");
+    }
+
+    let mut data = vec![0; usize::try_from(len)?];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut data)?;
+
+    if let Some(name) = name {
+        decrypt(name.as_bytes(), key, &mut data);
+    }
+
+    write_output(&data, output)
+}
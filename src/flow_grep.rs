@@ -0,0 +1,113 @@
+use std::path::Path;
+
+use anyhow::bail;
+use regex::bytes::Regex;
+
+use crate::{
+    archive::PakArchive,
+    key::KeyRef,
+};
+
+
+/// How the search pattern given to `grep` should be interpreted.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum PatternKind {
+    /// A literal string.
+    Literal,
+    /// A regular expression (matched against raw bytes).
+    Regex,
+    /// Hexadecimal bytes, optionally separated by whitespace.
+    Hex,
+}
+
+
+/// Build a byte regex from a user-supplied pattern.
+fn build_regex(pattern: &str, kind: PatternKind) -> anyhow::Result<Regex> {
+    let regex_str = match kind {
+        PatternKind::Literal => regex::escape(pattern),
+        PatternKind::Regex => pattern.to_owned(),
+        PatternKind::Hex => {
+            let digits: String = pattern.chars().filter(|c| !c.is_whitespace()).collect();
+            if !digits.len().is_multiple_of(2) || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                bail!("invalid hex pattern: {pattern:?}");
+            }
+            // (?-u) so that \xNN matches the raw byte rather than the
+            // UTF-8 encoding of U+00NN
+            let mut s = "(?-u)".to_owned();
+            for i in (0..digits.len()).step_by(2) {
+                s.push_str("\\x");
+                s.push_str(&digits[i..i + 2]);
+            }
+            s
+        },
+    };
+    Ok(Regex::new(&regex_str)?)
+}
+
+
+/// Search the decrypted and decompressed contents of every asset in a
+/// .pak for a pattern, and print the name and offset of each match.
+pub fn grep(
+    input_file: &Path,
+    key: KeyRef,
+    pattern: &str,
+    kind: PatternKind,
+    names_only: bool,
+) -> anyhow::Result<()> {
+    let regex = build_regex(pattern, kind)?;
+
+    let mut archive = PakArchive::open(input_file, key)?;
+    let assets = archive.assets().collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut num_matches = 0;
+
+    for asset in &assets {
+        let name_str = String::from_utf8_lossy(&asset.name);
+        let data = match archive.read_asset(asset) {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("{name_str}: {e:#}");
+                continue;
+            },
+        };
+
+        if names_only {
+            if regex.is_match(&data) {
+                println!("{name_str}");
+                num_matches += 1;
+            }
+            continue;
+        }
+
+        for m in regex.find_iter(&data) {
+            println!("{name_str}: {:#x}: {}", m.start(), String::from_utf8_lossy(m.as_bytes()).escape_debug());
+            num_matches += 1;
+        }
+    }
+
+    if num_matches == 0 {
+        bail!("no matches found");
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_regex() {
+        let literal = build_regex("a.b", PatternKind::Literal).unwrap();
+        assert!(literal.is_match(b"xxa.bxx"));
+        assert!(!literal.is_match(b"xxaxbxx"));
+
+        let hex = build_regex("4b 43 41 50 ff", PatternKind::Hex).unwrap();
+        assert!(hex.is_match(b"\x00KCAP\xff\x00"));
+        assert!(!hex.is_match(b"KCAP\xc3\xbf"));
+
+        assert!(build_regex("4b4", PatternKind::Hex).is_err());
+        assert!(build_regex("zz", PatternKind::Hex).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Write},
+    path::Path,
+};
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    archive::PakArchive,
+    filter::AssetFilter,
+    key::get_key_for_pak,
+};
+
+
+/// Index of the assets in every .pak file in a folder.
+#[derive(Serialize, Deserialize, Default)]
+pub(crate) struct PakIndex {
+    pub(crate) paks: Vec<IndexedPak>,
+}
+
+
+/// A single .pak file in a `PakIndex`.
+#[derive(Serialize, Deserialize, Default)]
+pub(crate) struct IndexedPak {
+    /// Path of the .pak, relative to the indexed folder.
+    pub(crate) path: String,
+    pub(crate) assets: Vec<IndexedAsset>,
+}
+
+
+/// A single asset in an `IndexedPak`.
+#[derive(Serialize, Deserialize, Default)]
+pub(crate) struct IndexedAsset {
+    pub(crate) name: String,
+    pub(crate) size_decompressed: u32,
+    pub(crate) size_compressed: u32,
+}
+
+
+/// Build an index of the assets in all .pak files in `input_folder`
+/// (recursively), and save it to `output_file` as JSON. Each .pak's key
+/// is found in `key_file` with `get_key_for_pak`.
+///
+/// Files that can't be opened as .pak files with a key from `key_file`
+/// are skipped with a warning.
+pub fn build_index(input_folder: &Path, output_file: &Path, key_file: &Path, force: bool) -> anyhow::Result<()> {
+    let mut paks = Vec::new();
+
+    for entry in walkdir::WalkDir::new(input_folder).sort_by_file_name() {
+        let entry = entry?;
+        let path = entry.path();
+
+        if !entry.file_type().is_file()
+            || !path.extension().is_some_and(|e| e.eq_ignore_ascii_case("pak"))
+        {
+            continue;
+        }
+
+        let archive = match get_key_for_pak(key_file, path).and_then(|key| PakArchive::open(path, &key)) {
+            Ok(archive) => archive,
+            Err(e) => {
+                eprintln!("warning: skipping {}: {e}", path.display());
+                continue;
+            },
+        };
+
+        paks.push(IndexedPak {
+            path: path.strip_prefix(input_folder)?.to_string_lossy().replace('\\', "/"),
+            assets: archive.assets().map(|asset| asset.map(|asset| IndexedAsset {
+                name: String::from_utf8_lossy(&asset.name).into_owned(),
+                size_decompressed: asset.size_decompressed,
+                size_compressed: asset.size_compressed,
+            })).collect::<anyhow::Result<_>>()?,
+        });
+    }
+
+    let f = File::options()
+        .write(true)
+        .truncate(true)
+        .create(force)
+        .create_new(!force)
+        .open(output_file);
+    if let Err(ref e) = f
+        && e.kind() == ErrorKind::AlreadyExists
+    {
+        bail!("output file exists (use -f to force)");
+    }
+
+    let mut writer = BufWriter::new(f?);
+    serde_json::to_writer_pretty(&mut writer, &PakIndex { paks })?;
+    writeln!(writer)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+
+/// Print which .pak file(s) in an index contain assets matching
+/// `filter`.
+pub fn which(index_file: &Path, filter: &AssetFilter) -> anyhow::Result<()> {
+    let index: PakIndex = serde_json::from_reader(BufReader::new(File::open(index_file)?))?;
+
+    let mut found = false;
+    for pak in &index.paks {
+        for asset in &pak.assets {
+            if filter.matches_name(asset.name.as_bytes()) {
+                println!("{}: {}", pak.path, asset.name);
+                found = true;
+            }
+        }
+    }
+
+    if !found {
+        bail!("no matching assets found in any indexed .pak");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,208 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use binrw::BinRead;
+
+use crate::{
+    archive::{PakArchive, read_assets_list},
+    encryption::{decrypt, plaintext_tail_len},
+    key::KeyRef,
+    sniff::{SNIFF_SIZE, detect_content_type},
+    shared::{
+        ASSETS_LIST_NAME,
+        ASSETS_LIST_NAME_HASH,
+        FILE_VERSION,
+        PAK_HEADER_SIZE,
+        PakAsset,
+        PakHeader,
+        calc_pak_crc32_file,
+        check_is_encrypted,
+        TimeZone,
+        format_timestamp,
+    },
+};
+
+
+/// Print a summary of a .pak file's header and contents.
+pub fn print_info(input_file: &Path, key: KeyRef, hexdump: bool, types: bool, timezone: TimeZone) -> anyhow::Result<()> {
+    if hexdump {
+        return print_header_hexdump(input_file, key, timezone);
+    }
+
+    let mut archive = PakArchive::open(input_file, key)?;
+    let file_size = archive.reader_mut().seek(SeekFrom::End(0))?;
+    let crc32 = calc_pak_crc32_file(input_file)?;
+
+    let assets = archive.assets().collect::<anyhow::Result<Vec<_>>>()?;
+    let header = &archive.header;
+    let total_compressed: u64 = assets.iter().map(|a| u64::from(a.size_compressed)).sum();
+    let total_decompressed: u64 = assets.iter().map(|a| u64::from(a.size_decompressed)).sum();
+
+    println!("Version:          {}", header.version);
+    println!("Created:          {}", format_timestamp(header.timestamp, timezone)?);
+    println!("Encrypted:        {}", if archive.encrypted { "yes" } else { "no" });
+    println!("File size:        {file_size:#x}");
+    println!("Whole-file CRC32: {:#010x} ({})", header.crc32, crc_status(header.crc32, crc32, archive.encrypted));
+    println!("Assets list size: {:#x} ({:#x} decompressed)", header.assets_list_size_compressed, header.assets_list_size_decompressed);
+    println!("Asset data start: {:#x}", archive.data_start_offset());
+    println!("Assets:           {}", assets.len());
+    println!("Total asset size: {total_compressed:#x} ({total_decompressed:#x} decompressed)");
+
+    let past_end = assets.iter()
+        .filter(|a| archive.asset_offset(a) + u64::from(a.size_compressed) > file_size)
+        .count();
+    if past_end > 0 {
+        println!("WARNING: {past_end} asset(s) extend past the end of the file (truncated or corrupt .pak)");
+    }
+
+    print_plaintext_summary(&assets)?;
+
+    if types {
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for asset in &assets {
+            let head = archive.read_asset_head(asset, SNIFF_SIZE)?;
+            *counts.entry(detect_content_type(&head)).or_default() += 1;
+        }
+
+        println!("Content types:");
+        for (content_type, count) in counts {
+            println!("  {count:>8}  {content_type}");
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Print how many assets have the last few bytes of their stored data
+/// left unencrypted, and list the ones too small to be encrypted at
+/// all. (The engine leaves those unencrypted, too.)
+pub(crate) fn print_plaintext_summary(assets: &[PakAsset]) -> anyhow::Result<()> {
+    let mut unencrypted = Vec::new();
+    let mut plaintext_tails = 0;
+    for asset in assets {
+        let len = usize::try_from(asset.size_compressed)?;
+        let tail_len = plaintext_tail_len(len);
+        if len > 0 && tail_len == len {
+            unencrypted.push(String::from_utf8_lossy(&asset.name));
+        } else if tail_len > 0 {
+            plaintext_tails += 1;
+        }
+    }
+    println!("Plaintext tails:  {plaintext_tails} asset(s) with their last 1-7 bytes unencrypted");
+    if !unencrypted.is_empty() {
+        println!("Unencrypted:      {} asset(s) too small to be encrypted at all:", unencrypted.len());
+        for name in &unencrypted {
+            println!("  {name}");
+        }
+    }
+    Ok(())
+}
+
+
+/// Write the decrypted and decompressed assets-list blob of a .pak file
+/// to `output_file`, without parsing it.
+pub fn dump_assets_list(input_file: &Path, output_file: &Path, key: KeyRef) -> anyhow::Result<()> {
+    let encrypted = check_is_encrypted(input_file)?;
+    let mut reader = BufReader::new(File::open(input_file)?);
+    let header = PakHeader::read(&mut reader)?;
+
+    let assets_list_data = read_assets_list(&mut reader, &header, key, encrypted)?;
+    std::fs::write(output_file, assets_list_data)?;
+
+    Ok(())
+}
+
+
+/// Describe how a stored CRC32 compares to the calculated one. CRCs
+/// over encrypted data can't be checked in decrypted .pak files.
+fn crc_status(stored: u32, calculated: u32, checkable: bool) -> String {
+    if !checkable {
+        "not checkable in a decrypted pak".to_owned()
+    } else if stored == calculated {
+        "ok".to_owned()
+    } else {
+        format!("MISMATCH, calculated {calculated:#010x}")
+    }
+}
+
+
+/// Print one labeled line of the header hexdump.
+fn print_hexdump_line(header_bytes: &[u8], offset: usize, size: usize, label: &str, value: &str, status: &str) {
+    let hex: Vec<String> = header_bytes[offset..offset + size].iter().map(|b| format!("{b:02x}")).collect();
+    println!("{offset:#04x}  {:<20}  {label:<32}  {value:<32}  {status}", hex.join(" "));
+}
+
+
+/// Print the raw header with every field labeled, parsed and (where
+/// possible) validated.
+///
+/// This deliberately doesn't go through `PakHeader`, so that it still
+/// works on headers too broken to parse.
+fn print_header_hexdump(input_file: &Path, key: KeyRef, timezone: TimeZone) -> anyhow::Result<()> {
+    let encrypted = check_is_encrypted(input_file)?;
+    let mut reader = BufReader::new(File::open(input_file)?);
+
+    let mut header_bytes = [0; PAK_HEADER_SIZE];
+    reader.read_exact(&mut header_bytes)?;
+    let u32_at = |offset: usize| u32::from_le_bytes(header_bytes[offset..offset + 4].try_into().unwrap());
+
+    let version = u32_at(0x04);
+    let crc32 = u32_at(0x08);
+    let mut timestamp_bytes = [0; 8];
+    timestamp_bytes[..7].copy_from_slice(&header_bytes[0x0d..0x14]);
+    // sign-extend from 56 bits
+    let timestamp = i64::from_le_bytes(timestamp_bytes) << 8 >> 8;
+    let assets_list_size_decompressed = u32_at(0x14);
+    let assets_list_size_compressed = u32_at(0x18);
+    let field_1c = u32_at(0x1c);
+    let assets_list_plaintext_crc32 = u32_at(0x20);
+    let assets_list_ciphertext_crc32 = u32_at(0x24);
+
+    let calculated_crc32 = calc_pak_crc32_file(input_file)?;
+
+    // The assets-list CRCs are calculated over the stored (possibly
+    // compressed) blob, so there's no need to decompress it
+    let mut assets_list = vec![0; assets_list_size_compressed.try_into()?];
+    reader.seek(SeekFrom::Start(PAK_HEADER_SIZE.try_into()?))?;
+    let assets_list_status = reader.read_exact(&mut assets_list);
+    let (plaintext_status, ciphertext_status) = if assets_list_status.is_ok() {
+        let ciphertext_crc32 = crc32fast::hash(&assets_list);
+        if encrypted {
+            decrypt(ASSETS_LIST_NAME, key, &mut assets_list);
+        }
+        let plaintext_crc32 = crc32fast::hash(&assets_list);
+        (
+            crc_status(assets_list_plaintext_crc32, plaintext_crc32, true),
+            crc_status(assets_list_ciphertext_crc32, ciphertext_crc32, encrypted),
+        )
+    } else {
+        ("assets list extends past end of file".to_owned(), "assets list extends past end of file".to_owned())
+    };
+
+    let expected_field_1c = ASSETS_LIST_NAME_HASH ^ assets_list_size_compressed;
+
+    let h = &header_bytes;
+    print_hexdump_line(h, 0x00, 4, "Magic", &format!("{:?}", String::from_utf8_lossy(&h[..4])),
+        if &h[..4] == b"KCAP" { "ok" } else { "BAD (expected \"KCAP\")" });
+    print_hexdump_line(h, 0x04, 4, "Version", &version.to_string(),
+        if version == FILE_VERSION { "ok" } else { "UNKNOWN" });
+    print_hexdump_line(h, 0x08, 4, "Whole-file JAMCRC32", &format!("{crc32:#010x}"),
+        &crc_status(crc32, calculated_crc32, encrypted));
+    print_hexdump_line(h, 0x0c, 1, "Field 0x0C", &h[0x0c].to_string(),
+        if h[0x0c] == 1 { "ok" } else { "unusual (usually 1)" });
+    print_hexdump_line(h, 0x0d, 7, "Timestamp", &format_timestamp(timestamp, timezone).unwrap_or_else(|_| timestamp.to_string()), "");
+    print_hexdump_line(h, 0x14, 4, "Assets list size (decompressed)", &format!("{assets_list_size_decompressed:#x}"), "");
+    print_hexdump_line(h, 0x18, 4, "Assets list size (compressed)", &format!("{assets_list_size_compressed:#x}"),
+        if assets_list_size_compressed <= assets_list_size_decompressed { "" } else { "larger than decompressed size" });
+    print_hexdump_line(h, 0x1c, 4, "Field 0x1C", &format!("{field_1c:#010x}"),
+        &if field_1c == expected_field_1c { "ok".to_owned() } else { format!("MISMATCH, expected {expected_field_1c:#010x}") });
+    print_hexdump_line(h, 0x20, 4, "Assets list plaintext CRC32", &format!("{assets_list_plaintext_crc32:#010x}"), &plaintext_status);
+    print_hexdump_line(h, 0x24, 4, "Assets list ciphertext CRC32", &format!("{assets_list_ciphertext_crc32:#010x}"), &ciphertext_status);
+
+    Ok(())
+}
@@ -0,0 +1,199 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::bail;
+use binrw::BinRead;
+use serde::Serialize;
+
+use crate::{
+    archive::PakArchive,
+    flow_unpack::{UnpackOptions, unpack},
+    key::KeyRef,
+    manifest::{Manifest, ManifestAsset},
+    shared::{PakHeader, Verbosity},
+    sidecar::{SIDECAR_NAME, Sidecar},
+};
+
+
+/// File name of the manifest created in the project folder.
+pub const MANIFEST_NAME: &str = "build.toml";
+
+/// File name of the order file created in the project folder.
+pub const ORDER_FILE_NAME: &str = "order.txt";
+
+/// File name of the lock file created in the project folder.
+pub const LOCK_FILE_NAME: &str = "build.lock";
+
+/// Name of the folder that a .pak's assets are extracted into, within
+/// the project folder.
+pub const ASSETS_FOLDER_NAME: &str = "assets";
+
+
+/// The lock file: the size and CRC32 of every source file when the
+/// project was created, so that changes to them can be spotted (e.g.
+/// with a diff after regenerating it).
+#[derive(Serialize)]
+struct LockFile {
+    assets: Vec<LockFileAsset>,
+}
+
+
+#[derive(Serialize)]
+struct LockFileAsset {
+    name: String,
+    source: PathBuf,
+    size: u64,
+    crc32: u32,
+}
+
+
+/// Set up a project folder for building a .pak reproducibly, from either
+/// an existing .pak (which is extracted into the project folder, and
+/// needs `key`) or an extracted folder (which is used where it is).
+///
+/// This creates a manifest for `packling pack --manifest` listing every
+/// asset in its original order, and whether it was compressed (which
+/// also serves as the project's build configuration), an order file with the same order, and a lock file
+/// recording the size and CRC32 of every source file.
+pub fn init(input: &Path, project_folder: &Path, key: Option<KeyRef>, verbosity: Verbosity) -> anyhow::Result<()> {
+    let manifest_file = project_folder.join(MANIFEST_NAME);
+    if manifest_file.exists() {
+        bail!("{manifest_file:?} already exists (delete it first to start over)");
+    }
+    std::fs::create_dir_all(project_folder)?;
+
+    let order_file = project_folder.join(ORDER_FILE_NAME);
+
+    let mut manifest = Manifest {
+        timestamp: None,
+        compress: false,
+        assets: Vec::new(),
+    };
+
+    if input.is_file() {
+        let Some(key) = key else {
+            bail!("a key file is needed to set up a project from a .pak file");
+        };
+
+        // Keep the original timestamp, so that rebuilding the unmodified
+        // project gives an identical .pak
+        let header = PakHeader::read(&mut BufReader::new(File::open(input)?))?;
+        manifest.timestamp = Some(header.timestamp.to_string());
+
+        let Some(order_file_str) = order_file.to_str() else {
+            bail!("project folder path {project_folder:?} isn't valid UTF-8");
+        };
+        let assets_folder = project_folder.join(ASSETS_FOLDER_NAME);
+        let options = UnpackOptions {
+            order_file: Some(order_file_str),
+            ..UnpackOptions::default()
+        };
+        unpack(input, &assets_folder, key, &options, Verbosity::Quiet.min(verbosity))?;
+
+        // (so that each asset is compressed or not, as it was)
+        let mut compressed = HashMap::new();
+        for asset in PakArchive::open(input, key)?.assets() {
+            let asset = asset?;
+            compressed.insert(String::from_utf8_lossy(&asset.name).into_owned(), asset.size_compressed != asset.size_decompressed);
+        }
+
+        for name in std::fs::read_to_string(&order_file)?.lines() {
+            let source = Path::new(ASSETS_FOLDER_NAME).join(name);
+            // (empty folder placeholders are extracted as folders, which
+            // a manifest can't describe)
+            if project_folder.join(&source).is_file() {
+                manifest.assets.push(ManifestAsset {
+                    source,
+                    name: name.to_owned(),
+                    compress: compressed.get(name).copied(),
+                });
+            }
+        }
+    } else if input.is_dir() {
+        manifest.assets = assets_in_folder(input, project_folder)?;
+
+        let mut writer = BufWriter::new(File::create(&order_file)?);
+        for asset in &manifest.assets {
+            writeln!(writer, "{}", asset.name)?;
+        }
+        writer.flush()?;
+    } else {
+        bail!("input file/folder not found");
+    }
+
+    let mut lock_file = LockFile {assets: Vec::new()};
+    for asset in &manifest.assets {
+        let data = std::fs::read(project_folder.join(&asset.source))?;
+        lock_file.assets.push(LockFileAsset {
+            name: asset.name.clone(),
+            source: asset.source.clone(),
+            size: u64::try_from(data.len())?,
+            crc32: crc32fast::hash(&data),
+        });
+    }
+    let mut writer = BufWriter::new(File::create(project_folder.join(LOCK_FILE_NAME))?);
+    serde_json::to_writer_pretty(&mut writer, &lock_file)?;
+    writeln!(writer)?;
+    writer.flush()?;
+
+    manifest.save(&manifest_file)?;
+
+    if verbosity >= Verbosity::Normal {
+        println!("Created {MANIFEST_NAME}, {ORDER_FILE_NAME} and {LOCK_FILE_NAME} in {} ({} asset(s))", project_folder.display(), manifest.assets.len());
+        println!("Build the .pak with: packling pack <key file> --manifest {} <output .pak>", manifest_file.display());
+    }
+
+    Ok(())
+}
+
+
+/// List the files in an extracted folder as manifest assets, sorted by
+/// name (like packing the folder would), with source paths relative to
+/// `project_folder` if the folder is inside it, or absolute otherwise.
+fn assets_in_folder(folder: &Path, project_folder: &Path) -> anyhow::Result<Vec<ManifestAsset>> {
+    let sidecar = Sidecar::load(folder)?.unwrap_or_default();
+    if sidecar.raw.is_some() {
+        bail!("this folder was extracted with --raw, which manifests don't support (extract it again without --raw)");
+    }
+
+    let folder = folder.canonicalize()?;
+    let project_folder = project_folder.canonicalize()?;
+    let source_folder = folder.strip_prefix(&project_folder).map_or_else(|_| folder.clone(), Path::to_path_buf);
+
+    let mut assets = Vec::new();
+    for entry in walkdir::WalkDir::new(&folder) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let relative_path = entry.path().strip_prefix(&folder)?;
+        if relative_path == Path::new(SIDECAR_NAME) {
+            continue;
+        }
+        let Some(relative_path_str) = relative_path.to_str() else {
+            bail!("{relative_path:?}: name isn't valid UTF-8");
+        };
+        let name = relative_path.iter()
+            .map(|c| c.to_str().unwrap_or_default())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        // Folders extracted with --flatten have the original asset
+        // names in the sidecar
+        let name = sidecar.flattened.get(relative_path_str).cloned().unwrap_or(name);
+
+        assets.push(ManifestAsset {
+            source: source_folder.join(relative_path),
+            name,
+            compress: None,
+        });
+    }
+
+    assets.sort_by(|a, b| a.name.as_bytes().cmp(b.name.as_bytes()));
+    Ok(assets)
+}
@@ -1,6 +1,6 @@
 use std::{
     fs::File,
-    io::{BufReader, BufWriter, Write, Cursor, Seek, SeekFrom},
+    io::{BufWriter, Cursor, Read, Seek, SeekFrom, Write},
     path::Path,
 };
 
@@ -8,108 +8,241 @@ use anyhow::bail;
 use binrw::BinRead;
 
 use crate::{
-    encryption::decrypt_from_reader,
+    encryption::{self, XXTEA_CHUNK_SIZE, decrypt_chunk},
     key::KeyRef,
     shared::{
         ASSETS_LIST_NAME,
         FILE_VERSION,
         PAK_HEADER_SIZE,
-        TIME_FORMAT,
         PakHeader,
         PakAssets,
+        TimeZone,
         Verbosity,
+        format_timestamp,
+        print_asset_details,
+        print_header_details,
     },
 };
 
 
+/// How much encrypted data to read, decrypt and write at a time (a
+/// multiple of `XXTEA_CHUNK_SIZE`).
+const STREAM_BUFFER_SIZE: usize = 0x80 * XXTEA_CHUNK_SIZE;
+
+
+/// Copies or decrypts a .pak file from start to end, one region at a
+/// time. With no output, it decrypts the input in place instead, and
+/// regions that don't need decrypting are just skipped over.
+struct StreamingDecrypter<W: Write> {
+    input: File,
+    output: Option<W>,
+    position: u64,
+    buffer: Vec<u8>,
+}
+
+
+impl<W: Write> StreamingDecrypter<W> {
+    fn new(input: File, output: Option<W>) -> Self {
+        Self {input, output, position: 0, buffer: vec![0; STREAM_BUFFER_SIZE]}
+    }
+
+    /// Read the next `len` bytes as they are, and write them out as
+    /// they are.
+    fn read_plain(&mut self, len: usize) -> anyhow::Result<Vec<u8>> {
+        let mut data = vec![0; len];
+        self.input.read_exact(&mut data)?;
+        if let Some(output) = &mut self.output {
+            output.write_all(&data)?;
+        }
+        self.position += u64::try_from(len)?;
+        Ok(data)
+    }
+
+    /// Read the next `len` bytes, all at once, and write them out
+    /// decrypted. Returns the decrypted data.
+    fn read_decrypted(&mut self, name: &[u8], len: usize, key: KeyRef) -> anyhow::Result<Vec<u8>> {
+        let mut data = vec![0; len];
+        self.input.read_exact(&mut data)?;
+        encryption::decrypt(name, key, &mut data);
+        self.put(&data)?;
+        Ok(data)
+    }
+
+    /// Copy everything up to `offset` as it is.
+    fn copy_to(&mut self, offset: u64) -> anyhow::Result<()> {
+        let len = offset - self.position;
+        match &mut self.output {
+            Some(output) => {
+                let copied = std::io::copy(&mut Read::by_ref(&mut self.input).take(len), output)?;
+                if copied != len {
+                    bail!("unexpected end of file at {:#x}", self.position + copied);
+                }
+            }
+            None => {
+                self.input.seek(SeekFrom::Start(offset))?;
+            }
+        }
+        self.position = offset;
+        Ok(())
+    }
+
+    /// Copy everything from here to the end of the input as it is.
+    fn copy_rest(&mut self) -> anyhow::Result<()> {
+        if let Some(output) = &mut self.output {
+            self.position += std::io::copy(&mut self.input, output)?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Decrypt the next `len` bytes (a whole encrypted blob), a buffer
+    /// at a time.
+    fn decrypt_blob(&mut self, name: &[u8], len: u32, key: KeyRef) -> anyhow::Result<()> {
+        let mut done = 0;
+        while done < len {
+            // (buffer boundaries are always chunk boundaries)
+            let step = (len - done).min(u32::try_from(STREAM_BUFFER_SIZE)?);
+            let mut buffer = std::mem::take(&mut self.buffer);
+            let data = &mut buffer[..usize::try_from(step)?];
+            self.input.read_exact(data)?;
+            for (i, chunk) in data.chunks_mut(XXTEA_CHUNK_SIZE).enumerate() {
+                let chunk_offset = done + u32::try_from(i * XXTEA_CHUNK_SIZE)?;
+                decrypt_chunk(name, key, len, chunk_offset, chunk);
+            }
+            let result = self.put(data);
+            self.buffer = buffer;
+            result?;
+            done += step;
+        }
+        Ok(())
+    }
+
+    /// Write out data that was just read from the input (and then
+    /// decrypted), advancing the position past it.
+    fn put(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        match &mut self.output {
+            Some(output) => output.write_all(data)?,
+            None => {
+                self.input.seek(SeekFrom::Start(self.position))?;
+                self.input.write_all(data)?;
+            }
+        }
+        self.position += u64::try_from(data.len())?;
+        Ok(())
+    }
+}
+
+
 /// Decrypt the contents of a .pak file, without extracting it to the
 /// filesystem.
+///
+/// This is done in a single pass over the file. If `output_file` is
+/// `input_file`, each encrypted region is decrypted in place, and the
+/// rest of the file is left alone.
 pub fn decrypt(
     input_file: &Path,
     output_file: &Path,
     key: KeyRef,
     force: bool,
+    timezone: TimeZone,
     verbosity: Verbosity,
 ) -> anyhow::Result<()> {
+    if output_file.canonicalize().ok() == Some(input_file.canonicalize()?) {
+        let input = File::options().read(true).write(true).open(input_file)?;
+        return decrypt_stream(StreamingDecrypter::<File>::new(input, None), key, timezone, verbosity);
+    }
 
-    // If we're not decrypting in-place...
-    if input_file.canonicalize()? != output_file.canonicalize()? {
-        // ...make a copy of the input file at the output file path
-        if !force && output_file.is_file() {
-            bail!("output file exists (use -f to force)");
-        }
-        std::fs::copy(input_file, output_file)?;
+    if !force && output_file.is_file() {
+        bail!("output file exists (use -f to force)");
     }
+    let input = File::open(input_file)?;
+    let output = BufWriter::new(File::create(output_file)?);
+    decrypt_stream(StreamingDecrypter::new(input, Some(output)), key, timezone, verbosity)
+}
 
-    // From now on, we decrypt output_file in-place.
-    #[allow(unused_variables)]
-    let input_file = ();
 
-    // Open the output file
-    let f = File::options()
-        .read(true)
-        .write(true)
-        .open(output_file);
+/// Decrypt the contents of a .pak file to a writer, such as stdout.
+/// Since the writer only ever has to be written to from start to end, it
+/// doesn't need to be seekable. (Progress messages still go to stdout,
+/// so to write to stdout, use `Verbosity::Silent`.)
+pub fn decrypt_to_writer<W: Write>(
+    input_file: &Path,
+    output: W,
+    key: KeyRef,
+    timezone: TimeZone,
+    verbosity: Verbosity,
+) -> anyhow::Result<()> {
+    let input = File::open(input_file)?;
+    decrypt_stream(StreamingDecrypter::new(input, Some(output)), key, timezone, verbosity)
+}
+
 
-    let mut reader = BufReader::new(f?);
+/// Decrypt a .pak file from start to end.
+fn decrypt_stream<W: Write>(
+    mut stream: StreamingDecrypter<W>,
+    key: KeyRef,
+    timezone: TimeZone,
+    verbosity: Verbosity,
+) -> anyhow::Result<()> {
 
     // Read header and assets list, and decrypt the latter
-    let header = PakHeader::read(&mut reader)?;
+    let header_data = stream.read_plain(PAK_HEADER_SIZE)?;
+    let header = PakHeader::read(&mut Cursor::new(header_data))?;
 
     if header.version != FILE_VERSION {
         bail!("unknown PAK version: {}", header.version);
     }
 
-    if verbosity == Verbosity::Verbose {
-        let ts = time::OffsetDateTime::from_unix_timestamp(header.timestamp)?;
-        let format = time::format_description::parse(TIME_FORMAT)?;
-        println!("PAK file created {} ({})", ts.format(&format)?, header.timestamp);
+    if verbosity >= Verbosity::Normal {
+        println!("PAK file created {}", format_timestamp(header.timestamp, timezone)?);
+    }
+    if verbosity >= Verbosity::Verbose {
+        print_header_details(&header);
     }
 
-    let assets_list_data = decrypt_from_reader(
-        &mut reader,
+    let assets_list_data = stream.read_decrypted(
         ASSETS_LIST_NAME,
-        u64::try_from(PAK_HEADER_SIZE)?,
         header.assets_list_size_compressed.try_into()?,
         key,
     )?;
-
-    // Write it back
-    let mut writer = BufWriter::new(reader.into_inner());
-    writer.seek(SeekFrom::Start(PAK_HEADER_SIZE.try_into()?))?;
-    writer.write_all(&assets_list_data)?;
-
-    // Parse it
     let assets = PakAssets::read(&mut Cursor::new(assets_list_data))?;
 
-    // Decrypt all the files and write them back, too
-    let mut writer_holder = Some(writer);
-    for asset in assets.contents {
+    // Decrypt all the files, in the order they're stored in
+    let data_start = stream.position;
+    let mut assets = assets.contents;
+    assets.sort_by_key(|asset| asset.offset);
+
+    for asset in assets {
         let name_str = std::str::from_utf8(&asset.name)?;
-        if verbosity == Verbosity::Verbose {
+        if verbosity >= Verbosity::Normal {
             println!("{name_str}");
         }
+        if verbosity >= Verbosity::Debug {
+            print_asset_details(
+                asset.offset.into(),
+                asset.size_compressed.into(),
+                asset.size_decompressed.into(),
+                asset.plaintext_crc32,
+                asset.ciphertext_crc32,
+            );
+        }
 
-        let abs_offset = u32::try_from(PAK_HEADER_SIZE)? + header.assets_list_size_compressed + asset.offset;
-
-        let writer = writer_holder.expect("writer_holder should be Some here");
-        let mut reader = BufReader::new(writer.into_inner()?);
-
-        let asset_data = decrypt_from_reader(
-            &mut reader,
-            &asset.name,
-            abs_offset.into(),
-            asset.size_compressed.try_into()?,
-            key,
-        )?;
-
-        let mut writer = BufWriter::new(reader.into_inner());
-
-        writer.seek(SeekFrom::Start(abs_offset.into()))?;
-        writer.write_all(&asset_data)?;
+        let abs_offset = data_start + u64::from(asset.offset);
+        if asset.size_compressed == 0 {
+            continue;
+        }
+        if abs_offset < stream.position {
+            // (decrypting the same bytes twice would garble them)
+            eprintln!("warning: {name_str} overlaps another asset, so was left encrypted");
+            continue;
+        }
 
-        writer_holder = Some(writer);
+        stream.copy_to(abs_offset)?;
+        stream.decrypt_blob(&asset.name, asset.size_compressed, key)?;
     }
 
+    // Anything after the last asset is kept as it is
+    stream.copy_rest()?;
+
     Ok(())
 }
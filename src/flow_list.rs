@@ -0,0 +1,289 @@
+use std::{
+    cmp::Ordering,
+    io::Write,
+    path::Path,
+};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{
+    archive::PakArchive,
+    encryption::{XXTEA_CHUNK_SIZE, plaintext_tail_len},
+    filter::AssetFilter,
+    key::KeyRef,
+    sniff::{SNIFF_SIZE, detect_content_type},
+    shared::{PakAsset, TableFormat, calc_field_0x0c, calc_field_0x10, csv_field},
+};
+
+
+/// Keys that asset listings can be sorted by.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, ValueEnum)]
+#[clap(rename_all = "kebab_case")]
+pub enum SortKey {
+    /// Asset name (bytewise)
+    Name,
+    /// Decompressed size
+    Size,
+    /// Compressed (stored) size
+    Compressed,
+    /// Offset in the file (i.e. storage order)
+    Offset,
+    /// Compression ratio (compressed / decompressed size)
+    Ratio,
+}
+
+
+/// Options controlling how assets are listed.
+#[derive(Default)]
+pub struct ListOptions {
+    /// Only list assets matching this filter.
+    pub filter: AssetFilter,
+    /// Sort by this key instead of listing in assets-list order.
+    pub sort: Option<SortKey>,
+    /// Reverse the listing order.
+    pub reverse: bool,
+    /// Print sizes, compression ratio and offset along with each name.
+    pub long: bool,
+    /// Print all asset fields in a machine-readable format instead.
+    pub format: Option<TableFormat>,
+    /// Detect and show the content type of each asset (requires reading
+    /// part or all of every asset).
+    pub types: bool,
+    /// Show how much of each asset is encrypted: how many chunks it's
+    /// encrypted in, and how many bytes at the end are left as
+    /// plaintext.
+    pub encryption: bool,
+    /// Print just the raw names, NUL-terminated.
+    pub print0: bool,
+}
+
+
+/// All fields of a single asset, for machine-readable listings.
+///
+/// Fields 0x0c and 0x10 aren't kept when reading the assets list, so
+/// these are the values the game expects, derived from the other
+/// fields.
+#[derive(Serialize, Default)]
+pub(crate) struct ListEntry {
+    pub(crate) name: String,
+    pub(crate) size_decompressed: u32,
+    pub(crate) size_compressed: u32,
+    pub(crate) offset: u32,
+    pub(crate) absolute_offset: u64,
+    pub(crate) field_0c: u32,
+    pub(crate) field_10: u32,
+    pub(crate) plaintext_crc32: u32,
+    pub(crate) ciphertext_crc32: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) content_type: Option<&'static str>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub(crate) encryption: Option<EncryptionCoverage>,
+}
+
+
+/// How much of an asset's stored data is encrypted.
+#[derive(Serialize, Clone, Copy, Default)]
+pub(crate) struct EncryptionCoverage {
+    /// Number of `XXTEA_CHUNK_SIZE`-byte chunks (each with its own key).
+    pub(crate) chunks: usize,
+    /// Number of bytes at the end that are left as plaintext.
+    pub(crate) plaintext_tail: usize,
+    /// Whether none of it is encrypted at all (if it's too small).
+    pub(crate) fully_plaintext: bool,
+}
+
+
+impl EncryptionCoverage {
+    fn of(asset: &PakAsset) -> Self {
+        let len = asset.size_compressed as usize;
+        let plaintext_tail = plaintext_tail_len(len);
+        Self {
+            chunks: len.div_ceil(XXTEA_CHUNK_SIZE),
+            plaintext_tail,
+            fully_plaintext: plaintext_tail == len,
+        }
+    }
+
+    /// Short description for text listings: the number of chunks, and
+    /// the number of plaintext bytes at the end (or "all").
+    fn describe(self) -> String {
+        if self.fully_plaintext {
+            format!("{:>6} {:>5}", self.chunks, "all")
+        } else {
+            format!("{:>6} {:>5}", self.chunks, self.plaintext_tail)
+        }
+    }
+}
+
+
+/// Compression ratio of an asset (1.0 for uncompressed or empty ones).
+#[allow(clippy::cast_precision_loss)]
+fn compression_ratio(asset: &PakAsset) -> f64 {
+    if asset.size_decompressed == 0 {
+        1.0
+    } else {
+        f64::from(asset.size_compressed) / f64::from(asset.size_decompressed)
+    }
+}
+
+
+/// Compare two assets by a sort key.
+fn compare_assets(a: &PakAsset, b: &PakAsset, key: SortKey) -> Ordering {
+    match key {
+        SortKey::Name => a.name.cmp(&b.name),
+        SortKey::Size => a.size_decompressed.cmp(&b.size_decompressed),
+        SortKey::Compressed => a.size_compressed.cmp(&b.size_compressed),
+        SortKey::Offset => a.offset.cmp(&b.offset),
+        SortKey::Ratio => compression_ratio(a).total_cmp(&compression_ratio(b)),
+    }
+}
+
+
+/// Print the names of the assets in a .pak.
+pub fn list(input_file: &Path, key: KeyRef, options: &ListOptions) -> anyhow::Result<()> {
+    let mut archive = PakArchive::open(input_file, key)?;
+    let all_assets = archive.assets().collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut assets: Vec<&PakAsset> = all_assets.iter().filter(|a| options.filter.matches(a)).collect();
+
+    if let Some(sort_key) = options.sort {
+        // (stable, so ties stay in assets-list order)
+        assets.sort_by(|a, b| compare_assets(a, b, sort_key));
+    }
+    if options.reverse {
+        assets.reverse();
+    }
+
+    if options.print0 {
+        let mut stdout = std::io::stdout().lock();
+        for asset in assets {
+            stdout.write_all(&asset.name)?;
+            stdout.write_all(b"\0")?;
+        }
+        stdout.flush()?;
+        return Ok(());
+    }
+
+    let mut content_types = Vec::new();
+    if options.types {
+        for asset in &assets {
+            let head = archive.read_asset_head(asset, SNIFF_SIZE)?;
+            content_types.push(detect_content_type(&head));
+        }
+    }
+
+    if let Some(format) = options.format {
+        let entries: Vec<ListEntry> = assets.iter().enumerate().map(|(i, asset)| ListEntry {
+            name: String::from_utf8_lossy(&asset.name).into_owned(),
+            size_decompressed: asset.size_decompressed,
+            size_compressed: asset.size_compressed,
+            offset: asset.offset,
+            absolute_offset: archive.asset_offset(asset),
+            field_0c: calc_field_0x0c(&asset.name, asset.size_compressed),
+            field_10: calc_field_0x10(&asset.name, asset.size_compressed),
+            plaintext_crc32: asset.plaintext_crc32,
+            ciphertext_crc32: asset.ciphertext_crc32,
+            content_type: content_types.get(i).copied(),
+            encryption: Some(EncryptionCoverage::of(asset)).filter(|_| options.encryption),
+        }).collect();
+        return print_entries(&entries, format, options.encryption);
+    }
+
+    if options.long {
+        if options.types {
+            print!("{:<24}", "type");
+        }
+        if options.encryption {
+            print!("{:>6} {:>5} ", "chunks", "plain");
+        }
+        println!("{:>12} {:>12} {:>6} {:>12}  name", "size", "compressed", "ratio", "offset");
+    }
+
+    for (i, asset) in assets.into_iter().enumerate() {
+        let name_str = String::from_utf8_lossy(&asset.name);
+        if let Some(content_type) = content_types.get(i) {
+            print!("{content_type:<24}");
+        }
+        if options.encryption {
+            print!("{} ", EncryptionCoverage::of(asset).describe());
+        }
+        if options.long {
+            println!(
+                "{:>12} {:>12} {:>5.1}% {:>#12x}  {name_str}",
+                asset.size_decompressed,
+                asset.size_compressed,
+                compression_ratio(asset) * 100.0,
+                archive.asset_offset(asset),
+            );
+        } else {
+            println!("{name_str}");
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Print asset fields in a machine-readable format.
+fn print_entries(entries: &[ListEntry], format: TableFormat, encryption: bool) -> anyhow::Result<()> {
+    match format {
+        TableFormat::Csv => {
+            print!("name,size_decompressed,size_compressed,offset,absolute_offset,field_0c,field_10,plaintext_crc32,ciphertext_crc32,content_type");
+            println!("{}", if encryption { ",chunks,plaintext_tail,fully_plaintext" } else { "" });
+            for e in entries {
+                print!(
+                    "{},{},{},{},{},{},{:#010x},{:#010x},{:#010x},{}",
+                    csv_field(&e.name),
+                    e.size_decompressed,
+                    e.size_compressed,
+                    e.offset,
+                    e.absolute_offset,
+                    e.field_0c,
+                    e.field_10,
+                    e.plaintext_crc32,
+                    e.ciphertext_crc32,
+                    csv_field(e.content_type.unwrap_or_default()),
+                );
+                match e.encryption {
+                    Some(coverage) => println!(",{},{},{}", coverage.chunks, coverage.plaintext_tail, coverage.fully_plaintext),
+                    None => println!(),
+                }
+            }
+        },
+        TableFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(entries)?);
+        },
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coverage(size: u32) -> (usize, usize, bool) {
+        let asset = PakAsset {
+            name: b"a.bin".to_vec(),
+            size_decompressed: size,
+            size_compressed: size,
+            offset: 0,
+            plaintext_crc32: 0,
+            ciphertext_crc32: 0,
+        };
+        let coverage = EncryptionCoverage::of(&asset);
+        (coverage.chunks, coverage.plaintext_tail, coverage.fully_plaintext)
+    }
+
+    #[test]
+    fn test_encryption_coverage() {
+        assert_eq!(coverage(0), (0, 0, true));
+        assert_eq!(coverage(7), (1, 7, true));
+        assert_eq!(coverage(8), (1, 0, false));
+        assert_eq!(coverage(13), (1, 1, false));
+        assert_eq!(coverage(0x2000), (1, 0, false));
+        assert_eq!(coverage(0x2003), (2, 3, false));
+    }
+}
@@ -0,0 +1,52 @@
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{
+    archive::PakArchive,
+    key::KeyRef,
+    shared::{TableFormat, csv_field},
+};
+
+
+/// Location of a single asset within a .pak file.
+#[derive(Serialize, Default)]
+pub(crate) struct MapEntry {
+    pub(crate) name: String,
+    pub(crate) offset: u64,
+    pub(crate) size_compressed: u32,
+    pub(crate) size_decompressed: u32,
+}
+
+
+/// Print the absolute file offset and sizes of every asset in a .pak.
+pub fn print_map(input_file: &Path, key: KeyRef, format: TableFormat) -> anyhow::Result<()> {
+    let archive = PakArchive::open(input_file, key)?;
+
+    let entries: Vec<MapEntry> = archive.assets().map(|asset| asset.map(|asset| MapEntry {
+        name: String::from_utf8_lossy(&asset.name).into_owned(),
+        offset: archive.asset_offset(&asset),
+        size_compressed: asset.size_compressed,
+        size_decompressed: asset.size_decompressed,
+    })).collect::<anyhow::Result<_>>()?;
+
+    match format {
+        TableFormat::Csv => {
+            println!("name,offset,size_compressed,size_decompressed");
+            for entry in &entries {
+                println!(
+                    "{},{},{},{}",
+                    csv_field(&entry.name),
+                    entry.offset,
+                    entry.size_compressed,
+                    entry.size_decompressed,
+                );
+            }
+        },
+        TableFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&entries)?);
+        },
+    }
+
+    Ok(())
+}
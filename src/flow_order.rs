@@ -0,0 +1,164 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::bail;
+
+use crate::{
+    flow_pack::{NameNormalization, OrderEntry, asset_name_bytes, read_order_file},
+    sidecar::SIDECAR_NAME,
+};
+
+
+/// Find folders whose entries are split into several separate runs in
+/// an order file, which usually means entries were added in the wrong
+/// place (the original .pak files keep each folder together). Returns
+/// each such folder ("" for the top level), with the index of the first
+/// entry in each run.
+fn find_split_folders(names: &[&str]) -> Vec<(String, Vec<usize>)> {
+    let mut runs: Vec<(String, Vec<usize>)> = Vec::new();
+    let mut folder_indices: HashMap<&str, usize> = HashMap::new();
+    let mut previous_folder = None;
+
+    for (i, name) in names.iter().enumerate() {
+        let folder = name.rsplit_once('/').map_or("", |(folder, _)| folder);
+        if previous_folder == Some(folder) {
+            continue;
+        }
+        previous_folder = Some(folder);
+        let index = *folder_indices.entry(folder).or_insert_with(|| {
+            runs.push((folder.to_owned(), Vec::new()));
+            runs.len() - 1
+        });
+        runs[index].1.push(i);
+    }
+
+    runs.retain(|(_, starts)| starts.len() > 1);
+    runs
+}
+
+
+/// Check an order file against the folder it's meant for, printing any
+/// duplicate entries, entries that don't match a file, and files that
+/// aren't listed, as well as folders split into several runs (which
+/// isn't an error, but is probably a mistake).
+///
+/// With `fix`, the order file is then rewritten without the duplicates
+/// and unmatched entries, and with the unlisted files added at the end
+/// (in bytewise order, as when packing). Otherwise, any problems are an
+/// error.
+pub fn check_order_file(order_file: &Path, folder: &Path, fix: bool) -> anyhow::Result<()> {
+    let entries = read_order_file(order_file)?;
+
+    // (the order file itself may well be in the folder, but isn't an
+    // asset)
+    let order_file_path = std::fs::canonicalize(order_file).ok();
+    let sidecar_path = folder.join(SIDECAR_NAME);
+    let mut files = Vec::new();
+    for entry in walkdir::WalkDir::new(folder) {
+        let entry = entry?;
+        if !entry.file_type().is_file()
+            || entry.path() == sidecar_path
+            || std::fs::canonicalize(entry.path()).ok() == order_file_path
+        {
+            continue;
+        }
+        let name = asset_name_bytes(entry.path().strip_prefix(folder)?, NameNormalization::None);
+        files.push(String::from_utf8_lossy(&name).into_owned());
+    }
+    let file_set: HashSet<&str> = files.iter().map(String::as_str).collect();
+
+    let mut kept: Vec<&OrderEntry> = Vec::new();
+    let mut duplicates = Vec::new();
+    let mut unmatched = Vec::new();
+    let mut first_lines: HashMap<String, usize> = HashMap::new();
+    for entry in &entries {
+        // (compared ignoring ASCII case, like the game does)
+        if let Some(&first_line) = first_lines.get(&entry.name.to_ascii_lowercase()) {
+            duplicates.push(format!("  line {}: {} (already on line {first_line})", entry.line, entry.name));
+        } else if !file_set.contains(entry.name.as_str()) && !folder.join(&entry.name).is_file() {
+            unmatched.push(format!("  line {}: {}", entry.line, entry.name));
+        } else {
+            first_lines.insert(entry.name.to_ascii_lowercase(), entry.line);
+            kept.push(entry);
+        }
+    }
+
+    let listed: HashSet<&str> = kept.iter().map(|entry| entry.name.as_str()).collect();
+    let mut unlisted: Vec<&str> = files.iter().map(String::as_str).filter(|name| !listed.contains(name)).collect();
+    unlisted.sort_unstable();
+
+    let unlisted_lines: Vec<String> = unlisted.iter().map(|name| format!("  {name}")).collect();
+    let split_folders = find_split_folders(&kept.iter().map(|entry| entry.name.as_str()).collect::<Vec<_>>());
+
+    for (heading, lines) in [
+        ("Duplicate entries", &duplicates),
+        ("Entries that don't match a file", &unmatched),
+        ("Files that aren't listed", &unlisted_lines),
+    ] {
+        if !lines.is_empty() {
+            println!("{heading} ({}):", lines.len());
+            for line in lines {
+                println!("{line}");
+            }
+        }
+    }
+    if !split_folders.is_empty() {
+        println!("Folders listed in several separate runs ({}):", split_folders.len());
+        for (split_folder, starts) in &split_folders {
+            let display = if split_folder.is_empty() { "(top level)".to_owned() } else { format!("{split_folder}/") };
+            let start_lines = starts.iter().map(|&i| kept[i].line.to_string()).collect::<Vec<_>>().join(", ");
+            println!("  {display}: {} runs, starting on lines {start_lines}", starts.len());
+        }
+    }
+
+    let problems = duplicates.len() + unmatched.len() + unlisted.len();
+    if problems == 0 {
+        println!("All {} entries match the folder", kept.len());
+        return Ok(());
+    }
+    if !fix {
+        bail!("{problems} problem(s) found (use --fix to rewrite the order file without them)");
+    }
+
+    let mut writer = BufWriter::new(File::create(order_file)?);
+    for entry in &kept {
+        match entry.crc32 {
+            Some(crc32) => writeln!(writer, "{}\t{crc32:08x}", entry.name)?,
+            None => writeln!(writer, "{}", entry.name)?,
+        }
+    }
+    for name in &unlisted {
+        writeln!(writer, "{name}")?;
+    }
+    writer.flush()?;
+
+    println!(
+        "Rewrote {}: removed {} duplicate and {} unmatched entr{}, and added {} unlisted file(s) at the end",
+        order_file.display(),
+        duplicates.len(),
+        unmatched.len(),
+        if duplicates.len() + unmatched.len() == 1 { "y" } else { "ies" },
+        unlisted.len(),
+    );
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_split_folders() {
+        assert!(find_split_folders(&["a/1", "a/2", "b/1", "c"]).is_empty());
+        assert_eq!(
+            find_split_folders(&["a/1", "b/1", "a/2", "c", "b/2", "d", "a/3"]),
+            [("a".to_owned(), vec![0, 2, 6]), ("b".to_owned(), vec![1, 4]), (String::new(), vec![3, 5])],
+        );
+    }
+}
@@ -1,33 +1,173 @@
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     fs::File,
-    io::{BufReader, BufRead, BufWriter, Read, Write, Cursor, Seek, SeekFrom, ErrorKind},
-    path::Path,
+    io::{BufWriter, Write, Cursor, Seek, SeekFrom, ErrorKind},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::bail;
-use binrw::{BinWrite, BinWriterExt};
+use binrw::BinWrite;
+use clap::ValueEnum;
+use unicode_normalization::UnicodeNormalization;
 
 use crate::{
-    encryption::encrypt,
+    encryption::{encrypt, plaintext_tail_len},
+    interrupt,
     jamcrc32::Jamcrc32Hasher,
+    journal::{self, JournalEntry, PackJournal},
+    report::{AssetAction, Report, ReportAsset},
     key::KeyRef,
+    manifest::Manifest,
+    progress::{Phase, Progress},
+    sidecar::{EMPTY_FOLDER_PLACEHOLDER, SIDECAR_NAME, RawForm, Sidecar, SidecarAsset},
     shared::{
         ASSETS_LIST_NAME,
+        EXTENDED_FILE_VERSION,
         FILE_VERSION,
         PAK_HEADER_SIZE,
-        PAK_CRC32_OFFSET,
         PAK_CRC32_START_OFFSET,
         Verbosity,
         PakHeader,
         PakAsset,
         PakAssets,
+        ExtendedPakAsset,
+        ExtendedPakAssets,
+        hash_file_section,
+        lock_for_writing,
+        preallocate,
+        print_asset_details,
+        temp_dir,
     },
 };
 
 
-// Just using the same value as `BufReader` from the Rust stdlib
-const CRC32_DATA_BUFFER_SIZE: usize = 8 * 1024;
+/// The order to store assets in, apart from those listed in an order
+/// file (which always come first, in the listed order).
+///
+/// Both orders compare raw bytes (no locale or case folding), so they're
+/// the same on every OS.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default, ValueEnum)]
+#[clap(rename_all = "kebab_case")]
+pub enum AssetOrder {
+    /// Sort by the full asset name, byte by byte (so "a.txt" comes
+    /// before "a/b.txt", since "." < "/").
+    #[default]
+    Bytewise,
+    /// Sort folder by folder, so each folder's contents stay together,
+    /// as if walking the folder tree with each level sorted byte by byte
+    /// (so "a/b.txt" comes before "a.txt"). This is the order packling
+    /// used to use. To match an original .pak exactly, use an order
+    /// file extracted from it instead.
+    Tree,
+}
+
+
+impl AssetOrder {
+    /// The key to sort an asset by, given its path within the .pak.
+    fn sort_key(self, path_within_pak: &Path, normalization: NameNormalization) -> Vec<Vec<u8>> {
+        match self {
+            AssetOrder::Bytewise => vec![asset_name_bytes(path_within_pak, normalization)],
+            AssetOrder::Tree => path_within_pak.iter().map(|c| normalization.apply(c.as_encoded_bytes().to_vec())).collect(),
+        }
+    }
+}
+
+
+/// Unicode normalization form to convert asset names to.
+///
+/// The game expects names as they're stored in the original .pak files
+/// (NFC, as far as anyone knows), but some filesystems (notably macOS's)
+/// hand back names in NFD, which are different bytes.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default, ValueEnum)]
+#[clap(rename_all = "kebab_case")]
+pub enum NameNormalization {
+    /// Use names exactly as the filesystem gives them.
+    #[default]
+    None,
+    /// Compose characters (e.g. "e" + combining acute accent -> "é").
+    Nfc,
+    /// Decompose characters (e.g. "é" -> "e" + combining acute accent).
+    Nfd,
+}
+
+
+impl NameNormalization {
+    /// Normalize an asset name. Names that aren't valid UTF-8 are left
+    /// as they are.
+    pub fn apply(self, name: Vec<u8>) -> Vec<u8> {
+        let Ok(name_str) = std::str::from_utf8(&name) else {
+            return name;
+        };
+        match self {
+            NameNormalization::None => name,
+            NameNormalization::Nfc => name_str.nfc().collect::<String>().into_bytes(),
+            NameNormalization::Nfd => name_str.nfd().collect::<String>().into_bytes(),
+        }
+    }
+}
+
+
+/// Options controlling how a .pak is packed.
+#[derive(Default)]
+pub struct PackOptions<'a> {
+    /// Timestamp to put in the header.
+    pub timestamp: i64,
+    /// Overwrite the output file if it already exists, and keep only
+    /// the last of any files with the same asset name.
+    pub force: bool,
+    /// Compress the assets list (not supported yet).
+    pub compress_header: bool,
+    /// LZ4-compress assets, where that makes them smaller.
+    pub compress_files: bool,
+    /// Text file listing asset names in the order they should be
+    /// stored in.
+    pub order_file: Option<&'a str>,
+    /// Order to store the assets not in the order file in.
+    pub asset_order: AssetOrder,
+    /// Treat the order file as complete: fail if any of its entries
+    /// don't match a file, or any files aren't listed in it.
+    pub strict_order: bool,
+    /// Unicode normalization to apply to asset names (including the ones
+    /// in the order file, before matching them to files).
+    pub normalization: NameNormalization,
+    /// Keep empty folders as placeholder assets.
+    pub keep_empty_folders: bool,
+    /// More folders to lay over the input folder, in order: their files
+    /// replace the ones at the same paths in the input folder (or an
+    /// earlier overlay), and are added alongside the rest.
+    pub overlays: &'a [PathBuf],
+    /// Pack the folders that were unpacked from nested .pak files
+    /// (according to the sidecar) back into .pak files, recursively.
+    pub recursive: bool,
+    /// Use the experimental extended-offsets layout (which the game
+    /// can't load).
+    pub extended_offsets: bool,
+    /// Warn about assets whose stored data ends up partly or entirely
+    /// unencrypted.
+    pub warn_plaintext_tails: bool,
+    /// Fail (and delete the output file) if the .pak would be larger
+    /// than this many bytes.
+    pub max_size: Option<u64>,
+    /// JSON file to write a report of the operation to.
+    pub report: Option<&'a Path>,
+    /// Show a live progress line with the throughput of each phase and
+    /// an estimate of the time remaining, instead of listing each asset.
+    pub progress: bool,
+    /// If an earlier run of the same pack was interrupted, continue from
+    /// the last asset its journal recorded, instead of starting over.
+    pub resume: bool,
+}
+
+
+/// Size of the output file's write buffer. This is much larger than
+/// `BufWriter`'s default, so that runs of small assets are written in
+/// fewer, larger batches, which matters on network filesystems.
+const OUTPUT_BUFFER_SIZE: usize = 1024 * 1024;
+
+
+/// Labels of the progress phases when packing, in `Phase` order.
+const PROGRESS_LABELS: [&str; 4] = ["read", "encrypt", "compress", "write"];
 
 
 /// Create a .pak file with the contents of the specified folder.
@@ -35,138 +175,732 @@ pub fn pack(
     input_folder: &Path,
     output_file: &Path,
     key: KeyRef,
-    timestamp: i64,
-    force: bool,
-    compress_header: bool,
-    compress_files: bool,
-    order_file: Option<&str>,
+    options: &PackOptions,
+    verbosity: Verbosity,
+) -> anyhow::Result<()> {
+    let mut report = Report::new("pack", input_folder, output_file, verbosity);
+    let result = pack_with_report(input_folder, output_file, key, options, verbosity, &mut report);
+    if let Some(report_file) = options.report {
+        report.save(report_file, &result)?;
+    }
+    result
+}
+
+
+/// Create a .pak file as described by a manifest (loaded from
+/// `manifest_file`), with each asset's source file, name and compression
+/// given explicitly, in the manifest's order.
+///
+/// The manifest's timestamp has to be resolved by the caller, into
+/// `options.timestamp`. Options about finding and naming files in a
+/// folder (and `compress_files`) don't apply.
+pub fn pack_manifest(
+    manifest_file: &Path,
+    manifest: &Manifest,
+    output_file: &Path,
+    key: KeyRef,
+    options: &PackOptions,
     verbosity: Verbosity,
 ) -> anyhow::Result<()> {
+    let mut report = Report::new("pack", manifest_file, output_file, verbosity);
+    let result = pack_manifest_with_report(manifest_file, manifest, output_file, key, options, verbosity, &mut report);
+    if let Some(report_file) = options.report {
+        report.save(report_file, &result)?;
+    }
+    result
+}
+
+
+/// The guts of `pack_manifest`, recording what happens in `report`.
+fn pack_manifest_with_report(
+    manifest_file: &Path,
+    manifest: &Manifest,
+    output_file: &Path,
+    key: KeyRef,
+    options: &PackOptions,
+    verbosity: Verbosity,
+    report: &mut Report,
+) -> anyhow::Result<()> {
+    let base_folder = manifest_file.parent().unwrap_or(Path::new(""));
+
+    let mut bad_names = Vec::new();
+    let mut entries = Vec::with_capacity(manifest.assets.len());
+    for asset in &manifest.assets {
+        // (folder packing can't produce names like these, but a manifest
+        // can)
+        if asset.name.split('/').any(|c| c.is_empty() || c == "." || c == "..") {
+            bad_names.push(format!("  {:?}", asset.name));
+        }
+        entries.push(PackEntry {
+            name: asset.name.clone().into_bytes(),
+            display_path: asset.source.clone(),
+            source: Some(base_folder.join(&asset.source)),
+            compress: asset.compress.unwrap_or(manifest.compress),
+            expected_crc32: None,
+        });
+    }
+
+    if !bad_names.is_empty() {
+        bail!(
+            "{} asset name(s) in the manifest are empty, or have empty, \".\" or \"..\" components:\n{}",
+            bad_names.len(),
+            bad_names.join("\n"),
+        );
+    }
+
+    let entries = check_duplicate_names(entries, options.force, report)?;
+    write_pak(entries, output_file, key, options, &HashMap::new(), None, verbosity, report)
+}
+
+
+/// The guts of `pack`, recording what happens in `report`.
+fn pack_with_report(
+    input_folder: &Path,
+    output_file: &Path,
+    key: KeyRef,
+    options: &PackOptions,
+    verbosity: Verbosity,
+    report: &mut Report,
+) -> anyhow::Result<()> {
+    let &PackOptions {
+        force,
+        compress_files,
+        order_file,
+        asset_order,
+        strict_order,
+        normalization,
+        keep_empty_folders,
+        recursive,
+        overlays,
+        ..
+    } = options;
+
+    // If the folder was extracted with --raw, the sidecar has the
+    // metadata for the assets that are still in raw form
+    let sidecar = Sidecar::load(input_folder)?;
+    if sidecar.as_ref().is_some_and(|s| !s.flattened.is_empty()) {
+        bail!("this folder was extracted with --flatten, so its file names aren't the asset names (the mapping between them is in {SIDECAR_NAME})");
+    }
+    let raw_form = sidecar.as_ref().and_then(|s| s.raw);
+    let raw_assets: HashMap<&str, _> = match &sidecar {
+        Some(sidecar) if sidecar.raw.is_some() => {
+            sidecar.assets.iter().map(|a| (a.name.as_str(), a)).collect()
+        },
+        _ => HashMap::new(),
+    };
+
+    // Folders unpacked from nested .pak files stand in for single assets
+    let nested_folders: HashSet<PathBuf> = sidecar.as_ref()
+        .map(|s| s.nested.iter().map(|name| input_folder.join(name)).collect())
+        .unwrap_or_default();
+    if !nested_folders.is_empty() && !recursive {
+        bail!(
+            "{} folder(s) were unpacked from nested .pak files (use --recursive to pack them back into .pak files):\n{}",
+            nested_folders.len(),
+            sidecar.iter().flat_map(|s| &s.nested).map(|name| format!("  {name}")).collect::<Vec<_>>().join("\n"),
+        );
+    }
+
+    // The overlays' sidecars can't be merged with the input folder's, so
+    // they can only say which game they're for
+    for overlay in overlays {
+        if !overlay.is_dir() {
+            bail!("overlay folder {} not found", overlay.display());
+        }
+        if Sidecar::load(overlay)?.is_some_and(|s| s.raw.is_some() || !s.flattened.is_empty() || !s.nested.is_empty()) {
+            bail!("overlay folder {} was extracted with --raw, --flatten or --recursive, so it can't be laid over another folder", overlay.display());
+        }
+    }
+    if !overlays.is_empty() && !nested_folders.is_empty() {
+        bail!("folders unpacked from nested .pak files can't be packed with overlay folders");
+    }
+    let roots: Vec<&Path> = std::iter::once(input_folder).chain(overlays.iter().map(PathBuf::as_path)).collect();
 
     // First, gather file entries in the correct order (first following
-    // the order file if provided, then everything else in sorted order)
+    // the order file if provided, then everything else sorted by
+    // `asset_order`)
+
+    let mut empty_folders = Vec::new();
+    let mut remaining_paths = Vec::new();
+    // (by path within the roots, for overlaid files to replace the ones
+    // they're laid over)
+    let mut remaining_by_path = HashMap::new();
+
+    for root in &roots {
+        let sidecar_path = root.join(SIDECAR_NAME);
+        let mut walker = walkdir::WalkDir::new(root).sort_by_file_name().into_iter();
+        while let Some(entry) = walker.next() {
+            let entry = entry?;
+
+            if entry.file_type().is_dir() && nested_folders.contains(entry.path()) {
+                remaining_paths.push(entry.path().to_path_buf());
+                walker.skip_current_dir();
+                continue;
+            } else if entry.file_type().is_dir() {
+                if entry.depth() > 0 && std::fs::read_dir(entry.path())?.next().is_none() {
+                    empty_folders.push(entry.path().to_path_buf());
+                }
+                continue;
+            } else if !entry.file_type().is_file() || entry.path() == sidecar_path {
+                continue;
+            }
+
+            let path_within_roots = strip_root(entry.path(), &roots).to_path_buf();
+            if let Some(&i) = remaining_by_path.get(&path_within_roots) {
+                remaining_paths[i] = entry.path().to_path_buf();
+            } else {
+                remaining_by_path.insert(path_within_roots, remaining_paths.len());
+                remaining_paths.push(entry.path().to_path_buf());
+            }
+        }
+    }
+
+    // (a folder that's empty in one root may not be in another)
+    if !overlays.is_empty() {
+        let mut seen = HashSet::new();
+        empty_folders.retain(|folder| {
+            let folder = strip_root(folder, &roots);
+            seen.insert(folder.to_path_buf())
+                && !remaining_by_path.keys().any(|path| path.starts_with(folder))
+        });
+    }
 
     let mut file_paths_vec = Vec::new();
-    let mut file_paths_set = HashSet::new();
 
     if let Some(order_file) = order_file {
-        let order_file_reader = BufReader::new(File::open(order_file)?);
-        for path_within_pak in order_file_reader.lines().map_while(Result::ok) {
+        // Match the order file's entries to the files found by their
+        // (normalized) asset names
+        let mut remaining_by_name = HashMap::new();
+        for (i, path) in remaining_paths.iter().enumerate() {
+            remaining_by_name.insert(asset_name_bytes(strip_root(path, &roots), normalization), i);
+        }
+        let mut listed = vec![false; remaining_paths.len()];
+
+        let mut unmatched = Vec::new();
+        for OrderEntry {name: path_within_pak, crc32, ..} in read_order_file(Path::new(order_file))? {
             let path_on_host = input_folder.join(&path_within_pak);
+            let Ok(relative_path) = path_on_host.strip_prefix(input_folder) else {
+                // (an absolute path outside the input folder)
+                unmatched.push(path_within_pak);
+                continue;
+            };
+            let name = asset_name_bytes(relative_path, normalization);
+
+            if let Some(&i) = remaining_by_name.get(&name) {
+                if !listed[i] {
+                    listed[i] = true;
+                    file_paths_vec.push((remaining_paths[i].clone(), crc32));
+                }
+            } else if relative_path != Path::new(SIDECAR_NAME)
+                && let Some(path_on_host) = roots.iter().rev().map(|root| root.join(relative_path)).find(|path| path.is_file())
+            {
+                // (e.g. a symlink, which isn't found by walking the
+                // folder)
+                file_paths_vec.push((path_on_host, crc32));
+            } else {
+                unmatched.push(path_within_pak);
+            }
+        }
+
+        remaining_paths = remaining_paths.into_iter().zip(listed).filter(|(_, listed)| !listed).map(|(path, _)| path).collect();
 
-            if path_on_host.is_file() {
-                file_paths_vec.push(path_on_host.clone());
-                file_paths_set.insert(path_on_host);
+        if strict_order && (!unmatched.is_empty() || !remaining_paths.is_empty()) {
+            let mut problems = Vec::new();
+            if !unmatched.is_empty() {
+                problems.push(format!(
+                    "{} order file entr{} didn't match any file:\n{}",
+                    unmatched.len(),
+                    if unmatched.len() == 1 { "y" } else { "ies" },
+                    unmatched.iter().map(|name| format!("  {name}")).collect::<Vec<_>>().join("\n"),
+                ));
             }
-            // ignore any lines referring to nonexistent files
+            if !remaining_paths.is_empty() {
+                problems.push(format!(
+                    "{} file(s) aren't listed in the order file:\n{}",
+                    remaining_paths.len(),
+                    remaining_paths.iter()
+                        .map(|path| format!("  {}", strip_root(path, &roots).display()))
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                ));
+            }
+            bail!("the order file doesn't match the folder:\n{}", problems.join("\n"));
+        }
+
+        // These are ignored, but a lot of them probably means the order
+        // file doesn't belong to this folder
+        if !unmatched.is_empty() {
+            report.warn(format!(
+                "{} order file entr{} didn't match any file, and were ignored:\n{}",
+                unmatched.len(),
+                if unmatched.len() == 1 { "y" } else { "ies" },
+                unmatched.iter().map(|name| format!("  {name}")).collect::<Vec<_>>().join("\n"),
+            ));
         }
     }
 
-    for entry in walkdir::WalkDir::new(input_folder).sort_by_file_name() {
-        let entry = entry?;
+    remaining_paths.sort_by_cached_key(|path| asset_order.sort_key(strip_root(path, &roots), normalization));
+    file_paths_vec.extend(remaining_paths.into_iter().map(|path| (path, None)));
 
-        if !entry.file_type().is_file() {
-            continue;
+    let mut entries = Vec::with_capacity(file_paths_vec.len());
+    for (path_on_host, expected_crc32) in file_paths_vec {
+        let path_within_pak = strip_root(&path_on_host, &roots).to_path_buf();
+        entries.push(PackEntry {
+            name: asset_name_bytes(&path_within_pak, normalization),
+            display_path: path_within_pak,
+            source: Some(path_on_host),
+            compress: compress_files,
+            expected_crc32,
+        });
+    }
+
+    let mut entries = check_duplicate_names(entries, force, report)?;
+
+    // Empty folders can't be represented in a .pak, so they're either
+    // dropped or kept as placeholder assets (which don't exist on disk)
+    if keep_empty_folders {
+        for folder in &empty_folders {
+            let path_within_pak = strip_root(folder, &roots).join(EMPTY_FOLDER_PLACEHOLDER);
+            entries.push(PackEntry {
+                name: asset_name_bytes(&path_within_pak, normalization),
+                display_path: path_within_pak,
+                source: None,
+                compress: false,
+                expected_crc32: None,
+            });
         }
+    } else if !empty_folders.is_empty() {
+        report.warn(format!(
+            "{} empty folder(s) won't be in the .pak (use --keep-empty-folders to keep them):\n{}",
+            empty_folders.len(),
+            empty_folders.iter()
+                .map(|f| format!("  {}", strip_root(f, &roots).display()))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ));
+    }
+
+    let mut nested_paks = Vec::new();
+    let result = pack_nested_folders(&mut entries, &nested_folders, output_file, key, options, verbosity, &mut nested_paks)
+        .and_then(|()| write_pak(entries, output_file, key, options, &raw_assets, raw_form, verbosity, report));
+    for path in &nested_paks {
+        let _ = std::fs::remove_file(path);
+        let _ = std::fs::remove_file(journal::journal_path(path));
+    }
+    result
+}
+
+
+/// Path of a file within whichever of the `roots` (the input folder and
+/// its overlays) it was found in.
+fn strip_root<'p>(path: &'p Path, roots: &[&Path]) -> &'p Path {
+    // (the deepest one, in case one's inside another)
+    roots.iter()
+        .filter_map(|root| path.strip_prefix(root).ok())
+        .min_by_key(|relative| relative.components().count())
+        .unwrap_or(path)
+}
 
-        let path_on_host = entry.path();
 
-        if file_paths_set.contains(path_on_host) {
+/// Pack each entry whose source is one of `nested_folders` into a
+/// temporary .pak file (in `temp_dir()`), with the same options
+/// (apart from the ones about the outer .pak as a whole), and use that
+/// as its source instead. The temporary files are added to
+/// `nested_paks` as they're created, for the caller to delete.
+fn pack_nested_folders(
+    entries: &mut [PackEntry],
+    nested_folders: &HashSet<PathBuf>,
+    output_file: &Path,
+    key: KeyRef,
+    options: &PackOptions,
+    verbosity: Verbosity,
+    nested_paks: &mut Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    let nested_options = PackOptions {
+        timestamp: options.timestamp,
+        force: true,
+        compress_files: options.compress_files,
+        asset_order: options.asset_order,
+        normalization: options.normalization,
+        keep_empty_folders: options.keep_empty_folders,
+        recursive: true,
+        ..PackOptions::default()
+    };
+
+    for entry in entries {
+        let Some(folder) = entry.source.as_ref().filter(|source| nested_folders.contains(*source)) else {
             continue;
+        };
+        if verbosity >= Verbosity::Normal {
+            println!("Packing nested .pak {}", entry.display_path.display());
         }
 
-        file_paths_vec.push(path_on_host.to_path_buf());
-        // no need to update the set anymore
+        // (named after the outer .pak, which is itself one of these if
+        // it's nested, so they can't collide)
+        let mut pak_file_name = output_file.file_name().unwrap_or_default().to_owned();
+        pak_file_name.push(format!(".{}.nested{}.tmp", std::process::id(), nested_paks.len()));
+        let pak_path = temp_dir().join(pak_file_name);
+        nested_paks.push(pak_path.clone());
+
+        // (the report is about the outer .pak, so the nested assets
+        // aren't in it)
+        let mut nested_report = Report::new("pack", folder, &pak_path, verbosity);
+        pack_with_report(folder, &pak_path, key, &nested_options, verbosity, &mut nested_report)
+            .map_err(|e| e.context(format!("couldn't pack nested .pak {}", entry.display_path.display())))?;
+
+        entry.source = Some(pak_path);
+        // (a CRC32 from the order file is of the .pak file as it was
+        // extracted, which repacking doesn't reproduce)
+        entry.expected_crc32 = None;
     }
 
+    Ok(())
+}
+
+
+/// Write a .pak file containing `entries`, in order.
+///
+/// `raw_assets` are the assets (by name) whose files are already in
+/// `raw_form`, according to the input folder's sidecar.
+#[allow(clippy::too_many_arguments)]
+fn write_pak(
+    entries: Vec<PackEntry>,
+    output_file: &Path,
+    key: KeyRef,
+    options: &PackOptions,
+    raw_assets: &HashMap<&str, &SidecarAsset>,
+    raw_form: Option<RawForm>,
+    verbosity: Verbosity,
+    report: &mut Report,
+) -> anyhow::Result<()> {
+    let &PackOptions {
+        timestamp,
+        force,
+        compress_header,
+        compress_files: _,
+        order_file: _,
+        asset_order: _,
+        strict_order: _,
+        normalization: _,
+        keep_empty_folders: _,
+        overlays: _,
+        recursive: _,
+        extended_offsets,
+        warn_plaintext_tails,
+        max_size,
+        report: _,
+        progress: show_progress,
+        resume,
+    } = options;
+
     // With this, we can calculate the total size of the assets list and
     // header
+    let asset_entry_size = if extended_offsets { 0x24 } else { 0x20 };
     let mut assets_list_bytes_len = 4;
-    for path_on_host in &file_paths_vec {
-        let path_within_pak = path_on_host.strip_prefix(input_folder)?;
-        assets_list_bytes_len += 0x20 + path_within_pak.as_os_str().len();
+    for entry in &entries {
+        assets_list_bytes_len += asset_entry_size + entry.name.len();
     }
 
     let total_header_size = PAK_HEADER_SIZE + assets_list_bytes_len;
 
-    // Open the output file
-    let f = File::options()
-        .read(true)
-        .write(true)
-        .truncate(true)
-        .create(force)
-        .create_new(!force)
-        .open(output_file);
-    if let Err(ref e) = f {
-        if e.kind() == ErrorKind::AlreadyExists {
-            bail!("output file exists (use -f to force)");
+    validate_input(&entries, assets_list_bytes_len, extended_offsets, report)?;
+
+    // Assets are only stored compressed if that makes them smaller, so
+    // this is the most the file can end up as (and exactly what it ends
+    // up as if nothing is compressed)
+    //
+    // (each source file's size and modification time are also kept, to
+    // check that it hasn't changed by the time it's read)
+    let snapshots = entries.iter()
+        .map(|e| e.source.as_deref().map(FileSnapshot::take).transpose())
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let total_source_size: u64 = snapshots.iter().flatten().map(|snapshot| snapshot.size).sum();
+    let max_file_size = u64::try_from(total_header_size)? + total_source_size;
+
+    // If nothing is going to be compressed, there's no need to write
+    // the whole thing to find out that it's too big
+    if let Some(max_size) = max_size
+        && max_file_size > max_size
+        && !entries.iter().any(|e| e.compress)
+    {
+        bail!("the .pak would be {max_file_size} bytes, which is over the maximum size of {max_size} bytes by {}", max_file_size - max_size);
+    }
+
+    // Resume the pack that an interrupted earlier run left, if asked to,
+    // and there's a journal for exactly the same pack, and the output
+    // file still has everything in it that the journal says
+    let plan = plan_id(&entries, &snapshots, key, extended_offsets, raw_form);
+    let mut resumed = None;
+    if resume {
+        if let Some(done) = journal::load(output_file, plan)?
+            && done.len() <= entries.len()
+            && let Some((f, data_hasher)) = open_for_resume(output_file, total_header_size, &done)?
+        {
+            if verbosity >= Verbosity::Normal {
+                println!("Resuming after {} of {} asset(s)", done.len(), entries.len());
+            }
+            resumed = Some((f, data_hasher, done));
+        } else {
+            report.warn("there's no interrupted pack of these same files to resume, so packing from the beginning".to_owned());
         }
     }
 
-    let mut writer = BufWriter::new(f?);
+    // (the data hasher is the whole-file CRC32 of the asset data, to
+    // combine with the header and assets list's once they're known, so
+    // the file doesn't need to be read back)
+    let (f, mut data_hasher, done) = match resumed {
+        Some(resumed) => resumed,
+        None => {
+            // Open the output file (only truncating it once it's locked,
+            // in case another packling process is still writing to it).
+            // With nothing to resume, an existing file is only replaced
+            // with -f, as usual
+            let f = File::options()
+                .read(true)
+                .write(true)
+                .truncate(false)
+                .create(force)
+                .create_new(!force)
+                .open(output_file);
+            if let Err(ref e) = f
+                && e.kind() == ErrorKind::AlreadyExists
+            {
+                if resume {
+                    bail!("output file exists, and isn't from an interrupted pack of these same files (use -f to overwrite it)");
+                }
+                if journal::journal_path(output_file).exists() {
+                    bail!("output file exists (use --resume to continue the interrupted pack that left it, or -f to start over)");
+                }
+                bail!("output file exists (use -f to force)");
+            }
+            let f = f?;
+            lock_for_writing(&f, output_file)?;
+            f.set_len(0)?;
+            (f, crc32fast::Hasher::new(), Vec::new())
+        },
+    };
+
+    // Reserve the space up front, so the filesystem can keep the file
+    // in one piece (it's truncated to the actual size at the end, or by
+    // this if packing fails before then)
+    let mut cleanup = FailedPackCleanup {
+        output_file,
+        plan,
+        data_start: u64::try_from(total_header_size)?,
+        armed: true,
+    };
+    preallocate(&f, max_file_size)?;
+
+    let mut writer = BufWriter::with_capacity(OUTPUT_BUFFER_SIZE, f);
 
-    // Write some zeroes to reserve space for the header
-    writer.write_all(&vec![0_u8; total_header_size])?;
+    let mut assets_data_offset = 0;
+    if let Some(last) = done.last() {
+        assets_data_offset = usize::try_from(last.end())?;
+        writer.seek(SeekFrom::Start(u64::try_from(total_header_size)? + last.end()))?;
+    } else {
+        // Write some zeroes to reserve space for the header
+        writer.write_all(&vec![0_u8; total_header_size])?;
+    }
+    let mut journal = PackJournal::create(output_file, plan, &done)?;
 
     // Now write all the files (encrypted), and prepare the PakAssets
 
     let mut assets_list = Vec::new();
-    let mut assets_data_offset = 0;
+    let mut extended_assets_list = Vec::new();
+    let mut plaintext_tails = Vec::new();
+    let mut changed_since_order_file = Vec::new();
 
-    for path_on_host in file_paths_vec {
-        let path_within_pak = path_on_host.strip_prefix(input_folder)?;
+    let mut progress = if show_progress {
+        Progress::new(total_source_size, PROGRESS_LABELS)
+    } else {
+        Progress::disabled()
+    };
+
+    // On Ctrl-C, stop between assets, with the journal up to date so the
+    // pack can be resumed
+    let _watch = interrupt::watch();
+    let asset_count = entries.len();
+
+    for (i, (PackEntry {name: asset_name_bytes, display_path, source, compress, expected_crc32}, snapshot)) in entries.into_iter().zip(snapshots).enumerate() {
+        if let Some(entry) = done.get(i) {
+            // (written before the pack was interrupted)
+            record_asset(&asset_name_bytes, entry, extended_offsets, &mut assets_list, &mut extended_assets_list, report)?;
+            progress.advance(snapshot.map_or(0, |snapshot| snapshot.size));
+            continue;
+        }
 
-        // Need to build this string manually in case we're running on
-        // a platform that doesn't use "/" separators (e.g. Windows)
-        let capacity = path_within_pak.as_os_str().as_encoded_bytes().len() + 1;
-        let mut asset_name_bytes = Vec::with_capacity(capacity);
-        for component in path_within_pak.iter() {
-            asset_name_bytes.extend_from_slice(component.as_encoded_bytes());
-            asset_name_bytes.push(b'/');
+        if let Err(e) = interrupt::check() {
+            progress.finish();
+            writer.flush()?;
+            journal.save()?;
+            eprintln!(
+                "Packed {i} of {asset_count} asset(s) before being interrupted; {} is incomplete (pack again with --resume to continue, or delete it)",
+                output_file.display(),
+            );
+            return Err(e.into());
         }
-        asset_name_bytes.pop();
 
-        if verbosity == Verbosity::Verbose {
+        if verbosity >= Verbosity::Normal && !progress.is_enabled() {
             println!("{}", String::from_utf8_lossy(&asset_name_bytes));
         }
 
-        let mut asset_data = std::fs::read(&path_on_host)?;
+        let mut asset_data = if let (Some(source), Some(snapshot)) = (&source, snapshot) {
+            // (checked both before and after reading it, in case it's
+            // being written to right then)
+            let mut data = None;
+            if FileSnapshot::take(source)? == snapshot {
+                let read = progress.measure(Phase::Read, snapshot.size, || Ok(std::fs::read(source)?))?;
+                if u64::try_from(read.len())? == snapshot.size && FileSnapshot::take(source)? == snapshot {
+                    data = Some(read);
+                }
+            }
+            let Some(data) = data else {
+                progress.finish();
+                drop(writer);
+                std::fs::remove_file(output_file)?;
+                journal.remove()?;
+                bail!(
+                    "{} changed while packing, so the .pak would have a mix of old and new contents (the output file has been deleted; pack again once nothing is writing to it)",
+                    display_path.display(),
+                );
+            };
+            data
+        } else {
+            // Empty folder placeholder
+            Vec::new()
+        };
+        let input_size = asset_data.len();
 
-        let decompressed_size = asset_data.len();
+        if let Some(expected_crc32) = expected_crc32
+            && crc32fast::hash(&asset_data) != expected_crc32
+        {
+            changed_since_order_file.push(display_path);
+        }
 
-        if compress_files {
-            let compressed_asset_data = lz4_flex::block::compress(&asset_data);
-            // only use the compressed version if it's actually smaller
-            if compressed_asset_data.len() < asset_data.len() {
-                asset_data = compressed_asset_data;
+        let mut decompressed_size = asset_data.len();
+
+        let raw_asset = std::str::from_utf8(&asset_name_bytes).ok().and_then(|n| raw_assets.get(n));
+        if let Some(raw_asset) = raw_asset {
+            // Already in its stored form, so just carry over the size
+            if asset_data.len() != usize::try_from(raw_asset.size_compressed)? {
+                bail!(
+                    "raw asset {} has been modified (expected {} bytes, found {})",
+                    raw_asset.name,
+                    raw_asset.size_compressed,
+                    asset_data.len(),
+                );
             }
+            decompressed_size = raw_asset.size_decompressed.try_into()?;
+        } else if compress {
+            let size = u64::try_from(asset_data.len())?;
+            asset_data = progress.measure(Phase::Compression, size, || Ok(compress_asset(asset_data)))?;
         }
         let compressed_size = asset_data.len();
 
-        let plaintext_crc32 = crc32fast::hash(&asset_data);
-        encrypt(&asset_name_bytes, key, &mut asset_data);
-        writer.write_all(&asset_data)?;
-        let ciphertext_crc32 = crc32fast::hash(&asset_data);
+        let plaintext_crc32 = if let Some(raw_asset) = raw_asset
+            && raw_form == Some(RawForm::Encrypted)
+        {
+            // Already encrypted, so the plaintext isn't available
+            if crc32fast::hash(&asset_data) != raw_asset.ciphertext_crc32 {
+                bail!("raw asset {} has been modified (ciphertext CRC32 mismatch)", raw_asset.name);
+            }
+            raw_asset.plaintext_crc32
+        } else {
+            let plaintext_crc32 = progress.measure(Phase::Crypt, u64::try_from(asset_data.len())?, || {
+                let plaintext_crc32 = crc32fast::hash(&asset_data);
+                encrypt(&asset_name_bytes, key, &mut asset_data);
+                Ok(plaintext_crc32)
+            })?;
+            // (an edit to a still-compressed asset that keeps its size
+            // would otherwise go unnoticed)
+            if let Some(raw_asset) = raw_asset
+                && plaintext_crc32 != raw_asset.plaintext_crc32
+            {
+                bail!("raw asset {} has been modified (plaintext CRC32 mismatch)", raw_asset.name);
+            }
+            let tail_len = plaintext_tail_len(asset_data.len());
+            if warn_plaintext_tails && tail_len > 0 {
+                plaintext_tails.push((String::from_utf8_lossy(&asset_name_bytes).into_owned(), tail_len, asset_data.len()));
+            }
+            plaintext_crc32
+        };
+        let ciphertext_crc32 = progress.measure(Phase::Write, u64::try_from(asset_data.len())?, || {
+            writer.write_all(&asset_data)?;
+            data_hasher.update(&asset_data);
+            Ok(crc32fast::hash(&asset_data))
+        })?;
 
-        assets_list.push(PakAsset {
-            name: asset_name_bytes.to_vec(),
-            size_decompressed: u32::try_from(decompressed_size)?,
-            size_compressed: u32::try_from(compressed_size)?,
-            offset: u32::try_from(assets_data_offset)?,
+        let entry = JournalEntry {
+            size_decompressed: u64::try_from(decompressed_size)?,
+            size_compressed: u64::try_from(compressed_size)?,
+            offset: u64::try_from(assets_data_offset)?,
             plaintext_crc32,
             ciphertext_crc32,
-        });
+            data_crc32: data_hasher.clone().finalize(),
+        };
+        record_asset(&asset_name_bytes, &entry, extended_offsets, &mut assets_list, &mut extended_assets_list, report)?;
+
+        if verbosity >= Verbosity::Debug && !progress.is_enabled() {
+            print_asset_details(
+                entry.offset,
+                entry.size_compressed,
+                entry.size_decompressed,
+                plaintext_crc32,
+                ciphertext_crc32,
+            );
+        }
+
+        if journal.record(entry) {
+            writer.flush()?;
+            journal.save()?;
+        }
 
         assets_data_offset += asset_data.len();
+        progress.advance(u64::try_from(input_size)?);
+    }
+
+    progress.finish();
+
+    if !changed_since_order_file.is_empty() {
+        report.warn(format!(
+            "{} file(s) have changed since the order file was written (is this the folder you meant to pack?):\n{}",
+            changed_since_order_file.len(),
+            changed_since_order_file.iter().map(|path| format!("  {}", path.display())).collect::<Vec<_>>().join("\n"),
+        ));
+    }
+
+    if !plaintext_tails.is_empty() {
+        let list = plaintext_tails.iter().map(|(name, tail_len, len)| if tail_len == len {
+            format!("  {name}: all {len} byte(s)")
+        } else {
+            format!("  {name}: last {tail_len} of {len} bytes")
+        }).collect::<Vec<_>>().join("\n");
+        report.warn(format!("{} asset(s) are stored partly or entirely unencrypted:\n{list}", plaintext_tails.len()));
     }
 
     let total_file_size = writer.stream_position()?;
 
+    if let Some(max_size) = max_size
+        && total_file_size > max_size
+    {
+        drop(writer);
+        std::fs::remove_file(output_file)?;
+        journal.remove()?;
+        bail!(
+            "the .pak came out at {total_file_size} bytes, which is over the maximum size of {max_size} bytes by {} (the output file has been deleted)",
+            total_file_size - max_size,
+        );
+    }
+
     // Now go back and fill in the PakAssets list (encrypted)...
     writer.seek(SeekFrom::Start(PAK_HEADER_SIZE.try_into()?))?;
 
     let mut header_buf_cursor = Cursor::new(Vec::new());
-    (PakAssets {contents: assets_list}).write(&mut header_buf_cursor)?;
+    if extended_offsets {
+        (ExtendedPakAssets {contents: extended_assets_list}).write(&mut header_buf_cursor)?;
+    } else {
+        (PakAssets {contents: assets_list}).write(&mut header_buf_cursor)?;
+    }
     let mut header_buf = header_buf_cursor.into_inner();
 
     // TODO: support compressing the file table
@@ -181,9 +915,9 @@ pub fn pack(
     writer.write_all(&header_buf)?;
     let ciphertext_crc32 = crc32fast::hash(&header_buf);
 
-    // ...and the unencrypted header (without the CRC32 yet)
-    let header = PakHeader {
-        version: FILE_VERSION,
+    // ...and the unencrypted header
+    let mut header = PakHeader {
+        version: if extended_offsets { EXTENDED_FILE_VERSION } else { FILE_VERSION },
         crc32: 0,
         unk0c: 1,
         timestamp,
@@ -193,41 +927,485 @@ pub fn pack(
         ciphertext_crc32,
     };
 
+    // The whole-file CRC32 covers everything from partway through the
+    // header onward, so it doesn't depend on its own value
+    let mut header_bytes = Cursor::new(Vec::new());
+    header.write(&mut header_bytes)?;
+    #[allow(clippy::cast_possible_truncation)]
+    let mut hasher = Jamcrc32Hasher::new_with_initial(total_file_size as u32);
+    hasher.update(&header_bytes.into_inner()[PAK_CRC32_START_OFFSET..]);
+    hasher.update(&header_buf);
+    hasher.combine(&data_hasher);
+    header.crc32 = hasher.finalize();
+
     writer.seek(SeekFrom::Start(0))?;
     header.write(&mut writer)?;
 
-    // Finally, fix the header CRC32
-    fix_header_crc32(writer.into_inner()?, total_file_size)
+    writer.flush()?;
+    writer.get_ref().set_len(total_file_size)?;
+    drop(writer);
+    cleanup.armed = false;
+    journal.remove()?;
+    Ok(())
 }
 
 
-fn fix_header_crc32(file: File, total_file_size: u64) -> anyhow::Result<()> {
-    let mut reader = BufReader::new(file);
+/// Cleans up after a pack that fails partway through (once it's dropped,
+/// unless it's been disarmed). The output file was preallocated at its
+/// largest possible size, so it's cut back to the assets that the
+/// journal says were written, so the pack can still be resumed, or if
+/// there aren't any, deleted along with the journal.
+struct FailedPackCleanup<'a> {
+    output_file: &'a Path,
+    plan: u32,
+    /// Offset of the asset data in the output file.
+    data_start: u64,
+    armed: bool,
+}
 
-    // Calculate the JAMCRC32 of the entire file starting at
-    // PAK_CRC32_START_OFFSET
 
-    reader.seek(SeekFrom::Start(PAK_CRC32_START_OFFSET.try_into()?))?;
+impl Drop for FailedPackCleanup<'_> {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
 
-    let mut data_buffer = vec![0; CRC32_DATA_BUFFER_SIZE];
-    #[allow(clippy::cast_possible_truncation)]
-    let mut hasher = Jamcrc32Hasher::new_with_initial(total_file_size as u32);
-    loop {
-        let amount_read = reader.read(&mut data_buffer)?;
-        hasher.update(&data_buffer[..amount_read]);
-        if amount_read < CRC32_DATA_BUFFER_SIZE {
-            break;
+        let done = journal::load(self.output_file, self.plan).ok().flatten().unwrap_or_default();
+        if let Some(last) = done.last()
+            && let Ok(f) = File::options().write(true).open(self.output_file)
+        {
+            let _ = f.set_len(self.data_start + last.end());
+        } else {
+            let _ = std::fs::remove_file(self.output_file);
+            let _ = std::fs::remove_file(journal::journal_path(self.output_file));
         }
     }
-    let crc = hasher.finalize();
+}
 
-    // Switch back to a BufWriter, and write that value to 0x08
 
-    let mut writer = BufWriter::new(reader.into_inner());
+/// Add an asset that's been written to the assets list being built, and
+/// to the report.
+fn record_asset(
+    name: &[u8],
+    entry: &JournalEntry,
+    extended_offsets: bool,
+    assets_list: &mut Vec<PakAsset>,
+    extended_assets_list: &mut Vec<ExtendedPakAsset>,
+    report: &mut Report,
+) -> anyhow::Result<()> {
+    if extended_offsets {
+        extended_assets_list.push(ExtendedPakAsset {
+            name: name.to_vec(),
+            size_decompressed: entry.size_decompressed,
+            size_compressed: entry.size_compressed,
+            offset: entry.offset,
+            plaintext_crc32: entry.plaintext_crc32,
+            ciphertext_crc32: entry.ciphertext_crc32,
+        });
+    } else {
+        let Ok(offset) = u32::try_from(entry.offset) else {
+            bail!(
+                "asset {} would start at offset {:#x} in the asset data, past the 4 GiB limit of the .pak format, even after compression (split the folder into multiple .pak files)",
+                String::from_utf8_lossy(name),
+                entry.offset,
+            );
+        };
 
-    writer.seek(SeekFrom::Start(PAK_CRC32_OFFSET.try_into()?))?;
-    writer.write_le(&crc)?;
+        assets_list.push(PakAsset {
+            name: name.to_vec(),
+            size_decompressed: u32::try_from(entry.size_decompressed)?,
+            size_compressed: u32::try_from(entry.size_compressed)?,
+            offset,
+            plaintext_crc32: entry.plaintext_crc32,
+            ciphertext_crc32: entry.ciphertext_crc32,
+        });
+    }
+
+    report.assets.push(ReportAsset {
+        name: String::from_utf8_lossy(name).into_owned(),
+        action: AssetAction::Packed,
+        size_decompressed: entry.size_decompressed,
+        size_compressed: entry.size_compressed,
+        plaintext_crc32: entry.plaintext_crc32,
+        ciphertext_crc32: entry.ciphertext_crc32,
+        error: None,
+    });
+    Ok(())
+}
+
+
+/// Checksum identifying a pack, for its journal: covering everything
+/// that affects the asset data and where each asset ends up.
+fn plan_id(
+    entries: &[PackEntry],
+    snapshots: &[Option<FileSnapshot>],
+    key: KeyRef,
+    extended_offsets: bool,
+    raw_form: Option<RawForm>,
+) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(key);
+    hasher.update(format!("{extended_offsets} {raw_form:?}").as_bytes());
+    for (entry, snapshot) in entries.iter().zip(snapshots) {
+        hasher.update(&(entry.name.len() as u64).to_le_bytes());
+        hasher.update(&entry.name);
+        hasher.update(&[u8::from(entry.compress), u8::from(snapshot.is_some())]);
+        if let Some(snapshot) = snapshot {
+            let modified = snapshot.modified
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |duration| duration.as_nanos());
+            hasher.update(&snapshot.size.to_le_bytes());
+            hasher.update(&modified.to_le_bytes());
+        }
+    }
+    hasher.finalize()
+}
+
+
+/// Open the output file left by an interrupted pack, to resume it after
+/// the assets in `done`, and hash the asset data that's already in it.
+/// Returns `None` if it doesn't have the data the journal says it should
+/// (e.g. if it's been deleted or replaced since).
+fn open_for_resume(
+    output_file: &Path,
+    total_header_size: usize,
+    done: &[JournalEntry],
+) -> anyhow::Result<Option<(File, crc32fast::Hasher)>> {
+    let Ok(f) = File::options().read(true).write(true).open(output_file) else {
+        return Ok(None);
+    };
+    lock_for_writing(&f, output_file)?;
+
+    let data_start = u64::try_from(total_header_size)?;
+    let data_end = data_start + done.last().map_or(0, JournalEntry::end);
+    if f.metadata()?.len() < data_end {
+        return Ok(None);
+    }
+    let data_hasher = hash_file_section(output_file, data_start, data_end)?;
+    if done.last().is_some_and(|last| data_hasher.clone().finalize() != last.data_crc32) {
+        return Ok(None);
+    }
+
+    Ok(Some((f, data_hasher)))
+}
+
+
+/// An entry in an order file.
+pub struct OrderEntry {
+    /// Line number (starting from 1).
+    pub line: usize,
+    /// Asset name, with "/" separators.
+    pub name: String,
+    /// The CRC32 recorded after the name (following a tab), if any.
+    pub crc32: Option<u32>,
+}
+
+
+/// Read the entries in an order file, one per line.
+///
+/// This tolerates files edited on Windows: a UTF-8 BOM, CRLF line
+/// endings, backslash separators, and stray whitespace around names are
+/// all removed, and blank lines are skipped.
+pub fn read_order_file(order_file: &Path) -> anyhow::Result<Vec<OrderEntry>> {
+    let contents = match std::fs::read_to_string(order_file) {
+        Ok(contents) => contents,
+        Err(e) => bail!("couldn't read order file {order_file:?}: {e}"),
+    };
+    let contents = contents.strip_prefix('\u{feff}').unwrap_or(&contents);
+
+    Ok(contents
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let line = line.trim();
+            let (name, crc32) = line.rsplit_once('\t')
+                .filter(|(_, crc32)| crc32.len() == 8)
+                .and_then(|(name, crc32)| Some((name.trim_end(), Some(u32::from_str_radix(crc32, 16).ok()?))))
+                .unwrap_or((line, None));
+            OrderEntry {line: i + 1, name: name.replace('\\', "/"), crc32}
+        })
+        .filter(|entry| !entry.name.is_empty())
+        .collect())
+}
+
+
+/// Check for files that would end up with the same asset name (e.g.
+/// because the order file lists one twice, or under different case on a
+/// case-insensitive filesystem), which the game would resolve
+/// unpredictably. Names are compared ignoring ASCII case.
+///
+/// This is an error, unless `force` is set, in which case only the last
+/// file with each name is kept.
+fn check_duplicate_names(
+    entries: Vec<PackEntry>,
+    force: bool,
+    report: &mut Report,
+) -> anyhow::Result<Vec<PackEntry>> {
+    let mut keys = Vec::with_capacity(entries.len());
+    let mut last_index = HashMap::new();
+    let mut duplicates = Vec::new();
+
+    for (i, entry) in entries.iter().enumerate() {
+        let key = entry.name.to_ascii_lowercase();
+        if let Some(previous) = last_index.insert(key.clone(), i) {
+            duplicates.push((previous, i));
+        }
+        keys.push(key);
+    }
+
+    if duplicates.is_empty() {
+        return Ok(entries);
+    }
+
+    let list = duplicates.iter().map(|&(a, b)| format!(
+        "  {} and {}",
+        entries[a].display_path.display(),
+        entries[b].display_path.display(),
+    )).collect::<Vec<_>>().join("\n");
+
+    if !force {
+        bail!(
+            "{} asset name(s) would appear more than once (possibly differing only in case), which the game would resolve unpredictably (use -f to keep only the last of each):\n{list}",
+            duplicates.len(),
+        );
+    }
+
+    report.warn(format!(
+        "{} asset name(s) would appear more than once (possibly differing only in case); keeping only the last of each:\n{list}",
+        duplicates.len(),
+    ));
+
+    Ok(entries.into_iter().zip(keys).enumerate()
+        .filter(|(i, (_, key))| last_index[key] == *i)
+        .map(|(_, (entry, _))| entry)
+        .collect())
+}
+
+
+/// Validate the input files before writing anything, and report every
+/// problem at once (rather than failing partway through packing).
+///
+/// This checks that asset names are valid, and that everything will fit
+/// within the 32-bit counts, sizes and offsets of the .pak format (unless
+/// `extended_offsets` is set). Asset offsets are relative to the start of
+/// the asset data, so the limit is on where the last asset starts, not on
+/// the total file size. With compression, the final sizes aren't known
+/// yet, so exceeding that limit is just a warning, as are empty files.
+fn validate_input(
+    entries: &[PackEntry],
+    assets_list_bytes_len: usize,
+    extended_offsets: bool,
+    report: &mut Report,
+) -> anyhow::Result<()> {
+    let max = u64::from(u32::MAX);
+    let compress_files = entries.iter().any(|e| e.compress);
+
+    let mut problems = Vec::new();
+    let mut empty_files = Vec::new();
+    let mut total_size: u64 = 0;
+    let mut last_offset: u64 = 0;
+
+    if !extended_offsets && u32::try_from(entries.len()).is_err() {
+        problems.push(format!("there are {} files, more than a .pak can list", entries.len()));
+    }
+
+    for entry in entries {
+        let display_name = entry.display_path.display();
+
+        let name_bytes = &entry.name;
+        match std::str::from_utf8(name_bytes) {
+            Err(_) => problems.push(format!("{display_name}: name isn't valid UTF-8")),
+            Ok(name) if name.contains(|c: char| c == '\\' || c.is_control()) => {
+                problems.push(format!("{display_name}: name contains a backslash or control character"));
+            },
+            Ok(_) => {},
+        }
+        if u32::try_from(name_bytes.len()).is_err() {
+            problems.push(format!("{display_name}: name is too long ({} bytes)", name_bytes.len()));
+        }
+
+        let Some(path) = &entry.source else {
+            // (an empty folder placeholder)
+            continue;
+        };
+        let size = match path.metadata() {
+            Ok(metadata) => metadata.len(),
+            Err(e) => {
+                problems.push(format!("{display_name}: can't read metadata: {e}"));
+                continue;
+            },
+        };
+        if size == 0 {
+            empty_files.push(format!("  {display_name}"));
+        } else if size > max && !extended_offsets {
+            problems.push(format!(
+                "{display_name}: {size:#x} bytes is 4 GiB or larger, which the .pak format can't store (even compressed, since the decompressed size is stored as 32 bits too)",
+            ));
+        }
+        last_offset = total_size;
+        total_size += size;
+    }
+
+    if !extended_offsets {
+        if u64::try_from(assets_list_bytes_len)? > max {
+            problems.push(format!(
+                "the assets list would be {assets_list_bytes_len:#x} bytes, past the 4 GiB limit of the .pak format (split the folder into multiple .pak files)",
+            ));
+        }
+
+        if last_offset > max {
+            let message = format!(
+                "the asset data totals {total_size:#x} bytes, and the last asset would start at offset {last_offset:#x}, past the 4 GiB limit of the .pak format",
+            );
+            if compress_files {
+                report.warn(format!("{message} unless compression shrinks the data enough"));
+            } else {
+                problems.push(format!("{message} (try --compress-files, or split the folder into multiple .pak files)"));
+            }
+        }
+    }
+
+    if !empty_files.is_empty() {
+        report.warn(format!("{} file(s) are empty:\n{}", empty_files.len(), empty_files.join("\n")));
+    }
+
+    if !problems.is_empty() {
+        bail!(
+            "found {} problem(s) with the input files, so nothing was written:\n{}",
+            problems.len(),
+            problems.iter().map(|p| format!("  {p}")).collect::<Vec<_>>().join("\n"),
+        );
+    }
 
-    writer.flush()?;
     Ok(())
 }
+
+
+/// A single asset to be packed.
+struct PackEntry {
+    /// Asset name in the .pak.
+    name: Vec<u8>,
+    /// Path to show in messages about this asset (relative to the input
+    /// folder, when packing a folder).
+    display_path: PathBuf,
+    /// File to read the asset's data from, or `None` for an empty-folder
+    /// placeholder.
+    source: Option<PathBuf>,
+    /// Whether to LZ4-compress the asset (if that makes it smaller).
+    compress: bool,
+    /// CRC32 the source file had when it was extracted, according to
+    /// the order file, to warn if it's changed since.
+    expected_crc32: Option<u32>,
+}
+
+
+/// The size and modification time of a source file, to tell if it's
+/// changed since it was first looked at.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+struct FileSnapshot {
+    size: u64,
+    /// (`None` on platforms that don't have modification times)
+    modified: Option<SystemTime>,
+}
+
+
+impl FileSnapshot {
+    fn take(path: &Path) -> anyhow::Result<Self> {
+        let metadata = path.metadata()?;
+        Ok(Self {size: metadata.len(), modified: metadata.modified().ok()})
+    }
+}
+
+
+/// Build an asset name out of a path relative to the input folder, with
+/// `normalization` applied.
+///
+/// Need to build this manually in case we're running on a platform that
+/// doesn't use "/" separators (e.g. Windows).
+pub fn asset_name_bytes(path_within_pak: &Path, normalization: NameNormalization) -> Vec<u8> {
+    let capacity = path_within_pak.as_os_str().as_encoded_bytes().len() + 1;
+    let mut name = Vec::with_capacity(capacity);
+    for component in path_within_pak.iter() {
+        name.extend_from_slice(component.as_encoded_bytes());
+        name.push(b'/');
+    }
+    name.pop();
+    normalization.apply(name)
+}
+
+
+/// LZ4-compress an asset's data, if that actually makes it smaller.
+pub fn compress_asset(data: Vec<u8>) -> Vec<u8> {
+    let compressed_data = lz4_flex::block::compress(&data);
+    if compressed_data.len() < data.len() {
+        compressed_data
+    } else {
+        data
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::PakArchive;
+
+    const TEST_KEY: [u8; 16] = *b"packling testing";
+
+    #[test]
+    fn test_resume() {
+        let temp = tempfile::tempdir().unwrap();
+        let input_folder = temp.path().join("input");
+        let pak_file = temp.path().join("test.pak");
+        let resumed_file = temp.path().join("resumed.pak");
+
+        let names = ["a.bin", "b.bin", "c.bin"];
+        std::fs::create_dir(&input_folder).unwrap();
+        for (i, name) in names.iter().enumerate() {
+            std::fs::write(input_folder.join(name), vec![u8::try_from(i).unwrap(); 0x1000 * (i + 1) + 3]).unwrap();
+        }
+        pack(&input_folder, &pak_file, &TEST_KEY, &PackOptions::default(), Verbosity::Silent).unwrap();
+        let packed = std::fs::read(&pak_file).unwrap();
+
+        // Simulate a pack interrupted after the first asset: no header
+        // yet, the asset data cut off after that asset, and a journal
+        // recording it
+        let archive = PakArchive::open(&pak_file, &TEST_KEY).unwrap();
+        let data_start = usize::try_from(archive.data_start_offset()).unwrap();
+        let first = archive.assets().next().unwrap().unwrap();
+        assert_eq!(first.offset, 0);
+        let first_end = data_start + usize::try_from(first.size_compressed).unwrap();
+        let mut interrupted = vec![0; data_start];
+        interrupted.extend_from_slice(&packed[data_start..first_end]);
+        std::fs::write(&resumed_file, interrupted).unwrap();
+
+        let entries: Vec<PackEntry> = names.iter().map(|name| PackEntry {
+            name: name.as_bytes().to_vec(),
+            display_path: PathBuf::from(name),
+            source: Some(input_folder.join(name)),
+            compress: false,
+            expected_crc32: None,
+        }).collect();
+        let snapshots: Vec<_> = entries.iter()
+            .map(|entry| Some(FileSnapshot::take(entry.source.as_deref().unwrap()).unwrap()))
+            .collect();
+        let done = JournalEntry {
+            size_decompressed: first.size_decompressed.into(),
+            size_compressed: first.size_compressed.into(),
+            offset: 0,
+            plaintext_crc32: first.plaintext_crc32,
+            ciphertext_crc32: first.ciphertext_crc32,
+            data_crc32: crc32fast::hash(&packed[data_start..first_end]),
+        };
+        PackJournal::create(&resumed_file, plan_id(&entries, &snapshots, &TEST_KEY, false, None), &[done]).unwrap();
+
+        // (without -f, so this only works by resuming)
+        let options = PackOptions {
+            resume: true,
+            ..PackOptions::default()
+        };
+        pack(&input_folder, &resumed_file, &TEST_KEY, &options, Verbosity::Silent).unwrap();
+        assert_eq!(std::fs::read(&resumed_file).unwrap(), packed);
+        assert!(!journal::journal_path(&resumed_file).exists());
+    }
+}
@@ -0,0 +1,84 @@
+use std::path::Path;
+
+use crate::{
+    archive::PakArchive,
+    key::KeyRef,
+};
+
+
+/// Minimum length of a run of printable characters for `--strings` to
+/// report it (same default as GNU strings).
+const MIN_STRING_LENGTH: usize = 4;
+
+
+/// Print a hexdump of `data`, 16 bytes per line, with an ASCII column.
+fn print_hexdump(data: &[u8]) {
+    for (i, line) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|b| format!("{b:02x}")).collect();
+        let ascii: String = line.iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect();
+        println!("{:08x}  {:<47}  {ascii}", i * 16, hex.join(" "));
+    }
+}
+
+
+/// Find runs of printable ASCII characters in `data`, returning their
+/// offsets and contents.
+fn find_strings(data: &[u8]) -> Vec<(usize, &str)> {
+    let mut strings = Vec::new();
+    let mut start = None;
+
+    for (i, &b) in data.iter().chain(std::iter::once(&0)).enumerate() {
+        let printable = b.is_ascii_graphic() || b == b' ' || b == b'\t';
+        match (printable, start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                if i - s >= MIN_STRING_LENGTH {
+                    // (can't fail, since it's all ASCII)
+                    strings.push((s, std::str::from_utf8(&data[s..i]).unwrap_or_default()));
+                }
+                start = None;
+            },
+            _ => {},
+        }
+    }
+
+    strings
+}
+
+
+/// Print a preview of a single asset: either a hexdump of its first
+/// `num_bytes` bytes, or the printable strings in it.
+pub fn peek(input_file: &Path, key: KeyRef, asset_name: &str, num_bytes: usize, strings: bool) -> anyhow::Result<()> {
+    let mut archive = PakArchive::open(input_file, key)?;
+    let asset = archive.find_asset(asset_name)?;
+    let data = archive.read_asset(&asset)?;
+
+    if strings {
+        for (offset, s) in find_strings(&data) {
+            println!("{offset:08x}  {s}");
+        }
+    } else {
+        print_hexdump(&data[..num_bytes.min(data.len())]);
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_strings() {
+        assert_eq!(find_strings(b""), vec![]);
+        assert_eq!(find_strings(b"abc"), vec![]);
+        assert_eq!(find_strings(b"abcd"), vec![(0, "abcd")]);
+        assert_eq!(
+            find_strings(b"\x00\x01hello\xffab\x00world!"),
+            vec![(2, "hello"), (11, "world!")],
+        );
+    }
+}
@@ -0,0 +1,589 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    ffi::OsStr,
+    fs::File,
+    io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use anyhow::bail;
+use binrw::BinRead;
+
+use crate::{
+    encryption::{XXTEA_CHUNK_SIZE, decrypt, decrypt_chunk, generate_key},
+    flow_unpack::remove_previous_extraction,
+    key::KeyRef,
+    shared::{
+        ASSETS_LIST_NAME,
+        FILE_VERSION,
+        PAK_HEADER_SIZE,
+        PakAsset,
+        PakHeader,
+        Verbosity,
+        calc_field_0x10,
+        is_empty_folder,
+    },
+};
+
+
+/// Largest asset size that will be considered when searching for the
+/// end of an asset. Every candidate size costs one chunk decryption,
+/// so this also bounds how long a single name can take.
+const MAX_CANDIDATE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Smallest asset size that will be considered. Assets smaller than
+/// this are either stored unencrypted or too short for the plausibility
+/// check to tell apart from noise.
+const MIN_CANDIDATE_SIZE: u64 = 8;
+
+/// How many standard deviations above random noise the plausibility
+/// score of a decrypted chunk must be for it to be accepted. Chosen to
+/// make false positives vanishingly rare over millions of candidates.
+const PLAUSIBILITY_THRESHOLD: f64 = 6.0;
+
+
+/// Check whether a byte looks like it belongs to structured (i.e.
+/// non-encrypted) data: zero, or printable ASCII.
+fn is_typical_byte(b: u8) -> bool {
+    b == 0 || b == b'\t' || b == b'\n' || b == b'\r' || (0x20..0x7f).contains(&b)
+}
+
+
+/// Score how unlikely it is that `data` is random noise, as the number
+/// of standard deviations by which its count of "typical" bytes exceeds
+/// what uniformly random data would have.
+#[allow(clippy::cast_precision_loss)]
+fn plausibility(data: &[u8]) -> f64 {
+    const P: f64 = 100.0 / 256.0;  // 0x00, \t, \n, \r, and 0x20-0x7e
+    let n = data.len() as f64;
+    let typical = data.iter().filter(|b| is_typical_byte(**b)).count() as f64;
+    (typical - n * P) / (n * P * (1.0 - P)).sqrt()
+}
+
+
+/// Check whether the chunk of the asset named `name` starting at
+/// `chunk_offset` decrypts to plausible data, assuming that the asset
+/// is `size` bytes long and starts at `offset`.
+fn chunk_is_plausible<R: Read + Seek>(
+    reader: &mut R,
+    name: &[u8],
+    offset: u64,
+    size: u64,
+    chunk_offset: u64,
+    key: KeyRef,
+) -> anyhow::Result<bool> {
+    let chunk_size = (size - chunk_offset).min(XXTEA_CHUNK_SIZE as u64);
+    let mut chunk = vec![0; usize::try_from(chunk_size)?];
+    reader.seek(SeekFrom::Start(offset + chunk_offset))?;
+    reader.read_exact(&mut chunk)?;
+    decrypt_chunk(name, key, u32::try_from(size)?, u32::try_from(chunk_offset)?, &mut chunk);
+
+    // Chunks that are too short to judge get the benefit of the doubt
+    Ok(chunk_size < 64 || plausibility(&chunk) >= PLAUSIBILITY_THRESHOLD)
+}
+
+
+/// A place where an asset could start, in `find_asset_size()`.
+struct Candidate {
+    offset: u64,
+    max_size: u64,
+    /// The stored data from `offset`, up to a chunk of it. The first
+    /// chunk always starts at the asset's offset, so its ciphertext is
+    /// the same for every candidate size -- only the key changes.
+    window: Vec<u8>,
+    /// The last key tried on the whole window, and whether it gave
+    /// plausible data. (Once the candidate size reaches the end of the
+    /// window, the first chunk is the same for every larger size, and
+    /// key generation ANDs the size into the fixed key, so runs of
+    /// sizes differing only in bits that the fixed key masks out decrypt
+    /// it the same way.)
+    full_window_result: Option<(Box<[u8]>, bool)>,
+}
+
+
+/// Check whether the first chunk of the asset named `name` decrypts to
+/// plausible data, assuming that it's `size` bytes long and starts at
+/// `candidate.offset`.
+fn first_chunk_is_plausible(name: &[u8], candidate: &mut Candidate, size: u64, key: KeyRef) -> anyhow::Result<bool> {
+    let chunk_size = usize::try_from(size)?.min(candidate.window.len());
+    if chunk_size == candidate.window.len() {
+        let chunk_key = generate_key(name, u32::try_from(size)?, 0, key);
+        if let Some((previous_key, plausible)) = &candidate.full_window_result
+            && *previous_key == chunk_key
+        {
+            return Ok(*plausible);
+        }
+        let mut chunk = candidate.window.clone();
+        decrypt_chunk(name, key, u32::try_from(size)?, 0, &mut chunk);
+        let plausible = plausibility(&chunk) >= PLAUSIBILITY_THRESHOLD;
+        candidate.full_window_result = Some((chunk_key, plausible));
+        Ok(plausible)
+    } else {
+        let mut chunk = candidate.window[..chunk_size].to_vec();
+        decrypt_chunk(name, key, u32::try_from(size)?, 0, &mut chunk);
+        Ok(plausibility(&chunk) >= PLAUSIBILITY_THRESHOLD)
+    }
+}
+
+
+/// Find where the asset named `name` most likely starts (as an index
+/// into `starts`, which pairs each place it could start with the
+/// largest size it could have there) and its size, by trying every
+/// candidate size and checking whether the first and last chunks
+/// decrypt to something that looks like real data.
+///
+/// Sizes are tried smallest first, at every start in turn, so that a
+/// wrong start (which never decrypts plausibly) doesn't have to be
+/// searched all the way to its largest size before the right one is
+/// tried. Only mostly-text (or zero-filled) data looks real enough, so
+/// binary and LZ4-compressed assets are never found this way.
+fn find_asset_size<R: Read + Seek>(
+    reader: &mut R,
+    name: &[u8],
+    starts: &[(u64, u64)],
+    key: KeyRef,
+) -> anyhow::Result<Option<(usize, u64)>> {
+    let mut candidates = Vec::with_capacity(starts.len());
+    for &(offset, max_size) in starts {
+        let mut window = vec![0; usize::try_from(max_size.min(XXTEA_CHUNK_SIZE as u64))?];
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read_exact(&mut window)?;
+        candidates.push(Candidate {offset, max_size, window, full_window_result: None});
+    }
+
+    let max_size = starts.iter().map(|&(_, max_size)| max_size).max().unwrap_or(0);
+    for size in MIN_CANDIDATE_SIZE..=max_size {
+        for (i, candidate) in candidates.iter_mut().enumerate() {
+            if size > candidate.max_size || !first_chunk_is_plausible(name, candidate, size, key)? {
+                continue;
+            }
+
+            // Key generation ANDs the size into the fixed key, so
+            // several sizes can share a first-chunk key. The last chunk
+            // overlaps the following data if the size is wrong, which
+            // weeds those out.
+            let last_chunk_offset = (size - 1) / XXTEA_CHUNK_SIZE as u64 * XXTEA_CHUNK_SIZE as u64;
+            if last_chunk_offset == 0
+                || chunk_is_plausible(reader, name, candidate.offset, size, last_chunk_offset, key)?
+            {
+                return Ok(Some((i, size)));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+
+/// The sizes from `size` up to `max_size` that decrypt the same as it,
+/// apart from the plaintext tail: those with the same number of whole
+/// words, whose chunks all get the same keys. (Trial decryption can't
+/// tell these apart, so it finds the smallest.)
+fn equivalent_sizes(name: &[u8], size: u64, max_size: u64, key: KeyRef) -> anyhow::Result<Vec<u64>> {
+    let mut sizes = vec![size];
+    for other in size + 1..=(size | 3).min(max_size) {
+        let mut same_keys = true;
+        for chunk_offset in (0..size).step_by(XXTEA_CHUNK_SIZE) {
+            let chunk_offset = u32::try_from(chunk_offset)?;
+            same_keys &= generate_key(name, u32::try_from(size)?, chunk_offset, key)
+                == generate_key(name, u32::try_from(other)?, chunk_offset, key);
+        }
+        if same_keys {
+            sizes.push(other);
+        }
+    }
+    Ok(sizes)
+}
+
+
+/// Check whether all of an asset's first and last chunks decrypt to
+/// plausible data.
+fn decrypts_plausibly(name: &[u8], data: &[u8], key: KeyRef) -> bool {
+    let mut data = data.to_vec();
+    decrypt(name, key, &mut data);
+    let last_chunk_offset = data.len().saturating_sub(1) / XXTEA_CHUNK_SIZE * XXTEA_CHUNK_SIZE;
+    plausibility(&data[..data.len().min(XXTEA_CHUNK_SIZE)]) >= PLAUSIBILITY_THRESHOLD
+        && plausibility(&data[last_chunk_offset..]) >= PLAUSIBILITY_THRESHOLD
+}
+
+
+/// An asset found by trial decryption, whose size may still be
+/// ambiguous between `sizes` (see `equivalent_sizes()`) until where the
+/// next asset starts is known.
+struct FoundAsset<'a> {
+    name: &'a str,
+    offset: u64,
+    sizes: Vec<u64>,
+}
+
+
+/// Read `size` bytes of stored asset data from `offset`.
+fn read_stored<R: Read + Seek>(reader: &mut R, offset: u64, size: u64) -> anyhow::Result<Vec<u8>> {
+    let mut data = vec![0; usize::try_from(size)?];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+
+/// Decrypt a recovered asset's stored data, and write it to its place
+/// in `output_folder`.
+fn write_recovered(output_folder: &Path, name: &str, mut data: Vec<u8>, key: KeyRef) -> anyhow::Result<()> {
+    let asset_path = Path::new(OsStr::new(name));
+    if asset_path.components().any(|c| !matches!(c, std::path::Component::Normal(_) | std::path::Component::CurDir)) {
+        bail!("directory traversal: {asset_path:?}");
+    }
+
+    decrypt(name.as_bytes(), key, &mut data);
+
+    let output_path = output_folder.join(asset_path);
+    let Some(output_subfolder) = output_path.parent() else {
+        bail!("output file {output_path:?} has no clear parent");
+    };
+    std::fs::create_dir_all(output_subfolder)?;
+    std::fs::write(output_path, data)?;
+    Ok(())
+}
+
+
+/// Salvage what's left of a damaged (but uncompressed) assets list,
+/// `list_size` bytes long, assuming that it lists `names` in order.
+///
+/// Damage to encrypted data only garbles the chunk it's in, so entries
+/// in other chunks can still be read. An entry is only trusted if its
+/// name is the expected one, and its field 0x10 (a hash of its name and
+/// stored size) agrees with its stored size. Returns the trusted
+/// entries by name.
+fn salvage_assets_list<R: Read + Seek>(
+    reader: &mut R,
+    list_size: u64,
+    names: &[String],
+    key: KeyRef,
+) -> anyhow::Result<HashMap<String, PakAsset>> {
+    let mut entries = HashMap::new();
+
+    let mut data = vec![0; usize::try_from(list_size)?];
+    reader.seek(SeekFrom::Start(PAK_HEADER_SIZE as u64))?;
+    if reader.read_exact(&mut data).is_err() {
+        return Ok(entries);
+    }
+    decrypt(ASSETS_LIST_NAME, key, &mut data);
+
+    // (each entry is its name length, name, and 7 more u32s)
+    let mut position = 4;
+    for name in names {
+        let end = position + 0x20 + name.len();
+        let Some(entry) = data.get(position..end) else {
+            break;
+        };
+        position = end;
+
+        let field_0x10 = u32::from_le_bytes(entry[entry.len() - 0xc..entry.len() - 8].try_into()?);
+        if let Ok(asset) = PakAsset::read(&mut Cursor::new(entry))
+            && asset.name == name.as_bytes()
+            && field_0x10 == calc_field_0x10(&asset.name, asset.size_compressed)
+        {
+            entries.insert(name.clone(), asset);
+        }
+    }
+
+    Ok(entries)
+}
+
+
+/// Best-effort extraction of a .pak whose assets list is damaged.
+///
+/// Assets are assumed to be listed and stored back-to-back in the order
+/// given by `names_file` (in the same format as an order file). Entries
+/// that can still be read from the assets list are used as they are,
+/// and their data is checked against their ciphertext CRC32s (if it
+/// doesn't match, the asset is skipped as damaged, and the search
+/// carries on from its end). For each other name, if the next name's
+/// entry was readable and it's clear where this asset starts, the asset
+/// is taken to fill the gap up to where that one starts (and to be
+/// missing if there's no gap). Otherwise, its size is brute-forced by
+/// trial decryption with every candidate size, up to the start of the
+/// next asset whose entry was readable (or the end of the file), until
+/// the result looks like plausible data. Sizes that only differ in the
+/// plaintext tail are settled by where the next asset turns out to
+/// start. Names that can't be found are skipped, on the assumption that
+/// the asset isn't present in this pak.
+///
+/// Only mostly-text data looks plausible, so binary and LZ4-compressed
+/// assets can only be recovered when they're followed by an asset whose
+/// entry was readable; otherwise, they're reported as not found. Either
+/// way, recovered files are written as they were stored (possibly still
+/// LZ4-compressed).
+pub fn recover(
+    input_file: &Path,
+    output_folder: &Path,
+    key: KeyRef,
+    force: bool,
+    names_file: &str,
+    verbosity: Verbosity,
+) -> anyhow::Result<()> {
+    let replace_output_folder = output_folder.is_dir() && !is_empty_folder(output_folder);
+    if replace_output_folder && !force {
+        bail!("output directory exists (use -f to force)");
+    }
+
+    let names: Vec<String> = BufReader::new(File::open(names_file)?)
+        .lines()
+        .map_while(Result::ok)
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    if replace_output_folder {
+        remove_previous_extraction(output_folder, input_file, names.len())?;
+    }
+
+    let input = File::open(input_file)?;
+    let file_size = input.metadata()?.len();
+    let mut reader = BufReader::new(input);
+
+    // If the header is still readable, trust its assets-list size to
+    // find the start of the asset data. Otherwise, assume the assets
+    // list is uncompressed and has exactly these names in it.
+    let header = PakHeader::read(&mut reader).ok().filter(|h| h.version == FILE_VERSION);
+    let assets_list_size = if let Some(header) = &header {
+        u64::from(header.assets_list_size_compressed)
+    } else {
+        if verbosity >= Verbosity::Quiet {
+            eprintln!("PAK header is damaged; guessing the assets list size from the names list");
+        }
+        4 + names.iter().map(|n| 0x20 + n.len() as u64).sum::<u64>()
+    };
+
+    let data_start = u64::try_from(PAK_HEADER_SIZE)? + assets_list_size;
+
+    // (a compressed assets list can't be salvaged a chunk at a time)
+    let mut known = if header.is_none_or(|h| h.assets_list_size_compressed == h.assets_list_size_decompressed) {
+        salvage_assets_list(&mut reader, assets_list_size, &names, key)?
+    } else {
+        HashMap::new()
+    };
+    known.retain(|_, asset| data_start + u64::from(asset.offset) + u64::from(asset.size_compressed) <= file_size);
+    let known_offsets: BTreeSet<u64> = known.values().map(|asset| data_start + u64::from(asset.offset)).collect();
+    if verbosity >= Verbosity::Normal && !known.is_empty() {
+        println!("{} of the {} assets list entries are still readable", known.len(), names.len());
+    }
+
+    let mut not_found = Vec::new();
+    let mut damaged = Vec::new();
+
+    // Where the next asset could start (more than one place if the last
+    // one found has an ambiguous size), and the last asset found by
+    // trial decryption, which is written once its size is settled
+    let mut starts = vec![data_start];
+    let mut pending: Option<FoundAsset> = None;
+    let mut end = data_start;
+
+    for (i, name) in names.iter().enumerate() {
+        let next_known_offset = names.get(i + 1)
+            .and_then(|next_name| known.get(next_name))
+            .map(|next_asset| data_start + u64::from(next_asset.offset));
+
+        let next = if let Some(asset) = known.get(name) {
+            let offset = data_start + u64::from(asset.offset);
+            let size = u64::from(asset.size_compressed);
+            (offset, vec![size])
+        } else {
+            let mut found = None;
+            if let Some(next_offset) = next_known_offset
+                && let [start] = starts[..]
+            {
+                // (the size is whatever fills the gap, which works for
+                // data that trial decryption can't recognize, too)
+                if next_offset > start && next_offset <= file_size {
+                    found = Some((start, vec![next_offset - start]));
+                }
+            } else {
+                // (an asset can't overlap the next one that's known)
+                let candidates: Vec<(u64, u64)> = starts.iter()
+                    .map(|&start| (start, known_offsets.range(start + 1..).next().copied().unwrap_or(file_size)))
+                    .filter(|&(start, limit)| start < limit)
+                    .map(|(start, limit)| (start, (limit - start).min(MAX_CANDIDATE_SIZE)))
+                    .collect();
+                if let Some((i, size)) = find_asset_size(&mut reader, name.as_bytes(), &candidates, key)? {
+                    let (start, max_size) = candidates[i];
+                    found = Some((start, equivalent_sizes(name.as_bytes(), size, max_size, key)?));
+                }
+            }
+            let Some(found) = found else {
+                // (probably not in this pak, so the next asset is
+                // looked for in the same place)
+                not_found.push(name.as_str());
+                continue;
+            };
+            found
+        };
+        let (offset, sizes) = next;
+
+        if let Some(previous) = pending.take() {
+            let size = previous.sizes.iter().copied().find(|size| previous.offset + size == offset).unwrap_or(previous.sizes[0]);
+            if verbosity >= Verbosity::Normal {
+                println!("{} (offset {:#x}, {size} bytes)", previous.name, previous.offset);
+            }
+            write_recovered(output_folder, previous.name, read_stored(&mut reader, previous.offset, size)?, key)?;
+        }
+
+        if let Some(asset) = known.get(name) {
+            let data = read_stored(&mut reader, offset, sizes[0])?;
+            // (the end of an entry that straddles a damaged chunk of the
+            // assets list is garbled, which can include its CRC32s)
+            if crc32fast::hash(&data) == asset.ciphertext_crc32 || decrypts_plausibly(name.as_bytes(), &data, key) {
+                if verbosity >= Verbosity::Normal {
+                    println!("{name} (offset {offset:#x}, {} bytes)", sizes[0]);
+                }
+                write_recovered(output_folder, name, data, key)?;
+            } else {
+                // (but its entry says where it ends, so the search
+                // carries on from there)
+                damaged.push(name.as_str());
+            }
+        } else {
+            pending = Some(FoundAsset {name, offset, sizes: sizes.clone()});
+        }
+        starts = sizes.iter().map(|size| offset + size).collect();
+        end = end.max(starts[0]);
+    }
+
+    if let Some(previous) = pending {
+        let size = previous.sizes.iter().copied().find(|size| previous.offset + size == file_size).unwrap_or(previous.sizes[0]);
+        if verbosity >= Verbosity::Normal {
+            println!("{} (offset {:#x}, {size} bytes)", previous.name, previous.offset);
+        }
+        write_recovered(output_folder, previous.name, read_stored(&mut reader, previous.offset, size)?, key)?;
+        end = end.max(previous.offset + size);
+    }
+
+    if !damaged.is_empty() {
+        eprintln!("{} asset(s) were found, but are damaged (their ciphertext CRC32s don't match), so they were skipped:", damaged.len());
+        for name in &damaged {
+            eprintln!("  {name}");
+        }
+    }
+
+    if !not_found.is_empty() {
+        eprintln!("Couldn't find {} of the {} asset(s):", not_found.len(), names.len());
+        for name in &not_found {
+            eprintln!("  {name}");
+        }
+        eprintln!("(binary and compressed assets can only be found if the next asset's entry in the assets list is readable)");
+    }
+
+    if end < file_size {
+        eprintln!("{} byte(s) at the end of the file (from {end:#x}) weren't matched to any asset", file_size - end);
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        archive::PakArchive,
+        flow_pack::{PackOptions, pack},
+    };
+
+    const TEST_KEY: [u8; 16] = *b"packling testing";
+
+    #[test]
+    fn test_recover() {
+        let temp = tempfile::tempdir().unwrap();
+        let input_folder = temp.path().join("input");
+        let pak_file = temp.path().join("test.pak");
+        let names_file = temp.path().join("names.txt");
+        let output_folder = temp.path().join("output");
+
+        // (enough assets for the assets list to span two chunks)
+        let names: Vec<String> = (0..190).map(|i| format!("asset_{i:03}.txt")).collect();
+        let data = |i: usize| format!("This is asset number {i}.\n").repeat(2 + i % 3).into_bytes();
+        std::fs::create_dir(&input_folder).unwrap();
+        for (i, name) in names.iter().enumerate() {
+            std::fs::write(input_folder.join(name), data(i)).unwrap();
+        }
+        std::fs::write(&names_file, names.join("\n")).unwrap();
+        pack(&input_folder, &pak_file, &TEST_KEY, &PackOptions::default(), Verbosity::Silent).unwrap();
+
+        // Damage the second chunk of the assets list, so that the last
+        // few entries can't be read (and the one straddling the two
+        // chunks is partly garbled), and the data of the asset before
+        // them
+        let archive = PakArchive::open(&pak_file, &TEST_KEY).unwrap();
+        let damaged = archive.find_asset("asset_180.txt").unwrap();
+        let damaged_offset = usize::try_from(archive.asset_offset(&damaged)).unwrap();
+        drop(archive);
+        let mut pak = std::fs::read(&pak_file).unwrap();
+        pak[PAK_HEADER_SIZE + XXTEA_CHUNK_SIZE + 0x40] ^= 0xff;
+        pak[damaged_offset] ^= 0xff;
+        std::fs::write(&pak_file, pak).unwrap();
+
+        recover(&pak_file, &output_folder, &TEST_KEY, false, names_file.to_str().unwrap(), Verbosity::Silent).unwrap();
+
+        // The damaged asset is skipped, and the ones after it are found
+        // by trial decryption, starting from where its entry says it
+        // ends
+        for (i, name) in names.iter().enumerate() {
+            if i == 180 {
+                assert!(!output_folder.join(name).exists());
+            } else {
+                assert_eq!(std::fs::read(output_folder.join(name)).unwrap(), data(i), "{name}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_recover_binary() {
+        let temp = tempfile::tempdir().unwrap();
+        let input_folder = temp.path().join("input");
+        let pak_file = temp.path().join("test.pak");
+        let names_file = temp.path().join("names.txt");
+        let output_folder = temp.path().join("output");
+
+        // (one binary asset, whose entry is the last one entirely in the
+        // first chunk of the assets list, and a key that uses every bit
+        // of the size, so that where each asset ends is unambiguous)
+        let key = [0xff; 16];
+        let names: Vec<String> = (0..190).map(|i| format!("asset_{i:03}.txt")).collect();
+        let data = |i: usize| if i == 181 {
+            (0..0x300_u32).map(|j| (j.wrapping_mul(2_654_435_761) >> 13) as u8).collect()
+        } else {
+            format!("This is asset number {i}.\n").repeat(3).into_bytes()
+        };
+        std::fs::create_dir(&input_folder).unwrap();
+        for (i, name) in names.iter().enumerate() {
+            std::fs::write(input_folder.join(name), data(i)).unwrap();
+        }
+        std::fs::write(&names_file, names.join("\n")).unwrap();
+        pack(&input_folder, &pak_file, &key, &PackOptions::default(), Verbosity::Silent).unwrap();
+
+        // Damage the first chunk of the assets list, so that only the
+        // entries after the binary asset's can be read
+        let mut pak = std::fs::read(&pak_file).unwrap();
+        pak[PAK_HEADER_SIZE + 0x40] ^= 0xff;
+        std::fs::write(&pak_file, pak).unwrap();
+
+        recover(&pak_file, &output_folder, &key, false, names_file.to_str().unwrap(), Verbosity::Silent).unwrap();
+
+        // Trial decryption can't recognize the binary asset, but it
+        // fills the gap before the next readable entry
+        for (i, name) in names.iter().enumerate() {
+            assert_eq!(std::fs::read(output_folder.join(name)).unwrap(), data(i), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_plausibility() {
+        assert!(plausibility(&[0; 64]) > PLAUSIBILITY_THRESHOLD);
+        assert!(plausibility(b"The quick brown fox jumps over the lazy dog, repeatedly.") > PLAUSIBILITY_THRESHOLD);
+
+        // Too short to tell apart from noise, even if it's all zeroes
+        assert!(plausibility(&[0; 8]) < PLAUSIBILITY_THRESHOLD);
+
+        let noise: Vec<u8> = (0..4096_u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+        assert!(plausibility(&noise) < PLAUSIBILITY_THRESHOLD);
+    }
+}
@@ -0,0 +1,101 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use anyhow::bail;
+use serde::Serialize;
+
+use crate::{
+    archive::PakArchive,
+    key::KeyRef,
+    shared::{TableFormat, csv_field, uncovered_ranges},
+};
+
+
+/// A region of a .pak file's asset data that no asset refers to.
+#[derive(Serialize, Default)]
+pub(crate) struct SlackRegion {
+    pub(crate) offset: u64,
+    pub(crate) size: u64,
+    /// The asset whose data the region follows, if any.
+    pub(crate) after: Option<String>,
+    /// Whether the region runs to the end of the file.
+    pub(crate) trailing: bool,
+}
+
+
+/// Print every region of a .pak file's asset data that no asset refers
+/// to: gaps between assets, and any bytes after the last one. These
+/// sometimes contain leftover data from previous builds.
+///
+/// If `extract_folder` is given, each region is also saved there as-is
+/// (still encrypted, if it was), as `<offset in hex>.bin`.
+pub fn print_slack(
+    input_file: &Path,
+    key: KeyRef,
+    format: TableFormat,
+    extract_folder: Option<&Path>,
+) -> anyhow::Result<()> {
+    let mut archive = PakArchive::open(input_file, key)?;
+    let file_size = std::fs::metadata(input_file)?.len();
+
+    let mut spans = Vec::new();
+    for asset in archive.assets() {
+        let asset = asset?;
+        let start = archive.asset_offset(&asset);
+        spans.push((start, start + u64::from(asset.size_compressed), String::from_utf8_lossy(&asset.name).into_owned()));
+    }
+
+    let ranges = uncovered_ranges(
+        archive.data_start_offset(),
+        file_size,
+        spans.iter().map(|(start, end, _)| (*start, *end)).collect(),
+    );
+
+    let regions: Vec<SlackRegion> = ranges.iter().map(|&(start, end)| SlackRegion {
+        offset: start,
+        size: end - start,
+        // (the asset that ends furthest along before the region)
+        after: spans.iter()
+            .filter(|(_, span_end, _)| *span_end <= start)
+            .max_by_key(|(_, span_end, _)| *span_end)
+            .map(|(_, _, name)| name.clone()),
+        trailing: end == file_size,
+    }).collect();
+
+    if let Some(extract_folder) = extract_folder {
+        std::fs::create_dir_all(extract_folder)?;
+        let reader = archive.reader_mut();
+        for region in &regions {
+            reader.seek(SeekFrom::Start(region.offset))?;
+            let mut writer = BufWriter::new(File::create(extract_folder.join(format!("{:08x}.bin", region.offset)))?);
+            let copied = std::io::copy(&mut reader.by_ref().take(region.size), &mut writer)?;
+            if copied != region.size {
+                bail!("couldn't read all of the region at {:#x}", region.offset);
+            }
+            writer.flush()?;
+        }
+    }
+
+    match format {
+        TableFormat::Csv => {
+            println!("offset,size,after,trailing");
+            for region in &regions {
+                println!(
+                    "{},{},{},{}",
+                    region.offset,
+                    region.size,
+                    csv_field(region.after.as_deref().unwrap_or_default()),
+                    region.trailing,
+                );
+            }
+        },
+        TableFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&regions)?);
+        },
+    }
+
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use std::path::Path;
+
+use crate::{
+    archive::PakArchive,
+    encryption::{XXTEA_CHUNK_SIZE, decrypt, encrypt, plaintext_tail_len},
+    flow_explain::check,
+    key::KeyRef,
+    shared::{calc_field_0x0c, calc_field_0x10, decompress_lz4},
+    sniff::detect_content_type,
+};
+
+
+/// Print a run of encryption chunks of the same length, by their
+/// absolute offsets in the file.
+fn print_chunks(first: usize, last: usize, chunk_len: usize, asset_offset: u64) {
+    let start = asset_offset + (first * XXTEA_CHUNK_SIZE) as u64;
+    let end = asset_offset + (last * XXTEA_CHUNK_SIZE + chunk_len) as u64;
+    if first == last {
+        println!("    chunk {first}: {chunk_len:#x} bytes at {start:#x}-{:#x}", end - 1);
+    } else {
+        println!("    chunks {first}-{last}: {chunk_len:#x} bytes each, at {start:#x}-{:#x}", end - 1);
+    }
+}
+
+
+/// Print everything known about a single asset: its sizes, where it's
+/// stored, how it's encrypted and compressed, its derived fields, and
+/// whether its CRCs match its data.
+pub fn stat(input_file: &Path, key: KeyRef, asset_name: &str) -> anyhow::Result<()> {
+    let mut archive = PakArchive::open(input_file, key)?;
+    let asset = archive.find_asset(asset_name)?;
+    let offset = archive.asset_offset(&asset);
+    let len = usize::try_from(asset.size_compressed)?;
+
+    println!("{}", String::from_utf8_lossy(&asset.name));
+    println!("  Size:             {:#x} ({} bytes)", asset.size_decompressed, asset.size_decompressed);
+    println!("  Stored size:      {:#x} ({} bytes)", asset.size_compressed, asset.size_compressed);
+    if len == 0 {
+        println!("  Offset:           {:#x} in the asset data, {offset:#x} in the file", asset.offset);
+    } else {
+        println!(
+            "  Offset:           {:#x} in the asset data, {offset:#x}-{:#x} in the file",
+            asset.offset,
+            offset + u64::from(asset.size_compressed) - 1,
+        );
+    }
+    println!("  Field 0x0c:       {:#010x} (derived)", calc_field_0x0c(&asset.name, asset.size_compressed));
+    println!("  Field 0x10:       {:#010x} (derived)", calc_field_0x10(&asset.name, asset.size_compressed));
+
+    let chunks = len.div_ceil(XXTEA_CHUNK_SIZE);
+    let tail = plaintext_tail_len(len);
+    println!(
+        "  Encryption:       {chunks} chunk(s), {}{}",
+        if tail == len && len > 0 { "all left as plaintext".to_owned() } else { format!("last {tail} byte(s) left as plaintext") },
+        if archive.encrypted { "" } else { " (but this .pak has been decrypted)" },
+    );
+    if chunks > 1 {
+        print_chunks(0, chunks - 2, XXTEA_CHUNK_SIZE, offset);
+    }
+    if chunks > 0 {
+        print_chunks(chunks - 1, chunks - 1, len - (chunks - 1) * XXTEA_CHUNK_SIZE, offset);
+    }
+
+    // The stored data may be missing, e.g. if the file was truncated
+    let stored = match archive.read_asset_raw(&asset) {
+        Ok(stored) => stored,
+        Err(e) => {
+            println!("  Data:             unreadable: {e:#}");
+            return Ok(());
+        },
+    };
+
+    // (for a decrypted .pak, the ciphertext has to be recreated to check
+    // its CRC)
+    let (plaintext, ciphertext) = if archive.encrypted {
+        let mut plaintext = stored.clone();
+        decrypt(&asset.name, key, &mut plaintext);
+        (plaintext, stored)
+    } else {
+        let mut ciphertext = stored.clone();
+        encrypt(&asset.name, key, &mut ciphertext);
+        (stored, ciphertext)
+    };
+    println!("  Plaintext CRC32:  {:#010x} ({})", asset.plaintext_crc32, check(asset.plaintext_crc32, crc32fast::hash(&plaintext)));
+    println!("  Ciphertext CRC32: {:#010x} ({})", asset.ciphertext_crc32, check(asset.ciphertext_crc32, crc32fast::hash(&ciphertext)));
+
+    let data = if asset.size_compressed == asset.size_decompressed {
+        println!("  Compression:      none");
+        plaintext
+    } else {
+        match decompress_lz4(&plaintext, asset.size_decompressed) {
+            Ok(data) => {
+                println!(
+                    "  Compression:      LZ4, to {:.1}% of the size (decompresses correctly)",
+                    f64::from(asset.size_compressed) * 100.0 / f64::from(asset.size_decompressed),
+                );
+                data
+            },
+            Err(e) => {
+                println!("  Compression:      LZ4, but DECOMPRESSION FAILED: {e:#}");
+                return Ok(());
+            },
+        }
+    };
+    println!("  Content type:     {}", detect_content_type(&data));
+
+    Ok(())
+}
@@ -0,0 +1,131 @@
+use std::{
+    collections::BTreeMap,
+    path::Path,
+};
+
+use serde::Serialize;
+
+use crate::{
+    archive::PakArchive,
+    key::KeyRef,
+    shared::{TableFormat, csv_field},
+};
+
+
+/// Total sizes of a group of assets.
+#[derive(Serialize, Default)]
+pub(crate) struct SizeStats {
+    /// The folder within the .pak ("" for the top level), or `None` for
+    /// all assets.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) folder: Option<String>,
+    pub(crate) assets: usize,
+    pub(crate) compressed_assets: usize,
+    pub(crate) size_decompressed: u64,
+    pub(crate) size_compressed: u64,
+}
+
+
+impl SizeStats {
+    /// Stored size as a fraction of the decompressed size (1.0 if
+    /// empty).
+    #[allow(clippy::cast_precision_loss)]
+    fn ratio(&self) -> f64 {
+        if self.size_decompressed == 0 {
+            1.0
+        } else {
+            self.size_compressed as f64 / self.size_decompressed as f64
+        }
+    }
+}
+
+
+/// Print the total sizes and compression ratio of the assets in a
+/// .pak, either overall or (with `by_folder`) for each folder within
+/// it, largest first, along with each folder's share of the total.
+///
+/// Assets are counted towards the folder they're directly in, not the
+/// folders above that.
+pub fn print_stats(input_file: &Path, key: KeyRef, by_folder: bool, format: Option<TableFormat>) -> anyhow::Result<()> {
+    let archive = PakArchive::open(input_file, key)?;
+
+    let mut total = SizeStats::default();
+    let mut folders: BTreeMap<String, SizeStats> = BTreeMap::new();
+    for asset in archive.assets() {
+        let asset = asset?;
+        let name = String::from_utf8_lossy(&asset.name);
+        let folder = name.rsplit_once('/').map_or("", |(folder, _)| folder);
+
+        let folder_stats = folders.entry(folder.to_owned()).or_insert_with(|| SizeStats {
+            folder: Some(folder.to_owned()),
+            ..SizeStats::default()
+        });
+        for stats in [&mut total, folder_stats] {
+            stats.assets += 1;
+            if asset.size_compressed != asset.size_decompressed {
+                stats.compressed_assets += 1;
+            }
+            stats.size_decompressed += u64::from(asset.size_decompressed);
+            stats.size_compressed += u64::from(asset.size_compressed);
+        }
+    }
+
+    let rows = if by_folder {
+        let mut rows = folders.into_values().collect::<Vec<_>>();
+        // (stable, so ties stay in name order)
+        rows.sort_by_key(|r| std::cmp::Reverse(r.size_compressed));
+        rows
+    } else {
+        vec![total]
+    };
+
+    match format {
+        Some(TableFormat::Csv) => {
+            if by_folder {
+                print!("folder,");
+            }
+            println!("assets,compressed_assets,size_decompressed,size_compressed");
+            for row in &rows {
+                if let Some(folder) = &row.folder {
+                    print!("{},", csv_field(folder));
+                }
+                println!("{},{},{},{}", row.assets, row.compressed_assets, row.size_decompressed, row.size_compressed);
+            }
+        },
+        Some(TableFormat::Json) => {
+            if by_folder {
+                println!("{}", serde_json::to_string_pretty(&rows)?);
+            } else {
+                println!("{}", serde_json::to_string_pretty(&rows[0])?);
+            }
+        },
+        None => {
+            let total_compressed = rows.iter().map(|row| row.size_compressed).sum::<u64>();
+            println!("{:>8} {:>10} {:>12} {:>12} {:>6} {:>6}  folder", "assets", "compressed", "size", "stored", "ratio", "share");
+            for row in &rows {
+                #[allow(clippy::cast_precision_loss)]
+                let share = if total_compressed == 0 {
+                    0.0
+                } else {
+                    row.size_compressed as f64 / total_compressed as f64
+                };
+                let folder = match row.folder.as_deref() {
+                    None => "(all)".to_owned(),
+                    Some("") => "(top level)".to_owned(),
+                    Some(folder) => format!("{folder}/"),
+                };
+                println!(
+                    "{:>8} {:>10} {:>12} {:>12} {:>5.1}% {:>5.1}%  {folder}",
+                    row.assets,
+                    row.compressed_assets,
+                    row.size_decompressed,
+                    row.size_compressed,
+                    row.ratio() * 100.0,
+                    share * 100.0,
+                );
+            }
+        },
+    }
+
+    Ok(())
+}
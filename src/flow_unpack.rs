@@ -1,69 +1,277 @@
 use std::{
+    collections::HashSet,
     ffi::OsStr,
     fs::File,
-    io::{BufReader, BufWriter, Write, Cursor},
-    path::Path,
+    io::{BufReader, BufWriter, Read, Write, Cursor, Seek, SeekFrom},
+    path::{Component, Path, PathBuf},
 };
 
 use anyhow::bail;
 use binrw::BinRead;
+use clap::ValueEnum;
 
 use crate::{
-    encryption::decrypt_from_reader,
-    key::KeyRef,
+    encryption::{decrypt, decrypt_from_reader},
+    filter::AssetFilter,
+    interrupt,
+    key::{DecryptionError, KeyRef, fingerprint, is_known},
+    preset::GamePreset,
+    progress::{Phase, Progress},
+    report::{AssetAction, Report, ReportAsset},
+    sidecar::{SIDECAR_NAME, RawForm, Sidecar, SidecarAsset, is_empty_folder_placeholder},
     shared::{
         ASSETS_LIST_NAME,
+        EXTENDED_FILE_VERSION,
         FILE_VERSION,
         PAK_HEADER_SIZE,
-        TIME_FORMAT,
         PakHeader,
+        PakAsset,
         PakAssets,
+        ExtendedPakAsset,
+        ExtendedPakAssets,
+        TimeZone,
         Verbosity,
+        decompress_lz4,
+        format_timestamp,
+        free_space,
+        is_empty_folder,
+        print_asset_details,
+        print_header_details,
     },
 };
 
 
+/// What to do when an extracted file would replace an existing one.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, ValueEnum)]
+#[clap(rename_all = "kebab_case")]
+pub enum ConflictPolicy {
+    /// Replace the existing file.
+    Overwrite,
+    /// Keep the existing file, and don't extract the asset.
+    Skip,
+    /// Extract the asset next to the existing file, with a number
+    /// added to its name.
+    Rename,
+    /// Ask what to do for each file.
+    Ask,
+}
+
+
+/// Permissions to give extracted files and folders.
+///
+/// By default, they get the platform's defaults for new files and
+/// folders (on Unix, 0o666 and 0o777 filtered by the process's umask).
+/// An explicit `mode` is used exactly as given, without the umask.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct Permissions {
+    /// Unix-style mode bits for files (e.g. 0o644). Folders that the
+    /// extraction creates get the same bits, plus execute (search)
+    /// wherever read is set (ones that were already there are left as
+    /// they are). On other
+    /// platforms, only the owner write bit means anything: without it,
+    /// files and folders are made read-only.
+    pub mode: Option<u32>,
+    /// Remove execute bits from extracted files (which matters for
+    /// files that already existed and are extracted over).
+    pub no_executable: bool,
+}
+
+
+impl Permissions {
+    fn apply_to_file(self, path: &Path) -> anyhow::Result<()> {
+        if self.mode.is_none() && !self.no_executable {
+            return Ok(());
+        }
+        set_mode(path, |current| {
+            let mode = self.mode.unwrap_or(current);
+            if self.no_executable { mode & !0o111 } else { mode }
+        })
+    }
+
+    fn apply_to_folder(self, path: &Path) -> anyhow::Result<()> {
+        let Some(mode) = self.mode else {
+            return Ok(());
+        };
+        set_mode(path, |_| mode | ((mode & 0o444) >> 2))
+    }
+}
+
+
+/// Change the mode bits of a file or folder, given its current ones.
+#[cfg(unix)]
+fn set_mode(path: &Path, change: impl FnOnce(u32) -> u32) -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    permissions.set_mode(change(permissions.mode() & 0o7777));
+    std::fs::set_permissions(path, permissions)?;
+    Ok(())
+}
+
+
+/// Change the mode bits of a file or folder, given its current ones.
+/// Only the owner write bit is used, as the read-only attribute.
+#[cfg(not(unix))]
+fn set_mode(path: &Path, change: impl FnOnce(u32) -> u32) -> anyhow::Result<()> {
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    let current = if permissions.readonly() { 0o444 } else { 0o666 };
+    permissions.set_readonly(change(current) & 0o200 == 0);
+    std::fs::set_permissions(path, permissions)?;
+    Ok(())
+}
+
+
+/// Options controlling how a .pak is unpacked.
+#[derive(Clone, Default)]
+pub struct UnpackOptions<'a> {
+    /// Delete the output folder first if it already exists (and isn't
+    /// empty).
+    pub force: bool,
+    /// Text file to write the asset names to, in the order they're
+    /// stored in.
+    pub order_file: Option<&'a str>,
+    /// Append the CRC32 of each extracted file to its line in the order
+    /// file, so that packing can warn about files that have changed
+    /// since.
+    pub order_file_crcs: bool,
+    /// Skip assets that can't be extracted, instead of stopping. Each
+    /// asset's ciphertext and plaintext CRC32s are checked, so without
+    /// this, a mismatch in either stops the unpack.
+    pub keep_going: bool,
+    /// Extract assets in this form instead of fully decoding them, and
+    /// record their metadata in the sidecar.
+    pub raw: Option<RawForm>,
+    /// Time zone to display the .pak's timestamp in.
+    pub timezone: TimeZone,
+    /// If set, extract into the output folder even if it already
+    /// exists (without deleting it), and handle existing files like
+    /// this.
+    pub on_conflict: Option<ConflictPolicy>,
+    /// Never overwrite existing files: extract into the output folder
+    /// even if it already exists, but keep any files already there,
+    /// with a warning for each. This overrides `on_conflict`.
+    pub no_clobber: bool,
+    /// Accept .pak files in packling's experimental extended-offsets
+    /// layout (which the game can't load).
+    pub extended_offsets: bool,
+    /// JSON file to write a report of the operation to.
+    pub report: Option<&'a Path>,
+    /// Extract every asset directly into the output folder, with unique
+    /// file names derived from the asset names, and record which is
+    /// which in the sidecar.
+    pub flatten: bool,
+    /// Permissions to give extracted files and folders.
+    pub permissions: Permissions,
+    /// Show a live progress line with the throughput of each phase and
+    /// an estimate of the time remaining, instead of listing each asset.
+    pub progress: bool,
+    /// Game the .pak is from, to record in the sidecar.
+    pub game: Option<GamePreset>,
+    /// Unpack assets that are .pak files themselves (with the same key)
+    /// into folders of the same name, recursively, and record them in
+    /// the sidecar so that they can be packed back.
+    pub recursive: bool,
+    /// Don't check that the output folder's filesystem has enough free
+    /// space for the extracted assets before starting.
+    pub no_space_check: bool,
+    /// Only extract the assets this accepts.
+    pub filter: Option<&'a AssetFilter>,
+}
+
+
+/// Labels of the progress phases when unpacking, in `Phase` order.
+const PROGRESS_LABELS: [&str; 4] = ["read", "decrypt", "decompress", "write"];
+
+
 /// Read and unpack a .pak to a specified output folder.
 pub fn unpack(
     input_file: &Path,
     output_folder: &Path,
     key: KeyRef,
-    force: bool,
-    order_file: Option<&str>,
+    options: &UnpackOptions,
     verbosity: Verbosity,
 ) -> anyhow::Result<()> {
-    if output_folder.is_dir() {
-        if force {
-            std::fs::remove_dir_all(output_folder).ok();
-        } else {
-            bail!("output directory exists (use -f to force)");
-        }
+    let mut report = Report::new("unpack", input_file, output_folder, verbosity);
+    let result = unpack_with_report(input_file, output_folder, key, options, verbosity, &mut report);
+    if let Some(report_file) = options.report {
+        report.save(report_file, &result)?;
     }
+    result
+}
 
-    let mut reader = BufReader::new(File::open(input_file)?);
 
-    let mut order_file_writer = if let Some(order_file) = order_file {
-        let f = File::options()
-            .read(true)
-            .write(true)
-            .truncate(true)
-            .create(true)
-            .open(order_file);
-        Some(BufWriter::new(f?))
+/// The guts of `unpack`, recording what happens in `report`.
+fn unpack_with_report(
+    input_file: &Path,
+    output_folder: &Path,
+    key: KeyRef,
+    options: &UnpackOptions,
+    verbosity: Verbosity,
+    report: &mut Report,
+) -> anyhow::Result<()> {
+    if options.extended_offsets
+        && (options.raw.is_some()
+            || options.on_conflict.is_some()
+            || options.no_clobber
+            || options.flatten
+            || options.order_file_crcs
+            || options.permissions != Permissions::default()
+            || options.filter.is_some())
+    {
+        bail!("--raw, --on-conflict, --no-clobber, --flatten, --order-file-crcs, --chmod, --no-executable, --min-asset-size and --max-asset-size aren't supported with --extended-offsets");
+    }
+    if options.order_file.is_some() && options.filter.is_some() {
+        // (it'd leave out the assets that aren't extracted, so packing
+        // them back wouldn't keep the .pak's order)
+        bail!("--order-file can't be combined with --min-asset-size or --max-asset-size");
+    }
+    if options.recursive
+        && (options.raw.is_some()
+            || options.on_conflict.is_some()
+            || options.no_clobber
+            || options.flatten
+            || options.extended_offsets)
+    {
+        bail!("--recursive can't be combined with --raw, --on-conflict, --merge, --no-clobber, --flatten or --extended-offsets");
+    }
+
+    let on_conflict = if options.no_clobber {
+        Some(ConflictPolicy::Skip)
     } else {
-        None
+        options.on_conflict
     };
 
+    let replace_output_folder = output_folder.is_dir() && !is_empty_folder(output_folder) && on_conflict.is_none();
+    if replace_output_folder && !options.force {
+        bail!("output directory exists (use -f to force)");
+    }
+
+    let input = File::open(input_file)?;
+    let file_size = input.metadata()?.len();
+    let mut reader = BufReader::new(input);
+
     let header = PakHeader::read(&mut reader)?;
 
-    if header.version != FILE_VERSION {
+    let extended = header.version == EXTENDED_FILE_VERSION;
+    if extended && !options.extended_offsets {
+        bail!("this .pak uses packling's experimental extended-offsets layout, which the game can't load (use --extended-offsets to unpack it anyway)");
+    } else if header.version != FILE_VERSION && !extended {
         bail!("unknown PAK version: {}", header.version);
     }
 
-    if verbosity == Verbosity::Verbose {
-        let ts = time::OffsetDateTime::from_unix_timestamp(header.timestamp)?;
-        let format = time::format_description::parse(TIME_FORMAT)?;
-        println!("PAK file created {} ({})", ts.format(&format)?, header.timestamp);
+    if verbosity >= Verbosity::Normal {
+        println!("PAK file created {}", format_timestamp(header.timestamp, options.timezone)?);
+    }
+    if verbosity >= Verbosity::Verbose {
+        print_header_details(&header);
+    }
+
+    let data_start_offset = u64::try_from(PAK_HEADER_SIZE)? + u64::from(header.assets_list_size_compressed);
+
+    if data_start_offset > file_size {
+        bail!(
+            "PAK file is truncated ({file_size} bytes), and doesn't even contain the full assets list ({data_start_offset} bytes)",
+        );
     }
 
     let mut assets_list_data = decrypt_from_reader(
@@ -75,59 +283,887 @@ pub fn unpack(
     )?;
 
     if header.assets_list_size_compressed != header.assets_list_size_decompressed {
-        assets_list_data = lz4_flex::block::decompress(
+        assets_list_data = decompress_lz4(&assets_list_data, header.assets_list_size_decompressed)?.into();
+    }
+
+    // (only now that the assets list has been read, so that it's known
+    // how many files to expect in an earlier extraction, and nothing is
+    // deleted if the .pak turns out to be unreadable)
+    if replace_output_folder {
+        let asset_count = assets_list_data.first_chunk().map_or(0, |count| u32::from_le_bytes(*count));
+        remove_previous_extraction(output_folder, input_file, asset_count.try_into()?)?;
+    }
+
+    let mut order_file_writer = if let Some(order_file) = options.order_file {
+        let f = File::options()
+            .read(true)
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(order_file);
+        Some(BufWriter::new(f?))
+    } else {
+        None
+    };
+
+    if extended {
+        return unpack_extended(
+            &mut reader,
             &assets_list_data,
-            header.assets_list_size_decompressed.try_into().unwrap(),
-        )?.into();
+            data_start_offset,
+            file_size,
+            output_folder,
+            key,
+            options.keep_going,
+            options.progress,
+            order_file_writer,
+            verbosity,
+            report,
+        );
+    }
+
+    let mut assets = PakAssets::read(&mut Cursor::new(assets_list_data))?;
+    if let Some(filter) = options.filter {
+        assets.contents.retain(|asset| filter.matches(asset));
     }
 
-    let assets = PakAssets::read(&mut Cursor::new(assets_list_data))?;
+    if !options.no_space_check {
+        let needed = assets.contents.iter().map(|asset| u64::from(if options.raw.is_some() {
+            asset.size_compressed
+        } else {
+            asset.size_decompressed
+        })).sum();
+        check_free_space(output_folder, needed)?;
+    }
+
+    // (so that only the folders this creates are given the mode)
+    let existing_folders: HashSet<PathBuf> = if options.permissions.mode.is_some() {
+        walkdir::WalkDir::new(output_folder).into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_dir())
+            .map(walkdir::DirEntry::into_path)
+            .collect()
+    } else {
+        HashSet::new()
+    };
+
+    let mut progress = if options.progress {
+        Progress::new(assets.contents.iter().map(|a| u64::from(a.size_compressed)).sum(), PROGRESS_LABELS)
+    } else {
+        Progress::disabled()
+    };
+
+    let mut skipped = Vec::new();
+    let mut missing = Vec::new();
+    let mut kept_existing = 0;
+    // (without a policy, the output folder was just deleted if it
+    // existed, so there can't be any conflicts)
+    let mut on_conflict = on_conflict.unwrap_or(ConflictPolicy::Overwrite);
+    let mut sidecar = Sidecar {
+        raw: options.raw,
+        ..Sidecar::default()
+    };
+    let mut used_flat_names = HashSet::new();
+    let mut extracted_paths = Vec::new();
+
+    // On Ctrl-C, stop between assets, with the order file covering the
+    // ones that were extracted
+    let _watch = interrupt::watch();
+    let asset_count = assets.contents.len();
+
+    for (i, asset) in assets.contents.into_iter().enumerate() {
+        if let Err(e) = interrupt::check() {
+            progress.finish();
+            stop_interrupted(order_file_writer.as_mut(), output_folder, i, asset_count)?;
+            return Err(e.into());
+        }
+
+        let name_str = String::from_utf8_lossy(&asset.name);
+        if verbosity >= Verbosity::Normal && !progress.is_enabled() {
+            println!("{name_str}");
+        }
+        if verbosity >= Verbosity::Debug && !progress.is_enabled() {
+            print_asset_details(
+                asset.offset.into(),
+                asset.size_compressed.into(),
+                asset.size_decompressed.into(),
+                asset.plaintext_crc32,
+                asset.ciphertext_crc32,
+            );
+        }
+
+        // Assets extending past the end of a truncated file can't be
+        // recovered, but everything before them still can
+        let asset_end = data_start_offset + u64::from(asset.offset) + u64::from(asset.size_compressed);
+        if asset_end > file_size {
+            write_order_line(&mut order_file_writer, &name_str, None)?;
+            missing.push((name_str.into_owned(), asset_end));
+            report.assets.push(ReportAsset::new(&asset, AssetAction::Missing));
+            continue;
+        }
+
+        let output_path = if options.flatten {
+            flat_name(&asset.name, &mut used_flat_names).map(|flat_name| {
+                let output_path = output_folder.join(&flat_name);
+                sidecar.flattened.insert(flat_name, name_str.clone().into_owned());
+                output_path
+            })
+        } else {
+            asset_output_path(&asset.name, output_folder)
+        };
+        // (to look for nested .pak files in afterward)
+        let extracted_path = output_path.as_ref().ok().filter(|_| options.recursive).cloned();
+
+        let result = output_path.and_then(|output_path| {
+            extract_asset(
+                &mut reader,
+                &asset,
+                data_start_offset,
+                output_path,
+                key,
+                options.raw,
+                options.permissions,
+                &mut on_conflict,
+                &mut progress,
+            )
+        });
+        progress.advance(asset.size_compressed.into());
+        let crc32 = result.as_ref().ok().copied().flatten().filter(|_| options.order_file_crcs);
+        write_order_line(&mut order_file_writer, &name_str, crc32)?;
+        match result {
+            Ok(Some(_)) => {
+                if options.raw.is_some() {
+                    sidecar.assets.push(SidecarAsset::from(&asset));
+                }
+                if let Some(path) = extracted_path {
+                    extracted_paths.push((name_str.clone().into_owned(), path));
+                }
+                report.assets.push(ReportAsset::new(&asset, AssetAction::Extracted));
+            },
+            Ok(None) => {
+                kept_existing += 1;
+                if options.no_clobber {
+                    report.warn(format!("{name_str}: already exists, so not extracting over it"));
+                }
+                report.assets.push(ReportAsset::new(&asset, AssetAction::KeptExisting));
+            },
+            Err(e) => {
+                if !options.keep_going {
+                    progress.finish();
+                    report.assets.push(ReportAsset::new(&asset, AssetAction::Failed).with_error(&e));
+                    return Err(e);
+                }
+                report.assets.push(ReportAsset::new(&asset, AssetAction::Skipped).with_error(&e));
+                skipped.push((name_str.into_owned(), e));
+            },
+        }
+    }
+
+    progress.finish();
+
+    if options.recursive {
+        sidecar.nested = unpack_nested(&extracted_paths, key, options, verbosity, report)?;
+    }
+
+    let key_fingerprint = (!is_known(key)).then(|| fingerprint(key));
+    if options.raw.is_some() || options.flatten || !sidecar.nested.is_empty() {
+        sidecar.game = options.game;
+        sidecar.key = key_fingerprint;
+        std::fs::create_dir_all(output_folder)?;
+        sidecar.save(output_folder)?;
+    } else if options.game.is_some() || key_fingerprint.is_some() {
+        Sidecar::record_source(output_folder, options.game, key_fingerprint)?;
+    }
+
+    if options.permissions.mode.is_some() && output_folder.is_dir() {
+        for entry in walkdir::WalkDir::new(output_folder) {
+            let entry = entry?;
+            if entry.file_type().is_dir() && !existing_folders.contains(entry.path()) {
+                options.permissions.apply_to_folder(entry.path())?;
+            }
+        }
+    }
+
+    if let Some(ref mut w) = order_file_writer {
+        w.flush()?;
+    }
+
+    if kept_existing > 0 && verbosity >= Verbosity::Quiet {
+        println!("Kept {kept_existing} existing file(s) instead of extracting over them");
+    }
+
+    if !missing.is_empty() {
+        eprintln!("PAK file is truncated ({file_size} bytes); {} asset(s) are missing:", missing.len());
+        for (name, end) in &missing {
+            eprintln!("  {name} (needs {end} bytes)");
+        }
+    }
+
+    if !skipped.is_empty() {
+        eprintln!("Skipped {} asset(s):", skipped.len());
+        for (name, e) in &skipped {
+            eprintln!("  {name}: {e:#}");
+        }
+    }
+
+    if !missing.is_empty() || !skipped.is_empty() {
+        bail!("{} of the assets could not be extracted", missing.len() + skipped.len());
+    }
+
+    Ok(())
+}
+
+
+/// Unpack the extracted assets that are .pak files themselves (going by
+/// their magic) into folders of the same name, in place of the files,
+/// and so on recursively. Returns the names of those assets.
+///
+/// A nested .pak that can't be unpacked is put back as it was. That's
+/// an error, unless `options.keep_going` is set.
+fn unpack_nested(
+    extracted_paths: &[(String, PathBuf)],
+    key: KeyRef,
+    options: &UnpackOptions,
+    verbosity: Verbosity,
+    report: &mut Report,
+) -> anyhow::Result<Vec<String>> {
+    let nested_options = UnpackOptions {
+        keep_going: options.keep_going,
+        timezone: options.timezone,
+        permissions: options.permissions,
+        recursive: true,
+        no_space_check: options.no_space_check,
+        ..UnpackOptions::default()
+    };
+
+    let mut nested = Vec::new();
+    for (name, path) in extracted_paths {
+        let mut magic = [0; 4];
+        if File::open(path).and_then(|mut f| f.read_exact(&mut magic)).is_err() || &magic != b"KCAP" {
+            continue;
+        }
+        if verbosity >= Verbosity::Normal {
+            println!("Unpacking nested .pak {name}");
+        }
+
+        let mut pak_file_name = path.file_name().unwrap_or_default().to_owned();
+        pak_file_name.push(".packling-nested");
+        let pak_path = path.with_file_name(pak_file_name);
+        std::fs::rename(path, &pak_path)?;
+
+        // (the report is about the outer .pak, so the nested assets
+        // aren't in it)
+        let mut nested_report = Report::new("unpack", &pak_path, path, verbosity);
+        match unpack_with_report(&pak_path, path, key, &nested_options, verbosity, &mut nested_report) {
+            Ok(()) => {
+                std::fs::remove_file(&pak_path)?;
+                nested.push(name.clone());
+            },
+            Err(e) => {
+                if path.is_dir() {
+                    std::fs::remove_dir_all(path)?;
+                }
+                std::fs::rename(&pak_path, path)?;
+                if !options.keep_going {
+                    return Err(e.context(format!("couldn't unpack nested .pak {name}")));
+                }
+                report.warn(format!("{name}: couldn't unpack it as a nested .pak, so it's extracted as is: {e:#}"));
+            },
+        }
+    }
+
+    Ok(nested)
+}
+
+
+/// Write an asset's name to the order file (if there is one), followed
+/// by a tab and the CRC32 of its extracted file if given.
+fn write_order_line<W: Write>(order_file_writer: &mut Option<W>, name: &str, crc32: Option<u32>) -> std::io::Result<()> {
+    let Some(w) = order_file_writer else {
+        return Ok(());
+    };
+    match crc32 {
+        Some(crc32) => writeln!(w, "{name}\t{crc32:08x}"),
+        None => writeln!(w, "{name}"),
+    }
+}
+
+
+/// Unpack the assets of a .pak in the experimental extended-offsets
+/// layout, with `reader` positioned just after the assets list. Only the
+/// basic options are supported.
+#[allow(clippy::too_many_arguments)]
+fn unpack_extended<R: Read + Seek, W: Write>(
+    reader: &mut R,
+    assets_list_data: &[u8],
+    data_start_offset: u64,
+    file_size: u64,
+    output_folder: &Path,
+    key: KeyRef,
+    keep_going: bool,
+    show_progress: bool,
+    mut order_file_writer: Option<W>,
+    verbosity: Verbosity,
+    report: &mut Report,
+) -> anyhow::Result<()> {
+    let assets = ExtendedPakAssets::read(&mut Cursor::new(assets_list_data))?;
+
+    let mut progress = if show_progress {
+        Progress::new(assets.contents.iter().map(|a| a.size_compressed).sum(), PROGRESS_LABELS)
+    } else {
+        Progress::disabled()
+    };
+
+    let _watch = interrupt::watch();
+    let asset_count = assets.contents.len();
+
+    let mut skipped = Vec::new();
+    for (i, asset) in assets.contents.into_iter().enumerate() {
+        if let Err(e) = interrupt::check() {
+            progress.finish();
+            stop_interrupted(order_file_writer.as_mut(), output_folder, i, asset_count)?;
+            return Err(e.into());
+        }
 
-    for asset in assets.contents {
-        let name_str = std::str::from_utf8(&asset.name)?;
-        if verbosity == Verbosity::Verbose {
+        let name_str = String::from_utf8_lossy(&asset.name);
+        if verbosity >= Verbosity::Normal && !progress.is_enabled() {
             println!("{name_str}");
         }
+        if verbosity >= Verbosity::Debug && !progress.is_enabled() {
+            print_asset_details(
+                asset.offset,
+                asset.size_compressed,
+                asset.size_decompressed,
+                asset.plaintext_crc32,
+                asset.ciphertext_crc32,
+            );
+        }
         if let Some(ref mut w) = order_file_writer {
             writeln!(w, "{name_str}")?;
         }
 
-        let abs_offset = u32::try_from(PAK_HEADER_SIZE)? + header.assets_list_size_compressed + asset.offset;
-        let mut asset_data = decrypt_from_reader(
-            &mut reader,
-            &asset.name,
-            abs_offset.into(),
-            asset.size_compressed.try_into()?,
-            key,
-        )?;
+        let result = extract_extended_asset(reader, &asset, data_start_offset, file_size, output_folder, key, &mut progress);
+        progress.advance(asset.size_compressed);
+        let mut report_asset = ReportAsset {
+            name: name_str.to_string(),
+            action: AssetAction::Extracted,
+            size_decompressed: asset.size_decompressed,
+            size_compressed: asset.size_compressed,
+            plaintext_crc32: asset.plaintext_crc32,
+            ciphertext_crc32: asset.ciphertext_crc32,
+            error: None,
+        };
+        if let Err(e) = result {
+            report_asset = report_asset.with_error(&e);
+            if !keep_going {
+                progress.finish();
+                report_asset.action = AssetAction::Failed;
+                report.assets.push(report_asset);
+                return Err(e);
+            }
+            report_asset.action = AssetAction::Skipped;
+            report.assets.push(report_asset);
+            skipped.push((name_str.into_owned(), e));
+        } else {
+            report.assets.push(report_asset);
+        }
+    }
 
-        if asset.size_compressed != asset.size_decompressed {
-            asset_data = lz4_flex::block::decompress(
-                &asset_data,
-                asset.size_decompressed.try_into().unwrap(),
-            )?.into();
+    progress.finish();
+
+    if let Some(ref mut w) = order_file_writer {
+        w.flush()?;
+    }
+
+    if !skipped.is_empty() {
+        eprintln!("Skipped {} asset(s):", skipped.len());
+        for (name, e) in &skipped {
+            eprintln!("  {name}: {e:#}");
         }
+        bail!("{} of the assets could not be extracted", skipped.len());
+    }
+
+    Ok(())
+}
+
+
+/// Flush the order file and say how far unpacking got, when it's been
+/// interrupted.
+fn stop_interrupted<W: Write>(order_file_writer: Option<&mut W>, output_folder: &Path, done: usize, asset_count: usize) -> anyhow::Result<()> {
+    if let Some(w) = order_file_writer {
+        w.flush()?;
+    }
+    eprintln!(
+        "Unpacked {done} of {asset_count} asset(s) before being interrupted; {} is incomplete",
+        output_folder.display(),
+    );
+    Ok(())
+}
 
-        let asset_path = Path::new(OsStr::new(name_str));
 
-        // https://stackoverflow.com/a/69515135
-        if asset_path.components().any(|c| c == std::path::Component::ParentDir) {
-            bail!("directory traversal: {asset_path:?}");
+/// Like `extract_asset`, but for an asset in the experimental
+/// extended-offsets layout.
+fn extract_extended_asset<R: Read + Seek>(
+    reader: &mut R,
+    asset: &ExtendedPakAsset,
+    data_start_offset: u64,
+    file_size: u64,
+    output_folder: &Path,
+    key: KeyRef,
+    progress: &mut Progress,
+) -> anyhow::Result<()> {
+    let output_path = asset_output_path(&asset.name, output_folder)?;
+
+    if is_empty_folder_placeholder(&asset.name, asset.size_decompressed) {
+        if let Some(folder) = output_path.parent() {
+            std::fs::create_dir_all(folder)?;
         }
+        return Ok(());
+    }
 
-        let output_path = output_folder.join(asset_path);
-        let output_subfolder = output_path.parent();
-        let Some(output_subfolder) = output_subfolder else {
-            bail!("output file {output_path:?} has no clear parent");
-        };
+    let asset_start = data_start_offset.checked_add(asset.offset);
+    let asset_end = asset_start.and_then(|start| start.checked_add(asset.size_compressed));
+    let (Some(asset_start), Some(asset_end)) = (asset_start, asset_end) else {
+        bail!("offset {:#x} and size {:#x} overflow", asset.offset, asset.size_compressed);
+    };
+    if asset_end > file_size {
+        bail!("extends past the end of the file ({asset_end} > {file_size} bytes)");
+    }
+
+    let asset_data = progress.measure(Phase::Read, asset.size_compressed, || {
+        reader.seek(SeekFrom::Start(asset_start))?;
+        let mut asset_data = vec![0; asset.size_compressed.try_into()?];
+        reader.read_exact(&mut asset_data)?;
+        Ok(asset_data)
+    })?;
+
+    let size_decompressed = (asset.size_compressed != asset.size_decompressed).then_some(asset.size_decompressed);
+    let asset_data = decode_asset_data(
+        &asset.name,
+        asset_data,
+        asset.plaintext_crc32,
+        asset.ciphertext_crc32,
+        size_decompressed,
+        key,
+        None,
+        progress,
+    )?;
 
+    let Some(output_subfolder) = output_path.parent() else {
+        bail!("output file {output_path:?} has no clear parent");
+    };
+    progress.measure(Phase::Write, asset.size_decompressed, || {
         std::fs::create_dir_all(output_subfolder)?;
-        std::fs::write(output_path, asset_data)?;
+        std::fs::write(&output_path, &asset_data)?;
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+
+/// Pick a file name for an asset when extracting with `--flatten`: its
+/// name with the folder separators replaced by "__", and a number added
+/// if that's already used (ignoring case, for case-insensitive
+/// filesystems).
+fn flat_name(name: &[u8], used: &mut HashSet<String>) -> anyhow::Result<String> {
+    let mut base = std::str::from_utf8(name)?.replace(['/', '\\'], "__");
+    if base.chars().all(|c| c == '.') {
+        // (empty, "." or "..")
+        base.insert(0, '_');
     }
 
-    if let Some(ref mut w) = order_file_writer {
-        w.flush()?;
+    let (stem, extension) = match base.rfind('.') {
+        Some(i) if i > 0 => base.split_at(i),
+        _ => (base.as_str(), ""),
+    };
+    let flat_name = (0..)
+        .map(|i| if i == 0 { base.clone() } else { format!("{stem}_{i}{extension}") })
+        .find(|candidate| !used.contains(&candidate.to_lowercase()))
+        .expect("there should be some unused file name");
+
+    used.insert(flat_name.to_lowercase());
+    Ok(flat_name)
+}
+
+
+/// Check that there's at least `needed` bytes of free space for the
+/// output folder, so that running out doesn't stop an extraction
+/// partway through. (If the free space can't be found out, this just
+/// assumes there's enough.)
+pub fn check_free_space(output_folder: &Path, needed: u64) -> anyhow::Result<()> {
+    if let Some(available) = free_space(output_folder)
+        && needed > available
+    {
+        bail!(
+            "not enough disk space: unpacking needs {needed} bytes, but only {available} are free ({} more needed; use --no-space-check to try anyway)",
+            needed - available,
+        );
+    }
+    Ok(())
+}
+
+
+/// Delete an output folder left over from an earlier extraction (for
+/// `-f`), after checking that it really looks like one, so that a typo
+/// in the output path can't wipe out something unrelated.
+///
+/// It has to contain a sidecar file, or at most `max_files` files (the
+/// number of assets about to be extracted into it), and it can't be the
+/// filesystem root, the home folder, or a folder that contains the
+/// current folder or `input_file`.
+pub fn remove_previous_extraction(folder: &Path, input_file: &Path, max_files: usize) -> anyhow::Result<()> {
+    let canonical = folder.canonicalize()?;
+    let is_inside = |path: &Path| path.canonicalize().is_ok_and(|path| path.starts_with(&canonical));
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .and_then(|home| PathBuf::from(home).canonicalize().ok());
+
+    if canonical.parent().is_none()
+        || home.is_some_and(|home| home == canonical)
+        || std::env::current_dir().is_ok_and(|dir| is_inside(&dir))
+        || is_inside(input_file)
+    {
+        bail!("refusing to delete {folder:?}, since it can't be an earlier extraction (check the output path)");
     }
 
+    if !folder.join(SIDECAR_NAME).is_file() {
+        let file_count = walkdir::WalkDir::new(folder)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_file())
+            .take(max_files.saturating_add(1))
+            .count();
+        if file_count > max_files {
+            bail!(
+                "refusing to delete {folder:?}, since it has more files in it than the .pak has assets, so it doesn't look like an earlier extraction (delete it yourself if it should really go)",
+            );
+        }
+    }
+
+    std::fs::remove_dir_all(folder).ok();
     Ok(())
 }
+
+
+/// Pick an unused path next to `path`, by adding a number to the file
+/// name.
+fn pick_free_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default();
+    let extension = path.extension();
+
+    (1..).map(|i| {
+        let mut name = stem.to_owned();
+        name.push(format!("_{i}"));
+        if let Some(extension) = extension {
+            name.push(".");
+            name.push(extension);
+        }
+        path.with_file_name(name)
+    }).find(|candidate| !candidate.exists()).expect("there should be some unused file name")
+}
+
+
+/// Decide where to write an extracted file if something already exists
+/// at `path`, according to `policy` (which may be changed by the user's
+/// answer, with `ConflictPolicy::Ask`). Returns `None` if the file
+/// should be skipped.
+fn resolve_conflict(path: PathBuf, policy: &mut ConflictPolicy) -> anyhow::Result<Option<PathBuf>> {
+    if !path.exists() {
+        return Ok(Some(path));
+    }
+
+    match *policy {
+        ConflictPolicy::Overwrite => Ok(Some(path)),
+        ConflictPolicy::Skip => Ok(None),
+        ConflictPolicy::Rename => Ok(Some(pick_free_path(&path))),
+        ConflictPolicy::Ask => loop {
+            eprint!("{} already exists. Overwrite? [y]es, [n]o, [r]ename, [A]ll, [N]one: ", path.display());
+            std::io::stderr().flush()?;
+
+            let mut answer = String::new();
+            if std::io::stdin().read_line(&mut answer)? == 0 {
+                bail!("no answer given for {path:?}");
+            }
+
+            match answer.trim() {
+                "y" => return Ok(Some(path)),
+                "n" => return Ok(None),
+                "r" => return Ok(Some(pick_free_path(&path))),
+                "A" => {
+                    *policy = ConflictPolicy::Overwrite;
+                    return Ok(Some(path));
+                },
+                "N" => {
+                    *policy = ConflictPolicy::Skip;
+                    return Ok(None);
+                },
+                _ => {},
+            }
+        },
+    }
+}
+
+
+/// The path that an asset named `name` should be extracted to within
+/// `output_folder`, after checking that it doesn't escape it.
+pub fn asset_output_path(name: &[u8], output_folder: &Path) -> anyhow::Result<PathBuf> {
+    let name_str = std::str::from_utf8(name)?;
+    let asset_path = Path::new(OsStr::new(name_str));
+
+    // https://stackoverflow.com/a/69515135
+    // (absolute paths would escape the output folder, too)
+    if asset_path.components().any(|c| !matches!(c, Component::Normal(_) | Component::CurDir)) {
+        bail!("directory traversal: {asset_path:?}");
+    }
+
+    Ok(output_folder.join(asset_path))
+}
+
+
+/// Check, decrypt and decompress a single asset's stored data (as far
+/// as `raw` allows).
+pub fn decode_asset(asset: &PakAsset, asset_data: Vec<u8>, key: KeyRef, raw: Option<RawForm>) -> anyhow::Result<Vec<u8>> {
+    let size_decompressed = (asset.size_compressed != asset.size_decompressed).then_some(u64::from(asset.size_decompressed));
+    decode_asset_data(
+        &asset.name,
+        asset_data,
+        asset.plaintext_crc32,
+        asset.ciphertext_crc32,
+        size_decompressed,
+        key,
+        raw,
+        &mut Progress::disabled(),
+    )
+}
+
+
+/// The guts of `decode_asset`, taking the asset's fields individually,
+/// and timing each step in `progress`. `size_decompressed` is `None` if
+/// the asset isn't compressed.
+#[allow(clippy::too_many_arguments)]
+fn decode_asset_data(
+    name: &[u8],
+    mut asset_data: Vec<u8>,
+    expected_plaintext_crc32: u32,
+    expected_ciphertext_crc32: u32,
+    size_decompressed: Option<u64>,
+    key: KeyRef,
+    raw: Option<RawForm>,
+    progress: &mut Progress,
+) -> anyhow::Result<Vec<u8>> {
+    check_and_decrypt(
+        name,
+        &mut asset_data,
+        expected_plaintext_crc32,
+        expected_ciphertext_crc32,
+        key,
+        raw != Some(RawForm::Encrypted),
+        progress,
+    )?;
+
+    if raw.is_none()
+        && let Some(size_decompressed) = size_decompressed
+    {
+        asset_data = progress.measure(Phase::Compression, size_decompressed, || decompress_lz4(&asset_data, size_decompressed))?;
+    }
+
+    Ok(asset_data)
+}
+
+
+/// Check a single asset's stored data against its ciphertext CRC32, and
+/// then (if `should_decrypt`) decrypt it in place and check it against
+/// its plaintext CRC32.
+fn check_and_decrypt(
+    name: &[u8],
+    asset_data: &mut [u8],
+    expected_plaintext_crc32: u32,
+    expected_ciphertext_crc32: u32,
+    key: KeyRef,
+    should_decrypt: bool,
+    progress: &mut Progress,
+) -> anyhow::Result<()> {
+    let size = u64::try_from(asset_data.len())?;
+
+    let ciphertext_crc32 = progress.measure(Phase::Read, 0, || Ok(crc32fast::hash(asset_data)))?;
+    if ciphertext_crc32 != expected_ciphertext_crc32 {
+        bail!("ciphertext CRC32 mismatch (expected {expected_ciphertext_crc32:#010x}, got {ciphertext_crc32:#010x})");
+    }
+
+    if should_decrypt {
+        let plaintext_crc32 = progress.measure(Phase::Crypt, size, || {
+            decrypt(name, key, asset_data);
+            Ok(crc32fast::hash(asset_data))
+        })?;
+        if plaintext_crc32 != expected_plaintext_crc32 {
+            return Err(DecryptionError {
+                expected_crc32: expected_plaintext_crc32,
+                actual_crc32: plaintext_crc32,
+                key_fingerprint: fingerprint(key),
+            }.into());
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Assets stored uncompressed that are at least this big are extracted
+/// by `extract_asset_mapped()`, where saving a copy of the data is worth
+/// setting up a memory mapping for.
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+const MAPPED_EXTRACT_THRESHOLD: u32 = 1024 * 1024;
+
+
+/// Unmaps a memory mapping when dropped.
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+struct Mapping {
+    ptr: *mut std::ffi::c_void,
+    len: usize,
+}
+
+
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: `ptr` and `len` describe a mapping made by `mmap()`,
+        // which nothing refers to any more.
+        let _ = unsafe { rustix::mm::munmap(self.ptr, self.len) };
+    }
+}
+
+
+/// Like `extract_asset`, for an asset that's stored uncompressed and
+/// going to a new file (so there's nothing to lose if it turns out to
+/// be bad): the ciphertext is read straight into a memory mapping of the
+/// output file and decrypted there, skipping a copy of the whole asset
+/// through a separate buffer. The file is deleted if the asset doesn't
+/// check out. Returns the CRC32 of the data written.
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+fn extract_asset_mapped<R: Read + Seek>(
+    reader: &mut R,
+    asset: &PakAsset,
+    data_start_offset: u64,
+    output_path: &Path,
+    key: KeyRef,
+    permissions: Permissions,
+    progress: &mut Progress,
+) -> anyhow::Result<u32> {
+    let Some(output_subfolder) = output_path.parent() else {
+        bail!("output file {output_path:?} has no clear parent");
+    };
+    std::fs::create_dir_all(output_subfolder)?;
+
+    let file = File::options().read(true).write(true).create_new(true).open(output_path)?;
+    let result: anyhow::Result<u32> = (|| {
+        let len = usize::try_from(asset.size_compressed)?;
+        crate::shared::preallocate(&file, u64::from(asset.size_compressed))?;
+
+        // SAFETY: the file was just created, so nothing else has it
+        // mapped or open, and its length (set above) covers the whole
+        // mapping. The mapping is only accessed through `data`, which
+        // doesn't outlive it.
+        let mapping = Mapping {
+            ptr: unsafe {
+                rustix::mm::mmap(
+                    std::ptr::null_mut(),
+                    len,
+                    rustix::mm::ProtFlags::READ | rustix::mm::ProtFlags::WRITE,
+                    rustix::mm::MapFlags::SHARED,
+                    &file,
+                    0,
+                )?
+            },
+            len,
+        };
+        // SAFETY: (as above) the mapping is `len` bytes of readable and
+        // writable memory that nothing else refers to.
+        let data = unsafe { std::slice::from_raw_parts_mut(mapping.ptr.cast::<u8>(), mapping.len) };
+
+        progress.measure(Phase::Read, len.try_into()?, || {
+            reader.seek(SeekFrom::Start(data_start_offset + u64::from(asset.offset)))?;
+            reader.read_exact(data)?;
+            Ok(())
+        })?;
+        check_and_decrypt(&asset.name, data, asset.plaintext_crc32, asset.ciphertext_crc32, key, true, progress)?;
+        Ok(asset.plaintext_crc32)
+    })();
+    drop(file);
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(output_path);
+    } else {
+        permissions.apply_to_file(output_path)?;
+    }
+    result
+}
+
+
+/// Read, check, decrypt and decompress a single asset (as far as `raw`
+/// allows), and write it to `output_path`. Returns the CRC32 of the
+/// data written, or `None` if it was skipped because of a conflict with
+/// an existing file.
+#[allow(clippy::too_many_arguments)]
+fn extract_asset<R: Read + Seek>(
+    reader: &mut R,
+    asset: &PakAsset,
+    data_start_offset: u64,
+    output_path: PathBuf,
+    key: KeyRef,
+    raw: Option<RawForm>,
+    permissions: Permissions,
+    on_conflict: &mut ConflictPolicy,
+    progress: &mut Progress,
+) -> anyhow::Result<Option<u32>> {
+    if is_empty_folder_placeholder(&asset.name, asset.size_decompressed.into()) {
+        if let Some(folder) = output_path.parent() {
+            std::fs::create_dir_all(folder)?;
+        }
+        // (the CRC32 of no data)
+        return Ok(Some(0));
+    }
+
+    #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+    if raw.is_none()
+        && asset.size_compressed == asset.size_decompressed
+        && asset.size_compressed >= MAPPED_EXTRACT_THRESHOLD
+        && !output_path.exists()
+    {
+        return extract_asset_mapped(reader, asset, data_start_offset, &output_path, key, permissions, progress).map(Some);
+    }
+
+    let asset_data = progress.measure(Phase::Read, asset.size_compressed.into(), || {
+        reader.seek(SeekFrom::Start(data_start_offset + u64::from(asset.offset)))?;
+        let mut asset_data = vec![0; asset.size_compressed.try_into()?];
+        reader.read_exact(&mut asset_data)?;
+        Ok(asset_data)
+    })?;
+
+    let size_decompressed = (asset.size_compressed != asset.size_decompressed).then_some(u64::from(asset.size_decompressed));
+    let asset_data = decode_asset_data(
+        &asset.name,
+        asset_data,
+        asset.plaintext_crc32,
+        asset.ciphertext_crc32,
+        size_decompressed,
+        key,
+        raw,
+        progress,
+    )?;
+
+    let output_subfolder = output_path.parent();
+    let Some(output_subfolder) = output_subfolder else {
+        bail!("output file {output_path:?} has no clear parent");
+    };
+
+    std::fs::create_dir_all(output_subfolder)?;
+
+    let Some(output_path) = resolve_conflict(output_path, on_conflict)? else {
+        return Ok(None);
+    };
+    progress.measure(Phase::Write, u64::try_from(asset_data.len())?, || Ok(std::fs::write(&output_path, &asset_data)?))?;
+    permissions.apply_to_file(&output_path)?;
+
+    Ok(Some(crc32fast::hash(&asset_data)))
+}
@@ -0,0 +1,108 @@
+use std::path::Path;
+
+use anyhow::bail;
+
+use crate::{
+    flow_unpack::{UnpackOptions, unpack},
+    key::{detect_game, get_key_for_game, get_key_for_pak},
+    shared::{Verbosity, is_empty_folder},
+};
+
+
+/// Find every .pak file in `input_folder` (recursively), e.g. a dump of
+/// a game's romfs, and unpack each one in place: into a folder next to
+/// it, named like it without the ".pak", so that the layout is kept.
+///
+/// Each .pak's key is found in `key_file` with `get_key_for_pak` (or
+/// `get_key_for_game`, if `options.game` is set), so they don't all
+/// need to have the known key. Unless `options.game` is set, each one's
+/// game is detected with `detect_game`, to record in its folder.
+///
+/// A .pak whose folder already exists (and isn't empty) is skipped,
+/// unless `options.force` is set. A .pak that can't be unpacked doesn't
+/// stop the rest, but once they've all been tried, it's an error. Either
+/// way, a summary is printed at the end.
+pub fn unpack_all(
+    input_folder: &Path,
+    key_file: &Path,
+    options: &UnpackOptions,
+    verbosity: Verbosity,
+) -> anyhow::Result<()> {
+    // (all found up front, so that .pak files extracted along the way
+    // aren't unpacked too)
+    let mut paks = Vec::new();
+    for entry in walkdir::WalkDir::new(input_folder).sort_by_file_name() {
+        let entry = entry?;
+        if entry.file_type().is_file() && entry.path().extension().is_some_and(|e| e.eq_ignore_ascii_case("pak")) {
+            paks.push(entry.into_path());
+        }
+    }
+    if paks.is_empty() {
+        bail!("no .pak files found in {}", input_folder.display());
+    }
+
+    // (listing every asset of every .pak would drown out the rest)
+    let pak_verbosity = verbosity.min(Verbosity::Quiet);
+
+    let mut unpacked = 0;
+    let mut skipped = Vec::new();
+    let mut failed = Vec::new();
+    for (i, pak) in paks.iter().enumerate() {
+        let relative_path = pak.strip_prefix(input_folder).unwrap_or(pak);
+        let output_folder = pak.with_extension("");
+        if !options.force && output_folder.exists() && !is_empty_folder(&output_folder) {
+            skipped.push(relative_path);
+            continue;
+        }
+
+        if verbosity >= Verbosity::Normal {
+            println!("[{}/{}] {}", i + 1, paks.len(), relative_path.display());
+        }
+        match unpack_one(pak, &output_folder, key_file, options, pak_verbosity) {
+            Ok(()) => unpacked += 1,
+            Err(e) => failed.push((relative_path, e)),
+        }
+    }
+
+    if verbosity >= Verbosity::Quiet {
+        println!("Unpacked {unpacked} of {} .pak file(s) found in {}", paks.len(), input_folder.display());
+        if !skipped.is_empty() {
+            println!("Skipped {} whose folder already exists (use -f to replace them):", skipped.len());
+            for path in &skipped {
+                println!("  {}", path.display());
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        eprintln!("Couldn't unpack {} .pak file(s):", failed.len());
+        for (path, e) in &failed {
+            eprintln!("  {}: {e:#}", path.display());
+        }
+        bail!("{} of the .pak files could not be unpacked", failed.len());
+    }
+
+    Ok(())
+}
+
+
+/// Unpack a single .pak found by `unpack_all()`, with its own key and
+/// game.
+fn unpack_one(
+    pak: &Path,
+    output_folder: &Path,
+    key_file: &Path,
+    options: &UnpackOptions,
+    verbosity: Verbosity,
+) -> anyhow::Result<()> {
+    let (key, game) = match options.game {
+        Some(game) => (get_key_for_game(key_file, game)?, Some(game)),
+        None => {
+            let key = get_key_for_pak(key_file, pak)?;
+            let game = detect_game(key_file, pak, &key)?;
+            (key, game)
+        },
+    };
+    let options = UnpackOptions {game, ..options.clone()};
+    unpack(pak, output_folder, &key, &options, verbosity)
+}
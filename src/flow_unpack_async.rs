@@ -0,0 +1,195 @@
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::bail;
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::mpsc,
+    task::JoinSet,
+};
+
+use crate::{
+    archive::PakArchive,
+    flow_unpack::{
+        Permissions,
+        UnpackOptions,
+        asset_output_path,
+        check_free_space,
+        decode_asset,
+        remove_previous_extraction,
+    },
+    key::{KeyRef, OwnedKey, fingerprint, is_known},
+    sidecar::{Sidecar, is_empty_folder_placeholder},
+    shared::{PakAsset, Verbosity, format_timestamp, is_empty_folder, thread_count},
+};
+
+
+/// Maximum number of assets that can be read ahead of being processed,
+/// and processed ahead of being written.
+const MAX_IN_FLIGHT: usize = 16;
+
+
+/// Read and unpack a .pak to a specified output folder, like
+/// `flow_unpack::unpack()`, but with reading, decoding and writing
+/// overlapped instead of done strictly one after the other.
+///
+/// Only the basic options (`force` and `keep_going`) are supported.
+pub fn unpack(
+    input_file: &Path,
+    output_folder: &Path,
+    key: KeyRef,
+    options: &UnpackOptions,
+    verbosity: Verbosity,
+) -> anyhow::Result<()> {
+    if options.order_file.is_some() || options.raw.is_some() || options.on_conflict.is_some() || options.no_clobber || options.report.is_some() || options.flatten
+        || options.permissions != Permissions::default() || options.progress || options.recursive
+        || options.filter.is_some()
+    {
+        bail!("--order-file, --raw, --on-conflict, --no-clobber, --report, --flatten, --chmod, --no-executable, --progress, --recursive, --min-asset-size and --max-asset-size aren't supported with --async-io");
+    }
+
+    let replace_output_folder = output_folder.is_dir() && !is_empty_folder(output_folder);
+    if replace_output_folder && !options.force {
+        bail!("output directory exists (use -f to force)");
+    }
+
+    let archive = PakArchive::open(input_file, key)?;
+
+    if replace_output_folder {
+        remove_previous_extraction(output_folder, input_file, archive.asset_count())?;
+    }
+
+    if verbosity >= Verbosity::Normal {
+        println!("PAK file created {}", format_timestamp(archive.header.timestamp, options.timezone)?);
+    }
+
+    let assets: Vec<(PakAsset, u64)> = archive.assets()
+        .map(|asset| asset.map(|asset| {
+            let offset = archive.asset_offset(&asset);
+            (asset, offset)
+        }))
+        .collect::<anyhow::Result<_>>()?;
+
+    if !options.no_space_check {
+        check_free_space(output_folder, assets.iter().map(|(asset, _)| u64::from(asset.size_decompressed)).sum())?;
+    }
+
+    // (decoding and file I/O run on the blocking threads, so those are
+    // limited too, not just the workers)
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(thread_count())
+        .max_blocking_threads(thread_count())
+        .enable_all()
+        .build()?;
+    let skipped = runtime.block_on(unpack_assets(
+        input_file.to_path_buf(),
+        output_folder.to_path_buf(),
+        Box::new(*key),
+        assets,
+        options.keep_going,
+        verbosity,
+    ))?;
+
+    let key_fingerprint = (!is_known(key)).then(|| fingerprint(key));
+    if options.game.is_some() || key_fingerprint.is_some() {
+        Sidecar::record_source(output_folder, options.game, key_fingerprint)?;
+    }
+
+    if !skipped.is_empty() {
+        eprintln!("Skipped {} asset(s):", skipped.len());
+        for (name, e) in &skipped {
+            eprintln!("  {name}: {e:#}");
+        }
+        bail!("{} of the assets could not be extracted", skipped.len());
+    }
+
+    Ok(())
+}
+
+
+/// Read every asset in one task, and decode and write each of them in
+/// tasks of their own. Returns the assets that failed, if `keep_going`.
+async fn unpack_assets(
+    input_file: PathBuf,
+    output_folder: PathBuf,
+    key: OwnedKey,
+    assets: Vec<(PakAsset, u64)>,
+    keep_going: bool,
+    verbosity: Verbosity,
+) -> anyhow::Result<Vec<(String, anyhow::Error)>> {
+    let (sender, mut receiver) = mpsc::channel(MAX_IN_FLIGHT);
+
+    let reader = tokio::spawn(async move {
+        let mut file = tokio::fs::File::open(&input_file).await?;
+        for (asset, offset) in assets {
+            file.seek(SeekFrom::Start(offset)).await?;
+            let mut data = vec![0; asset.size_compressed.try_into()?];
+            file.read_exact(&mut data).await?;
+            if sender.send((asset, data)).await.is_err() {
+                // The receiving end gave up
+                break;
+            }
+        }
+        anyhow::Ok(())
+    });
+
+    let key = Arc::new(key);
+    let mut tasks = JoinSet::new();
+    let mut skipped = Vec::new();
+
+    let mut handle_result = |(name, result): (String, anyhow::Result<()>)| {
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) if keep_going => {
+                skipped.push((name, e));
+                Ok(())
+            },
+            Err(e) => Err(e.context(format!("couldn't extract {name}"))),
+        }
+    };
+
+    while let Some((asset, data)) = receiver.recv().await {
+        let name = String::from_utf8_lossy(&asset.name).into_owned();
+        if verbosity >= Verbosity::Normal {
+            println!("{name}");
+        }
+
+        let key = Arc::clone(&key);
+        let output_folder = output_folder.clone();
+        tasks.spawn(async move {
+            let result = async {
+                let output_path = asset_output_path(&asset.name, &output_folder)?;
+                if is_empty_folder_placeholder(&asset.name, asset.size_decompressed.into()) {
+                    if let Some(folder) = output_path.parent() {
+                        tokio::fs::create_dir_all(folder).await?;
+                    }
+                    return anyhow::Ok(());
+                }
+                let data = tokio::task::spawn_blocking(move || decode_asset(&asset, data, &key, None)).await??;
+                if let Some(parent) = output_path.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                tokio::fs::write(output_path, data).await?;
+                anyhow::Ok(())
+            }.await;
+            (name, result)
+        });
+
+        while tasks.len() >= MAX_IN_FLIGHT {
+            if let Some(joined) = tasks.join_next().await {
+                handle_result(joined?)?;
+            }
+        }
+    }
+
+    while let Some(joined) = tasks.join_next().await {
+        handle_result(joined?)?;
+    }
+
+    reader.await??;
+
+    Ok(skipped)
+}
@@ -0,0 +1,452 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail};
+
+use crate::{
+    archive::PakArchive,
+    flow_info::print_plaintext_summary,
+    key::KeyRef,
+    report::{AssetAction, Report, ReportAsset},
+    shared::{
+        ASSETS_LIST_NAME_HASH,
+        FILE_VERSION,
+        PAK_CRC32_START_OFFSET,
+        PAK_HEADER_SIZE,
+        PakAsset,
+        Verbosity,
+        calc_pak_crc32_file,
+        uncovered_ranges,
+    },
+};
+
+
+/// Check that a .pak file is intact: that its whole-file CRC32 matches,
+/// that its layout makes sense (see `check_layout()`), and that the
+/// assets list and every asset match their ciphertext CRC32s.
+/// (Decrypting and decompressing every asset isn't needed for this,
+/// since the ciphertext CRC32s cover everything as stored.)
+///
+/// Every problem found is listed in the error. If the whole-file CRC32
+/// doesn't match, the byte ranges responsible are pointed out, as far
+/// as the other checks can tell. If there are no problems, the assets
+/// that are stored partly or entirely unencrypted are summarized, as in
+/// `info`.
+///
+/// With `report_file`, a report of each asset's check is written there.
+pub fn verify(input_file: &Path, key: KeyRef, report_file: Option<&Path>, verbosity: Verbosity) -> anyhow::Result<()> {
+    let mut report = Report::for_input("verify", input_file, verbosity);
+    let result = verify_with_report(input_file, key, verbosity, &mut report);
+    if let Some(report_file) = report_file {
+        report.save(report_file, &result)?;
+    }
+    result
+}
+
+
+/// `verify()`, recording each asset's check in `report`.
+fn verify_with_report(input_file: &Path, key: KeyRef, verbosity: Verbosity, report: &mut Report) -> anyhow::Result<()> {
+    let mut archive = PakArchive::open(input_file, key)?;
+    let mut problems = Vec::new();
+
+    if !archive.encrypted {
+        bail!("this .pak is decrypted, so its CRC32s can't be checked (the game can't load it, either)");
+    }
+
+    let file_size = std::fs::metadata(input_file)?.len();
+    let data_start = archive.data_start_offset();
+
+    // Byte ranges that fail their own checks, to blame if the
+    // whole-file CRC32 doesn't match
+    let mut bad_ranges = Vec::new();
+
+    let assets_list_size = archive.header.assets_list_size_compressed;
+    let (field_1c, assets_list_data) = {
+        let reader = archive.reader_mut();
+        let mut field_1c = [0; 4];
+        reader.seek(SeekFrom::Start(0x1c))?;
+        reader.read_exact(&mut field_1c)?;
+        let mut assets_list_data = vec![0; assets_list_size.try_into()?];
+        reader.seek(SeekFrom::Start(PAK_HEADER_SIZE as u64))?;
+        reader.read_exact(&mut assets_list_data)?;
+        (u32::from_le_bytes(field_1c), assets_list_data)
+    };
+
+    if field_1c != ASSETS_LIST_NAME_HASH ^ assets_list_size {
+        problems.push(format!("header field 0x1c doesn't match the assets list size ({field_1c:#010x})"));
+        bad_ranges.push(format!("{PAK_CRC32_START_OFFSET:#x}..{PAK_HEADER_SIZE:#x} (header)"));
+    }
+
+    let crc32 = crc32fast::hash(&assets_list_data);
+    if crc32 != archive.header.ciphertext_crc32 {
+        problems.push(format!(
+            "assets list: ciphertext CRC32 mismatch (expected {:#010x}, got {crc32:#010x})",
+            archive.header.ciphertext_crc32,
+        ));
+        bad_ranges.push(format!("{PAK_HEADER_SIZE:#x}..{data_start:#x} (assets list)"));
+    }
+
+    let assets = archive.assets().collect::<anyhow::Result<Vec<_>>>()?;
+    problems.extend(check_layout(&assets, archive.header.assets_list_size_decompressed, data_start, file_size));
+
+    let mut spans = Vec::new();
+    for asset in &assets {
+        let name = String::from_utf8_lossy(&asset.name);
+        let start = archive.asset_offset(asset);
+        let end = start + u64::from(asset.size_compressed);
+        if end > file_size {
+            // (already reported by `check_layout()`)
+            let e = anyhow!("{start:#x}..{end:#x} extends past the end of the file ({file_size:#x})");
+            report.assets.push(ReportAsset::new(asset, AssetAction::Failed).with_error(&e));
+            continue;
+        }
+        match archive.read_asset_raw(asset) {
+            Ok(data) => {
+                spans.push((start, end));
+
+                let crc32 = crc32fast::hash(&data);
+                if crc32 == asset.ciphertext_crc32 {
+                    report.assets.push(ReportAsset::new(asset, AssetAction::Verified));
+                } else {
+                    let e = anyhow!("ciphertext CRC32 mismatch (expected {:#010x}, got {crc32:#010x})", asset.ciphertext_crc32);
+                    problems.push(format!("{name}: {e:#}"));
+                    report.assets.push(ReportAsset::new(asset, AssetAction::Failed).with_error(&e));
+                    bad_ranges.push(format!("{start:#x}..{end:#x} ({name})"));
+                }
+            },
+            Err(e) => {
+                problems.push(format!("{name}: {e:#}"));
+                report.assets.push(ReportAsset::new(asset, AssetAction::Failed).with_error(&e));
+            },
+        }
+    }
+
+    let crc32 = calc_pak_crc32_file(input_file)?;
+    if crc32 != archive.header.crc32 {
+        let mut problem = format!(
+            "whole-file CRC32 mismatch (expected {:#010x}, got {crc32:#010x})",
+            archive.header.crc32,
+        );
+        if !bad_ranges.is_empty() {
+            problem += &format!(", caused by {}", bad_ranges.join(", "));
+        } else {
+            let gaps = uncovered_ranges(data_start, file_size, spans);
+            if gaps.is_empty() {
+                problem += ", but every part of the file matches its own checks, so the stored whole-file CRC32 is probably wrong";
+            } else {
+                let gaps = gaps.iter().map(|(start, end)| format!("{start:#x}..{end:#x}")).collect::<Vec<_>>();
+                problem += &format!(
+                    ", but every part of the file matches its own checks, so either the stored whole-file CRC32 is wrong or bytes outside of any asset differ: {}",
+                    gaps.join(", "),
+                );
+            }
+        }
+        problems.insert(0, problem);
+    }
+
+    if !problems.is_empty() {
+        bail!(
+            "found {} problem(s) in {}:\n{}",
+            problems.len(),
+            input_file.display(),
+            problems.iter().map(|p| format!("  {p}")).collect::<Vec<_>>().join("\n"),
+        );
+    }
+
+    if verbosity >= Verbosity::Normal {
+        println!("{}: OK ({} assets)", input_file.display(), assets.len());
+        print_plaintext_summary(&assets)?;
+    }
+
+    Ok(())
+}
+
+
+/// Size of the buffer that `verify_fast()` reads asset data through.
+const FAST_VERIFY_BUFFER_SIZE: usize = 1024 * 1024;
+
+
+/// Check just the ciphertext CRC32s of the assets list and every asset
+/// against the bytes on disk, without decrypting or decompressing any
+/// asset, checking the layout, or calculating the whole-file CRC32.
+///
+/// The assets list still has to be decrypted to find the assets, but
+/// apart from that, this reads each byte of asset data once, in file
+/// order, so it runs at about the speed of the disk. Unencrypted assets
+/// are summarized, and `report_file` written, the same way as by
+/// `verify()`.
+pub fn verify_fast(input_file: &Path, key: KeyRef, report_file: Option<&Path>, verbosity: Verbosity) -> anyhow::Result<()> {
+    let mut report = Report::for_input("verify", input_file, verbosity);
+    let result = verify_fast_with_report(input_file, key, verbosity, &mut report);
+    if let Some(report_file) = report_file {
+        report.save(report_file, &result)?;
+    }
+    result
+}
+
+
+/// `verify_fast()`, recording each asset's check in `report`.
+fn verify_fast_with_report(input_file: &Path, key: KeyRef, verbosity: Verbosity, report: &mut Report) -> anyhow::Result<()> {
+    let mut archive = PakArchive::open(input_file, key)?;
+    let mut problems = Vec::new();
+
+    if !archive.encrypted {
+        bail!("this .pak is decrypted, so its CRC32s can't be checked (the game can't load it, either)");
+    }
+
+    let file_size = std::fs::metadata(input_file)?.len();
+    let data_start = archive.data_start_offset();
+    let mut buffer = vec![0; FAST_VERIFY_BUFFER_SIZE];
+
+    let crc32 = hash_range(archive.reader_mut(), PAK_HEADER_SIZE as u64, data_start - PAK_HEADER_SIZE as u64, &mut buffer)?;
+    if crc32 != archive.header.ciphertext_crc32 {
+        problems.push(format!(
+            "assets list: ciphertext CRC32 mismatch (expected {:#010x}, got {crc32:#010x})",
+            archive.header.ciphertext_crc32,
+        ));
+    }
+
+    let mut assets = archive.assets().collect::<anyhow::Result<Vec<_>>>()?;
+    assets.sort_by_key(|asset| asset.offset);
+
+    for asset in &assets {
+        let name = String::from_utf8_lossy(&asset.name);
+        let start = archive.asset_offset(asset);
+        let end = start + u64::from(asset.size_compressed);
+        if end > file_size {
+            let e = anyhow!("{start:#x}..{end:#x} extends past the end of the file ({file_size:#x})");
+            problems.push(format!("{name}: {e:#}"));
+            report.assets.push(ReportAsset::new(asset, AssetAction::Failed).with_error(&e));
+            continue;
+        }
+
+        let crc32 = hash_range(archive.reader_mut(), start, u64::from(asset.size_compressed), &mut buffer)?;
+        if crc32 == asset.ciphertext_crc32 {
+            report.assets.push(ReportAsset::new(asset, AssetAction::Verified));
+        } else {
+            let e = anyhow!("ciphertext CRC32 mismatch (expected {:#010x}, got {crc32:#010x})", asset.ciphertext_crc32);
+            problems.push(format!("{name}: {e:#}"));
+            report.assets.push(ReportAsset::new(asset, AssetAction::Failed).with_error(&e));
+        }
+    }
+
+    if !problems.is_empty() {
+        bail!(
+            "found {} problem(s) in {}:\n{}",
+            problems.len(),
+            input_file.display(),
+            problems.iter().map(|p| format!("  {p}")).collect::<Vec<_>>().join("\n"),
+        );
+    }
+
+    if verbosity >= Verbosity::Normal {
+        println!("{}: OK ({} assets, ciphertext CRC32s only)", input_file.display(), assets.len());
+        print_plaintext_summary(&assets)?;
+    }
+
+    Ok(())
+}
+
+
+/// Calculate the CRC32 of `len` bytes of `reader` starting at `start`,
+/// reading them through `buffer`.
+fn hash_range<R: Read + Seek>(reader: &mut R, start: u64, len: u64, buffer: &mut [u8]) -> anyhow::Result<u32> {
+    reader.seek(SeekFrom::Start(start))?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut remaining = len;
+    while remaining > 0 {
+        let chunk_len = usize::try_from(remaining).map_or(buffer.len(), |remaining| remaining.min(buffer.len()));
+        reader.read_exact(&mut buffer[..chunk_len])?;
+        hasher.update(&buffer[..chunk_len]);
+        remaining -= u64::try_from(chunk_len)?;
+    }
+    Ok(hasher.finalize())
+}
+
+
+/// Check the layout of a .pak's assets (given the absolute offset of
+/// the asset data and the file size), returning a description of each
+/// problem found:
+///
+/// - assets that extend past the end of the file
+/// - assets whose data overlaps another's
+/// - assets stored at a lower offset than the one before them in the
+///   assets list
+/// - an assets-list size (decompressed) that disagrees with how much
+///   space its entries actually take up
+///
+/// The game might cope with some of these, but no .pak built the way
+/// the original ones were has any of them, so they're a sign of a buggy
+/// .pak builder.
+fn check_layout(assets: &[PakAsset], assets_list_size: u32, data_start: u64, file_size: u64) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    // (a u32 count, then for each entry, a u32 name length, the name and
+    // 0x1c bytes of fields)
+    let entries_size = 4 + assets.iter().map(|asset| 4 + asset.name.len() as u64 + 0x1c).sum::<u64>();
+    if entries_size != u64::from(assets_list_size) {
+        problems.push(format!(
+            "assets list size is {assets_list_size:#x} bytes (decompressed), but its {} entries take up {entries_size:#x}",
+            assets.len(),
+        ));
+    }
+
+    let mut spans = Vec::new();
+    let mut previous: Option<&PakAsset> = None;
+    for asset in assets {
+        let name = String::from_utf8_lossy(&asset.name);
+        let start = data_start + u64::from(asset.offset);
+        let end = start + u64::from(asset.size_compressed);
+
+        if end > file_size {
+            problems.push(format!("{name}: {start:#x}..{end:#x} extends past the end of the file ({file_size:#x})"));
+        }
+
+        if let Some(previous) = previous
+            && asset.offset < previous.offset
+        {
+            problems.push(format!(
+                "{name}: stored at offset {:#x}, before the previous asset in the list ({}, at {:#x})",
+                asset.offset,
+                String::from_utf8_lossy(&previous.name),
+                previous.offset,
+            ));
+        }
+        previous = Some(asset);
+
+        if start < end {
+            spans.push((start, end, name));
+        }
+    }
+
+    spans.sort_unstable_by_key(|(start, end, _)| (*start, *end));
+    let mut furthest: Option<&(u64, u64, _)> = None;
+    for span in &spans {
+        if let Some(other) = furthest
+            && span.0 < other.1
+        {
+            problems.push(format!(
+                "{}: {:#x}..{:#x} overlaps {} ({:#x}..{:#x})",
+                span.2, span.0, span.1, other.2, other.0, other.1,
+            ));
+        }
+        if furthest.is_none_or(|other| span.1 > other.1) {
+            furthest = Some(span);
+        }
+    }
+
+    problems
+}
+
+
+/// Perform just the checks that the game's .pak loader is known to
+/// perform (magic, version, whole-file JAMCRC32 and header field 0x1c),
+/// printing whether each one passes, to predict whether the game will
+/// reject a .pak.
+///
+/// This reads the raw header bytes, so it works on any file, however
+/// broken. Passing doesn't guarantee that every asset is intact, since
+/// the game only checks those when it loads them.
+pub fn verify_as_game(input_file: &Path, verbosity: Verbosity) -> anyhow::Result<()> {
+    let mut header_bytes = Vec::with_capacity(PAK_HEADER_SIZE);
+    File::open(input_file)?.take(PAK_HEADER_SIZE as u64).read_to_end(&mut header_bytes)?;
+    let u32_at = |offset: usize| header_bytes.get(offset..offset + 4).map(|b| u32::from_le_bytes(b.try_into().unwrap()));
+
+    let mut checks = Vec::new();
+
+    let magic = header_bytes.get(..4);
+    checks.push(("magic", if magic == Some(b"KCAP".as_slice()) {
+        Ok(())
+    } else {
+        Err(format!("expected \"KCAP\", found {:?}", String::from_utf8_lossy(magic.unwrap_or(&header_bytes))))
+    }));
+
+    checks.push(("version", match u32_at(0x04) {
+        Some(FILE_VERSION) => Ok(()),
+        Some(version) => Err(format!("expected {FILE_VERSION}, found {version}")),
+        None => Err("file is too short".to_owned()),
+    }));
+
+    checks.push(("whole-file JAMCRC32", match u32_at(0x08) {
+        Some(stored) => {
+            let calculated = calc_pak_crc32_file(input_file)?;
+            if stored == calculated {
+                Ok(())
+            } else {
+                Err(format!("stored {stored:#010x}, calculated {calculated:#010x} (seeded with the file size)"))
+            }
+        },
+        None => Err("file is too short".to_owned()),
+    }));
+
+    checks.push(("field 0x1c", match (u32_at(0x18), u32_at(0x1c)) {
+        (Some(size), Some(field_1c)) => {
+            let expected = ASSETS_LIST_NAME_HASH ^ size;
+            if field_1c == expected {
+                Ok(())
+            } else {
+                Err(format!("expected {expected:#010x} (djb2a(\"header\") ^ assets list size), found {field_1c:#010x}"))
+            }
+        },
+        _ => Err("file is too short".to_owned()),
+    }));
+
+    let failed = checks.iter().filter(|(_, result)| result.is_err()).count();
+    for (name, result) in &checks {
+        match result {
+            Ok(()) if verbosity >= Verbosity::Normal => println!("PASS  {name}"),
+            Ok(()) => {},
+            Err(e) => println!("FAIL  {name}: {e}"),
+        }
+    }
+
+    if failed > 0 {
+        bail!("{failed} of the game's {} checks would fail, so it would reject this .pak", checks.len());
+    }
+
+    if verbosity >= Verbosity::Normal {
+        println!("The game's known checks all pass (individual assets are only checked when they're loaded)");
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn asset(name: &str, offset: u32, size: u32) -> PakAsset {
+        PakAsset {
+            name: name.as_bytes().to_vec(),
+            size_decompressed: size,
+            size_compressed: size,
+            offset,
+            plaintext_crc32: 0,
+            ciphertext_crc32: 0,
+        }
+    }
+
+    #[test]
+    fn test_check_layout() {
+        // 4 + (4 + 1 + 0x1c) * 3
+        let list_size = 0x67;
+
+        let assets = [asset("a", 0, 0x10), asset("b", 0x10, 0x20), asset("c", 0x30, 0)];
+        assert_eq!(check_layout(&assets, list_size, 0x100, 0x140), Vec::<String>::new());
+
+        let problems = check_layout(&assets[..2], 0x46 + 1, 0x100, 0x120);
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].starts_with("assets list size is 0x47 bytes"));
+        assert!(problems[1].starts_with("b: 0x110..0x130 extends past the end"));
+
+        let assets = [asset("a", 0x10, 0x20), asset("b", 0, 0x18), asset("c", 0x30, 0x10)];
+        let problems = check_layout(&assets, list_size, 0x100, 0x140);
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].starts_with("b: stored at offset 0x0, before the previous asset in the list (a"));
+        assert!(problems[1].starts_with("a: 0x110..0x130 overlaps b (0x100..0x118)"));
+    }
+}
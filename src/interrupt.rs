@@ -0,0 +1,85 @@
+//! Ctrl-C handling, so that packing and unpacking can stop between
+//! assets and leave their output in a known state, instead of being
+//! killed halfway through writing it.
+//!
+//! The handler is installed the first time something starts watching
+//! for Ctrl-C. While nothing is, or on a second Ctrl-C, it exits right
+//! away, the same as without it.
+
+use std::{
+    fmt,
+    sync::{
+        Once,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+};
+
+
+/// Exit code for being interrupted by Ctrl-C (128 + SIGINT), the same as
+/// what shells report for it.
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+
+static INSTALL_HANDLER: Once = Once::new();
+static WATCHERS: AtomicUsize = AtomicUsize::new(0);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+
+/// The error for an operation that stopped because of Ctrl-C.
+#[derive(Debug)]
+pub struct Interrupted;
+
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "interrupted")
+    }
+}
+
+
+impl std::error::Error for Interrupted {}
+
+
+/// While this is alive, Ctrl-C is left for `check()` to notice instead
+/// of exiting. Watches can be nested.
+pub struct Watch {
+    _private: (),
+}
+
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        WATCHERS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+
+/// Start watching for Ctrl-C.
+pub fn watch() -> Watch {
+    INSTALL_HANDLER.call_once(|| {
+        // (this fails if a program using the library already has its own
+        // handler, and then that's left to deal with Ctrl-C)
+        let _ = ctrlc::set_handler(|| {
+            if WATCHERS.load(Ordering::SeqCst) == 0 || INTERRUPTED.swap(true, Ordering::SeqCst) {
+                std::process::exit(INTERRUPTED_EXIT_CODE);
+            }
+            eprintln!("\nInterrupted, stopping after the current asset (press Ctrl-C again to stop right away)");
+        });
+    });
+
+    if WATCHERS.fetch_add(1, Ordering::SeqCst) == 0 {
+        INTERRUPTED.store(false, Ordering::SeqCst);
+    }
+    Watch {_private: ()}
+}
+
+
+/// Fail with `Interrupted` if Ctrl-C has been pressed since watching
+/// started.
+pub fn check() -> Result<(), Interrupted> {
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        Err(Interrupted)
+    } else {
+        Ok(())
+    }
+}
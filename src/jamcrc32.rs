@@ -21,6 +21,14 @@ impl Jamcrc32Hasher {
         self.wrapped.update(buf);
     }
 
+    /// Wrapper around [`crc32fast::Hasher::combine`]: continue the hash
+    /// as if it had also been updated with the bytes `other` was
+    /// updated with. `other` is a plain CRC32 hasher, created with
+    /// [`crc32fast::Hasher::new`].
+    pub fn combine(&mut self, other: &crc32fast::Hasher) {
+        self.wrapped.combine(other);
+    }
+
     /// Wrapper around [`crc32fast::Hasher::finalize`].
     pub fn finalize(self) -> u32 {
         // Note the bitflip here
@@ -45,4 +53,14 @@ mod tests {
         assert_jamcrc32(b"123456789", 0xffff_ffff, 0x340bc6d9);
         assert_jamcrc32(b"123456789", 0x1234, 0x60be8a00);
     }
+
+    #[test]
+    fn test_jamcrc32_combine() {
+        let mut hasher = Jamcrc32Hasher::new_with_initial(0x1234);
+        hasher.update(b"1234");
+        let mut rest = crc32fast::Hasher::new();
+        rest.update(b"56789");
+        hasher.combine(&rest);
+        assert_eq!(hasher.finalize(), 0x60be8a00);
+    }
 }
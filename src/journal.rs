@@ -0,0 +1,199 @@
+//! The journal that packing keeps next to the output file, recording
+//! the assets that have been written so far, so that an interrupted pack
+//! can be resumed (with `--resume`) instead of started over.
+//!
+//! It's a JSON Lines file: a header line identifying the pack (by a
+//! checksum of everything that affects the asset data, so that a
+//! journal left by a different pack is never used), then a line for
+//! each finished asset, in order. Assets are only recorded once their
+//! data has been flushed to the output file. If packling was killed
+//! while writing the journal, its last line may be cut off, so a line
+//! that can't be parsed ends it.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+
+/// Version of the journal format.
+const JOURNAL_VERSION: u32 = 1;
+
+
+/// How much asset data to write between updates of the journal. Each
+/// update flushes the output file, so doing it after every asset would
+/// undo the write buffering for runs of small ones.
+const JOURNAL_INTERVAL: u64 = 16 * 1024 * 1024;
+
+
+/// The first line of a journal.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JournalHeader {
+    version: u32,
+    /// Checksum identifying the pack.
+    plan: u32,
+}
+
+
+/// An asset that had been written to the output file, in a journal.
+/// (Its name isn't needed, since the assets are always written in the
+/// same order for the same pack.)
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JournalEntry {
+    pub size_decompressed: u64,
+    pub size_compressed: u64,
+    /// Offset from the start of the asset data.
+    pub offset: u64,
+    pub plaintext_crc32: u32,
+    pub ciphertext_crc32: u32,
+    /// CRC32 of all of the asset data, up to the end of this asset.
+    pub data_crc32: u32,
+}
+
+
+impl JournalEntry {
+    /// Offset of the end of the asset from the start of the asset data.
+    pub fn end(&self) -> u64 {
+        self.offset + self.size_compressed
+    }
+}
+
+
+/// Path of the journal for an output file.
+pub fn journal_path(output_file: &Path) -> PathBuf {
+    let mut name = output_file.file_name().unwrap_or_default().to_owned();
+    name.push(".packling-journal");
+    output_file.with_file_name(name)
+}
+
+
+/// Load the assets recorded in the journal next to `output_file`, if
+/// there is one, and it's for the pack identified by `plan`.
+pub fn load(output_file: &Path, plan: u32) -> anyhow::Result<Option<Vec<JournalEntry>>> {
+    let file = match File::open(journal_path(output_file)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut lines = BufReader::new(file).lines();
+    let Some(header) = lines.next() else {
+        return Ok(None);
+    };
+    match serde_json::from_str::<JournalHeader>(&header?) {
+        Ok(header) if header.version == JOURNAL_VERSION && header.plan == plan => {},
+        _ => return Ok(None),
+    }
+
+    let mut entries = Vec::new();
+    for line in lines {
+        let Ok(entry) = serde_json::from_str(&line?) else {
+            break;
+        };
+        entries.push(entry);
+    }
+    Ok(Some(entries))
+}
+
+
+/// A journal that's being written.
+pub struct PackJournal {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    pending: Vec<JournalEntry>,
+    pending_bytes: u64,
+}
+
+
+impl PackJournal {
+    /// Start the journal for `output_file` (replacing any old one), with
+    /// the assets in `done` already written.
+    pub fn create(output_file: &Path, plan: u32, done: &[JournalEntry]) -> anyhow::Result<Self> {
+        let path = journal_path(output_file);
+        let mut writer = BufWriter::new(File::create(&path)?);
+        serde_json::to_writer(&mut writer, &JournalHeader {version: JOURNAL_VERSION, plan})?;
+        writeln!(writer)?;
+        for entry in done {
+            serde_json::to_writer(&mut writer, entry)?;
+            writeln!(writer)?;
+        }
+        writer.flush()?;
+
+        Ok(Self {path, writer, pending: Vec::new(), pending_bytes: 0})
+    }
+
+    /// Record an asset that's been written (but maybe not flushed yet).
+    /// Returns whether enough have been recorded that it's time to flush
+    /// the output file and `save()`.
+    pub fn record(&mut self, entry: JournalEntry) -> bool {
+        self.pending_bytes += entry.size_compressed;
+        self.pending.push(entry);
+        self.pending_bytes >= JOURNAL_INTERVAL
+    }
+
+    /// Save the assets recorded since the last save. The output file has
+    /// to be flushed first.
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        for entry in self.pending.drain(..) {
+            serde_json::to_writer(&mut self.writer, &entry)?;
+            writeln!(self.writer)?;
+        }
+        self.writer.flush()?;
+        self.pending_bytes = 0;
+        Ok(())
+    }
+
+    /// Delete the journal, once the pack is complete (or the output file
+    /// has been deleted).
+    pub fn remove(self) -> anyhow::Result<()> {
+        drop(self.writer);
+        std::fs::remove_file(&self.path)?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(offset: u64, size: u64) -> JournalEntry {
+        JournalEntry {
+            size_decompressed: size,
+            size_compressed: size,
+            offset,
+            plaintext_crc32: 1,
+            ciphertext_crc32: 2,
+            data_crc32: 3,
+        }
+    }
+
+    #[test]
+    fn test_journal() {
+        let temp = tempfile::tempdir().unwrap();
+        let output_file = temp.path().join("test.pak");
+        assert!(load(&output_file, 1).unwrap().is_none());
+
+        let mut journal = PackJournal::create(&output_file, 1, &[entry(0, 0x10)]).unwrap();
+        assert!(!journal.record(entry(0x10, 0x20)));
+        assert_eq!(load(&output_file, 1).unwrap().unwrap().len(), 1);
+        journal.save().unwrap();
+        assert_eq!(load(&output_file, 1).unwrap().unwrap()[1].end(), 0x30);
+
+        // Only for the same pack
+        assert!(load(&output_file, 2).unwrap().is_none());
+
+        // A cut-off last line is ignored
+        let mut file = File::options().append(true).open(journal_path(&output_file)).unwrap();
+        file.write_all(b"{\"size_decompressed\":").unwrap();
+        assert_eq!(load(&output_file, 1).unwrap().unwrap().len(), 2);
+
+        journal.remove().unwrap();
+        assert!(load(&output_file, 1).unwrap().is_none());
+    }
+}
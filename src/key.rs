@@ -1,10 +1,21 @@
 use std::{
+    fmt,
     fs::File,
     io::{BufReader, Read, Seek, SeekFrom},
     path::Path,
 };
 
 use anyhow::bail;
+use binrw::BinRead;
+use clap::ValueEnum;
+
+use crate::{
+    archive::PakArchive,
+    encryption::decrypt,
+    preset::GamePreset,
+    sidecar::Sidecar,
+    shared::{ASSETS_LIST_NAME, PAK_HEADER_SIZE, PakHeader},
+};
 
 
 const KEY_OFFSETS: [u64; 6] = [
@@ -24,8 +35,67 @@ pub type KeyRef<'a> = &'a[u8; KEY_SIZE];
 const KEY_CRC32: u32 = 0xaa13_14bf;
 
 
-/// Try to retrieve the XXTEA encryption key from the indicated file.
-pub fn get_key(file: &Path) -> anyhow::Result<OwnedKey> {
+/// A plaintext CRC32 mismatch after decrypting something, which usually
+/// means the key is wrong.
+#[derive(Debug)]
+pub struct DecryptionError {
+    pub expected_crc32: u32,
+    pub actual_crc32: u32,
+    /// The `fingerprint()` of the key used.
+    pub key_fingerprint: String,
+}
+
+
+impl fmt::Display for DecryptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "plaintext CRC32 mismatch (expected {:#010x}, got {:#010x}, decrypting with key {})",
+            self.expected_crc32,
+            self.actual_crc32,
+            self.key_fingerprint,
+        )
+    }
+}
+
+
+impl std::error::Error for DecryptionError {}
+
+
+/// A short fingerprint of a key, to tell keys apart in messages without
+/// showing the key itself: its CRC32, in hex.
+pub fn fingerprint(key: KeyRef) -> String {
+    format!("{:08x}", crc32fast::hash(key))
+}
+
+
+/// Whether a key is the known one (that `get_key` looks for).
+pub fn is_known(key: KeyRef) -> bool {
+    crc32fast::hash(key) == KEY_CRC32
+}
+
+
+/// Describe a key and where it came from, for messages (e.g. "key
+/// aa1314bf, from \"libnsmb.so\" at 0x1056a0").
+pub fn describe_key(file: &Path, key: KeyRef) -> String {
+    let offset = File::open(file).ok().and_then(|f| {
+        let mut reader = BufReader::new(f);
+        let mut possible_key: [u8; KEY_SIZE] = [0; KEY_SIZE];
+        KEY_OFFSETS.into_iter().find(|&offset| {
+            reader.seek(SeekFrom::Start(offset)).is_ok()
+                && reader.read(&mut possible_key).is_ok_and(|n| n == KEY_SIZE)
+                && possible_key == *key
+        })
+    });
+    match offset {
+        Some(offset) => format!("key {}, from {file:?} at {offset:#x}", fingerprint(key)),
+        None => format!("key {}, from {file:?}", fingerprint(key)),
+    }
+}
+
+
+/// Look for the XXTEA encryption key at each of `offsets` in a file.
+fn find_key(file: &Path, offsets: &[u64]) -> anyhow::Result<Option<OwnedKey>> {
     let metadata = file.metadata()?;
     if !metadata.is_file() {
         bail!("XXTEA key file \"{}\" is not a file", file.display());
@@ -33,15 +103,140 @@ pub fn get_key(file: &Path) -> anyhow::Result<OwnedKey> {
 
     let mut reader = BufReader::new(File::open(file)?);
 
+    let mut possible_key: [u8; KEY_SIZE] = [0; KEY_SIZE];
+    for &offset in offsets {
+        reader.seek(SeekFrom::Start(offset))?;
+        if reader.read(&mut possible_key)? == KEY_SIZE
+            && crc32fast::hash(&possible_key) == KEY_CRC32
+        {
+            return Ok(Some(Box::new(possible_key)));
+        }
+    }
+
+    Ok(None)
+}
+
+
+/// Try to retrieve the XXTEA encryption key from the indicated file.
+pub fn get_key(file: &Path) -> anyhow::Result<OwnedKey> {
+    match find_key(file, &KEY_OFFSETS)? {
+        Some(key) => Ok(key),
+        None => bail!("unable to find XXTEA key in \"{}\"", file.display()),
+    }
+}
+
+
+/// Find the key in a key file that decrypts a .pak's assets list to
+/// data with the plaintext CRC32 stored in its header, among the 16
+/// bytes at each place a key can be (as with `get_key`, but without
+/// requiring them to be the known key). Returns `None` if none do, or
+/// the assets list isn't encrypted.
+fn find_key_for_pak(file: &Path, pak_file: &Path) -> anyhow::Result<Option<OwnedKey>> {
+    let mut pak_reader = BufReader::new(File::open(pak_file)?);
+    let header = PakHeader::read(&mut pak_reader)?;
+    pak_reader.seek(SeekFrom::Start(PAK_HEADER_SIZE as u64))?;
+    let mut assets_list = vec![0; header.assets_list_size_compressed.try_into()?];
+    pak_reader.read_exact(&mut assets_list)?;
+
+    // (the same heuristic as `check_is_encrypted()`)
+    if assets_list.first_chunk().is_some_and(|count| u32::from_le_bytes(*count) <= 0x000f_ffff) {
+        return Ok(None);
+    }
+
+    let mut reader = BufReader::new(File::open(file)?);
     let mut possible_key: [u8; KEY_SIZE] = [0; KEY_SIZE];
     for offset in KEY_OFFSETS {
         reader.seek(SeekFrom::Start(offset))?;
-        if reader.read(&mut possible_key)? == KEY_SIZE {
-            if crc32fast::hash(&possible_key) == KEY_CRC32 {
+        if reader.read(&mut possible_key)? != KEY_SIZE {
+            continue;
+        }
+        let mut data = assets_list.clone();
+        decrypt(ASSETS_LIST_NAME, &possible_key, &mut data);
+        if crc32fast::hash(&data) == header.plaintext_crc32 {
+            return Ok(Some(Box::new(possible_key)));
+        }
+    }
+
+    Ok(None)
+}
+
+
+/// Retrieve the XXTEA encryption key for a particular .pak from the
+/// indicated file, by checking which candidate decrypts its assets list
+/// correctly. Unlike `get_key`, this works with games whose key isn't
+/// the known one, as long as it's in a key.bin file or where it would
+/// be in one of the known libraries.
+///
+/// If the .pak can't settle it (e.g. it's damaged, or not encrypted),
+/// this falls back to `get_key`.
+pub fn get_key_for_pak(file: &Path, pak_file: &Path) -> anyhow::Result<OwnedKey> {
+    if let Ok(Some(key)) = find_key_for_pak(file, pak_file) {
+        return Ok(key);
+    }
+    match find_key(file, &KEY_OFFSETS)? {
+        Some(key) => Ok(key),
+        None => bail!(
+            "unable to find XXTEA key in \"{}\" (nothing where a key could be decrypts {pak_file:?} correctly)",
+            file.display(),
+        ),
+    }
+}
+
+
+/// Retrieve the XXTEA encryption key for packing a folder from the
+/// indicated file. If the folder was unpacked from a .pak whose key
+/// isn't the known one, its sidecar has that key's `fingerprint()`, so
+/// the candidate with that fingerprint is used (as `get_key_for_pak`
+/// would have found it). Otherwise, if the sidecar records which game
+/// the .pak was from, this is the same as `get_key_for_game`, and if
+/// not, `get_key`.
+pub fn get_key_for_folder(file: &Path, folder: &Path) -> anyhow::Result<OwnedKey> {
+    let sidecar = Sidecar::load(folder).ok().flatten();
+    if let Some(recorded) = sidecar.as_ref().and_then(|sidecar| sidecar.key.as_ref()) {
+        let mut reader = BufReader::new(File::open(file)?);
+        let mut possible_key: [u8; KEY_SIZE] = [0; KEY_SIZE];
+        for offset in KEY_OFFSETS {
+            reader.seek(SeekFrom::Start(offset))?;
+            if reader.read(&mut possible_key)? == KEY_SIZE && fingerprint(&possible_key) == *recorded {
                 return Ok(Box::new(possible_key));
             }
         }
     }
+    if let Some(game) = sidecar.and_then(|sidecar| sidecar.game) {
+        return get_key_for_game(file, game);
+    }
+    get_key(file)
+}
+
+
+/// Work out which game a .pak is from, by its asset names (see
+/// `GamePreset::from_asset_names`), or failing that, by where the key is
+/// in the key file, if it's one of the games' libraries (as opposed to a
+/// key.bin file, which could be from any of them).
+pub fn detect_game(file: &Path, pak_file: &Path, key: KeyRef) -> anyhow::Result<Option<GamePreset>> {
+    // (a .pak that can't be read is left for unpacking to report)
+    if let Ok(archive) = PakArchive::open(pak_file, key) {
+        let names: Vec<_> = archive.assets().map_while(Result::ok).map(|asset| asset.name).collect();
+        if let Some(preset) = GamePreset::from_asset_names(names.iter().map(Vec::as_slice)) {
+            return Ok(Some(preset));
+        }
+    }
+
+    for preset in GamePreset::value_variants() {
+        if find_key(file, &[preset.settings().key_offset])?.is_some() {
+            return Ok(Some(*preset));
+        }
+    }
+    Ok(None)
+}
 
-    bail!("unable to find XXTEA key in \"{}\"", file.display());
+
+/// Like `get_key`, but only look where the key is in a key.bin file or
+/// `preset`'s game's library.
+pub fn get_key_for_game(file: &Path, preset: GamePreset) -> anyhow::Result<OwnedKey> {
+    let settings = preset.settings();
+    match find_key(file, &[0, settings.key_offset])? {
+        Some(key) => Ok(key),
+        None => bail!("unable to find XXTEA key in \"{}\" (expected key.bin or {})", file.display(), settings.library_name),
+    }
 }
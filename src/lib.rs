@@ -0,0 +1,48 @@
+//! Library for reading and writing the .pak archives used by games
+//! built on the Lingcod engine. The `packling` binary is a CLI on top
+//! of this.
+
+pub mod archive;
+pub mod cache;
+pub mod deploy;
+pub mod encryption;
+pub mod filter;
+pub mod flow_assets_list;
+pub mod flow_bench;
+pub mod flow_compare;
+pub mod flow_crc;
+pub mod flow_delta;
+pub mod flow_diff;
+pub mod flow_explain;
+pub mod flow_extract;
+pub mod flow_grep;
+pub mod flow_index;
+pub mod flow_info;
+pub mod flow_init;
+pub mod flow_just_decrypt;
+pub mod flow_list;
+pub mod flow_map;
+pub mod flow_order;
+pub mod flow_pack;
+pub mod flow_peek;
+pub mod flow_recover;
+pub mod flow_slack;
+pub mod flow_stat;
+pub mod flow_stats;
+pub mod flow_unpack;
+pub mod flow_unpack_all;
+#[cfg(feature = "async-io")]
+pub mod flow_unpack_async;
+pub mod flow_verify;
+pub mod interrupt;
+pub mod jamcrc32;
+pub mod journal;
+pub mod key;
+pub mod manifest;
+pub mod preset;
+pub mod progress;
+pub mod report;
+pub mod schema;
+pub mod shared;
+pub mod sidecar;
+pub mod sniff;
@@ -1,25 +1,19 @@
 use std::{
     ffi::OsStr,
+    io::IsTerminal,
     path::{Path, PathBuf},
     time::{SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::bail;
-use clap::{ValueEnum, Parser};
+use clap::{ArgAction, Args, CommandFactory, Parser, Subcommand, ValueEnum};
 
-use crate::{
+use packling::{
     key::KeyRef,
-    shared::{Verbosity, check_is_encrypted},
+    shared::{TableFormat, TimeZone, Verbosity, check_is_encrypted},
+    sidecar::RawForm,
 };
 
-mod encryption;
-mod flow_just_decrypt;
-mod flow_pack;
-mod flow_unpack;
-mod jamcrc32;
-mod key;
-mod shared;
-
 
 /// Available formats to output to.
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default, ValueEnum)]
@@ -45,29 +39,941 @@ enum OutputFormat {
 
 
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    convert: Option<ConvertArgs>,
+
+    /// Number of threads to use for work that can run in parallel (by
+    /// default, $PACKLING_THREADS, or else the number of CPU cores). With
+    /// --async-io, this limits the decoding and file I/O threads and the
+    /// threads that coordinate them separately
+    #[arg(long, global = true, value_name = "N")]
+    threads: Option<usize>,
+
+    /// Folder for intermediate files (by default, $TMPDIR or the
+    /// system's temporary folder), e.g. if that's on a small partition.
+    /// Files that are moved into place once complete are still written
+    /// next to their destination
+    #[arg(long, global = true, value_name = "FOLDER")]
+    temp_dir: Option<PathBuf>,
+
+    /// Print the JSON schema for a kind of machine-readable output, and
+    /// exit
+    #[arg(long, value_name = "KIND", exclusive = true)]
+    schema: Option<packling::schema::SchemaKind>,
+}
+
+
+/// Environment variable that sets the default for --threads.
+const THREADS_ENV_VAR: &str = "PACKLING_THREADS";
+
+
+// Subcommands. If none is given, the arguments are interpreted as
+// `ConvertArgs` instead.
+#[derive(Subcommand)]
+enum Command {
+    /// Print information about a .pak file
+    Info(InfoArgs),
+    /// Walk through the structure of a .pak file, explaining what each
+    /// part means
+    Explain(ExplainArgs),
+    /// Check that a .pak file's whole-file and per-asset checksums match
+    #[command(alias = "validate")]
+    Verify(VerifyArgs),
+    /// List the assets in a .pak file
+    List(ListArgs),
+    /// Print the absolute file offset and sizes of every asset
+    Map(MapArgs),
+    /// Print the regions of asset data that no asset refers to (gaps
+    /// between assets, and any bytes after the last one)
+    Slack(SlackArgs),
+    /// Print the total sizes and compression ratio of the assets
+    Stats(StatsArgs),
+    /// Compare the assets in two .pak files: which were added, removed
+    /// or changed
+    Diff(DiffArgs),
+    /// Compare two .pak files without the key: header fields, sizes, and
+    /// which byte ranges differ
+    Compare(CompareArgs),
+    /// Create a patch that turns one .pak file into another
+    Delta(DeltaArgs),
+    /// Apply a patch created with the delta command (or any BPS or IPS
+    /// patch) to a .pak file
+    Apply(ApplyArgs),
+    /// Work with order files
+    Order(OrderArgs),
+    /// Dump a .pak file's header and assets list to an editable file, or
+    /// rebuild them from one
+    AssetsList(AssetsListArgs),
+    /// Preview the contents of a single asset
+    Peek(PeekArgs),
+    /// Print everything known about a single asset: sizes, offsets,
+    /// encryption chunks, derived fields, compression, and whether its
+    /// CRCs match
+    Stat(StatArgs),
+    /// Extract a single asset by its position in the assets list, or an
+    /// arbitrary range of bytes, even from a .pak with a damaged assets
+    /// list
+    Extract(ExtractArgs),
+    /// Search the contents of every asset for a string, regex or byte
+    /// pattern
+    Grep(GrepArgs),
+    /// Build an index of the assets in every .pak file in a folder
+    Index(IndexArgs),
+    /// Find every .pak file in a folder (e.g. a romfs dump), and unpack
+    /// each one in place, into a folder next to it
+    UnpackAll(UnpackAllArgs),
+    /// Find which .pak file(s) in an index contain an asset
+    Which(WhichArgs),
+    /// Calculate the CRC32 or JAMCRC32 of a file
+    Crc(CrcArgs),
+    /// Calculate the sizes and CRC32s a file would have as an asset in
+    /// a .pak
+    AssetCrc(AssetCrcArgs),
+    /// Measure how fast each phase of extracting a .pak file is on this
+    /// machine
+    Bench(BenchArgs),
+    /// Create a .pak file as described by a manifest file
+    Pack(PackArgs),
+    /// Set up a project folder for building a .pak reproducibly from a
+    /// manifest, starting from an existing .pak or extracted folder
+    Init(InitArgs),
+    /// Copy a .pak file into the folder layout used by Atmosphere's
+    /// LayeredFS (atmosphere/contents/<title ID>/romfs/...), locally or
+    /// over FTP
+    Layeredfs(LayeredfsArgs),
+    /// Print a man page (in roff format) generated from these command
+    /// line options
+    Man(ManArgs),
+}
+
+
+/// Arguments shared by all subcommands that read a .pak file.
+#[derive(Args)]
+struct PakArgs {
+    /// key.bin or lib<game>.so file containing the XXTEA encryption key
+    key_file: PathBuf,
+
+    /// Input .pak file
+    input: PathBuf,
+}
+
+
+/// Arguments selecting the time zone that timestamps are displayed and
+/// interpreted in.
+#[derive(Args)]
+struct TimeZoneArgs {
+    /// Display and interpret timestamps in UTC (the default)
+    #[arg(long, conflicts_with_all = ["local", "timezone"])]
+    utc: bool,
+
+    /// Display and interpret timestamps in the system's local time zone
+    #[arg(long, conflicts_with = "timezone")]
+    local: bool,
+
+    /// Display and interpret timestamps in this time zone ("utc",
+    /// "local", or a UTC offset like "+09:00")
+    #[arg(long, value_name = "TZ")]
+    timezone: Option<String>,
+}
+
+
+impl TimeZoneArgs {
+    fn resolve(&self) -> anyhow::Result<TimeZone> {
+        resolve_timezone(self.local, self.timezone.as_deref())
+    }
+}
+
+
+/// Work out the time zone selected by --utc/--local/--timezone.
+fn resolve_timezone(local: bool, timezone: Option<&str>) -> anyhow::Result<TimeZone> {
+    Ok(if let Some(timezone) = timezone {
+        TimeZone::parse(timezone)?
+    } else if local {
+        TimeZone::Local
+    } else {
+        TimeZone::Utc
+    })
+}
+
+
+#[derive(Args)]
+struct InfoArgs {
+    #[command(flatten)]
+    pak: PakArgs,
+
+    #[command(flatten)]
+    timezone: TimeZoneArgs,
+
+    /// Print the raw header bytes, with each field labeled and validated
+    #[arg(long)]
+    hexdump: bool,
+
+    /// Also detect the content type of every asset and print how many
+    /// there are of each (requires reading part or all of every asset)
+    #[arg(long, conflicts_with = "hexdump")]
+    types: bool,
+
+    /// Also write the decrypted and decompressed assets-list blob to
+    /// this file
+    #[arg(long, value_name = "FILE")]
+    dump_assets_list: Option<PathBuf>,
+}
+
+
+#[derive(Args)]
+struct ExplainArgs {
+    #[command(flatten)]
+    pak: PakArgs,
+
+    #[command(flatten)]
+    timezone: TimeZoneArgs,
+
+    /// How many assets-list entries to explain in detail
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    entries: usize,
+}
+
+
+#[derive(Args)]
+struct VerifyArgs {
+    // (not `PakArgs`, since --as-game doesn't need the key)
+    /// key.bin or lib<game>.so file containing the XXTEA encryption key.
+    /// With --as-game, this can be left out, and the .pak file given in
+    /// its place
+    key_file: PathBuf,
+
+    /// Input .pak file
+    #[arg(required_unless_present = "as_game")]
+    input: Option<PathBuf>,
+
+    /// Only perform the checks that the game's loader is known to
+    /// perform (magic, version, whole-file JAMCRC32 and header field
+    /// 0x1c), and report whether each one passes
+    #[arg(long)]
+    as_game: bool,
+
+    /// Only check each asset's ciphertext CRC32 against its bytes on
+    /// disk, without decrypting or decompressing anything (apart from
+    /// the assets list), checking the layout or calculating the
+    /// whole-file CRC32. Much faster for big .pak files
+    #[arg(long, conflicts_with = "as_game")]
+    fast: bool,
+
+    /// Write a machine-readable JSON report of each asset's check, and
+    /// any problems, to this file
+    #[arg(long, value_name = "FILE", conflicts_with = "as_game")]
+    report: Option<PathBuf>,
+
+    /// Don't print anything unless there's a problem
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+
+#[derive(Args)]
+struct ListArgs {
+    #[command(flatten)]
+    pak: PakArgs,
+
+    /// Only list assets whose names match this glob pattern (or regex,
+    /// with -E). Patterns without a "/" match against the file name in
+    /// any folder.
+    #[arg(long)]
+    filter: Option<String>,
+
+    /// Interpret --filter as a regular expression
+    #[arg(short = 'E', long, requires = "filter")]
+    regex: bool,
+
+    /// Only list assets of at least this (decompressed) size, in bytes,
+    /// optionally with a K, M or G suffix
+    #[arg(long, value_name = "BYTES")]
+    min_size: Option<String>,
+
+    /// Only list assets of at most this (decompressed) size
+    #[arg(long, value_name = "BYTES")]
+    max_size: Option<String>,
+
+    /// Sort the listing (by default, assets are listed in assets-list
+    /// order)
+    #[arg(long)]
+    sort: Option<packling::flow_list::SortKey>,
+
+    /// Reverse the listing order
+    #[arg(short, long)]
+    reverse: bool,
+
+    /// Also print sizes, compression ratio and file offset
+    #[arg(short, long)]
+    long: bool,
+
+    /// Print all asset fields in a machine-readable format
+    #[arg(long, conflicts_with = "long")]
+    format: Option<TableFormat>,
+
+    /// Detect the content type of each asset from its first bytes, and
+    /// show it in an extra column
+    #[arg(short, long)]
+    types: bool,
+
+    /// Show how each asset is covered by encryption: how many chunks
+    /// (of 0x2000 bytes, each with its own key) it's encrypted in, and
+    /// how many bytes at the end are left as plaintext ("all" if it's
+    /// too small to be encrypted at all)
+    #[arg(long)]
+    encryption: bool,
+
+    /// Print just the asset names, each followed by a NUL byte instead
+    /// of a newline (for use with "xargs -0")
+    #[arg(short = '0', long, conflicts_with_all = ["long", "format", "types", "encryption"])]
+    print0: bool,
+}
+
+
+#[derive(Args)]
+struct MapArgs {
+    #[command(flatten)]
+    pak: PakArgs,
+
+    /// Output format
+    #[arg(long, default_value = "csv")]
+    format: TableFormat,
+}
+
+
+#[derive(Args)]
+struct SlackArgs {
+    #[command(flatten)]
+    pak: PakArgs,
+
+    /// Output format
+    #[arg(long, default_value = "csv")]
+    format: TableFormat,
+
+    /// Also save each region to this folder, exactly as stored
+    #[arg(long, value_name = "FOLDER")]
+    extract: Option<PathBuf>,
+}
+
+
+#[derive(Args)]
+struct StatsArgs {
+    #[command(flatten)]
+    pak: PakArgs,
+
+    /// Break the totals down by folder within the .pak, largest first
+    #[arg(long)]
+    by_dir: bool,
+
+    /// Print the totals in a machine-readable format
+    #[arg(long)]
+    format: Option<TableFormat>,
+}
+
+
+#[derive(Args)]
+struct DiffArgs {
+    /// key.bin or lib<game>.so file containing the XXTEA encryption key
+    key_file: PathBuf,
+
+    /// Original .pak file
+    old: PathBuf,
+
+    /// Modified .pak file
+    new: PathBuf,
+
+    /// Output format ("json" for a machine-readable report, "markdown"
+    /// for release notes)
+    #[arg(long, default_value = "text")]
+    format: packling::flow_diff::DiffFormat,
+}
+
+
+#[derive(Args)]
+struct CompareArgs {
+    /// First .pak file
+    old: PathBuf,
+
+    /// Second .pak file
+    new: PathBuf,
+
+    /// Size of the blocks to compare the files in, which is how
+    /// precisely differing ranges are located
+    #[arg(long, value_name = "BYTES", default_value_t = 0x1_0000)]
+    block_size: u64,
+
+    /// Don't print anything unless the files differ
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+
+#[derive(Args)]
+struct DeltaArgs {
+    /// key.bin or lib<game>.so file containing the XXTEA encryption key
+    key_file: PathBuf,
+
+    /// Original .pak file
+    old: PathBuf,
+
+    /// Modified .pak file
+    new: PathBuf,
+
+    /// Output patch file
+    patch: PathBuf,
+
+    /// Patch format ("bps" or "ips" for use with other patchers; IPS
+    /// only works for files under 16 MiB)
+    #[arg(long, default_value = "packling")]
+    format: packling::flow_delta::PatchFormat,
+
+    /// Overwrite the patch file if it already exists
+    #[arg(short, long)]
+    force: bool,
+
+    /// Don't print a summary
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+
+#[derive(Args)]
+struct OrderArgs {
+    #[command(subcommand)]
+    command: OrderCommand,
+}
+
+
+#[derive(Subcommand)]
+enum OrderCommand {
+    /// Check an order file against a folder: list duplicate entries,
+    /// entries that don't match a file, files that aren't listed, and
+    /// folders whose entries are split up
+    Check(OrderCheckArgs),
+}
+
+
+#[derive(Args)]
+struct OrderCheckArgs {
+    /// Order file to check
+    order_file: PathBuf,
+
+    /// Folder to check it against
+    folder: PathBuf,
+
+    /// Rewrite the order file without the duplicate and unmatched
+    /// entries, and with the unlisted files added at the end
+    #[arg(long)]
+    fix: bool,
+}
+
+
+#[derive(Args)]
+struct AssetsListArgs {
+    #[command(subcommand)]
+    command: AssetsListCommand,
+}
+
+
+#[derive(Subcommand)]
+enum AssetsListCommand {
+    /// Dump the header and every assets-list entry (names, sizes,
+    /// offsets, CRCs, and the fields derived from them) to JSON or TOML
+    Export(AssetsListExportArgs),
+    /// Write a copy of a .pak file with its header and assets list
+    /// rebuilt from an edited dump, keeping the asset data as it is
+    Import(AssetsListImportArgs),
+}
+
+
+#[derive(Args)]
+struct AssetsListExportArgs {
+    #[command(flatten)]
+    pak: PakArgs,
+
+    /// File to write the dump to, instead of stdout
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Dump format (by default, implied by the output file's extension)
+    #[arg(long)]
+    format: Option<packling::flow_assets_list::AssetsListFormat>,
+}
+
+
+#[derive(Args)]
+struct AssetsListImportArgs {
+    #[command(flatten)]
+    pak: PakArgs,
+
+    /// Dump to rebuild the header and assets list from (TOML if it ends
+    /// in ".toml", JSON otherwise); derived fields in it are ignored
+    dump: PathBuf,
+
+    /// Output .pak file
+    output: PathBuf,
+
+    /// Overwrite the output file if it already exists
+    #[arg(short, long)]
+    force: bool,
+}
+
+
+#[derive(Args)]
+struct ApplyArgs {
+    /// key.bin or lib<game>.so file containing the XXTEA encryption key
+    key_file: PathBuf,
+
+    /// Original .pak file
+    old: PathBuf,
+
+    /// Patch file (packling, BPS or IPS)
+    patch: PathBuf,
+
+    /// Output .pak file
+    new: PathBuf,
+
+    /// Overwrite the output file if it already exists
+    #[arg(short, long)]
+    force: bool,
+
+    /// Don't print anything if the result checks out
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+
+#[derive(Args)]
+struct PeekArgs {
+    #[command(flatten)]
+    pak: PakArgs,
+
+    /// Name of the asset within the .pak
+    asset: String,
+
+    /// Number of bytes to hexdump
+    #[arg(long, default_value_t = 256, conflicts_with = "strings")]
+    bytes: usize,
+
+    /// Print the printable strings in the asset instead of a hexdump
+    #[arg(long)]
+    strings: bool,
+}
+
+
+#[derive(Args)]
+struct StatArgs {
+    #[command(flatten)]
+    pak: PakArgs,
+
+    /// Name of the asset within the .pak
+    asset: String,
+}
+
+
+#[derive(Args)]
+struct ExtractArgs {
+    #[command(flatten)]
+    pak: PakArgs,
+
+    /// Extract the asset at this position in the assets list (counting
+    /// from 0), decrypted and decompressed
+    #[arg(long, value_name = "N", required_unless_present = "raw_range", conflicts_with = "raw_range")]
+    asset_index: Option<usize>,
+
+    /// Extract this range of bytes from the file (absolute offset and
+    /// length, decimal or 0x-prefixed hexadecimal), without reading the
+    /// header or assets list
+    #[arg(long, value_name = "OFFSET:LEN")]
+    raw_range: Option<String>,
+
+    /// Decrypt the --raw-range bytes as an asset with this name (which
+    /// is part of the encryption key). Without this, they're extracted
+    /// exactly as stored.
+    #[arg(long, value_name = "KEYNAME", requires = "raw_range")]
+    name: Option<String>,
+
+    /// Write the extracted data to this file instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+
+#[derive(Args)]
+struct GrepArgs {
+    #[command(flatten)]
+    pak: PakArgs,
+
+    /// Pattern to search for (a literal string, by default)
+    pattern: String,
+
+    /// Interpret the pattern as a regular expression
+    #[arg(short = 'E', long, conflicts_with = "hex")]
+    regex: bool,
+
+    /// Interpret the pattern as hexadecimal bytes (e.g. "4b 43 41 50")
+    #[arg(long)]
+    hex: bool,
+
+    /// Only print the names of matching assets
+    #[arg(short = 'l', long)]
+    files_with_matches: bool,
+}
+
+
+#[derive(Args)]
+struct IndexArgs {
+    /// key.bin or lib<game>.so file containing the XXTEA encryption key
+    key_file: PathBuf,
+
+    /// Folder to search (recursively) for .pak files
+    input: PathBuf,
+
+    /// Output index file (JSON)
+    output: PathBuf,
+
+    /// Overwrite the output file if it already exists
+    #[arg(short, long)]
+    force: bool,
+}
+
+
+#[derive(Args)]
+struct UnpackAllArgs {
+    /// key.bin or lib<game>.so file containing the XXTEA encryption key
+    key_file: PathBuf,
+
+    /// Folder to search (recursively) for .pak files
+    input: PathBuf,
+
+    /// Use the key for this game, and record it in each extracted
+    /// folder (by default, it's detected from each .pak, or the key
+    /// file, if possible)
+    #[arg(long, value_name = "GAME")]
+    game: Option<packling::preset::GamePreset>,
+
+    /// Replace the folders of .pak files that have already been
+    /// unpacked, instead of skipping them
+    #[arg(short, long)]
+    force: bool,
+
+    /// Skip assets that fail CRC, decompression or path checks instead
+    /// of giving up on the .pak they're in (without it, a CRC32 mismatch
+    /// in any asset is an error)
+    #[arg(long)]
+    keep_going: bool,
+
+    /// Also unpack assets that are .pak files themselves, recursively
+    #[arg(long)]
+    recursive: bool,
+
+    /// Don't check that there's enough free disk space for each .pak's
+    /// assets before unpacking it (the check is only done on Unix)
+    #[arg(long)]
+    no_space_check: bool,
+
+    /// Only print the summary (and any problems)
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+
+#[derive(Args)]
+struct WhichArgs {
+    /// Index file created by the "index" subcommand
+    index: PathBuf,
+
+    /// Asset name to look for, as a glob pattern (or regex, with -E).
+    /// Patterns without a "/" match against the file name in any folder.
+    asset: String,
+
+    /// Interpret the asset name as a regular expression
+    #[arg(short = 'E', long)]
+    regex: bool,
+}
+
+
+#[derive(Args)]
+struct CrcArgs {
+    /// File to checksum
+    input: PathBuf,
+
+    /// Calculate a JAMCRC32 (bitwise-inverted CRC32) instead of a
+    /// regular CRC32
+    #[arg(long)]
+    jam: bool,
+
+    /// Initial CRC value (decimal or 0x-prefixed hex), or "size" for
+    /// the file size
+    #[arg(long, default_value = "0")]
+    init: String,
+
+    /// Offset to start checksumming at (decimal or 0x-prefixed hex)
+    #[arg(long, default_value = "0")]
+    start: String,
+
+    /// Calculate the whole-file checksum exactly as stored at 0x08 in a
+    /// .pak header (equivalent to "--jam --init size --start 0x14")
+    #[arg(long, conflicts_with_all = ["jam", "init", "start"])]
+    pak: bool,
+}
+
+
+#[derive(Args)]
+struct AssetCrcArgs {
+    /// key.bin or lib<game>.so file containing the XXTEA encryption key
+    key_file: PathBuf,
+
+    /// File to checksum
+    input: PathBuf,
+
+    /// Name the asset would have within the .pak (part of the
+    /// encryption key, so this affects the ciphertext CRC32)
+    name: String,
+
+    /// Compress the file first, as --compress-files would when packing
+    #[arg(long)]
+    compress: bool,
+}
+
+
+#[derive(Args)]
+struct BenchArgs {
+    #[command(flatten)]
+    pak: PakArgs,
+
+    /// Also measure writing the extracted assets, to a temporary folder
+    /// created (and deleted afterward) inside this folder
+    #[arg(long, value_name = "FOLDER")]
+    write_to: Option<PathBuf>,
+}
+
+
+#[derive(Args)]
+struct PackArgs {
+    /// key.bin or lib<game>.so file containing the XXTEA encryption key
+    key_file: PathBuf,
+
+    /// TOML file listing each asset's source file and name (in the order
+    /// they should be stored in), and optionally the timestamp and
+    /// whether to compress each asset. Source paths are relative to the
+    /// manifest's folder.
+    #[arg(long, value_name = "FILE")]
+    manifest: PathBuf,
+
+    /// Output .pak file
+    output: PathBuf,
+
+    #[command(flatten)]
+    timezone: TimeZoneArgs,
+
+    /// Overwrite the output file if it already exists (when running
+    /// interactively, you'll be asked first), and keep only the last of
+    /// any assets with the same name instead of stopping
+    #[arg(short, long)]
+    force: bool,
+
+    /// Don't ask for confirmation before overwriting an existing output
+    /// file
+    #[arg(short, long)]
+    yes: bool,
+
+    /// Print less: -q for just warnings, -qq for just errors
+    #[arg(short, long, action = ArgAction::Count)]
+    quiet: u8,
+
+    /// Write a machine-readable JSON report of the operation to this
+    /// file
+    #[arg(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+
+    /// Show a live progress line on stderr instead of listing each
+    /// asset
+    #[arg(long)]
+    progress: bool,
+
+    /// Continue an interrupted earlier run of this same pack (from the
+    /// journal it left next to the output file), instead of starting
+    /// over
+    #[arg(long)]
+    resume: bool,
+
+    /// After packing, verify the .pak and copy it into this folder, or
+    /// upload it to this FTP URL (see the main --deploy option)
+    #[arg(long, value_name = "PATH_OR_URL")]
+    deploy: Option<String>,
+
+    /// Fail if the .pak would be larger than this many bytes, or than
+    /// an existing file with "match:FILE" (see the main --max-size
+    /// option)
+    #[arg(long, value_name = "BYTES|match:FILE")]
+    max_size: Option<String>,
+}
+
+
+#[derive(Args)]
+struct InitArgs {
+    /// Existing .pak file (which is extracted into the project folder)
+    /// or extracted folder (which is used where it is)
+    input: PathBuf,
+
+    /// Project folder to create the manifest, order file and lock file
+    /// in
+    project: PathBuf,
+
+    /// key.bin or lib<game>.so file containing the XXTEA encryption key
+    /// (only needed if the input is a .pak file)
+    #[arg(long, value_name = "KEY_FILE")]
+    key: Option<PathBuf>,
+
+    /// Print less: -q for just warnings, -qq for just errors
+    #[arg(short, long, action = ArgAction::Count)]
+    quiet: u8,
+}
+
+
+#[derive(Args)]
+struct LayeredfsArgs {
+    /// .pak file to place
+    input: PathBuf,
+
+    /// Title ID of the game (16 hexadecimal digits)
+    #[arg(long)]
+    title_id: String,
+
+    /// Folder within the game's romfs that the .pak file belongs in
+    /// (e.g. "data"; the .pak keeps its file name)
+    #[arg(long, value_name = "PATH", default_value = "")]
+    romfs_path: String,
+
+    /// Folder to create the layout in (e.g. the root of the SD card), or
+    /// an FTP URL, as with --deploy
+    #[arg(long, value_name = "PATH_OR_URL", default_value = ".")]
+    root: String,
+
+    /// Don't print where the .pak file was placed
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+
+#[derive(Args)]
+struct ManArgs {
+    /// Write the man page to this file instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+
+// Arguments for the default mode: converting between .pak files and
+// extracted folders. (Not a doc comment, since clap would use it as the
+// program description.)
+#[derive(Args)]
+struct ConvertArgs {
     /// key.bin or lib<game>.so file containing the XXTEA encryption key
     key_file: PathBuf,
 
     /// Input .pak file (for unpacking) or folder (for packing)
     input: PathBuf,
 
-    /// Output .pak file (for packing) or folder (for unpacking)
+    /// Output .pak file (for packing) or folder (for unpacking).
+    ///
+    /// By default, this is named after the input, with a number added
+    /// (e.g. "foo (2).pak") if that's already taken, unless -f is given.
+    /// Converting a .pak file in place only happens if it's given as the
+    /// output explicitly. "-" writes a decrypted .pak file to stdout.
     output: Option<PathBuf>,
 
     /// Output format
     #[arg(long, default_value="default")]
     output_format: OutputFormat,
 
-    /// Suppress output
-    #[arg(short, long)]
-    quiet: bool,
+    /// Only look for the key where it is in this game's library (or a
+    /// key.bin file). No other settings depend on the game
+    #[arg(long, value_name = "GAME")]
+    game: Option<packling::preset::GamePreset>,
+
+    /// Treat the input as a .pak file, even if it isn't a regular file
+    /// (e.g. /dev/stdin or a named pipe, which is copied to a temporary
+    /// file first, since it has to be seekable). An output path is
+    /// required in that case
+    #[arg(long, conflicts_with = "pack")]
+    unpack: bool,
+
+    /// Treat the input as a folder to pack, without checking what it is
+    /// first
+    #[arg(long)]
+    pack: bool,
+
+    /// Print less: -q for just warnings and summaries (no line per
+    /// file), -qq for just errors
+    #[arg(short, long, action = ArgAction::Count)]
+    quiet: u8,
 
-    /// Overwrite output file/folder if it already exists
+    /// Print more: -v for the .pak header fields, -vv for how each
+    /// asset is stored (offset, sizes, encryption chunks and CRCs)
+    #[arg(short, long, action = ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Overwrite output file/folder if it already exists (when running
+    /// interactively, you'll be asked first). When packing, this also
+    /// keeps only the last of any files with the same asset name instead
+    /// of stopping.
     #[arg(short, long)]
     force: bool,
 
+    /// Don't ask for confirmation before deleting or overwriting an
+    /// existing output file/folder (or converting a .pak file in place)
+    #[arg(short, long)]
+    yes: bool,
+
+    /// When unpacking, extract into the output folder even if it
+    /// already exists (without deleting it first), and handle files
+    /// that are already there like this
+    #[arg(long, value_name = "POLICY", conflicts_with = "force")]
+    on_conflict: Option<packling::flow_unpack::ConflictPolicy>,
+
+    /// When unpacking, extract into the output folder even if it
+    /// already has files in it, replacing only the ones that are
+    /// extracted and leaving the rest alone (the same as --on-conflict
+    /// overwrite). An empty output folder can be extracted into without
+    /// this
+    #[arg(long, conflicts_with_all = ["force", "on_conflict"])]
+    merge: bool,
+
+    /// When unpacking, never overwrite existing files: extract into the
+    /// output folder even if it already exists, but keep any files that
+    /// are already there (e.g. with local edits), with a warning for
+    /// each
+    #[arg(long, conflicts_with_all = ["force", "on_conflict", "merge"])]
+    no_clobber: bool,
+
+    /// When unpacking, overlap reading, decoding and writing assets
+    /// instead of doing them one after the other (faster on
+    /// high-latency storage). Packing is unaffected.
+    #[cfg(feature = "async-io")]
+    #[arg(long)]
+    async_io: bool,
+
+    /// When unpacking, skip assets that fail CRC, decompression or path
+    /// checks instead of stopping, and report them at the end. (Without
+    /// it, an asset whose ciphertext or plaintext CRC32 doesn't match
+    /// stops the unpack with an error.)
+    #[arg(long)]
+    keep_going: bool,
+
+    /// When unpacking, don't check that there's enough free disk space
+    /// for the extracted assets first (e.g. on filesystems with
+    /// compression, where they'd take up less than their size). The
+    /// check is only done on Unix.
+    #[arg(long)]
+    no_space_check: bool,
+
     /// Compress the .pak header (WARNING: may nearly double the encoding time)
     #[arg(long)]
     compress_header: bool,
@@ -84,13 +990,188 @@ struct Cli {
     #[arg(long)]
     order_file: Option<String>,
 
+    /// When unpacking, append the CRC32 of each extracted file to its
+    /// line in the order file (after a tab). Packing then warns about
+    /// any file that no longer matches, e.g. if you edited a different
+    /// copy of the folder
+    #[arg(long, requires = "order_file")]
+    order_file_crcs: bool,
+
+    /// When packing, treat the order file as the complete list of
+    /// assets: fail if any of its entries don't match a file, or any
+    /// files aren't listed in it (instead of ignoring the former and
+    /// adding the latter at the end)
+    #[arg(long, requires = "order_file")]
+    strict_order: bool,
+
+    /// When packing, keep empty folders by adding an empty placeholder
+    /// asset (".packling_keep") to each. Placeholders are always turned
+    /// back into empty folders when unpacking.
+    #[arg(long)]
+    keep_empty_folders: bool,
+
+    /// When packing, lay this folder over the input folder, so its
+    /// files replace the ones at the same paths (and the rest are
+    /// added). Can be given more than once, with each one laid over the
+    /// ones before it, to build a merged .pak from a base folder and
+    /// separate mod folders
+    #[arg(long = "root", value_name = "FOLDER")]
+    roots: Vec<PathBuf>,
+
+    /// When unpacking, also unpack assets that are .pak files
+    /// themselves (with the same key) into folders of the same name,
+    /// recursively. When packing, pack those folders back into .pak
+    /// files.
+    #[arg(long)]
+    recursive: bool,
+
+    /// When packing, the order to store files that aren't in the order
+    /// file in. Both orders compare raw bytes, so they're the same on
+    /// every OS.
+    #[arg(long, value_name = "ORDER", default_value = "bytewise")]
+    asset_order: packling::flow_pack::AssetOrder,
+
+    /// When packing, convert asset names (including the ones in the
+    /// order file) to this Unicode normalization form. Use "nfc" when
+    /// packing on macOS, whose filesystems may give names in NFD.
+    #[arg(long, value_name = "FORM", default_value = "none")]
+    normalize: packling::flow_pack::NameNormalization,
+
+    /// When packing, list assets that end up stored partly or entirely
+    /// unencrypted: assets of 7 bytes or less aren't encrypted at all,
+    /// and the last 1-3 bytes of assets whose size isn't a multiple of
+    /// 4 (or up to 7, if that's all of their last chunk) are left as
+    /// plaintext
+    #[arg(long)]
+    warn_plaintext_tails: bool,
+
+    /// Write a machine-readable JSON report of the operation to this
+    /// file: what was done with each asset, their sizes and CRCs, any
+    /// warnings, and how long it took
+    #[arg(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+
+    /// Show a live progress line on stderr instead of listing each
+    /// asset: how much is done, the throughput of each phase (reading,
+    /// decrypting/encrypting, decompressing/compressing and writing),
+    /// and an estimate of the time remaining
+    #[arg(long)]
+    progress: bool,
+
+    /// When packing, continue an interrupted earlier run of the same
+    /// pack (from the journal it left next to the output file), instead
+    /// of starting over
+    #[arg(long)]
+    resume: bool,
+
+    /// After packing, verify the .pak and copy it into this folder, or
+    /// upload it to this FTP URL
+    /// ("ftp://[user[:password]@]host[:port]/folder"; the port defaults
+    /// to 5000, as used by the Switch homebrew ftpd)
+    #[arg(long, value_name = "PATH_OR_URL")]
+    deploy: Option<String>,
+
+    /// When packing, fail if the .pak would be larger than this many
+    /// bytes (decimal or 0x-prefixed hex, optionally with a K, M or G
+    /// suffix), or with "match:FILE", than an existing file (e.g. the
+    /// original .pak, for injection methods that can't handle a larger
+    /// one). The output file is deleted if it turns out to be too large
+    #[arg(long, value_name = "BYTES|match:FILE")]
+    max_size: Option<String>,
+
+    /// When unpacking, only extract assets of at least this
+    /// (decompressed) size, in bytes, optionally with a K, M or G suffix
+    #[arg(long, value_name = "BYTES")]
+    min_asset_size: Option<String>,
+
+    /// When unpacking, only extract assets of at most this
+    /// (decompressed) size
+    #[arg(long, value_name = "BYTES")]
+    max_asset_size: Option<String>,
+
+    /// When unpacking, extract assets in a raw form instead of fully
+    /// decoding them, and record their metadata in a sidecar file so
+    /// they can be packed back without reprocessing.
+    #[arg(long, value_name = "FORM")]
+    raw: Option<RawForm>,
+
+    /// When unpacking, extract every asset directly into the output
+    /// folder instead of recreating the folder tree, with unique file
+    /// names derived from the asset names ("a/b.txt" -> "a__b.txt").
+    /// The original name of each file is recorded in the sidecar file
+    /// (.packling.json).
+    #[arg(long)]
+    flatten: bool,
+
+    /// When unpacking, give extracted files these mode bits (octal, e.g.
+    /// 644), regardless of the umask. Folders it creates get the same
+    /// bits, plus execute wherever read is set (e.g. 755). On Windows,
+    /// only the
+    /// owner write bit is used: without it, everything is read-only.
+    #[arg(long, value_name = "MODE")]
+    chmod: Option<String>,
+
+    /// When unpacking, make sure no extracted file is executable (even
+    /// existing files that are extracted over)
+    #[arg(long)]
+    no_executable: bool,
+
+    /// Recover assets from a .pak with a damaged assets list, using a
+    /// text file listing the asset names in storage order (e.g. an order
+    /// file from a matching unmodified .pak).
+    ///
+    /// This is slow and best-effort: asset sizes are guessed by trial
+    /// decryption, and recovered assets are written as stored (possibly
+    /// still compressed).
+    #[arg(long, value_name = "NAMES_FILE")]
+    recover_with: Option<String>,
+
     /// Timestamp to put in the created .pak file header.
     ///
-    /// Unix timestamp values (decimal, or hexadecimal with leading "0x") and the ISO 8601-style "2000-01-01T01:01:01" format are both supported.
+    /// Supported formats are Unix timestamp values (decimal, or hexadecimal with leading "0x"), the ISO 8601-style "2000-01-01T01:01:01" format, RFC 3339 timestamps with an explicit UTC offset ("2000-01-01T01:01:01Z", "2000-01-01T01:01:01+09:00"), plain dates ("2000-01-01", meaning midnight), "now", "today" (midnight), and times relative to now ("+3d", "-12h"; units are s, m, h, d and w). Dates and times without an offset are interpreted in the time zone selected by --utc/--local/--timezone (UTC by default).
     ///
     /// If unspecified, the current local system time will be used.
     #[arg(long)]
     timestamp: Option<String>,
+
+    /// Keep the timestamp of the source .pak file instead of using the
+    /// current time: the --template file when packing, or the input
+    /// file when converting a .pak file to another .pak file (which
+    /// always keeps it anyway).
+    #[arg(long, conflicts_with = "timestamp")]
+    keep_timestamp: bool,
+
+    /// Existing .pak file to take the timestamp from when packing with
+    /// --keep-timestamp (e.g. the original .pak the folder was
+    /// extracted from)
+    #[arg(long, value_name = "PAK", requires = "keep_timestamp")]
+    template: Option<PathBuf>,
+
+    /// EXPERIMENTAL: use (when packing) or accept (when unpacking)
+    /// packling's extended .pak layout, with 64-bit sizes and offsets
+    /// instead of the 4 GiB limits of the normal format.
+    ///
+    /// The game can NOT load .pak files in this layout. It's only meant
+    /// for research and private tooling.
+    #[arg(long)]
+    extended_offsets: bool,
+
+    // (the same as `TimeZoneArgs`, which can't be flattened in here:
+    // clap can't tell whether an optional group of arguments was given
+    // if it contains another one)
+
+    /// Display and interpret timestamps in UTC (the default)
+    #[arg(long, conflicts_with_all = ["local", "timezone"])]
+    utc: bool,
+
+    /// Display and interpret timestamps in the system's local time zone
+    #[arg(long, conflicts_with = "timezone")]
+    local: bool,
+
+    /// Display and interpret timestamps in this time zone ("utc",
+    /// "local", or a UTC offset like "+09:00")
+    #[arg(long, value_name = "TZ")]
+    timezone: Option<String>,
 }
 
 
@@ -127,31 +1208,240 @@ fn pick_default_output_file(input_folder: &Path) -> PathBuf {
 }
 
 
-fn parse_timestamp_arg(string: Option<&str>) -> anyhow::Result<i64> {
-    Ok(match string {
-        Some(ts) => {
-            if ts.chars().all(|c| c.is_ascii_digit() || c == '-') {
-                ts.parse::<i64>()?
-            } else if let Some(stripped) = ts.strip_prefix("0x") {
-                i64::from_str_radix(stripped, 16)?
-            } else if let Some(stripped) = ts.strip_prefix("-0x") {
-                -i64::from_str_radix(stripped, 16)?
-            } else {
-                let format = time::format_description::parse(crate::shared::TIME_FORMAT)?;
-                time::PrimitiveDateTime::parse(ts, &format)?
-                    .assume_utc()
-                    .unix_timestamp()
-            }
+/// Add " (n)" to a file or folder name (before the extension, if
+/// `keep_extension`).
+fn numbered_path(path: &Path, n: u32, keep_extension: bool) -> PathBuf {
+    let extension = path.extension().filter(|_| keep_extension);
+    let stem = if extension.is_some() { path.file_stem() } else { path.file_name() };
+    let mut name = stem.unwrap_or_default().to_owned();
+    name.push(format!(" ({n})"));
+    if let Some(extension) = extension {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+
+/// Use `default` as the output path if it's free (or `reuse` is set),
+/// and otherwise the first numbered variant of it that is (see
+/// `numbered_path()`), so that a default output path never clobbers
+/// anything the user didn't mention.
+fn pick_unused_output_path(default: PathBuf, keep_extension: bool, reuse: bool, verbosity: Verbosity) -> PathBuf {
+    // (an empty folder is fine to extract into)
+    if reuse || !default.exists() || packling::shared::is_empty_folder(&default) {
+        return default;
+    }
+    let mut n = 2;
+    let mut path = numbered_path(&default, n, keep_extension);
+    while path.exists() {
+        n += 1;
+        path = numbered_path(&default, n, keep_extension);
+    }
+    if verbosity >= Verbosity::Normal {
+        println!("{default:?} already exists, so using {path:?} instead (give an output path to choose one, or use -f to replace it)");
+    }
+    path
+}
+
+
+/// Select a reasonable output file name when encrypting or decrypting a
+/// .pak file to another file, if the user didn't specify one: the input
+/// file's name with `suffix` added (before the extension).
+fn pick_default_output_converted_file(input_file: &Path, suffix: &str) -> PathBuf {
+    let mut name = input_file.file_stem().unwrap_or_default().to_owned();
+    name.push(suffix);
+    if let Some(extension) = input_file.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    input_file.with_file_name(name)
+}
+
+
+/// Whether an output path is "-", meaning stdout.
+fn is_stdout_path(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+
+/// Parse a relative time like "+3d" or "-12h" into a number of seconds.
+fn parse_relative_time(string: &str) -> Option<i64> {
+    let (sign, rest) = if let Some(rest) = string.strip_prefix('+') {
+        (1, rest)
+    } else if let Some(rest) = string.strip_prefix('-') {
+        (-1, rest)
+    } else {
+        return None;
+    };
+
+    let unit = rest.chars().last()?;
+    let amount = &rest[..rest.len() - unit.len_utf8()];
+    let unit_seconds = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+
+    let amount = i64::from(amount.parse::<u32>().ok()?);
+    Some(sign * amount.checked_mul(unit_seconds)?)
+}
+
+
+/// Convert a date and time in a particular time zone to a Unix
+/// timestamp.
+fn datetime_to_timestamp(datetime: time::PrimitiveDateTime, timezone: TimeZone) -> anyhow::Result<i64> {
+    // (for the local time zone, the offset in effect at the same
+    // wall-clock time in UTC is close enough)
+    let offset = timezone.offset_at(datetime.assume_utc())?;
+    Ok(datetime.assume_offset(offset).unix_timestamp())
+}
+
+
+/// Decide whether a destructive operation on `target` (deleting or
+/// overwriting it) may go ahead. When running interactively, the user
+/// is asked, unless they gave --yes; otherwise, it's allowed only with
+/// -f (or --yes).
+///
+/// Returns `force` unchanged if `target` doesn't exist.
+fn confirm_destructive(target: &Path, question: &str, force: bool, yes: bool) -> anyhow::Result<bool> {
+    if !target.exists() {
+        Ok(force)
+    } else if yes {
+        Ok(true)
+    } else if std::io::stdin().is_terminal() {
+        packling::shared::ask_yes_no(question)
+    } else {
+        Ok(force)
+    }
+}
+
+
+fn parse_timestamp_arg(string: Option<&str>, timezone: TimeZone) -> anyhow::Result<i64> {
+    let now: i64 = SystemTime::now()
+        .duration_since(UNIX_EPOCH)?
+        .as_secs()
+        .try_into()?;
+
+    let Some(ts) = string else {
+        return Ok(now);
+    };
+
+    if let Ok(value) = ts.parse::<i64>() {
+        return Ok(value);
+    } else if let Some(stripped) = ts.strip_prefix("0x") {
+        return Ok(i64::from_str_radix(stripped, 16)?);
+    } else if let Some(stripped) = ts.strip_prefix("-0x") {
+        return Ok(-i64::from_str_radix(stripped, 16)?);
+    }
+
+    match ts {
+        "now" => return Ok(now),
+        "today" => {
+            let now = time::OffsetDateTime::from_unix_timestamp(now)?;
+            let Some(now) = now.checked_to_offset(timezone.offset_at(now)?) else {
+                bail!("the current time is out of range in this time zone");
+            };
+            return datetime_to_timestamp(now.date().midnight(), timezone);
         },
-        None => SystemTime::now()
-            .duration_since(UNIX_EPOCH)?
-            .as_secs()
-            .try_into()?,
+        _ => {},
+    }
+
+    if let Some(relative) = parse_relative_time(ts) {
+        let Some(value) = now.checked_add(relative) else {
+            bail!("timestamp {ts:?} is out of range");
+        };
+        return Ok(value);
+    }
+
+    // Timestamps with an explicit UTC offset don't need a time zone
+    if let Ok(datetime) = time::OffsetDateTime::parse(ts, &time::format_description::well_known::Rfc3339) {
+        return Ok(datetime.unix_timestamp());
+    }
+
+    let datetime_format = time::format_description::parse(packling::shared::TIME_FORMAT)?;
+    let date_format = time::format_description::parse(packling::shared::DATE_FORMAT)?;
+    let datetime = if let Ok(datetime) = time::PrimitiveDateTime::parse(ts, &datetime_format) {
+        datetime
+    } else if let Ok(date) = time::Date::parse(ts, &date_format) {
+        date.midnight()
+    } else {
+        bail!(
+            "unrecognized timestamp {ts:?} (expected a Unix timestamp, \"2000-01-01T01:01:01\" (optionally followed by \"Z\" or a UTC offset like \"+09:00\"), \"2000-01-01\", \"now\", \"today\", or a relative time like \"+3d\")",
+        );
+    };
+
+    datetime_to_timestamp(datetime, timezone)
+}
+
+
+/// Parse a decimal or 0x-prefixed hexadecimal integer argument.
+fn parse_int_arg(string: &str) -> anyhow::Result<u64> {
+    Ok(match string.strip_prefix("0x") {
+        Some(stripped) => u64::from_str_radix(stripped, 16)?,
+        None => string.parse::<u64>()?,
     })
 }
 
 
-fn handle_unpack_file_to_folder(cli: Cli, key: KeyRef, verbosity: Verbosity) -> anyhow::Result<()> {
+/// Parse an "OFFSET:LEN" byte range argument, where both parts are
+/// integers in the format accepted by `parse_int_arg`.
+fn parse_range_arg(string: &str) -> anyhow::Result<(u64, u64)> {
+    let Some((offset, len)) = string.split_once(':') else {
+        bail!("byte range {string:?} isn't in the form OFFSET:LEN");
+    };
+    Ok((parse_int_arg(offset)?, parse_int_arg(len)?))
+}
+
+
+/// Parse a size argument: an integer in the format accepted by
+/// `parse_int_arg`, optionally followed by "K", "M" or "G" (for KiB,
+/// MiB or GiB).
+fn parse_size_arg(string: &str) -> anyhow::Result<u64> {
+    let (number, shift) = match string.as_bytes().last() {
+        Some(b'k' | b'K') => (&string[..string.len() - 1], 10),
+        Some(b'm' | b'M') => (&string[..string.len() - 1], 20),
+        Some(b'g' | b'G') => (&string[..string.len() - 1], 30),
+        _ => (string, 0),
+    };
+    match parse_int_arg(number)?.checked_mul(1 << shift) {
+        Some(size) => Ok(size),
+        None => bail!("size {string:?} is too large"),
+    }
+}
+
+
+/// Parse a --max-size argument: a number of bytes in the format
+/// accepted by `parse_size_arg`, or "match:FILE" for the size of an
+/// existing file.
+fn parse_max_size_arg(string: &str) -> anyhow::Result<u64> {
+    if let Some(path) = string.strip_prefix("match:") {
+        return match std::fs::metadata(path) {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(e) => bail!("couldn't get the size of {path:?}: {e}"),
+        };
+    }
+    parse_size_arg(string)
+}
+
+
+/// Parse an octal file mode argument, like chmod's.
+fn parse_mode_arg(string: &str) -> anyhow::Result<u32> {
+    let mode = u32::from_str_radix(string, 8)?;
+    if mode > 0o7777 {
+        bail!("file mode {string} is out of range (the maximum is 7777)");
+    }
+    Ok(mode)
+}
+
+
+fn handle_unpack_file_to_folder(cli: ConvertArgs, key: KeyRef, verbosity: Verbosity) -> anyhow::Result<()> {
+    if cli.output.as_deref().is_some_and(is_stdout_path) {
+        bail!("only decrypted .pak files can be written to stdout (-), not when unpacking");
+    }
     if cli.compress_header {
         bail!("--compress-header is only allowed when packing");
     }
@@ -161,47 +1451,276 @@ fn handle_unpack_file_to_folder(cli: Cli, key: KeyRef, verbosity: Verbosity) ->
     if cli.timestamp.is_some() {
         bail!("--timestamp is only allowed when packing");
     }
+    if cli.keep_timestamp {
+        bail!("--keep-timestamp is not allowed when unpacking");
+    }
+    if cli.keep_empty_folders {
+        bail!("--keep-empty-folders is only allowed when packing (empty folders are always restored when unpacking)");
+    }
+    if !cli.roots.is_empty() {
+        bail!("--root is only allowed when packing");
+    }
+    if cli.warn_plaintext_tails {
+        bail!("--warn-plaintext-tails is only allowed when packing (use the info command to check an existing .pak)");
+    }
+    if cli.deploy.is_some() {
+        bail!("--deploy is only allowed when packing");
+    }
+    if cli.strict_order {
+        bail!("--strict-order is only allowed when packing");
+    }
+    if cli.resume {
+        bail!("--resume is only allowed when packing");
+    }
+    if cli.max_size.is_some() {
+        bail!("--max-size is only allowed when packing (use --max-asset-size to only extract assets up to a size)");
+    }
+
+    let on_conflict = if cli.merge {
+        Some(packling::flow_unpack::ConflictPolicy::Overwrite)
+    } else {
+        cli.on_conflict
+    };
 
     let output = match cli.output {
         Some(p) => p,
-        None => pick_default_output_folder(&cli.input),
+        None => {
+            let reuse = cli.force || cli.yes || on_conflict.is_some() || cli.no_clobber;
+            pick_unused_output_path(pick_default_output_folder(&cli.input), false, reuse, verbosity)
+        },
+    };
+
+    let force = if on_conflict.is_some() || cli.no_clobber || packling::shared::is_empty_folder(&output) {
+        // (nothing is deleted in these cases)
+        cli.force
+    } else {
+        confirm_destructive(
+            &output,
+            &format!("Output folder {output:?} already exists. Delete it and everything in it?"),
+            cli.force,
+            cli.yes,
+        )?
+    };
+
+    if let Some(names_file) = &cli.recover_with {
+        if cli.keep_going {
+            bail!("--keep-going can't be combined with --recover-with");
+        }
+        if cli.raw.is_some() {
+            bail!("--raw can't be combined with --recover-with");
+        }
+        if on_conflict.is_some() || cli.no_clobber {
+            bail!("--on-conflict, --merge and --no-clobber can't be combined with --recover-with");
+        }
+        #[cfg(feature = "async-io")]
+        if cli.async_io {
+            bail!("--async-io can't be combined with --recover-with");
+        }
+        if cli.extended_offsets {
+            bail!("--extended-offsets can't be combined with --recover-with");
+        }
+        if cli.report.is_some() {
+            bail!("--report can't be combined with --recover-with");
+        }
+        if cli.progress {
+            bail!("--progress can't be combined with --recover-with");
+        }
+        if cli.flatten {
+            bail!("--flatten can't be combined with --recover-with");
+        }
+        if cli.chmod.is_some() || cli.no_executable {
+            bail!("--chmod and --no-executable can't be combined with --recover-with");
+        }
+        if cli.recursive {
+            bail!("--recursive can't be combined with --recover-with");
+        }
+        if cli.min_asset_size.is_some() || cli.max_asset_size.is_some() {
+            bail!("--min-asset-size and --max-asset-size can't be combined with --recover-with");
+        }
+        return packling::flow_recover::recover(&cli.input, &output, key, force, names_file, verbosity);
+    }
+
+    // (the game the .pak is from is recorded, so that packing the folder
+    // again looks for the key in the same place)
+    let game = match cli.game {
+        Some(game) => Some(game),
+        None => packling::key::detect_game(&cli.key_file, &cli.input, key)?,
+    };
+    if let Some(game) = game
+        && verbosity >= Verbosity::Verbose
+    {
+        println!("Game: {}", game.to_possible_value().map_or_else(String::new, |v| v.get_name().to_owned()));
+    }
+
+    let filter = if cli.min_asset_size.is_some() || cli.max_asset_size.is_some() {
+        Some(packling::filter::AssetFilter::default().with_size_range(
+            cli.min_asset_size.as_deref().map(parse_size_arg).transpose()?,
+            cli.max_asset_size.as_deref().map(parse_size_arg).transpose()?,
+        )?)
+    } else {
+        None
+    };
+    let options = packling::flow_unpack::UnpackOptions {
+        force,
+        order_file: cli.order_file.as_deref(),
+        order_file_crcs: cli.order_file_crcs,
+        keep_going: cli.keep_going,
+        raw: cli.raw,
+        timezone: resolve_timezone(cli.local, cli.timezone.as_deref())?,
+        on_conflict,
+        no_clobber: cli.no_clobber,
+        extended_offsets: cli.extended_offsets,
+        report: cli.report.as_deref(),
+        progress: cli.progress,
+        flatten: cli.flatten,
+        permissions: packling::flow_unpack::Permissions {
+            mode: cli.chmod.as_deref().map(parse_mode_arg).transpose()?,
+            no_executable: cli.no_executable,
+        },
+        game,
+        recursive: cli.recursive,
+        no_space_check: cli.no_space_check,
+        filter: filter.as_ref(),
     };
 
-    crate::flow_unpack::unpack(&cli.input, &output, key, cli.force, cli.order_file.as_deref(), verbosity)
+    #[cfg(feature = "async-io")]
+    if cli.async_io {
+        if cli.extended_offsets {
+            bail!("--extended-offsets can't be combined with --async-io");
+        }
+        return packling::flow_unpack_async::unpack(&cli.input, &output, key, &options, verbosity);
+    }
+
+    packling::flow_unpack::unpack(&cli.input, &output, key, &options, verbosity)
 }
 
 
-fn handle_pack_folder_to_file(cli: Cli, key: KeyRef, verbosity: Verbosity) -> anyhow::Result<()> {
+fn handle_pack_folder_to_file(cli: ConvertArgs, key: KeyRef, verbosity: Verbosity) -> anyhow::Result<()> {
+    if cli.output.as_deref().is_some_and(is_stdout_path) {
+        bail!("only decrypted .pak files can be written to stdout (-), not when packing");
+    }
+    if cli.keep_going {
+        bail!("--keep-going is only allowed when unpacking");
+    }
+    if cli.recover_with.is_some() {
+        bail!("--recover-with is only allowed when unpacking");
+    }
+    if cli.no_space_check {
+        bail!("--no-space-check is only allowed when unpacking");
+    }
+    if cli.min_asset_size.is_some() || cli.max_asset_size.is_some() {
+        bail!("--min-asset-size and --max-asset-size are only allowed when unpacking (use --max-size to limit the size of the .pak)");
+    }
+    #[cfg(feature = "async-io")]
+    if cli.async_io {
+        bail!("--async-io is only allowed when unpacking");
+    }
+    if cli.raw.is_some() {
+        bail!("--raw is only allowed when unpacking (raw assets are detected automatically when packing)");
+    }
+    if cli.on_conflict.is_some() || cli.merge || cli.no_clobber {
+        bail!("--on-conflict, --merge and --no-clobber are only allowed when unpacking");
+    }
+    if cli.flatten {
+        bail!("--flatten is only allowed when unpacking");
+    }
+    if cli.chmod.is_some() || cli.no_executable {
+        bail!("--chmod and --no-executable are only allowed when unpacking");
+    }
+    if cli.order_file_crcs {
+        bail!("--order-file-crcs is only allowed when unpacking (CRCs in the order file are always checked when packing)");
+    }
+
     let output = match cli.output {
         Some(p) => p,
-        None => pick_default_output_file(&cli.input),
+        None => pick_unused_output_path(pick_default_output_file(&cli.input), true, cli.force || cli.yes, verbosity),
     };
 
-    let timestamp = parse_timestamp_arg(cli.timestamp.as_deref())?;
+    let timezone = resolve_timezone(cli.local, cli.timezone.as_deref())?;
+    let timestamp = if cli.keep_timestamp {
+        let Some(template) = &cli.template else {
+            bail!("--keep-timestamp requires --template when packing");
+        };
+        packling::shared::read_pak_header(template)?.timestamp
+    } else {
+        parse_timestamp_arg(cli.timestamp.as_deref(), timezone)?
+    };
 
     let should_decrypt = matches!(cli.output_format, OutputFormat::DecryptedPakFile);
+    if should_decrypt && cli.extended_offsets {
+        bail!("--extended-offsets can't be combined with decrypted output");
+    }
+    if should_decrypt && cli.deploy.is_some() {
+        bail!("--deploy can't be combined with decrypted output (the game can't load it)");
+    }
+    if cli.extended_offsets && cli.deploy.is_some() {
+        bail!("--deploy can't be combined with --extended-offsets (the game can't load it)");
+    }
+    let deploy_target = cli.deploy.as_deref().map(packling::deploy::DeployTarget::parse).transpose()?;
+    let max_size = cli.max_size.as_deref().map(parse_max_size_arg).transpose()?;
+    if cli.extended_offsets {
+        eprintln!("warning: creating a .pak with the experimental extended-offsets layout, which the game can't load");
+    }
 
     // Skipping encryption during packing makes it impossible to
     // calculate the correct whole-file checksum, so instead, we pack
     // the whole thing encrypted, and then decrypt it afterward
 
-    crate::flow_pack::pack(&cli.input, &output, key, timestamp, cli.force, cli.compress_header, cli.compress_files, cli.order_file.as_deref(), verbosity)?;
+    // (resuming continues the output file, rather than overwriting it,
+    // if it's from an interrupted pack)
+    let force = if cli.resume && packling::journal::journal_path(&output).exists() {
+        cli.force
+    } else {
+        confirm_destructive(
+            &output,
+            &format!("Output file {output:?} already exists. Overwrite it?"),
+            cli.force,
+            cli.yes,
+        )?
+    };
+
+    let options = packling::flow_pack::PackOptions {
+        timestamp,
+        force,
+        compress_header: cli.compress_header,
+        compress_files: cli.compress_files,
+        order_file: cli.order_file.as_deref(),
+        asset_order: cli.asset_order,
+        strict_order: cli.strict_order,
+        normalization: cli.normalize,
+        keep_empty_folders: cli.keep_empty_folders,
+        overlays: &cli.roots,
+        recursive: cli.recursive,
+        extended_offsets: cli.extended_offsets,
+        warn_plaintext_tails: cli.warn_plaintext_tails,
+        max_size,
+        report: cli.report.as_deref(),
+        progress: cli.progress,
+        resume: cli.resume,
+    };
+    packling::flow_pack::pack(&cli.input, &output, key, &options, verbosity)?;
 
     if should_decrypt {
-        crate::flow_just_decrypt::decrypt(
+        packling::flow_just_decrypt::decrypt(
             &output,
             &output,
             key,
             true,
+            timezone,
             verbosity,
         )?;
     }
 
+    if let Some(target) = &deploy_target {
+        packling::flow_verify::verify(&output, key, None, verbosity)?;
+        packling::deploy::deploy(&output, target, verbosity)?;
+    }
+
     Ok(())
 }
 
 
-fn handle_repack_file_to_file(cli: Cli, key: KeyRef, verbosity: Verbosity) -> anyhow::Result<()> {
+fn handle_repack_file_to_file(cli: ConvertArgs, key: KeyRef, verbosity: Verbosity) -> anyhow::Result<()> {
     if cli.compress_header {
         bail!("--compress-header is not allowed when encrypting or decrypting a file to another file");
     }
@@ -214,11 +1733,67 @@ fn handle_repack_file_to_file(cli: Cli, key: KeyRef, verbosity: Verbosity) -> an
     if cli.order_file.is_some() {
         bail!("--order-file is not allowed when encrypting or decrypting a file to another file");
     }
-
-    let output = match cli.output {
-        Some(p) => p,
-        None => cli.input.clone(),  // shrug
-    };
+    if cli.keep_going {
+        bail!("--keep-going is not allowed when encrypting or decrypting a file to another file");
+    }
+    if cli.recover_with.is_some() {
+        bail!("--recover-with is not allowed when encrypting or decrypting a file to another file");
+    }
+    if cli.no_space_check {
+        bail!("--no-space-check is not allowed when encrypting or decrypting a file to another file");
+    }
+    if cli.resume {
+        bail!("--resume is not allowed when encrypting or decrypting a file to another file");
+    }
+    if cli.min_asset_size.is_some() || cli.max_asset_size.is_some() {
+        bail!("--min-asset-size and --max-asset-size are not allowed when encrypting or decrypting a file to another file");
+    }
+    #[cfg(feature = "async-io")]
+    if cli.async_io {
+        bail!("--async-io is not allowed when encrypting or decrypting a file to another file");
+    }
+    if cli.raw.is_some() {
+        bail!("--raw is not allowed when encrypting or decrypting a file to another file");
+    }
+    if cli.on_conflict.is_some() || cli.merge || cli.no_clobber {
+        bail!("--on-conflict, --merge and --no-clobber are not allowed when encrypting or decrypting a file to another file");
+    }
+    if cli.template.is_some() {
+        bail!("--template is not allowed when encrypting or decrypting a file to another file (the input file's timestamp is kept)");
+    }
+    if cli.extended_offsets {
+        bail!("--extended-offsets is not allowed when encrypting or decrypting a file to another file");
+    }
+    if cli.keep_empty_folders {
+        bail!("--keep-empty-folders is not allowed when encrypting or decrypting a file to another file");
+    }
+    if !cli.roots.is_empty() {
+        bail!("--root is not allowed when encrypting or decrypting a file to another file");
+    }
+    if cli.recursive {
+        bail!("--recursive is not allowed when encrypting or decrypting a file to another file");
+    }
+    if cli.warn_plaintext_tails {
+        bail!("--warn-plaintext-tails is not allowed when encrypting or decrypting a file to another file");
+    }
+    if cli.report.is_some() {
+        bail!("--report is not allowed when encrypting or decrypting a file to another file");
+    }
+    if cli.progress {
+        bail!("--progress is not allowed when encrypting or decrypting a file to another file");
+    }
+    if cli.deploy.is_some() {
+        bail!("--deploy is not allowed when encrypting or decrypting a file to another file");
+    }
+    if cli.max_size.is_some() {
+        bail!("--max-size is not allowed when encrypting or decrypting a file to another file");
+    }
+    if cli.flatten {
+        bail!("--flatten is not allowed when encrypting or decrypting a file to another file");
+    }
+    if cli.chmod.is_some() || cli.no_executable {
+        bail!("--chmod and --no-executable are not allowed when encrypting or decrypting a file to another file");
+    }
 
     let input_encryption = check_is_encrypted(&cli.input)?;
     let output_encryption = match cli.output_format {
@@ -236,14 +1811,59 @@ fn handle_repack_file_to_file(cli: Cli, key: KeyRef, verbosity: Verbosity) -> an
         }
     }
 
+    if cli.output.as_deref().is_some_and(is_stdout_path) {
+        if output_encryption {
+            bail!("only decrypted .pak files can be written to stdout");
+        }
+        if std::io::stdout().is_terminal() {
+            bail!("not writing a .pak file to a terminal (redirect stdout, or give an output path)");
+        }
+        // (anything else printed to stdout would end up in the .pak)
+        return packling::flow_just_decrypt::decrypt_to_writer(
+            &cli.input,
+            std::io::BufWriter::new(std::io::stdout().lock()),
+            key,
+            resolve_timezone(cli.local, cli.timezone.as_deref())?,
+            Verbosity::Silent,
+        );
+    }
+
+    // (converting in place only ever happens when asked for explicitly)
+    let output = match cli.output {
+        Some(p) => p,
+        None => {
+            let suffix = if output_encryption { "_encrypted" } else { "_decrypted" };
+            let default = pick_default_output_converted_file(&cli.input, suffix);
+            pick_unused_output_path(default, true, cli.force || cli.yes, verbosity)
+        },
+    };
+
+    let in_place = output.exists() && output.canonicalize()? == cli.input.canonicalize()?;
+    let force = if in_place {
+        // Converting in place has never needed -f, so only ask when
+        // running interactively
+        if !confirm_destructive(&output, &format!("Convert {output:?} in place?"), true, cli.yes)? {
+            bail!("not converting in place");
+        }
+        cli.force
+    } else {
+        confirm_destructive(
+            &output,
+            &format!("Output file {output:?} already exists. Overwrite it?"),
+            cli.force,
+            cli.yes,
+        )?
+    };
+
     if output_encryption {
         todo!()
     } else {
-        crate::flow_just_decrypt::decrypt(
+        packling::flow_just_decrypt::decrypt(
             &cli.input,
             &output,
             key,
-            cli.force,
+            force,
+            resolve_timezone(cli.local, cli.timezone.as_deref())?,
             verbosity,
         )?;
     }
@@ -252,8 +1872,350 @@ fn handle_repack_file_to_file(cli: Cli, key: KeyRef, verbosity: Verbosity) -> an
 }
 
 
-fn handle_print_file_info(_cli: Cli, _key: KeyRef, _verbosity: Verbosity) -> anyhow::Result<()> {
-    todo!()
+fn handle_print_file_info(cli: ConvertArgs, key: KeyRef, _verbosity: Verbosity) -> anyhow::Result<()> {
+    if cli.output.is_some() {
+        bail!("an output path is not allowed when printing info");
+    }
+
+    packling::flow_info::print_info(&cli.input, key, false, false, resolve_timezone(cli.local, cli.timezone.as_deref())?)
+}
+
+
+fn handle_info(args: InfoArgs) -> anyhow::Result<()> {
+    let key = packling::key::get_key_for_pak(&args.pak.key_file, &args.pak.input)?;
+    packling::flow_info::print_info(&args.pak.input, &key, args.hexdump, args.types, args.timezone.resolve()?)?;
+
+    if let Some(dump_file) = &args.dump_assets_list {
+        packling::flow_info::dump_assets_list(&args.pak.input, dump_file, &key)?;
+    }
+
+    Ok(())
+}
+
+
+fn handle_explain(args: ExplainArgs) -> anyhow::Result<()> {
+    let key = packling::key::get_key_for_pak(&args.pak.key_file, &args.pak.input)?;
+    packling::flow_explain::explain(&args.pak.input, &key, args.entries, args.timezone.resolve()?)
+}
+
+
+fn handle_verify(args: VerifyArgs) -> anyhow::Result<()> {
+    let verbosity = if args.quiet { Verbosity::Quiet } else { Verbosity::Normal };
+    let Some(input) = args.input else {
+        // (only allowed with --as-game, whose checks don't involve the
+        // key, so the only file given is the .pak)
+        return packling::flow_verify::verify_as_game(&args.key_file, verbosity);
+    };
+    if args.as_game {
+        return packling::flow_verify::verify_as_game(&input, verbosity);
+    }
+    let key = packling::key::get_key_for_pak(&args.key_file, &input)?;
+    if args.fast {
+        return packling::flow_verify::verify_fast(&input, &key, args.report.as_deref(), verbosity);
+    }
+    packling::flow_verify::verify(&input, &key, args.report.as_deref(), verbosity)
+}
+
+
+fn handle_list(args: ListArgs) -> anyhow::Result<()> {
+    let key = packling::key::get_key_for_pak(&args.pak.key_file, &args.pak.input)?;
+
+    let mut filter = packling::filter::AssetFilter::default();
+    if let Some(pattern) = &args.filter {
+        filter = if args.regex {
+            filter.with_regex(pattern)?
+        } else {
+            filter.with_glob(pattern)?
+        };
+    }
+    let filter = filter.with_size_range(
+        args.min_size.as_deref().map(parse_size_arg).transpose()?,
+        args.max_size.as_deref().map(parse_size_arg).transpose()?,
+    )?;
+
+    let options = packling::flow_list::ListOptions {
+        filter,
+        sort: args.sort,
+        reverse: args.reverse,
+        long: args.long,
+        format: args.format,
+        types: args.types,
+        encryption: args.encryption,
+        print0: args.print0,
+    };
+
+    packling::flow_list::list(&args.pak.input, &key, &options)
+}
+
+
+fn handle_map(args: MapArgs) -> anyhow::Result<()> {
+    let key = packling::key::get_key_for_pak(&args.pak.key_file, &args.pak.input)?;
+    packling::flow_map::print_map(&args.pak.input, &key, args.format)
+}
+
+
+fn handle_slack(args: SlackArgs) -> anyhow::Result<()> {
+    let key = packling::key::get_key_for_pak(&args.pak.key_file, &args.pak.input)?;
+    packling::flow_slack::print_slack(&args.pak.input, &key, args.format, args.extract.as_deref())
+}
+
+
+fn handle_stats(args: StatsArgs) -> anyhow::Result<()> {
+    let key = packling::key::get_key_for_pak(&args.pak.key_file, &args.pak.input)?;
+    packling::flow_stats::print_stats(&args.pak.input, &key, args.by_dir, args.format)
+}
+
+
+fn handle_diff(args: DiffArgs) -> anyhow::Result<()> {
+    let key = packling::key::get_key_for_pak(&args.key_file, &args.old)?;
+    packling::flow_diff::print_diff(&args.old, &args.new, &key, args.format)
+}
+
+
+fn handle_compare(args: CompareArgs) -> anyhow::Result<()> {
+    // (nothing here involves the key)
+    let verbosity = if args.quiet { Verbosity::Quiet } else { Verbosity::Normal };
+    packling::flow_compare::compare(&args.old, &args.new, args.block_size, verbosity)
+}
+
+
+fn handle_delta(args: DeltaArgs) -> anyhow::Result<()> {
+    let key = packling::key::get_key_for_pak(&args.key_file, &args.old)?;
+    let verbosity = if args.quiet { Verbosity::Silent } else { Verbosity::Normal };
+    packling::flow_delta::create_delta(&args.old, &args.new, &args.patch, &key, args.format, args.force, verbosity)
+}
+
+
+fn handle_apply(args: ApplyArgs) -> anyhow::Result<()> {
+    let key = packling::key::get_key_for_pak(&args.key_file, &args.old)?;
+    let verbosity = if args.quiet { Verbosity::Quiet } else { Verbosity::Normal };
+    packling::flow_delta::apply_delta(&args.old, &args.patch, &args.new, &key, args.force, verbosity)
+}
+
+
+fn handle_order(args: OrderArgs) -> anyhow::Result<()> {
+    match args.command {
+        OrderCommand::Check(args) => packling::flow_order::check_order_file(&args.order_file, &args.folder, args.fix),
+    }
+}
+
+
+fn handle_assets_list(args: AssetsListArgs) -> anyhow::Result<()> {
+    match args.command {
+        AssetsListCommand::Export(args) => {
+            let key = packling::key::get_key_for_pak(&args.pak.key_file, &args.pak.input)?;
+            let format = args.format.unwrap_or_else(|| {
+                args.output.as_deref().map_or_else(Default::default, packling::flow_assets_list::AssetsListFormat::from_path)
+            });
+            packling::flow_assets_list::export(&args.pak.input, &key, format, args.output.as_deref())
+        },
+        AssetsListCommand::Import(args) => {
+            let key = packling::key::get_key_for_pak(&args.pak.key_file, &args.pak.input)?;
+            packling::flow_assets_list::import(&args.pak.input, &args.dump, &args.output, &key, args.force)
+        },
+    }
+}
+
+
+fn handle_peek(args: PeekArgs) -> anyhow::Result<()> {
+    let key = packling::key::get_key_for_pak(&args.pak.key_file, &args.pak.input)?;
+    packling::flow_peek::peek(&args.pak.input, &key, &args.asset, args.bytes, args.strings)
+}
+
+
+fn handle_stat(args: StatArgs) -> anyhow::Result<()> {
+    let key = packling::key::get_key_for_pak(&args.pak.key_file, &args.pak.input)?;
+    packling::flow_stat::stat(&args.pak.input, &key, &args.asset)
+}
+
+
+fn handle_extract(args: ExtractArgs) -> anyhow::Result<()> {
+    let key = packling::key::get_key_for_pak(&args.pak.key_file, &args.pak.input)?;
+
+    if let Some(range) = &args.raw_range {
+        let (offset, len) = parse_range_arg(range)?;
+        packling::flow_extract::extract_raw_range(
+            &args.pak.input,
+            &key,
+            offset,
+            len,
+            args.name.as_deref(),
+            args.output.as_deref(),
+        )
+    } else if let Some(index) = args.asset_index {
+        packling::flow_extract::extract_by_index(&args.pak.input, &key, index, args.output.as_deref())
+    } else {
+        bail!("either --asset-index or --raw-range is required");
+    }
+}
+
+
+fn handle_grep(args: GrepArgs) -> anyhow::Result<()> {
+    let key = packling::key::get_key_for_pak(&args.pak.key_file, &args.pak.input)?;
+
+    let kind = if args.regex {
+        packling::flow_grep::PatternKind::Regex
+    } else if args.hex {
+        packling::flow_grep::PatternKind::Hex
+    } else {
+        packling::flow_grep::PatternKind::Literal
+    };
+
+    packling::flow_grep::grep(&args.pak.input, &key, &args.pattern, kind, args.files_with_matches)
+}
+
+
+fn handle_index(args: IndexArgs) -> anyhow::Result<()> {
+    packling::flow_index::build_index(&args.input, &args.output, &args.key_file, args.force)
+}
+
+
+fn handle_unpack_all(args: UnpackAllArgs) -> anyhow::Result<()> {
+    let verbosity = if args.quiet { Verbosity::Quiet } else { Verbosity::Normal };
+
+    let options = packling::flow_unpack::UnpackOptions {
+        force: args.force,
+        keep_going: args.keep_going,
+        game: args.game,
+        recursive: args.recursive,
+        no_space_check: args.no_space_check,
+        ..packling::flow_unpack::UnpackOptions::default()
+    };
+    packling::flow_unpack_all::unpack_all(&args.input, &args.key_file, &options, verbosity)
+}
+
+
+fn handle_which(args: WhichArgs) -> anyhow::Result<()> {
+    let filter = packling::filter::AssetFilter::default();
+    let filter = if args.regex {
+        filter.with_regex(&args.asset)?
+    } else {
+        filter.with_glob(&args.asset)?
+    };
+
+    packling::flow_index::which(&args.index, &filter)
+}
+
+
+fn handle_crc(args: CrcArgs) -> anyhow::Result<()> {
+    use packling::flow_crc::{CrcInit, CrcOptions};
+
+    let options = if args.pak {
+        CrcOptions::pak_header()
+    } else {
+        CrcOptions {
+            jam: args.jam,
+            init: if args.init == "size" {
+                CrcInit::FileSize
+            } else {
+                CrcInit::Value(parse_int_arg(&args.init)?.try_into()?)
+            },
+            start: parse_int_arg(&args.start)?,
+        }
+    };
+
+    packling::flow_crc::print_crc(&args.input, &options)
+}
+
+
+fn handle_asset_crc(args: AssetCrcArgs) -> anyhow::Result<()> {
+    // (there's no .pak or extracted folder to find any other key with,
+    // so it has to be the known one)
+    let key = packling::key::get_key(&args.key_file)?;
+    packling::flow_crc::print_asset_fields(&args.input, &args.name, &key, args.compress)
+}
+
+
+fn handle_bench(args: BenchArgs) -> anyhow::Result<()> {
+    let key = packling::key::get_key_for_pak(&args.pak.key_file, &args.pak.input)?;
+    packling::flow_bench::bench(&args.pak.input, &key, args.write_to.as_deref())
+}
+
+
+fn handle_pack(args: PackArgs) -> anyhow::Result<()> {
+    // (a project set up from a .pak by `init` has the assets extracted
+    // from it, with a sidecar recording its key if it isn't the known
+    // one)
+    let project_folder = args.manifest.parent().unwrap_or(Path::new(""));
+    let assets_folder = project_folder.join(packling::flow_init::ASSETS_FOLDER_NAME);
+    let key = packling::key::get_key_for_folder(&args.key_file, &assets_folder)?;
+    let verbosity = match args.quiet {
+        0 => Verbosity::Normal,
+        1 => Verbosity::Quiet,
+        _ => Verbosity::Silent,
+    };
+
+    let deploy_target = args.deploy.as_deref().map(packling::deploy::DeployTarget::parse).transpose()?;
+    let max_size = args.max_size.as_deref().map(parse_max_size_arg).transpose()?;
+    let manifest = packling::manifest::Manifest::load(&args.manifest)?;
+    let timestamp = parse_timestamp_arg(manifest.timestamp.as_deref(), args.timezone.resolve()?)?;
+
+    // (resuming continues the output file, rather than overwriting it,
+    // if it's from an interrupted pack)
+    let force = if args.resume && packling::journal::journal_path(&args.output).exists() {
+        args.force
+    } else {
+        confirm_destructive(
+            &args.output,
+            &format!("Output file {:?} already exists. Overwrite it?", args.output),
+            args.force,
+            args.yes,
+        )?
+    };
+
+    let options = packling::flow_pack::PackOptions {
+        timestamp,
+        force,
+        max_size,
+        report: args.report.as_deref(),
+        progress: args.progress,
+        resume: args.resume,
+        ..packling::flow_pack::PackOptions::default()
+    };
+    packling::flow_pack::pack_manifest(&args.manifest, &manifest, &args.output, &key, &options, verbosity)?;
+
+    if let Some(target) = &deploy_target {
+        packling::flow_verify::verify(&args.output, &key, None, verbosity)?;
+        packling::deploy::deploy(&args.output, target, verbosity)?;
+    }
+
+    Ok(())
+}
+
+
+fn handle_init(args: InitArgs) -> anyhow::Result<()> {
+    let key = match &args.key {
+        Some(key_file) if args.input.is_file() => Some(packling::key::get_key_for_pak(key_file, &args.input)?),
+        Some(key_file) => Some(packling::key::get_key_for_folder(key_file, &args.input)?),
+        None => None,
+    };
+    let verbosity = match args.quiet {
+        0 => Verbosity::Normal,
+        1 => Verbosity::Quiet,
+        _ => Verbosity::Silent,
+    };
+    packling::flow_init::init(&args.input, &args.project, key.as_deref(), verbosity)
+}
+
+
+fn handle_layeredfs(args: LayeredfsArgs) -> anyhow::Result<()> {
+    let verbosity = if args.quiet { Verbosity::Quiet } else { Verbosity::Normal };
+    let folder = packling::deploy::layeredfs_folder(&args.title_id, &args.romfs_path)?;
+    let target = packling::deploy::DeployTarget::parse(&args.root)?.join(&folder);
+    packling::deploy::deploy(&args.input, &target, verbosity)
+}
+
+
+fn handle_man(args: ManArgs) -> anyhow::Result<()> {
+    let man = clap_mangen::Man::new(Cli::command());
+    if let Some(output) = args.output {
+        let mut buffer = Vec::new();
+        man.render(&mut buffer)?;
+        std::fs::write(output, buffer)?;
+    } else {
+        man.render(&mut std::io::stdout().lock())?;
+    }
+    Ok(())
 }
 
 
@@ -261,33 +2223,299 @@ fn handle_print_file_info(_cli: Cli, _key: KeyRef, _verbosity: Verbosity) -> any
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
-    let verbosity = if cli.quiet {
-        Verbosity::NotVerbose
+    if let Some(kind) = cli.schema {
+        print!("{}", kind.schema());
+        return Ok(());
+    }
+
+    let threads = match cli.threads {
+        Some(threads) => Some(threads),
+        None => match std::env::var(THREADS_ENV_VAR) {
+            Ok(value) => match value.trim().parse() {
+                Ok(threads) => Some(threads),
+                Err(_) => bail!("${THREADS_ENV_VAR} should be a number of threads, not {value:?}"),
+            },
+            Err(_) => None,
+        },
+    };
+    if threads == Some(0) {
+        bail!("the number of threads can't be 0");
+    }
+    if let Some(threads) = threads {
+        packling::shared::set_thread_count(threads);
+    }
+
+    if let Some(temp_dir) = cli.temp_dir {
+        if !temp_dir.is_dir() {
+            bail!("temporary folder {} not found", temp_dir.display());
+        }
+        packling::shared::set_temp_dir(temp_dir);
+    }
+
+    let result = match cli.command {
+        Some(Command::Info(args)) => handle_info(args),
+        Some(Command::Explain(args)) => handle_explain(args),
+        Some(Command::Verify(args)) => handle_verify(args),
+        Some(Command::List(args)) => handle_list(args),
+        Some(Command::Map(args)) => handle_map(args),
+        Some(Command::Slack(args)) => handle_slack(args),
+        Some(Command::Stats(args)) => handle_stats(args),
+        Some(Command::Diff(args)) => handle_diff(args),
+        Some(Command::Compare(args)) => handle_compare(args),
+        Some(Command::Delta(args)) => handle_delta(args),
+        Some(Command::Apply(args)) => handle_apply(args),
+        Some(Command::Order(args)) => handle_order(args),
+        Some(Command::AssetsList(args)) => handle_assets_list(args),
+        Some(Command::Peek(args)) => handle_peek(args),
+        Some(Command::Stat(args)) => handle_stat(args),
+        Some(Command::Extract(args)) => handle_extract(args),
+        Some(Command::Grep(args)) => handle_grep(args),
+        Some(Command::Index(args)) => handle_index(args),
+        Some(Command::UnpackAll(args)) => handle_unpack_all(args),
+        Some(Command::Which(args)) => handle_which(args),
+        Some(Command::Crc(args)) => handle_crc(args),
+        Some(Command::AssetCrc(args)) => handle_asset_crc(args),
+        Some(Command::Bench(args)) => handle_bench(args),
+        Some(Command::Pack(args)) => handle_pack(args),
+        Some(Command::Init(args)) => handle_init(args),
+        Some(Command::Layeredfs(args)) => handle_layeredfs(args),
+        Some(Command::Man(args)) => handle_man(args),
+        None => {
+            let Some(convert) = cli.convert else {
+                bail!("internal error: neither a subcommand nor conversion arguments were parsed");
+            };
+            convert_main(convert)
+        },
+    };
+
+    // (what was done before the interruption has already been printed)
+    if let Err(ref e) = result
+        && e.is::<packling::interrupt::Interrupted>()
+    {
+        std::process::exit(packling::interrupt::INTERRUPTED_EXIT_CODE);
+    }
+    result
+}
+
+
+/// Entrypoint for the default (no subcommand) mode
+fn convert_main(mut cli: ConvertArgs) -> anyhow::Result<()> {
+    let verbosity = match (cli.quiet, cli.verbose) {
+        (0, 0) => Verbosity::Normal,
+        (0, 1) => Verbosity::Verbose,
+        (0, _) => Verbosity::Debug,
+        (1, _) => Verbosity::Quiet,
+        _ => Verbosity::Silent,
+    };
+
+    let input_is_pak = if cli.unpack {
+        true
+    } else if cli.pack {
+        false
+    } else if cli.input.is_file() {
+        true
+    } else if cli.input.is_dir() {
+        false
+    } else if cli.input.exists() {
+        bail!("input isn't a regular file or folder (use --unpack to read a .pak from a pipe or device)");
     } else {
-        Verbosity::Verbose
+        bail!("input file/folder not found");
+    };
+
+    // Pipes and devices can't be seeked, so copy them to a temporary
+    // file first
+    let mut spooled_input = None;
+    if input_is_pak && !cli.input.is_file() {
+        if cli.output.is_none() && cli.output_format != OutputFormat::PrintInfo {
+            bail!("an output path is required when the input isn't a regular file");
+        }
+        let path = spool_to_temp_file(&cli.input)?;
+        cli.input.clone_from(&path);
+        spooled_input = Some(path);
+    }
+
+    let key = match cli.game {
+        Some(game) => packling::key::get_key_for_game(&cli.key_file, game),
+        None if input_is_pak => packling::key::get_key_for_pak(&cli.key_file, &cli.input),
+        None => packling::key::get_key_for_folder(&cli.key_file, &cli.input),
+    };
+    let key = match key {
+        Ok(key) => key,
+        Err(e) => {
+            if let Some(path) = spooled_input {
+                let _ = std::fs::remove_file(path);
+            }
+            return Err(e);
+        },
     };
 
-    let key = crate::key::get_key(&cli.key_file)?;
+    let key_description = packling::key::describe_key(&cli.key_file, &key);
+    if verbosity >= Verbosity::Verbose {
+        // (a .pak written to stdout would start with it otherwise)
+        if cli.output.as_deref().is_some_and(is_stdout_path) {
+            eprintln!("Using {key_description}");
+        } else {
+            println!("Using {key_description}");
+        }
+    }
 
-    if cli.input.is_file() {
+    let result = if input_is_pak {
         match cli.output_format {
+            OutputFormat::Default if cli.output.as_deref().is_some_and(is_stdout_path) => {
+                handle_repack_file_to_file(cli, &key, verbosity)
+            },
             OutputFormat::Folder
-            | OutputFormat::Default => handle_unpack_file_to_folder(cli, &key, verbosity)?,
+            | OutputFormat::Default => handle_unpack_file_to_folder(cli, &key, verbosity),
             OutputFormat::EncryptedPakFile
-            | OutputFormat::DecryptedPakFile => handle_repack_file_to_file(cli, &key, verbosity)?,
-            OutputFormat::PrintInfo => handle_print_file_info(cli, &key, verbosity)?,
+            | OutputFormat::DecryptedPakFile => handle_repack_file_to_file(cli, &key, verbosity),
+            OutputFormat::PrintInfo => handle_print_file_info(cli, &key, verbosity),
         }
-    } else if cli.input.is_dir() {
+    } else {
         match cli.output_format {
             OutputFormat::EncryptedPakFile
             | OutputFormat::DecryptedPakFile
-            | OutputFormat::Default => handle_pack_folder_to_file(cli, &key, verbosity)?,
+            | OutputFormat::Default => handle_pack_folder_to_file(cli, &key, verbosity),
             OutputFormat::Folder => bail!("converting an extracted folder to an extracted folder doesn't make sense"),
             OutputFormat::PrintInfo => bail!("printing info about an extracted folder doesn't make sense"),
         }
+    };
+
+    if let Some(path) = spooled_input {
+        let _ = std::fs::remove_file(path);
+    }
+
+    // (so that a wrong key file is easy to spot in pasted logs)
+    result.map_err(|e| if e.chain().any(|cause| cause.is::<packling::key::DecryptionError>()) {
+        e.context(format!("decryption failed, using {key_description}"))
     } else {
-        bail!("input file/folder not found");
+        e
+    })
+}
+
+
+/// Copy an input that can't be seeked (like a pipe) to a new temporary
+/// file, and return its path.
+fn spool_to_temp_file(input: &Path) -> anyhow::Result<PathBuf> {
+    let mut reader = std::fs::File::open(input)?;
+    let path = packling::shared::temp_dir().join(format!("packling-input-{}.pak", std::process::id()));
+    let mut writer = std::fs::File::create_new(&path)?;
+    if let Err(e) = std::io::copy(&mut reader, &mut writer) {
+        let _ = std::fs::remove_file(&path);
+        return Err(e.into());
     }
+    Ok(path)
+}
 
-    Ok(())
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size_filter_args() {
+        let cli = Cli::try_parse_from(["packling", "key.bin", "foo.pak", "--max-asset-size", "1M"]).unwrap();
+        assert_eq!(cli.convert.unwrap().max_asset_size.as_deref(), Some("1M"));
+        let cli = Cli::try_parse_from(["packling", "key.bin", "foo", "--max-size", "match:foo.pak"]).unwrap();
+        assert_eq!(cli.convert.unwrap().max_size.as_deref(), Some("match:foo.pak"));
+    }
+
+    #[test]
+    fn test_parse_convert_args() {
+        let cli = Cli::try_parse_from(["packling", "key.bin", "foo.pak", "--local"]).unwrap();
+        assert!(cli.command.is_none());
+        let convert = cli.convert.unwrap();
+        assert_eq!(convert.input, Path::new("foo.pak"));
+        assert!(convert.local);
+        assert!(Cli::try_parse_from(["packling", "info", "key.bin", "foo.pak"]).unwrap().convert.is_none());
+    }
+
+    #[test]
+    fn test_parse_verify_args() {
+        let Some(Command::Verify(args)) = Cli::try_parse_from(["packling", "verify", "--as-game", "foo.pak"]).unwrap().command else {
+            panic!("not parsed as verify");
+        };
+        assert_eq!(args.key_file, Path::new("foo.pak"));
+        assert!(args.input.is_none());
+        let Some(Command::Verify(args)) = Cli::try_parse_from(["packling", "verify", "--as-game", "key.bin", "foo.pak"]).unwrap().command else {
+            panic!("not parsed as verify");
+        };
+        assert_eq!(args.input.as_deref(), Some(Path::new("foo.pak")));
+        // (the key is needed for the other checks)
+        assert!(Cli::try_parse_from(["packling", "verify", "foo.pak"]).is_err());
+    }
+
+    #[test]
+    fn test_numbered_path() {
+        assert_eq!(numbered_path(Path::new("dir/foo.pak"), 2, true), Path::new("dir/foo (2).pak"));
+        assert_eq!(numbered_path(Path::new("dir/foo"), 3, true), Path::new("dir/foo (3)"));
+        assert_eq!(numbered_path(Path::new("dir/foo.v2"), 2, false), Path::new("dir/foo.v2 (2)"));
+        assert_eq!(pick_default_output_converted_file(Path::new("dir/foo.pak"), "_decrypted"), Path::new("dir/foo_decrypted.pak"));
+    }
+
+    #[test]
+    fn test_parse_relative_time() {
+        assert_eq!(parse_relative_time("+3d"), Some(3 * 24 * 60 * 60));
+        assert_eq!(parse_relative_time("-12h"), Some(-12 * 60 * 60));
+        assert_eq!(parse_relative_time("+90s"), Some(90));
+        assert_eq!(parse_relative_time("+1w"), Some(7 * 24 * 60 * 60));
+        assert_eq!(parse_relative_time("3d"), None);
+        assert_eq!(parse_relative_time("+d"), None);
+        assert_eq!(parse_relative_time("+3y"), None);
+        assert_eq!(parse_relative_time("+-3d"), None);
+        assert_eq!(parse_relative_time("+"), None);
+    }
+
+    #[test]
+    fn test_parse_timestamp_arg() {
+        let parse = |ts| parse_timestamp_arg(Some(ts), TimeZone::Utc).unwrap();
+        assert_eq!(parse("1234"), 1234);
+        assert_eq!(parse("-1234"), -1234);
+        assert_eq!(parse("0x100"), 0x100);
+        assert_eq!(parse("2000-01-01T01:01:01"), 946_688_461);
+        assert_eq!(parse("2000-01-01"), 946_684_800);
+        assert_eq!(parse("2000-01-01T01:01:01Z"), 946_688_461);
+        assert_eq!(parse("2000-01-01T10:01:01+09:00"), 946_688_461);
+        assert_eq!(parse("1999-12-31T20:01:01.5-05:00"), 946_688_461);
+        assert_eq!(
+            parse_timestamp_arg(Some("2000-01-01T01:01:01+09:00"), TimeZone::Local).unwrap(),
+            parse("2000-01-01T01:01:01+09:00"),
+        );
+        assert!(parse_timestamp_arg(Some("yesterday-ish"), TimeZone::Utc).is_err());
+    }
+
+    #[test]
+    fn test_parse_range_arg() {
+        assert_eq!(parse_range_arg("16:32").unwrap(), (16, 32));
+        assert_eq!(parse_range_arg("0x100:0x20").unwrap(), (0x100, 0x20));
+        assert!(parse_range_arg("0x100").is_err());
+        assert!(parse_range_arg("0x100:").is_err());
+    }
+
+    #[test]
+    fn test_parse_size_arg() {
+        assert_eq!(parse_size_arg("1000").unwrap(), 1000);
+        assert_eq!(parse_size_arg("0x1f").unwrap(), 0x1f);
+        assert_eq!(parse_size_arg("4k").unwrap(), 0x1000);
+        assert_eq!(parse_size_arg("1M").unwrap(), 0x10_0000);
+        assert_eq!(parse_size_arg("2G").unwrap(), 0x8000_0000);
+        assert!(parse_size_arg("M").is_err());
+        assert!(parse_size_arg("1.5M").is_err());
+        assert!(parse_size_arg("0xffffffffffffffffG").is_err());
+    }
+
+    #[test]
+    fn test_parse_max_size_arg() {
+        assert_eq!(parse_max_size_arg("1000").unwrap(), 1000);
+        assert_eq!(parse_max_size_arg("0x1000").unwrap(), 0x1000);
+        assert_eq!(parse_max_size_arg(&format!("match:{}", file!())).unwrap(), std::fs::metadata(file!()).unwrap().len());
+        assert!(parse_max_size_arg("match:does/not/exist.pak").is_err());
+    }
+
+    #[test]
+    fn test_parse_mode_arg() {
+        assert_eq!(parse_mode_arg("644").unwrap(), 0o644);
+        assert_eq!(parse_mode_arg("0755").unwrap(), 0o755);
+        assert!(parse_mode_arg("10000").is_err());
+        assert!(parse_mode_arg("8").is_err());
+    }
 }
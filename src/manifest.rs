@@ -0,0 +1,112 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+
+/// A description of exactly how to build a .pak file, for `packling
+/// pack --manifest`: which file each asset comes from, in what order,
+/// and whether it's compressed. It's read from a TOML file like this:
+///
+/// ```toml
+/// timestamp = "2024-01-01T00:00:00Z"  # optional, defaults to now
+/// compress = true  # default for every asset, optional
+///
+/// [[asset]]
+/// source = "build/levels/1-1.bin"  # relative to the manifest
+/// name = "levels/1-1.bin"
+///
+/// [[asset]]
+/// source = "videos/intro.mp4"
+/// name = "videos/intro.mp4"
+/// compress = false  # overrides the default, optional
+/// ```
+///
+/// Assets are stored in the order they're listed in.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Timestamp to put in the header, in any of the formats accepted by
+    /// `--timestamp`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+
+    /// Whether to LZ4-compress assets that don't say otherwise.
+    #[serde(default)]
+    pub compress: bool,
+
+    #[serde(default, rename = "asset")]
+    pub assets: Vec<ManifestAsset>,
+}
+
+
+/// A single asset in a `Manifest`.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestAsset {
+    /// File to read the asset's data from. Relative paths are relative
+    /// to the manifest's folder.
+    pub source: PathBuf,
+
+    /// Asset name in the .pak.
+    pub name: String,
+
+    /// Whether to LZ4-compress this asset, overriding the manifest's
+    /// default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compress: Option<bool>,
+}
+
+
+impl Manifest {
+    /// Load a manifest from a TOML file.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => bail!("couldn't read manifest {path:?}: {e}"),
+        };
+        match toml::from_str(&contents) {
+            Ok(manifest) => Ok(manifest),
+            Err(e) => bail!("couldn't parse manifest {path:?}: {e}"),
+        }
+    }
+
+    /// Save the manifest as a TOML file.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest: Manifest = toml::from_str(r#"
+            compress = true
+
+            [[asset]]
+            source = "build/a.bin"
+            name = "data/a.bin"
+
+            [[asset]]
+            source = "b.mp4"
+            name = "b.mp4"
+            compress = false
+        "#).unwrap();
+
+        assert_eq!(manifest.timestamp, None);
+        assert!(manifest.compress);
+        assert_eq!(manifest.assets.len(), 2);
+        assert_eq!(manifest.assets[0].source, Path::new("build/a.bin"));
+        assert_eq!(manifest.assets[0].name, "data/a.bin");
+        assert_eq!(manifest.assets[0].compress, None);
+        assert_eq!(manifest.assets[1].compress, Some(false));
+
+        // Typos shouldn't be silently ignored
+        assert!(toml::from_str::<Manifest>("[[asset]]\nsource = \"a\"\nname = \"a\"\ncompres = true").is_err());
+    }
+}
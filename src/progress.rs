@@ -0,0 +1,215 @@
+use std::{
+    io::Write,
+    time::{Duration, Instant},
+};
+
+
+/// How often the progress line is redrawn, at most.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+
+/// The phases that processing an asset is split into, for measuring
+/// their throughput separately.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Phase {
+    /// Reading from the input (including checking CRCs of stored data).
+    Read,
+    /// Decrypting or encrypting.
+    Crypt,
+    /// Decompressing or compressing.
+    Compression,
+    /// Writing to the output.
+    Write,
+}
+
+
+/// Time spent and bytes processed in one `Phase`.
+#[derive(Copy, Clone, Default)]
+struct PhaseTotals {
+    time: Duration,
+    bytes: u64,
+}
+
+
+/// A live, single-line progress display on stderr, showing how far along
+/// an operation is, the throughput of each phase, and an estimate of the
+/// time remaining.
+///
+/// A disabled `Progress` still accepts all calls (and runs the closures
+/// passed to `measure()`), but doesn't time anything or print anything,
+/// so code can use one unconditionally.
+pub struct Progress {
+    enabled: bool,
+    labels: [&'static str; 4],
+    total_bytes: u64,
+    done_bytes: u64,
+    start: Instant,
+    last_draw: Option<Instant>,
+    phases: [PhaseTotals; 4],
+}
+
+
+impl Progress {
+    /// Start showing progress towards `total_bytes`, with the phases
+    /// labeled (in `Phase` order) as given.
+    pub fn new(total_bytes: u64, labels: [&'static str; 4]) -> Self {
+        Self {
+            enabled: true,
+            labels,
+            total_bytes,
+            done_bytes: 0,
+            start: Instant::now(),
+            last_draw: None,
+            phases: [PhaseTotals::default(); 4],
+        }
+    }
+
+    /// A `Progress` that does nothing.
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Self::new(0, [""; 4])
+        }
+    }
+
+    /// Whether the progress line is being shown (in which case nothing
+    /// else should be printed to the terminal until `finish()`).
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Run `f`, adding its running time and `bytes` to the totals for
+    /// `phase`.
+    pub fn measure<T>(&mut self, phase: Phase, bytes: u64, f: impl FnOnce() -> anyhow::Result<T>) -> anyhow::Result<T> {
+        if !self.enabled {
+            return f();
+        }
+
+        let start = Instant::now();
+        let result = f()?;
+        let totals = &mut self.phases[phase as usize];
+        totals.time += start.elapsed();
+        totals.bytes += bytes;
+        Ok(result)
+    }
+
+    /// Count `bytes` more towards the total, and redraw the progress
+    /// line if it's been a while.
+    pub fn advance(&mut self, bytes: u64) {
+        if !self.enabled {
+            return;
+        }
+
+        self.done_bytes += bytes;
+        if self.last_draw.is_none_or(|t| t.elapsed() >= REDRAW_INTERVAL) {
+            self.draw();
+        }
+    }
+
+    /// Draw the progress line one last time, and move on to the next
+    /// line.
+    pub fn finish(&mut self) {
+        if !self.enabled {
+            return;
+        }
+
+        self.draw();
+        eprintln!();
+        self.enabled = false;
+    }
+
+    /// Redraw the progress line.
+    #[allow(clippy::cast_precision_loss)]
+    fn draw(&mut self) {
+        let elapsed = self.start.elapsed();
+        let percent = if self.total_bytes == 0 {
+            100.0
+        } else {
+            self.done_bytes as f64 / self.total_bytes as f64 * 100.0
+        };
+
+        let mut line = format!(
+            "{percent:5.1}%  {} / {}",
+            format_bytes(self.done_bytes),
+            format_bytes(self.total_bytes),
+        );
+
+        for (label, totals) in self.labels.iter().zip(&self.phases) {
+            let seconds = totals.time.as_secs_f64();
+            if totals.bytes > 0 && seconds > 0.0 {
+                line.push_str(&format!("  {label} {:.1} MB/s", totals.bytes as f64 / seconds / 1_000_000.0));
+            }
+        }
+
+        let seconds = elapsed.as_secs_f64();
+        if self.done_bytes >= self.total_bytes {
+            line.push_str(&format!("  done in {}", format_duration(seconds)));
+        } else if self.done_bytes > 0 && seconds > 0.0 {
+            let rate = self.done_bytes as f64 / seconds;
+            let remaining = (self.total_bytes - self.done_bytes) as f64 / rate;
+            line.push_str(&format!("  ETA {}", format_duration(remaining)));
+        }
+
+        // (\x1b[K clears the rest of the line, in case it got shorter)
+        let mut stderr = std::io::stderr().lock();
+        write!(stderr, "\r{line}\x1b[K").ok();
+        stderr.flush().ok();
+
+        self.last_draw = Some(Instant::now());
+    }
+}
+
+
+/// Format a byte count in human-readable units.
+#[allow(clippy::cast_precision_loss)]
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+
+    if bytes < 1000 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1000.0;
+    let mut unit = UNITS[0];
+    for next_unit in &UNITS[1..] {
+        if value < 1000.0 {
+            break;
+        }
+        value /= 1000.0;
+        unit = next_unit;
+    }
+    format!("{value:.1} {unit}")
+}
+
+
+/// Format a number of seconds as "M:SS" or "H:MM:SS".
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes}:{seconds:02}")
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(999), "999 B");
+        assert_eq!(format_bytes(1500), "1.5 KB");
+        assert_eq!(format_bytes(2_500_000_000), "2.5 GB");
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0.0), "0:00");
+        assert_eq!(format_duration(59.6), "1:00");
+        assert_eq!(format_duration(3725.0), "1:02:05");
+    }
+}
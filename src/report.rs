@@ -0,0 +1,139 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    time::Instant,
+};
+
+use serde::Serialize;
+
+use crate::shared::{PakAsset, Verbosity};
+
+
+/// What happened to a single asset during an operation.
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AssetAction {
+    /// Added to the .pak.
+    Packed,
+    /// Written to the output folder.
+    Extracted,
+    /// Not extracted, because a file was already there.
+    KeptExisting,
+    /// Not extracted because of an error, but the operation continued.
+    Skipped,
+    /// Not extracted because of an error, which stopped the operation
+    /// (or, when verifying, found to be damaged or unreadable).
+    Failed,
+    /// Not extracted, because it's past the end of a truncated .pak.
+    Missing,
+    /// Checked, and its ciphertext CRC32 matches.
+    Verified,
+}
+
+
+/// A single asset in a `Report`.
+#[derive(Serialize)]
+pub struct ReportAsset {
+    pub name: String,
+    pub action: AssetAction,
+    pub size_decompressed: u64,
+    pub size_compressed: u64,
+    pub plaintext_crc32: u32,
+    pub ciphertext_crc32: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+
+impl ReportAsset {
+    pub fn new(asset: &PakAsset, action: AssetAction) -> Self {
+        Self {
+            name: String::from_utf8_lossy(&asset.name).into_owned(),
+            action,
+            size_decompressed: asset.size_decompressed.into(),
+            size_compressed: asset.size_compressed.into(),
+            plaintext_crc32: asset.plaintext_crc32,
+            ciphertext_crc32: asset.ciphertext_crc32,
+            error: None,
+        }
+    }
+
+    pub fn with_error(mut self, error: &anyhow::Error) -> Self {
+        self.error = Some(format!("{error:#}"));
+        self
+    }
+}
+
+
+/// Machine-readable summary of a pack, unpack or verify operation,
+/// written as JSON with `--report`.
+#[derive(Serialize)]
+pub struct Report {
+    pub operation: &'static str,
+    pub input: String,
+    /// (`None` for operations that only read the input)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub elapsed_seconds: f64,
+    pub assets: Vec<ReportAsset>,
+    pub warnings: Vec<String>,
+
+    #[serde(skip)]
+    start: Instant,
+    #[serde(skip)]
+    verbosity: Verbosity,
+}
+
+
+impl Report {
+    /// Start a report for an operation, timed from now.
+    pub fn new(operation: &'static str, input: &Path, output: &Path, verbosity: Verbosity) -> Self {
+        let mut report = Self::for_input(operation, input, verbosity);
+        report.output = Some(output.display().to_string());
+        report
+    }
+
+    /// Start a report for an operation that only reads `input`, timed
+    /// from now.
+    pub fn for_input(operation: &'static str, input: &Path, verbosity: Verbosity) -> Self {
+        Self {
+            operation,
+            input: input.display().to_string(),
+            output: None,
+            success: false,
+            error: None,
+            elapsed_seconds: 0.0,
+            assets: Vec::new(),
+            warnings: Vec::new(),
+            start: Instant::now(),
+            verbosity,
+        }
+    }
+
+    /// Print a warning to stderr (unless silenced), and record it in the
+    /// report.
+    pub fn warn(&mut self, message: String) {
+        if self.verbosity >= Verbosity::Quiet {
+            eprintln!("warning: {message}");
+        }
+        self.warnings.push(message);
+    }
+
+    /// Finish the report with the operation's result, and save it to
+    /// `path`.
+    pub fn save(mut self, path: &Path, result: &anyhow::Result<()>) -> anyhow::Result<()> {
+        self.elapsed_seconds = self.start.elapsed().as_secs_f64();
+        self.success = result.is_ok();
+        self.error = result.as_ref().err().map(|e| format!("{e:#}"));
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, &self)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+//! JSON schemas for packling's machine-readable output, so that other
+//! tools can validate it. They're in the `schemas` folder, and printed by
+//! `packling --schema <KIND>`.
+//!
+//! Each schema's `$id` ends in `SCHEMA_VERSION`, which is bumped whenever
+//! a field is removed, renamed or changes type. (Adding a field is also a
+//! change, since the schemas don't allow unknown fields, but only an
+//! optional one is added without bumping the version.)
+
+use clap::ValueEnum;
+
+
+/// Version of the machine-readable output formats.
+pub const SCHEMA_VERSION: u32 = 1;
+
+
+/// Machine-readable outputs that have a schema.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, ValueEnum)]
+#[clap(rename_all = "kebab_case")]
+pub enum SchemaKind {
+    /// `list --format json`
+    List,
+    /// `map --format json`
+    Map,
+    /// `slack --format json`
+    Slack,
+    /// `stats --format json`
+    Stats,
+    /// `diff --format json`
+    Diff,
+    /// `--report` files
+    Report,
+    /// `index` files
+    Index,
+    /// `assets-list export --format json`
+    AssetsList,
+}
+
+
+impl SchemaKind {
+    /// The JSON schema for this kind of output.
+    pub fn schema(self) -> &'static str {
+        match self {
+            SchemaKind::List => include_str!("../schemas/list.schema.json"),
+            SchemaKind::Map => include_str!("../schemas/map.schema.json"),
+            SchemaKind::Slack => include_str!("../schemas/slack.schema.json"),
+            SchemaKind::Stats => include_str!("../schemas/stats.schema.json"),
+            SchemaKind::Diff => include_str!("../schemas/diff.schema.json"),
+            SchemaKind::Report => include_str!("../schemas/report.schema.json"),
+            SchemaKind::Index => include_str!("../schemas/index.schema.json"),
+            SchemaKind::AssetsList => include_str!("../schemas/assets-list.schema.json"),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{
+        flow_assets_list::{AssetsListDump, AssetsListDumpEntry},
+        flow_diff::PakDiff,
+        flow_index::{IndexedAsset, IndexedPak, PakIndex},
+        flow_list::{EncryptionCoverage, ListEntry},
+        flow_map::MapEntry,
+        flow_slack::SlackRegion,
+        flow_stats::SizeStats,
+        report::Report,
+        shared::Verbosity,
+    };
+
+    fn schema(kind: SchemaKind) -> serde_json::Value {
+        serde_json::from_str(kind.schema()).unwrap()
+    }
+
+    /// Check that an object has all of the schema's required fields, and
+    /// no others than its properties.
+    fn assert_matches_schema(value: &serde_json::Value, schema: &serde_json::Value) {
+        let fields = value.as_object().unwrap();
+        let properties = schema["properties"].as_object().unwrap();
+        for field in fields.keys() {
+            assert!(properties.contains_key(field), "{field} isn't in the schema");
+        }
+        for field in schema["required"].as_array().unwrap() {
+            assert!(fields.contains_key(field.as_str().unwrap()), "{field} is missing");
+        }
+    }
+
+    #[test]
+    fn test_schemas() {
+        for kind in SchemaKind::value_variants() {
+            let schema: serde_json::Value = serde_json::from_str(kind.schema()).unwrap();
+            let name = kind.to_possible_value().unwrap().get_name().to_owned();
+            assert_eq!(schema["$id"], format!("urn:packling:schema:{name}:{SCHEMA_VERSION}"));
+        }
+    }
+
+    #[test]
+    fn test_schemas_match_output() {
+        assert_matches_schema(&serde_json::to_value(PakDiff::default()).unwrap(), &schema(SchemaKind::Diff));
+
+        let report = Report::new("pack", Path::new("in"), Path::new("out.pak"), Verbosity::Silent);
+        assert_matches_schema(&serde_json::to_value(&report).unwrap(), &schema(SchemaKind::Report));
+        let report = Report::for_input("verify", Path::new("in.pak"), Verbosity::Silent);
+        assert_matches_schema(&serde_json::to_value(&report).unwrap(), &schema(SchemaKind::Report));
+
+        // (with the optional fields, and without)
+        let entry = ListEntry {
+            content_type: Some("text"),
+            encryption: Some(EncryptionCoverage::default()),
+            ..ListEntry::default()
+        };
+        assert_matches_schema(&serde_json::to_value(entry).unwrap(), &schema(SchemaKind::List)["items"]);
+        assert_matches_schema(&serde_json::to_value(ListEntry::default()).unwrap(), &schema(SchemaKind::List)["items"]);
+
+        assert_matches_schema(&serde_json::to_value(MapEntry::default()).unwrap(), &schema(SchemaKind::Map)["items"]);
+
+        assert_matches_schema(&serde_json::to_value(SlackRegion::default()).unwrap(), &schema(SchemaKind::Slack)["items"]);
+
+        let stats = SizeStats {folder: Some("folder".to_owned()), ..SizeStats::default()};
+        assert_matches_schema(&serde_json::to_value(stats).unwrap(), &schema(SchemaKind::Stats)["$defs"]["size_stats"]);
+        assert_matches_schema(&serde_json::to_value(SizeStats::default()).unwrap(), &schema(SchemaKind::Stats)["$defs"]["size_stats"]);
+
+        let index = PakIndex {
+            paks: vec![IndexedPak {
+                assets: vec![IndexedAsset::default()],
+                ..IndexedPak::default()
+            }],
+        };
+        let index_schema = schema(SchemaKind::Index);
+        let pak_schema = &index_schema["properties"]["paks"]["items"];
+        let value = serde_json::to_value(index).unwrap();
+        assert_matches_schema(&value, &index_schema);
+        assert_matches_schema(&value["paks"][0], pak_schema);
+        assert_matches_schema(&value["paks"][0]["assets"][0], &pak_schema["properties"]["assets"]["items"]);
+
+        let dump = AssetsListDump {
+            version: 0,
+            unk0c: 0,
+            timestamp: 0,
+            crc32: Some(0),
+            assets_list_size: Some(0),
+            assets_list_plaintext_crc32: Some(0),
+            assets_list_ciphertext_crc32: Some(0),
+            assets: vec![AssetsListDumpEntry {
+                name: String::new(),
+                size_decompressed: 0,
+                size_compressed: 0,
+                offset: 0,
+                plaintext_crc32: 0,
+                ciphertext_crc32: 0,
+                absolute_offset: Some(0),
+                field_0c: Some(0),
+                field_10: Some(0),
+            }],
+        };
+        let dump_schema = schema(SchemaKind::AssetsList);
+        let value = serde_json::to_value(dump).unwrap();
+        assert_matches_schema(&value, &dump_schema);
+        assert_matches_schema(&value["asset"][0], &dump_schema["properties"]["asset"]["items"]);
+    }
+}
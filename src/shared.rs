@@ -1,10 +1,22 @@
 use std::{
-    fs::File,
-    io::{BufReader, Seek, SeekFrom},
-    path::Path,
+    fs::{File, TryLockError},
+    io::{BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
 };
 
-use binrw::{binrw, BinReaderExt};
+use anyhow::bail;
+use binrw::{binrw, BinRead, BinReaderExt};
+use clap::ValueEnum;
+
+use crate::{
+    encryption::XXTEA_CHUNK_SIZE,
+    jamcrc32::Jamcrc32Hasher,
+    journal,
+};
 
 
 /// The size in bytes of `PakHeader`.
@@ -17,20 +29,103 @@ pub const PAK_CRC32_START_OFFSET: usize = 0x14;
 /// The value of the file header "version" field found in all publicly
 /// available PAK files.
 pub const FILE_VERSION: u32 = 103;
+/// The value of the file header "version" field that marks packling's
+/// experimental extended-offsets layout (see `ExtendedPakAsset`). The
+/// game can't load .pak files in this layout.
+pub const EXTENDED_FILE_VERSION: u32 = 0x8000_0000 | FILE_VERSION;
 /// The name (for key-generation purposes) of the assets list blob.
 pub const ASSETS_LIST_NAME: &[u8; 6] = b"header";
 
+/// The djb2a hash of `ASSETS_LIST_NAME`, which `PakHeader` field 0x1c
+/// is derived from.
+pub const ASSETS_LIST_NAME_HASH: u32 = djb2::Djb2a::hash_bytes_const(ASSETS_LIST_NAME).as_u32();
+
+/// The most that LZ4 can possibly compress data by (each extra byte of
+/// a match length encodes 255 more bytes of output).
+const LZ4_MAX_COMPRESSION_RATIO: u64 = 255;
+
+// Just using the same value as `BufReader` from the Rust stdlib
+const CRC32_DATA_BUFFER_SIZE: usize = 8 * 1024;
+
 /// Time format used for displaying dates to the user and reading them
 /// from the CLI. Similar to ISO 8601, but without any timezone info.
 pub const TIME_FORMAT: &str = "[year]-[month]-[day]T[hour]:[minute]:[second]";
+/// Date-only version of `TIME_FORMAT`, accepted from the CLI.
+pub const DATE_FORMAT: &str = "[year]-[month]-[day]";
+/// Format of UTC offsets accepted from the CLI and shown after
+/// displayed dates.
+const UTC_OFFSET_FORMAT: &str = "[offset_hour sign:mandatory]:[offset_minute]";
 
 
-/// Represents the user-selected verbosity level.
+/// Represents the user-selected verbosity level. Each level prints
+/// everything the ones before it do.
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
 pub enum Verbosity {
+    /// Only errors (-qq).
+    Silent,
+    /// Warnings and summaries, but not a line per asset (-q).
     #[default]
-    NotVerbose,
+    Quiet,
+    /// A line per asset (the CLI's default).
+    Normal,
+    /// The .pak header fields (-v).
     Verbose,
+    /// How each asset is stored: offset, sizes, encryption chunks and
+    /// CRCs (-vv).
+    Debug,
+}
+
+
+/// Time zone to display dates in and interpret user-provided dates in.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub enum TimeZone {
+    #[default]
+    Utc,
+    /// The system's local time zone.
+    Local,
+    /// A fixed offset from UTC.
+    Fixed(time::UtcOffset),
+}
+
+
+impl TimeZone {
+    /// Parse a time zone name from the CLI: "utc", "local", or a UTC
+    /// offset like "+09:00".
+    pub fn parse(string: &str) -> anyhow::Result<Self> {
+        if string.eq_ignore_ascii_case("utc") || string == "Z" {
+            return Ok(Self::Utc);
+        }
+        if string.eq_ignore_ascii_case("local") {
+            return Ok(Self::Local);
+        }
+
+        let format = time::format_description::parse(UTC_OFFSET_FORMAT)?;
+        match time::UtcOffset::parse(string, &format) {
+            Ok(offset) => Ok(Self::Fixed(offset)),
+            Err(_) => bail!("unknown time zone {string:?} (expected \"utc\", \"local\" or an offset like \"+09:00\")"),
+        }
+    }
+
+    /// The UTC offset of this time zone at a particular moment.
+    pub fn offset_at(self, datetime: time::OffsetDateTime) -> anyhow::Result<time::UtcOffset> {
+        Ok(match self {
+            Self::Utc => time::UtcOffset::UTC,
+            Self::Local => time::UtcOffset::local_offset_at(datetime)?,
+            Self::Fixed(offset) => offset,
+        })
+    }
+}
+
+
+/// Formats for machine-readable tabular output.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default, ValueEnum)]
+#[clap(rename_all = "kebab_case")]
+pub enum TableFormat {
+    /// Comma-separated values, with a header row.
+    #[default]
+    Csv,
+    /// A JSON array of objects.
+    Json,
 }
 
 
@@ -49,8 +144,11 @@ const fn u56_to_i64(value: u64) -> i64 {
 
 
 /// Represents the unencrypted PAK header, of length `PAK_HEADER_SIZE`.
+///
+/// With the `serde` feature, this can be serialized and deserialized.
 #[binrw]
 #[brw(little, magic = b"KCAP")]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PakHeader {
     /* 0x04 */ pub version: u32,
     /* 0x08 */ pub crc32: u32,
@@ -75,6 +173,30 @@ pub struct PakHeader {
 }
 
 
+/// Print the fields of a .pak header, for `Verbosity::Verbose`.
+pub fn print_header_details(header: &PakHeader) {
+    println!("  Version:          {}", header.version);
+    println!("  Whole-file CRC32: {:#010x}", header.crc32);
+    println!("  Assets list size: {:#x} ({:#x} decompressed)", header.assets_list_size_compressed, header.assets_list_size_decompressed);
+    println!("  Assets list CRCs: plaintext {:#010x}, ciphertext {:#010x}", header.plaintext_crc32, header.ciphertext_crc32);
+}
+
+
+/// Print how an asset is stored, for `Verbosity::Debug`.
+pub fn print_asset_details(
+    offset: u64,
+    size_compressed: u64,
+    size_decompressed: u64,
+    plaintext_crc32: u32,
+    ciphertext_crc32: u32,
+) {
+    let chunks = size_compressed.div_ceil(XXTEA_CHUNK_SIZE as u64);
+    println!(
+        "  offset {offset:#x}, {size_compressed:#x} bytes stored ({size_decompressed:#x} decompressed) in {chunks} encryption chunk(s), CRC32s {plaintext_crc32:#010x}/{ciphertext_crc32:#010x}",
+    );
+}
+
+
 /// Represents a length-prefixed list of `PakAsset`.
 #[binrw]
 #[brw(little)]
@@ -88,7 +210,7 @@ pub struct PakAssets {
 
 
 /// Calculate the expected value of `PakAsset` field 0x0c.
-fn calc_field_0x0c(name: &[u8], size: u32) -> u32 {
+pub fn calc_field_0x0c(name: &[u8], size: u32) -> u32 {
     // very weird
     if size >= 0xa00000 || name.ends_with(b".alf") {
         2
@@ -99,7 +221,7 @@ fn calc_field_0x0c(name: &[u8], size: u32) -> u32 {
 
 
 /// Calculate the expected value of `PakAsset` field 0x10.
-fn calc_field_0x10(name: &[u8], size_compressed: u32) -> u32 {
+pub fn calc_field_0x10(name: &[u8], size_compressed: u32) -> u32 {
     if size_compressed == 0 {
         0
     } else {
@@ -110,8 +232,12 @@ fn calc_field_0x10(name: &[u8], size_compressed: u32) -> u32 {
 
 /// Represents a single entry from the encrypted assets-list blob near
 /// the start of the PAK file.
+///
+/// With the `serde` feature, this can be serialized and deserialized.
 #[binrw]
 #[brw(little)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PakAsset {
     #[bw(try_calc(u32::try_from(name.len())))]
     name_len: u32,
@@ -134,11 +260,437 @@ pub struct PakAsset {
 }
 
 
+/// Represents a single entry in the assets list of a .pak file in the
+/// experimental extended-offsets layout (`EXTENDED_FILE_VERSION`).
+///
+/// This is like `PakAsset`, but with 64-bit sizes and offset, and
+/// without fields 0x0c and 0x10 (which only the game cares about).
+/// Everything else about the layout is the same, including the
+/// encryption (whose key generation only uses the low 32 bits of the
+/// data length).
+#[binrw]
+#[brw(little)]
+#[derive(Clone)]
+pub struct ExtendedPakAsset {
+    #[bw(try_calc(u32::try_from(name.len())))]
+    name_len: u32,
+    #[br(count = name_len)]
+    pub name: Vec<u8>,
+
+    // (Offsets measured from the end of `name`)
+    /* 0x00 */ pub size_decompressed: u64,
+    /* 0x08 */ pub size_compressed: u64,
+    /* 0x10 */ pub offset: u64,
+    /* 0x18 */ pub plaintext_crc32: u32,
+    /* 0x1c */ pub ciphertext_crc32: u32,
+}
+
+
+/// Represents a length-prefixed list of `ExtendedPakAsset`.
+#[binrw]
+#[brw(little)]
+pub struct ExtendedPakAssets {
+    #[bw(try_calc(u32::try_from(contents.len())))]
+    _count: u32,
+
+    #[br(count = _count)]
+    pub contents: Vec<ExtendedPakAsset>,
+}
+
+
+/// Quote a CSV field if necessary.
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+
+/// Decompress an LZ4 block stored in a PAK file, given the decompressed
+/// size stored alongside it.
+///
+/// Sizes that LZ4 couldn't possibly reach from `data` are rejected up
+/// front, so that corrupt sizes can't cause huge allocations.
+pub fn decompress_lz4(data: &[u8], size_decompressed: impl Into<u64>) -> anyhow::Result<Vec<u8>> {
+    let size_decompressed = size_decompressed.into();
+    let max_size = u64::try_from(data.len())?.saturating_mul(LZ4_MAX_COMPRESSION_RATIO).saturating_add(16);
+    if size_decompressed > max_size {
+        bail!("implausible decompressed size {size_decompressed:#x} for {:#x} bytes of LZ4 data", data.len());
+    }
+    Ok(lz4_flex::block::decompress(data, size_decompressed.try_into()?)?)
+}
+
+
+/// Format a PAK header timestamp for display to the user, in the given
+/// time zone.
+pub fn format_timestamp(timestamp: i64, timezone: TimeZone) -> anyhow::Result<String> {
+    let ts = time::OffsetDateTime::from_unix_timestamp(timestamp)?;
+    let offset = timezone.offset_at(ts)?;
+    let Some(ts) = ts.checked_to_offset(offset) else {
+        bail!("timestamp {timestamp} is out of range in time zone {offset}");
+    };
+
+    let format = time::format_description::parse(TIME_FORMAT)?;
+    let label = if timezone == TimeZone::Utc {
+        "UTC".to_owned()
+    } else {
+        offset.format(&time::format_description::parse(UTC_OFFSET_FORMAT)?)?
+    };
+
+    Ok(format!("{} {label} ({timestamp})", ts.format(&format)?))
+}
+
+
+/// Whether `path` is an existing folder with nothing in it (which is
+/// fine to extract into without deleting it first).
+pub fn is_empty_folder(path: &Path) -> bool {
+    std::fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_none())
+}
+
+
+/// Ask the user a yes/no question on the terminal. Anything other than
+/// "y" or "yes" counts as no.
+pub fn ask_yes_no(question: &str) -> anyhow::Result<bool> {
+    eprint!("{question} [y/N] ");
+    std::io::stderr().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes"))
+}
+
+
+/// Read just the header of the PAK file at `path`.
+pub fn read_pak_header(path: &Path) -> anyhow::Result<PakHeader> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let header = PakHeader::read(&mut reader)?;
+    if header.version != FILE_VERSION {
+        bail!("unknown PAK version: {}", header.version);
+    }
+    Ok(header)
+}
+
+
 /// Check if the PAK file at `path` appears to be encrypted, using a
-/// simple heuristic.
+/// simple heuristic. Fails if it's the incomplete output of an
+/// interrupted pack, which the heuristic would take for a decrypted one.
 pub fn check_is_encrypted(path: &Path) -> anyhow::Result<bool> {
+    if journal::journal_path(path).exists() {
+        bail!("{} is incomplete: packing it was interrupted (pack again with --resume to finish it, or delete it)", path.display());
+    }
     let mut reader = BufReader::new(File::open(path)?);
     reader.seek(SeekFrom::Start(PAK_HEADER_SIZE.try_into()?))?;
     let num_files: u32 = reader.read_le()?;
     Ok(num_files > 0x000f_ffff)
 }
+
+
+/// Calculate the whole-file JAMCRC32 stored at `PAK_CRC32_OFFSET` in
+/// `PakHeader`, from the contents of `reader` (which must be
+/// `total_file_size` bytes long).
+pub fn calc_pak_crc32<R: Read + Seek>(reader: &mut R, total_file_size: u64) -> anyhow::Result<u32> {
+    reader.seek(SeekFrom::Start(PAK_CRC32_START_OFFSET.try_into()?))?;
+
+    let mut data_buffer = vec![0; CRC32_DATA_BUFFER_SIZE];
+    #[allow(clippy::cast_possible_truncation)]
+    let mut hasher = Jamcrc32Hasher::new_with_initial(total_file_size as u32);
+    loop {
+        let amount_read = reader.read(&mut data_buffer)?;
+        if amount_read == 0 {
+            break;
+        }
+        hasher.update(&data_buffer[..amount_read]);
+    }
+
+    Ok(hasher.finalize())
+}
+
+
+/// Files at least this big have their whole-file CRC32 calculated in
+/// parallel by `calc_pak_crc32_file`.
+const PARALLEL_CRC32_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+
+/// Thread count set with `set_thread_count()`, or 0 if it hasn't been.
+static THREAD_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+
+/// Set how many threads work that can run in parallel uses (see
+/// `thread_count()`). 0 goes back to the default.
+pub fn set_thread_count(threads: usize) {
+    THREAD_COUNT.store(threads, Ordering::Relaxed);
+}
+
+
+/// How many threads to use for work that can run in parallel: as set
+/// with `set_thread_count()`, or by default, the number of CPU cores.
+///
+/// This is the number of threads that calculate the whole-file CRC32 of
+/// large files (with 1, it's calculated serially). When unpacking with
+/// `async-io`, it's both the number of runtime worker threads and the
+/// number of blocking threads that decode assets and do file I/O, so up
+/// to twice as many threads can be busy at once.
+pub fn thread_count() -> usize {
+    match THREAD_COUNT.load(Ordering::Relaxed) {
+        0 => std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get),
+        threads => threads,
+    }
+}
+
+
+/// Folder set with `set_temp_dir()`.
+static TEMP_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+
+/// Set the folder that intermediate files are written to (see
+/// `temp_dir()`). Only the first call has any effect.
+pub fn set_temp_dir(folder: PathBuf) {
+    let _ = TEMP_DIR.set(folder);
+}
+
+
+/// Folder to write intermediate files to (an input copied from a pipe,
+/// and the nested .pak files made when packing recursively): as set
+/// with `set_temp_dir()`, or by default, the system's ($TMPDIR, if it's
+/// set, on Unix-like systems). Files that are renamed into place once
+/// they're complete are written next to their destination instead,
+/// since renaming can't move them to another filesystem.
+pub fn temp_dir() -> PathBuf {
+    TEMP_DIR.get().cloned().unwrap_or_else(std::env::temp_dir)
+}
+
+
+/// Calculate the whole-file JAMCRC32 of a .pak file on disk, like
+/// `calc_pak_crc32`, using `thread_count()` threads for large files.
+pub fn calc_pak_crc32_file(path: &Path) -> anyhow::Result<u32> {
+    let file_size = std::fs::metadata(path)?.len();
+    let threads = thread_count();
+
+    if file_size < PARALLEL_CRC32_THRESHOLD || threads == 1 {
+        calc_pak_crc32(&mut BufReader::new(File::open(path)?), file_size)
+    } else {
+        calc_pak_crc32_parallel(path, threads)
+    }
+}
+
+
+/// Calculate the whole-file JAMCRC32 of a .pak file on disk, like
+/// `calc_pak_crc32`, by splitting it into `threads` sections that are
+/// hashed in parallel (each with its own file handle), and combining
+/// the results.
+pub fn calc_pak_crc32_parallel(path: &Path, threads: usize) -> anyhow::Result<u32> {
+    let file_size = std::fs::metadata(path)?.len();
+    let start = u64::try_from(PAK_CRC32_START_OFFSET)?;
+    let threads = u64::try_from(threads.max(1))?;
+    let section_size = file_size.saturating_sub(start).div_ceil(threads);
+
+    let sections = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads).map(|i| {
+            let section_start = (start + i * section_size).min(file_size);
+            let section_end = (section_start + section_size).min(file_size);
+            scope.spawn(move || hash_file_section(path, section_start, section_end))
+        }).collect();
+
+        handles.into_iter()
+            .map(|handle| handle.join().expect("CRC32 thread shouldn't panic"))
+            .collect::<anyhow::Result<Vec<_>>>()
+    })?;
+
+    #[allow(clippy::cast_possible_truncation)]
+    let mut hasher = Jamcrc32Hasher::new_with_initial(file_size as u32);
+    for section in &sections {
+        hasher.combine(section);
+    }
+    Ok(hasher.finalize())
+}
+
+
+/// Calculate the plain CRC32 of bytes `start..end` of a file.
+pub fn hash_file_section(path: &Path, start: u64, end: u64) -> anyhow::Result<crc32fast::Hasher> {
+    let mut reader = BufReader::new(File::open(path)?);
+    reader.seek(SeekFrom::Start(start))?;
+
+    let mut data_buffer = vec![0; CRC32_DATA_BUFFER_SIZE];
+    let mut hasher = crc32fast::Hasher::new();
+    let mut remaining = end - start;
+    while remaining > 0 {
+        let amount = data_buffer.len().min(usize::try_from(remaining).unwrap_or(usize::MAX));
+        reader.read_exact(&mut data_buffer[..amount])?;
+        hasher.update(&data_buffer[..amount]);
+        remaining -= u64::try_from(amount)?;
+    }
+
+    Ok(hasher)
+}
+
+
+/// Reserve `size` bytes of disk space for a file that's about to be
+/// written, so the filesystem can allocate it in one piece. This also
+/// sets the file's length, so it has to be truncated afterward if less
+/// than that ends up being written.
+///
+/// This is just an optimization, so filesystems that don't support it
+/// are ignored, but running out of space is still an error (which is
+/// better to find out about now than partway through).
+pub fn preallocate(file: &File, size: u64) -> anyhow::Result<()> {
+    #[cfg(target_os = "linux")]
+    match rustix::fs::fallocate(file, rustix::fs::FallocateFlags::empty(), 0, size) {
+        Ok(()) => return Ok(()),
+        Err(rustix::io::Errno::NOSPC) => bail!("not enough disk space for {size} bytes"),
+        // (not supported by this filesystem, so fall back to just
+        // setting the length)
+        Err(_) => {},
+    }
+
+    // (on Windows, this allocates the space, too)
+    file.set_len(size)?;
+    Ok(())
+}
+
+
+/// Take an exclusive advisory lock on a file that's about to be written,
+/// so that another packling process can't write to it at the same time
+/// (which would interleave their writes). The lock is released when the
+/// file is closed.
+///
+/// On filesystems that don't support locking, this does nothing.
+pub fn lock_for_writing(file: &File, path: &Path) -> anyhow::Result<()> {
+    match file.try_lock() {
+        Ok(()) => {},
+        Err(TryLockError::WouldBlock) => bail!("{} is being written by another process", path.display()),
+        // (not supported by this filesystem)
+        Err(TryLockError::Error(_)) => {},
+    }
+    Ok(())
+}
+
+
+/// The free space available to unprivileged users on the filesystem
+/// that `path` is (or would be) on, or `None` if that can't be found out
+/// (including on Windows, which isn't supported yet). `path` doesn't
+/// have to exist yet.
+pub fn free_space(path: &Path) -> Option<u64> {
+    let existing = path.ancestors()
+        .map(|p| if p.as_os_str().is_empty() { Path::new(".") } else { p })
+        .find(|p| p.exists())?;
+
+    #[cfg(unix)]
+    {
+        let stats = rustix::fs::statvfs(existing).ok()?;
+        Some(stats.f_bavail.saturating_mul(stats.f_frsize))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = existing;
+        None
+    }
+}
+
+
+/// Find the byte ranges between `start` and `end` that aren't covered
+/// by any of `spans` (which may overlap, and be in any order).
+pub fn uncovered_ranges(start: u64, end: u64, mut spans: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    spans.sort_unstable();
+
+    let mut ranges = Vec::new();
+    let mut position = start;
+    for (span_start, span_end) in spans {
+        if span_start > position {
+            ranges.push((position, span_start.min(end)));
+        }
+        position = position.max(span_end);
+        if position >= end {
+            break;
+        }
+    }
+    if position < end {
+        ranges.push((position, end));
+    }
+    ranges
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_pak_asset_serde() {
+        let asset = PakAsset {
+            name: b"a/b.txt".to_vec(),
+            size_decompressed: 0x20,
+            size_compressed: 0x10,
+            offset: 0x40,
+            plaintext_crc32: 0x1234_5678,
+            ciphertext_crc32: 0x9abc_def0,
+        };
+        let json = serde_json::to_string(&asset).unwrap();
+        let round_tripped: PakAsset = serde_json::from_str(&json).unwrap();
+        assert_eq!(round_tripped.name, asset.name);
+        assert_eq!(round_tripped.size_decompressed, asset.size_decompressed);
+        assert_eq!(round_tripped.size_compressed, asset.size_compressed);
+        assert_eq!(round_tripped.offset, asset.offset);
+        assert_eq!(round_tripped.plaintext_crc32, asset.plaintext_crc32);
+        assert_eq!(round_tripped.ciphertext_crc32, asset.ciphertext_crc32);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_pak_header_serde() {
+        let header = PakHeader {
+            version: FILE_VERSION,
+            crc32: 0x1234_5678,
+            unk0c: 1,
+            timestamp: -0x10_0000,
+            assets_list_size_decompressed: 0x80,
+            assets_list_size_compressed: 0x60,
+            plaintext_crc32: 0x9abc_def0,
+            ciphertext_crc32: 0x0fed_cba9,
+        };
+        let json = serde_json::to_string(&header).unwrap();
+        let round_tripped: PakHeader = serde_json::from_str(&json).unwrap();
+        assert_eq!(round_tripped.version, header.version);
+        assert_eq!(round_tripped.crc32, header.crc32);
+        assert_eq!(round_tripped.unk0c, header.unk0c);
+        assert_eq!(round_tripped.timestamp, header.timestamp);
+        assert_eq!(round_tripped.assets_list_size_decompressed, header.assets_list_size_decompressed);
+        assert_eq!(round_tripped.assets_list_size_compressed, header.assets_list_size_compressed);
+        assert_eq!(round_tripped.plaintext_crc32, header.plaintext_crc32);
+        assert_eq!(round_tripped.ciphertext_crc32, header.ciphertext_crc32);
+    }
+
+    #[test]
+    fn test_uncovered_ranges() {
+        assert_eq!(uncovered_ranges(0x10, 0x100, vec![]), vec![(0x10, 0x100)]);
+        assert_eq!(uncovered_ranges(0x10, 0x100, vec![(0x10, 0x100)]), Vec::new());
+        assert_eq!(
+            uncovered_ranges(0x10, 0x100, vec![(0x80, 0xa0), (0x20, 0x40), (0x30, 0x50)]),
+            vec![(0x10, 0x20), (0x50, 0x80), (0xa0, 0x100)],
+        );
+        // (spans past the end, e.g. in a truncated file)
+        assert_eq!(uncovered_ranges(0x10, 0x100, vec![(0x10, 0x40), (0x80, 0x200)]), vec![(0x40, 0x80)]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_free_space() {
+        // (the path doesn't have to exist)
+        let temp = tempfile::tempdir().unwrap();
+        assert!(free_space(&temp.path().join("a/b.pak")).is_some_and(|space| space > 0));
+    }
+
+    #[test]
+    fn test_lock_for_writing() {
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        let first = File::options().write(true).open(temp.path()).unwrap();
+        let second = File::options().write(true).open(temp.path()).unwrap();
+        lock_for_writing(&first, temp.path()).unwrap();
+        assert!(lock_for_writing(&second, temp.path()).is_err());
+
+        drop(first);
+        lock_for_writing(&second, temp.path()).unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    preset::GamePreset,
+    shared::PakAsset,
+};
+
+
+/// Name of the sidecar manifest file that may be written into extracted
+/// folders. It's never packed as an asset.
+pub const SIDECAR_NAME: &str = ".packling.json";
+
+
+/// File name of the placeholder assets that stand in for empty folders
+/// when packing with `--keep-empty-folders`. When unpacking, the folder
+/// is recreated instead of extracting the placeholder.
+pub const EMPTY_FOLDER_PLACEHOLDER: &str = ".packling_keep";
+
+
+/// Whether an asset is a placeholder for an empty folder (see
+/// `EMPTY_FOLDER_PLACEHOLDER`).
+pub fn is_empty_folder_placeholder(name: &[u8], size_decompressed: u64) -> bool {
+    size_decompressed == 0
+        && name.rsplit(|&b| b == b'/').next() == Some(EMPTY_FOLDER_PLACEHOLDER.as_bytes())
+}
+
+
+/// Forms in which assets can be extracted without fully converting
+/// them back to their original contents.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, ValueEnum, Serialize, Deserialize)]
+#[clap(rename_all = "kebab_case")]
+#[serde(rename_all = "kebab-case")]
+pub enum RawForm {
+    /// Decrypted, but still LZ4-compressed (if the asset was
+    /// compressed in the .pak).
+    Compressed,
+    /// Exactly as stored in the .pak: encrypted ciphertext. Such assets
+    /// can only be packed back under the same name, since the name is
+    /// part of the encryption key.
+    Encrypted,
+}
+
+
+/// Metadata about an extracted folder, stored in `SIDECAR_NAME`.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Sidecar {
+    /// If set, assets were extracted in this form, and `assets` has the
+    /// metadata needed to pack them back without reprocessing them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<RawForm>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub assets: Vec<SidecarAsset>,
+
+    /// If the folder was extracted with `--flatten`, the asset name that
+    /// each file was extracted from, by file name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub flattened: BTreeMap<String, String>,
+
+    /// The game the .pak was from, if known, so that packing the folder
+    /// again looks for the key where it is in that game's library.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub game: Option<GamePreset>,
+
+    /// The `key::fingerprint()` of the key the .pak was encrypted with,
+    /// if it isn't the known one, so that packing the folder again can
+    /// find it in the key file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+
+    /// Assets that were .pak files themselves, and were unpacked (with
+    /// `--recursive`) into folders of the same name. Packing with
+    /// `--recursive` packs these folders back into .pak files.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub nested: Vec<String>,
+}
+
+
+/// Metadata about a single extracted asset.
+#[derive(Serialize, Deserialize)]
+pub struct SidecarAsset {
+    pub name: String,
+    pub size_decompressed: u32,
+    pub size_compressed: u32,
+    pub plaintext_crc32: u32,
+    pub ciphertext_crc32: u32,
+}
+
+
+impl From<&PakAsset> for SidecarAsset {
+    fn from(asset: &PakAsset) -> Self {
+        Self {
+            name: String::from_utf8_lossy(&asset.name).into_owned(),
+            size_decompressed: asset.size_decompressed,
+            size_compressed: asset.size_compressed,
+            plaintext_crc32: asset.plaintext_crc32,
+            ciphertext_crc32: asset.ciphertext_crc32,
+        }
+    }
+}
+
+
+impl Sidecar {
+    /// Load the sidecar from an extracted folder, if it has one.
+    pub fn load(folder: &Path) -> anyhow::Result<Option<Self>> {
+        let path = folder.join(SIDECAR_NAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let reader = BufReader::new(File::open(path)?);
+        Ok(Some(serde_json::from_reader(reader)?))
+    }
+
+    /// Record the game an extracted folder's .pak was from and the
+    /// fingerprint of its key (whichever are given) in its sidecar,
+    /// creating one if needed (and otherwise keeping what's in it, since
+    /// the folder may have been extracted into before).
+    pub fn record_source(folder: &Path, game: Option<GamePreset>, key: Option<String>) -> anyhow::Result<()> {
+        let mut sidecar = Self::load(folder)?.unwrap_or_default();
+        if game.is_some() {
+            sidecar.game = game;
+        }
+        if key.is_some() {
+            sidecar.key = key;
+        }
+        std::fs::create_dir_all(folder)?;
+        sidecar.save(folder)
+    }
+
+    /// Save the sidecar into an extracted folder.
+    pub fn save(&self, folder: &Path) -> anyhow::Result<()> {
+        let mut writer = BufWriter::new(File::create(folder.join(SIDECAR_NAME))?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+/// Number of bytes at the start of an asset needed to recognize any of
+/// the formats in `SIGNATURES` (the Game Boy header's logo is the
+/// furthest in, at 0x104).
+pub const SNIFF_SIZE: usize = 0x108;
+
+
+/// Magic numbers of known file formats: offset, bytes, format name.
+const SIGNATURES: &[(usize, &[u8], &str)] = &[
+    // Lingcod / Nintendo
+    (0, b"KCAP", "Lingcod PAK"),
+    (0, b"Yaz0", "Yaz0"),
+    (0, b"SARC", "SARC"),
+    (0, b"FRES", "BFRES"),
+    (0, b"BNTX", "BNTX"),
+    (0, b"FSTM", "BFSTM"),
+    (0, b"FWAV", "BFWAV"),
+    (0, b"NES\x1a", "iNES ROM"),
+    (0, b"\x80\x37\x12\x40", "N64 ROM"),
+    (0x104, b"\xce\xed\x66\x66", "Game Boy ROM"),
+    // Wwise
+    (0, b"BKHD", "Wwise soundbank"),
+    (0, b"AKPK", "Wwise package"),
+    // General
+    (0, b"\x89PNG\r\n\x1a\n", "PNG"),
+    (0, b"\xff\xd8\xff", "JPEG"),
+    (0, b"GIF8", "GIF"),
+    (0, b"DDS ", "DDS"),
+    (0, b"\xabKTX", "KTX"),
+    (0, b"OggS", "Ogg"),
+    (0, b"fLaC", "FLAC"),
+    (0, b"MThd", "MIDI"),
+    (0, b"PK\x03\x04", "ZIP"),
+    (0, b"\x1f\x8b", "gzip"),
+    (0, b"\x28\xb5\x2f\xfd", "Zstandard"),
+    (0, b"\x1bLua", "Lua bytecode"),
+    (0, b"\x7fELF", "ELF"),
+    (0, b"\x00\x01\x00\x00\x00", "TrueType font"),
+    (0, b"OTTO", "OpenType font"),
+    (0, b"<?xml", "XML"),
+];
+
+
+/// Guess the format of an asset from its first bytes (at least
+/// `SNIFF_SIZE` of them, if the asset is that long).
+pub fn detect_content_type(data: &[u8]) -> &'static str {
+    if data.is_empty() {
+        return "empty";
+    }
+
+    for (offset, magic, name) in SIGNATURES {
+        if data.get(*offset..offset + magic.len()) == Some(magic) {
+            return name;
+        }
+    }
+
+    // RIFF is a container, so look at the form type too
+    if data.starts_with(b"RIFF") && data.len() >= 12 {
+        return match &data[8..12] {
+            b"WAVE" => "RIFF WAVE (WAV/Wwise)",
+            b"AVI " => "AVI",
+            b"WEBP" => "WebP",
+            _ => "RIFF",
+        };
+    }
+
+    // Anything that looks like UTF-8 without control characters (except
+    // whitespace) is probably text. The sample may end in the middle of
+    // a multibyte character, which is fine.
+    let text = match std::str::from_utf8(data) {
+        Ok(s) => s,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&data[..e.valid_up_to()]).unwrap_or_default(),
+        Err(_) => "",
+    };
+    if !text.is_empty() && text.chars().all(|c| !c.is_control() || c.is_ascii_whitespace()) {
+        let trimmed = text.trim_start_matches('\u{feff}').trim_start();
+        return if trimmed.starts_with('{') || trimmed.starts_with('[') {
+            "JSON (probably)"
+        } else {
+            "text"
+        };
+    }
+
+    "unknown"
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_content_type() {
+        assert_eq!(detect_content_type(b""), "empty");
+        assert_eq!(detect_content_type(b"KCAPg\x00\x00\x00"), "Lingcod PAK");
+        assert_eq!(detect_content_type(b"\x89PNG\r\n\x1a\n\x00\x00"), "PNG");
+        assert_eq!(detect_content_type(b"RIFF\x00\x00\x00\x00WAVEfmt "), "RIFF WAVE (WAV/Wwise)");
+        assert_eq!(detect_content_type(b"hello\nworld\n"), "text");
+        assert_eq!(detect_content_type(b"  {\"a\": 1}"), "JSON (probably)");
+        assert_eq!(detect_content_type("caf\u{e9}".as_bytes()), "text");
+        assert_eq!(detect_content_type(&"caf\u{e9}".as_bytes()[..4]), "text");
+        assert_eq!(detect_content_type(b"\x00\x12\xff\xfe"), "unknown");
+
+        let mut game_boy = vec![0; SNIFF_SIZE];
+        game_boy[0x104..0x108].copy_from_slice(b"\xce\xed\x66\x66");
+        assert_eq!(detect_content_type(&game_boy), "Game Boy ROM");
+    }
+
+    #[test]
+    fn test_sniff_size() {
+        for (offset, magic, name) in SIGNATURES {
+            assert!(offset + magic.len() <= SNIFF_SIZE, "{name}");
+        }
+    }
+}
@@ -0,0 +1,856 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::BufReader,
+    path::Path,
+};
+
+use binrw::BinRead;
+use packling::{
+    filter::AssetFilter,
+    flow_assets_list::{AssetsListFormat, export, import},
+    flow_init::{MANIFEST_NAME, init},
+    flow_just_decrypt::{decrypt, decrypt_to_writer},
+    flow_pack::{PackOptions, pack, pack_manifest},
+    flow_unpack::{ConflictPolicy, Permissions, UnpackOptions, unpack},
+    flow_unpack_all::unpack_all,
+    flow_verify::{verify, verify_fast},
+    key::{detect_game, get_key, get_key_for_folder, get_key_for_pak},
+    manifest::Manifest,
+    preset::GamePreset,
+    sidecar::{RawForm, SIDECAR_NAME},
+    shared::{EXTENDED_FILE_VERSION, FILE_VERSION, PakHeader, TimeZone, Verbosity, calc_pak_crc32, calc_pak_crc32_parallel},
+};
+use proptest::prelude::*;
+
+
+/// Any key works, since nothing checks it when packing or unpacking.
+const TEST_KEY: [u8; 16] = *b"packling testing";
+
+
+/// Asset names, up to a few folders deep. Folder and file names can't
+/// collide, since only file names end in ".bin".
+fn asset_name() -> impl Strategy<Value = String> {
+    (
+        prop::collection::vec("d_[a-z0-9]{1,6}", 0..3),
+        "[a-z0-9_]{1,8}\\.bin",
+    ).prop_map(|(mut components, file_name)| {
+        components.push(file_name);
+        components.join("/")
+    })
+}
+
+
+/// File contents, either incompressible or very compressible, and
+/// spanning a few encryption chunks at most.
+fn asset_data() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        prop::collection::vec(any::<u8>(), 0..0x5000),
+        (any::<u8>(), 0..0x5000_usize).prop_map(|(byte, len)| vec![byte; len]),
+    ]
+}
+
+
+fn write_folder(folder: &Path, files: &BTreeMap<String, Vec<u8>>) {
+    for (name, data) in files {
+        let path = folder.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, data).unwrap();
+    }
+}
+
+
+fn read_folder(folder: &Path) -> BTreeMap<String, Vec<u8>> {
+    let mut files = BTreeMap::new();
+    if !folder.exists() {
+        return files;
+    }
+
+    for entry in walkdir::WalkDir::new(folder) {
+        let entry = entry.unwrap();
+        // (TEST_KEY isn't the known key, so unpacking records it in a
+        // sidecar)
+        if !entry.file_type().is_file() || entry.file_name() == SIDECAR_NAME {
+            continue;
+        }
+        let name: Vec<_> = entry.path()
+            .strip_prefix(folder)
+            .unwrap()
+            .iter()
+            .map(|c| c.to_str().unwrap())
+            .collect();
+        files.insert(name.join("/"), std::fs::read(entry.path()).unwrap());
+    }
+    files
+}
+
+
+/// Pack `files`, check the whole-file checksum, unpack them again (which
+/// checks the per-asset checksums), and compare.
+fn assert_round_trip(files: &BTreeMap<String, Vec<u8>>, empty_folders: &[&str], compress_files: bool) {
+    let temp = tempfile::tempdir().unwrap();
+    let input_folder = temp.path().join("input");
+    let pak_file = temp.path().join("test.pak");
+    let output_folder = temp.path().join("output");
+
+    std::fs::create_dir(&input_folder).unwrap();
+    write_folder(&input_folder, files);
+    for folder in empty_folders {
+        std::fs::create_dir_all(input_folder.join(folder)).unwrap();
+    }
+
+    pack(&input_folder, &pak_file, &TEST_KEY, &PackOptions { compress_files, ..PackOptions::default() }, Verbosity::Quiet).unwrap();
+
+    let mut reader = BufReader::new(File::open(&pak_file).unwrap());
+    let header = PakHeader::read(&mut reader).unwrap();
+    let file_size = std::fs::metadata(&pak_file).unwrap().len();
+    assert_eq!(calc_pak_crc32(&mut reader, file_size).unwrap(), header.crc32);
+    assert_eq!(calc_pak_crc32_parallel(&pak_file, 3).unwrap(), header.crc32);
+
+    unpack(&pak_file, &output_folder, &TEST_KEY, &UnpackOptions::default(), Verbosity::Quiet).unwrap();
+
+    assert_eq!(read_folder(&output_folder), *files);
+}
+
+
+#[test]
+fn test_round_trip_edge_cases() {
+    let mut files = BTreeMap::new();
+    for size in [0, 1, 3, 4, 5, 7, 8, 9, 0x1fff, 0x2000, 0x2001, 0x2003, 0x4005] {
+        files.insert(format!("sizes/{size:#x}.bin"), (0..size).map(|i| (i * 7) as u8).collect());
+    }
+    assert_round_trip(&files, &[], false);
+    assert_round_trip(&files, &[], true);
+}
+
+
+#[test]
+fn test_round_trip_large_asset() {
+    // (big enough to be extracted through a memory mapping, where that's
+    // supported)
+    let mut files = BTreeMap::new();
+    files.insert("large.bin".to_owned(), (0..0x10_0005).map(|i| (i * 7) as u8).collect());
+    files.insert("small.bin".to_owned(), vec![1; 0x10]);
+    assert_round_trip(&files, &[], false);
+}
+
+
+#[test]
+fn test_unpack_large_asset_damaged() {
+    let temp = tempfile::tempdir().unwrap();
+    let input_folder = temp.path().join("input");
+    let pak_file = temp.path().join("test.pak");
+    let output_folder = temp.path().join("output");
+
+    std::fs::create_dir(&input_folder).unwrap();
+    std::fs::write(input_folder.join("large.bin"), (0..0x10_0005).map(|i| (i * 7) as u8).collect::<Vec<_>>()).unwrap();
+    pack(&input_folder, &pak_file, &TEST_KEY, &PackOptions::default(), Verbosity::Quiet).unwrap();
+
+    // Damage the asset, so its ciphertext CRC32 doesn't match
+    let mut pak = std::fs::read(&pak_file).unwrap();
+    let middle = pak.len() - 0x8_0000;
+    pak[middle] ^= 0xff;
+    std::fs::write(&pak_file, pak).unwrap();
+
+    assert!(unpack(&pak_file, &output_folder, &TEST_KEY, &UnpackOptions::default(), Verbosity::Silent).is_err());
+    assert!(!output_folder.join("large.bin").exists());
+}
+
+
+#[test]
+fn test_unpack_keep_going() {
+    let temp = tempfile::tempdir().unwrap();
+    let input_folder = temp.path().join("input");
+    let pak_file = temp.path().join("test.pak");
+    let output_folder = temp.path().join("output");
+
+    std::fs::create_dir(&input_folder).unwrap();
+    std::fs::write(input_folder.join("a.bin"), vec![1; 0x10]).unwrap();
+    std::fs::write(input_folder.join("b.bin"), vec![2; 0x10]).unwrap();
+    pack(&input_folder, &pak_file, &TEST_KEY, &PackOptions::default(), Verbosity::Quiet).unwrap();
+
+    // Damage the last asset, so its ciphertext CRC32 doesn't match
+    let mut pak = std::fs::read(&pak_file).unwrap();
+    *pak.last_mut().unwrap() ^= 0xff;
+    std::fs::write(&pak_file, pak).unwrap();
+
+    // By default, that stops the unpack...
+    let err = unpack(&pak_file, &output_folder, &TEST_KEY, &UnpackOptions::default(), Verbosity::Silent).unwrap_err();
+    assert!(format!("{err:#}").contains("ciphertext CRC32 mismatch"));
+
+    // ...but with --keep-going, everything else is still extracted
+    let options = UnpackOptions {
+        force: true,
+        keep_going: true,
+        ..UnpackOptions::default()
+    };
+    assert!(unpack(&pak_file, &output_folder, &TEST_KEY, &options, Verbosity::Silent).is_err());
+    assert_eq!(read_folder(&output_folder), BTreeMap::from([("a.bin".to_owned(), vec![1; 0x10])]));
+}
+
+
+#[test]
+fn test_verify_report() {
+    let temp = tempfile::tempdir().unwrap();
+    let input_folder = temp.path().join("input");
+    let pak_file = temp.path().join("test.pak");
+    let report_file = temp.path().join("report.json");
+
+    std::fs::create_dir(&input_folder).unwrap();
+    std::fs::write(input_folder.join("a.bin"), vec![1; 0x10]).unwrap();
+    std::fs::write(input_folder.join("b.bin"), vec![2; 0x10]).unwrap();
+    pack(&input_folder, &pak_file, &TEST_KEY, &PackOptions::default(), Verbosity::Quiet).unwrap();
+
+    for fast in [false, true] {
+        let check = if fast { verify_fast } else { verify };
+
+        check(&pak_file, &TEST_KEY, Some(&report_file), Verbosity::Silent).unwrap();
+        let report: serde_json::Value = serde_json::from_slice(&std::fs::read(&report_file).unwrap()).unwrap();
+        assert_eq!(report["operation"], "verify");
+        assert_eq!(report["success"], true);
+        assert!(report.get("output").is_none());
+        let actions: Vec<_> = report["assets"].as_array().unwrap().iter().map(|a| (a["name"].clone(), a["action"].clone())).collect();
+        assert_eq!(actions, [("a.bin".into(), "verified".into()), ("b.bin".into(), "verified".into())]);
+    }
+
+    // Damage the last asset, so its ciphertext CRC32 doesn't match
+    let mut pak = std::fs::read(&pak_file).unwrap();
+    *pak.last_mut().unwrap() ^= 0xff;
+    std::fs::write(&pak_file, pak).unwrap();
+
+    for fast in [false, true] {
+        let check = if fast { verify_fast } else { verify };
+
+        assert!(check(&pak_file, &TEST_KEY, Some(&report_file), Verbosity::Silent).is_err());
+        let report: serde_json::Value = serde_json::from_slice(&std::fs::read(&report_file).unwrap()).unwrap();
+        assert_eq!(report["success"], false);
+        assert_eq!(report["assets"][0]["action"], "verified");
+        assert_eq!(report["assets"][1]["action"], "failed");
+        assert!(report["assets"][1]["error"].as_str().unwrap().contains("ciphertext CRC32 mismatch"));
+    }
+}
+
+
+#[test]
+fn test_round_trip_empty_folders() {
+    assert_round_trip(&BTreeMap::new(), &[], false);
+    assert_round_trip(&BTreeMap::new(), &["empty", "nested/empty"], false);
+}
+
+
+#[test]
+fn test_round_trip_keep_empty_folders() {
+    let temp = tempfile::tempdir().unwrap();
+    let input_folder = temp.path().join("input");
+    let pak_file = temp.path().join("test.pak");
+    let output_folder = temp.path().join("output");
+
+    let mut files = BTreeMap::new();
+    files.insert("a.bin".to_owned(), vec![1; 0x10]);
+    std::fs::create_dir(&input_folder).unwrap();
+    write_folder(&input_folder, &files);
+    std::fs::create_dir_all(input_folder.join("nested/empty")).unwrap();
+
+    pack(&input_folder, &pak_file, &TEST_KEY, &PackOptions { keep_empty_folders: true, ..PackOptions::default() }, Verbosity::Quiet).unwrap();
+    unpack(&pak_file, &output_folder, &TEST_KEY, &UnpackOptions::default(), Verbosity::Quiet).unwrap();
+
+    assert_eq!(read_folder(&output_folder), files);
+    assert!(output_folder.join("nested/empty").is_dir());
+}
+
+
+#[test]
+fn test_round_trip_recursive() {
+    let temp = tempfile::tempdir().unwrap();
+    let inner_folder = temp.path().join("inner");
+    let input_folder = temp.path().join("input");
+    let pak_file = temp.path().join("test.pak");
+    let output_folder = temp.path().join("output");
+    let repacked_file = temp.path().join("repacked.pak");
+
+    let mut inner_files = BTreeMap::new();
+    inner_files.insert("b.bin".to_owned(), vec![2; 0x100]);
+    inner_files.insert("d_x/c.bin".to_owned(), (0..0x2003).map(|i| (i * 7) as u8).collect());
+    std::fs::create_dir(&inner_folder).unwrap();
+    write_folder(&inner_folder, &inner_files);
+
+    std::fs::create_dir_all(input_folder.join("d_y")).unwrap();
+    std::fs::write(input_folder.join("a.bin"), vec![1; 0x10]).unwrap();
+    pack(&inner_folder, &input_folder.join("d_y/inner.pak"), &TEST_KEY, &PackOptions::default(), Verbosity::Quiet).unwrap();
+    pack(&input_folder, &pak_file, &TEST_KEY, &PackOptions::default(), Verbosity::Quiet).unwrap();
+
+    let options = UnpackOptions {
+        recursive: true,
+        ..UnpackOptions::default()
+    };
+    unpack(&pak_file, &output_folder, &TEST_KEY, &options, Verbosity::Quiet).unwrap();
+    assert_eq!(read_folder(&output_folder.join("d_y/inner.pak")), inner_files);
+
+    // Packing the folder back needs --recursive too...
+    assert!(pack(&output_folder, &repacked_file, &TEST_KEY, &PackOptions::default(), Verbosity::Quiet).is_err());
+
+    // ...and with the same options, gives the same .pak
+    let options = PackOptions {
+        recursive: true,
+        ..PackOptions::default()
+    };
+    pack(&output_folder, &repacked_file, &TEST_KEY, &options, Verbosity::Quiet).unwrap();
+    assert_eq!(std::fs::read(&repacked_file).unwrap(), std::fs::read(&pak_file).unwrap());
+}
+
+
+#[test]
+fn test_unpack_size_filter() {
+    let temp = tempfile::tempdir().unwrap();
+    let input_folder = temp.path().join("input");
+    let pak_file = temp.path().join("test.pak");
+    let output_folder = temp.path().join("output");
+
+    let mut files = BTreeMap::new();
+    for size in [0x10, 0x100, 0x1000] {
+        files.insert(format!("{size:#x}.bin"), vec![1; size]);
+    }
+    std::fs::create_dir(&input_folder).unwrap();
+    write_folder(&input_folder, &files);
+    pack(&input_folder, &pak_file, &TEST_KEY, &PackOptions { compress_files: true, ..PackOptions::default() }, Verbosity::Quiet).unwrap();
+
+    // (by decompressed size, even though these all compress well)
+    let filter = AssetFilter::default().with_size_range(Some(0x20), Some(0x100)).unwrap();
+    let options = UnpackOptions {
+        filter: Some(&filter),
+        ..UnpackOptions::default()
+    };
+    unpack(&pak_file, &output_folder, &TEST_KEY, &options, Verbosity::Quiet).unwrap();
+
+    files.retain(|name, _| name == "0x100.bin");
+    assert_eq!(read_folder(&output_folder), files);
+
+    // An order file would only list some of the assets
+    let order_file = temp.path().join("order.txt");
+    let options = UnpackOptions {
+        force: true,
+        order_file: Some(order_file.to_str().unwrap()),
+        filter: Some(&filter),
+        ..UnpackOptions::default()
+    };
+    assert!(unpack(&pak_file, &output_folder, &TEST_KEY, &options, Verbosity::Quiet).is_err());
+    assert!(!order_file.exists());
+}
+
+
+#[cfg(unix)]
+#[test]
+fn test_unpack_chmod() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+    let temp = tempfile::tempdir().unwrap();
+    let input_folder = temp.path().join("input");
+    let pak_file = temp.path().join("test.pak");
+    let output_folder = temp.path().join("output");
+    let unrelated_folder = output_folder.join("unrelated");
+
+    std::fs::create_dir_all(input_folder.join("d_x")).unwrap();
+    std::fs::write(input_folder.join("d_x/a.bin"), vec![1; 0x10]).unwrap();
+    pack(&input_folder, &pak_file, &TEST_KEY, &PackOptions::default(), Verbosity::Quiet).unwrap();
+    std::fs::create_dir_all(&unrelated_folder).unwrap();
+    std::fs::set_permissions(&unrelated_folder, std::fs::Permissions::from_mode(0o700)).unwrap();
+
+    // (extracting into the existing output folder)
+    let options = UnpackOptions {
+        on_conflict: Some(ConflictPolicy::Overwrite),
+        permissions: Permissions {
+            mode: Some(0o640),
+            ..Permissions::default()
+        },
+        ..UnpackOptions::default()
+    };
+    unpack(&pak_file, &output_folder, &TEST_KEY, &options, Verbosity::Quiet).unwrap();
+
+    assert_eq!(mode(&output_folder.join("d_x/a.bin")), 0o640);
+    assert_eq!(mode(&output_folder.join("d_x")), 0o750);
+    assert_eq!(mode(&unrelated_folder), 0o700);
+}
+
+
+#[test]
+fn test_plaintext_tails() {
+    let temp = tempfile::tempdir().unwrap();
+    let input_folder = temp.path().join("input");
+    let pak_file = temp.path().join("test.pak");
+    let report_file = temp.path().join("report.json");
+    let key_file = temp.path().join("key.bin");
+
+    std::fs::create_dir(&input_folder).unwrap();
+    std::fs::write(input_folder.join("a.bin"), b"tiny").unwrap();
+    std::fs::write(input_folder.join("b.bin"), b"thirteen byte").unwrap();
+    std::fs::write(input_folder.join("c.bin"), b"sixteen bytes!!!").unwrap();
+    let options = PackOptions {
+        warn_plaintext_tails: true,
+        report: Some(&report_file),
+        ..PackOptions::default()
+    };
+    pack(&input_folder, &pak_file, &TEST_KEY, &options, Verbosity::Silent).unwrap();
+
+    let report: serde_json::Value = serde_json::from_slice(&std::fs::read(&report_file).unwrap()).unwrap();
+    assert_eq!(
+        report["warnings"],
+        serde_json::json!(["2 asset(s) are stored partly or entirely unencrypted:\n  a.bin: all 4 byte(s)\n  b.bin: last 1 of 13 bytes"]),
+    );
+
+    // verify lists the same ones
+    std::fs::write(&key_file, TEST_KEY).unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_packling"))
+        .arg("verify")
+        .args([&key_file, &pak_file])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Plaintext tails:  1 asset(s)"), "{stdout}");
+    assert!(stdout.contains("Unencrypted:      1 asset(s) too small to be encrypted at all:\n  a.bin\n"), "{stdout}");
+}
+
+
+#[test]
+fn test_pack_overlays() {
+    let temp = tempfile::tempdir().unwrap();
+    let base_folder = temp.path().join("base");
+    let mod_folder = temp.path().join("mod");
+    let pak_file = temp.path().join("test.pak");
+    let output_folder = temp.path().join("output");
+
+    let base_files = BTreeMap::from([("a.bin".to_owned(), vec![1; 0x10]), ("d_x/b.bin".to_owned(), vec![2; 0x20])]);
+    let mod_files = BTreeMap::from([("d_x/b.bin".to_owned(), vec![3; 0x30]), ("d_y/c.bin".to_owned(), vec![4; 0x40])]);
+    std::fs::create_dir(&base_folder).unwrap();
+    std::fs::create_dir(&mod_folder).unwrap();
+    write_folder(&base_folder, &base_files);
+    write_folder(&mod_folder, &mod_files);
+
+    let overlays = [mod_folder];
+    let options = PackOptions {
+        overlays: &overlays,
+        ..PackOptions::default()
+    };
+    pack(&base_folder, &pak_file, &TEST_KEY, &options, Verbosity::Quiet).unwrap();
+    unpack(&pak_file, &output_folder, &TEST_KEY, &UnpackOptions::default(), Verbosity::Quiet).unwrap();
+
+    let mut files = base_files;
+    files.extend(mod_files);
+    assert_eq!(read_folder(&output_folder), files);
+}
+
+
+#[test]
+fn test_pack_resume_starts_over() {
+    let temp = tempfile::tempdir().unwrap();
+    let input_folder = temp.path().join("input");
+    let pak_file = temp.path().join("test.pak");
+    let resumed_file = temp.path().join("resumed.pak");
+    let journal_file = temp.path().join("resumed.pak.packling-journal");
+
+    let files = BTreeMap::from([("a.bin".to_owned(), vec![1; 0x100]), ("b.bin".to_owned(), vec![2; 0x1000])]);
+    std::fs::create_dir(&input_folder).unwrap();
+    write_folder(&input_folder, &files);
+    pack(&input_folder, &pak_file, &TEST_KEY, &PackOptions::default(), Verbosity::Quiet).unwrap();
+
+    // Without --resume or -f, an output file left by an interrupted
+    // pack isn't touched
+    std::fs::write(&resumed_file, b"interrupted").unwrap();
+    std::fs::write(&journal_file, b"{\"version\":1,\"plan\":0}\n").unwrap();
+    assert!(pack(&input_folder, &resumed_file, &TEST_KEY, &PackOptions::default(), Verbosity::Quiet).is_err());
+
+    // A journal for a different pack can't be resumed, so the output
+    // file is only replaced with -f, as usual
+    let options = PackOptions {
+        resume: true,
+        ..PackOptions::default()
+    };
+    assert!(pack(&input_folder, &resumed_file, &TEST_KEY, &options, Verbosity::Quiet).is_err());
+    assert_eq!(std::fs::read(&resumed_file).unwrap(), b"interrupted");
+
+    let options = PackOptions {
+        force: true,
+        resume: true,
+        ..PackOptions::default()
+    };
+    pack(&input_folder, &resumed_file, &TEST_KEY, &options, Verbosity::Quiet).unwrap();
+    assert_eq!(std::fs::read(&resumed_file).unwrap(), std::fs::read(&pak_file).unwrap());
+    assert!(!journal_file.exists());
+
+    // With --resume, an unrelated file isn't overwritten without -f
+    std::fs::write(&resumed_file, b"unrelated").unwrap();
+    let options = PackOptions {
+        resume: true,
+        ..PackOptions::default()
+    };
+    assert!(pack(&input_folder, &resumed_file, &TEST_KEY, &options, Verbosity::Quiet).is_err());
+    assert_eq!(std::fs::read(&resumed_file).unwrap(), b"unrelated");
+}
+
+
+#[test]
+fn test_unpack_all() {
+    let temp = tempfile::tempdir().unwrap();
+    let input_folder = temp.path().join("input");
+    let romfs_folder = temp.path().join("romfs");
+    let key_file = temp.path().join("key.bin");
+
+    let mut files = BTreeMap::new();
+    files.insert("a.bin".to_owned(), vec![1; 0x10]);
+    std::fs::create_dir(&input_folder).unwrap();
+    write_folder(&input_folder, &files);
+    std::fs::create_dir_all(romfs_folder.join("d_x")).unwrap();
+    for pak_file in ["one.pak", "d_x/two.pak"] {
+        pack(&input_folder, &romfs_folder.join(pak_file), &TEST_KEY, &PackOptions::default(), Verbosity::Quiet).unwrap();
+    }
+    std::fs::write(romfs_folder.join("d_x/other.bin"), b"not a .pak").unwrap();
+    std::fs::write(&key_file, TEST_KEY).unwrap();
+
+    unpack_all(&romfs_folder, &key_file, &UnpackOptions::default(), Verbosity::Silent).unwrap();
+    assert_eq!(read_folder(&romfs_folder.join("one")), files);
+    assert_eq!(read_folder(&romfs_folder.join("d_x/two")), files);
+
+    // Already-unpacked .pak files are skipped, rather than failing
+    unpack_all(&romfs_folder, &key_file, &UnpackOptions::default(), Verbosity::Silent).unwrap();
+}
+
+
+#[test]
+fn test_decrypt() {
+    let temp = tempfile::tempdir().unwrap();
+    let input_folder = temp.path().join("input");
+    let pak_file = temp.path().join("test.pak");
+    let decrypted_file = temp.path().join("decrypted.pak");
+    let in_place_file = temp.path().join("in_place.pak");
+
+    let data: Vec<u8> = (0..0x4005).map(|i| (i * 7) as u8).collect();
+    std::fs::create_dir(&input_folder).unwrap();
+    std::fs::write(input_folder.join("a.bin"), vec![1; 0x10]).unwrap();
+    std::fs::write(input_folder.join("b.bin"), &data).unwrap();
+    pack(&input_folder, &pak_file, &TEST_KEY, &PackOptions::default(), Verbosity::Quiet).unwrap();
+    assert!(!std::fs::read(&pak_file).unwrap().windows(data.len()).any(|w| w == data));
+
+    decrypt(&pak_file, &decrypted_file, &TEST_KEY, false, TimeZone::Utc, Verbosity::Silent).unwrap();
+    let decrypted = std::fs::read(&decrypted_file).unwrap();
+    assert_eq!(decrypted.len(), std::fs::read(&pak_file).unwrap().len());
+    assert!(decrypted.windows(data.len()).any(|w| w == data));
+
+    // Decrypting in place gives the same result
+    std::fs::copy(&pak_file, &in_place_file).unwrap();
+    decrypt(&in_place_file, &in_place_file, &TEST_KEY, false, TimeZone::Utc, Verbosity::Silent).unwrap();
+    assert_eq!(std::fs::read(&in_place_file).unwrap(), decrypted);
+
+    // ...as does writing it somewhere that isn't seekable
+    let mut written = Vec::new();
+    decrypt_to_writer(&pak_file, &mut written, &TEST_KEY, TimeZone::Utc, Verbosity::Silent).unwrap();
+    assert_eq!(written, decrypted);
+}
+
+
+#[test]
+fn test_decrypt_to_stdout_verbose() {
+    let temp = tempfile::tempdir().unwrap();
+    let input_folder = temp.path().join("input");
+    let pak_file = temp.path().join("test.pak");
+    let decrypted_file = temp.path().join("decrypted.pak");
+    let key_file = temp.path().join("key.bin");
+
+    std::fs::create_dir(&input_folder).unwrap();
+    std::fs::write(input_folder.join("a.bin"), vec![1; 0x10]).unwrap();
+    pack(&input_folder, &pak_file, &TEST_KEY, &PackOptions::default(), Verbosity::Quiet).unwrap();
+    decrypt(&pak_file, &decrypted_file, &TEST_KEY, false, TimeZone::Utc, Verbosity::Silent).unwrap();
+    std::fs::write(&key_file, TEST_KEY).unwrap();
+
+    // Messages go to stderr, so stdout is just the .pak
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_packling"))
+        .arg("-v")
+        .args([&key_file, &pak_file])
+        .arg("-")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(output.stdout, std::fs::read(&decrypted_file).unwrap());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Using key"));
+}
+
+
+#[test]
+fn test_round_trip_extended_offsets() {
+    let temp = tempfile::tempdir().unwrap();
+    let input_folder = temp.path().join("input");
+    let pak_file = temp.path().join("test.pak");
+    let output_folder = temp.path().join("output");
+
+    let mut files = BTreeMap::new();
+    files.insert("a.bin".to_owned(), vec![1; 0x100]);
+    files.insert("d_x/b.bin".to_owned(), (0..0x2003).map(|i| (i * 7) as u8).collect());
+    std::fs::create_dir(&input_folder).unwrap();
+    write_folder(&input_folder, &files);
+
+    pack(&input_folder, &pak_file, &TEST_KEY, &PackOptions { compress_files: true, extended_offsets: true, ..PackOptions::default() }, Verbosity::Quiet).unwrap();
+
+    // Only accepted when explicitly asked for
+    assert!(unpack(&pak_file, &output_folder, &TEST_KEY, &UnpackOptions::default(), Verbosity::Quiet).is_err());
+
+    let options = UnpackOptions {
+        extended_offsets: true,
+        ..UnpackOptions::default()
+    };
+    unpack(&pak_file, &output_folder, &TEST_KEY, &options, Verbosity::Quiet).unwrap();
+
+    assert_eq!(read_folder(&output_folder), files);
+}
+
+
+#[test]
+fn test_round_trip_manifest() {
+    let temp = tempfile::tempdir().unwrap();
+    let source_folder = temp.path().join("build");
+    let manifest_file = temp.path().join("build.toml");
+    let pak_file = temp.path().join("test.pak");
+    let output_folder = temp.path().join("output");
+
+    std::fs::create_dir(&source_folder).unwrap();
+    std::fs::write(source_folder.join("x.dat"), vec![1; 0x100]).unwrap();
+    std::fs::write(source_folder.join("y.dat"), (0..0x2003).map(|i| (i * 7) as u8).collect::<Vec<_>>()).unwrap();
+    std::fs::write(&manifest_file, r#"
+        compress = true
+
+        [[asset]]
+        source = "build/y.dat"
+        name = "d_b/b.bin"
+        compress = false
+
+        [[asset]]
+        source = "build/x.dat"
+        name = "a.bin"
+    "#).unwrap();
+
+    let manifest = Manifest::load(&manifest_file).unwrap();
+    pack_manifest(&manifest_file, &manifest, &pak_file, &TEST_KEY, &PackOptions::default(), Verbosity::Quiet).unwrap();
+    unpack(&pak_file, &output_folder, &TEST_KEY, &UnpackOptions::default(), Verbosity::Quiet).unwrap();
+
+    let mut files = BTreeMap::new();
+    files.insert("a.bin".to_owned(), vec![1; 0x100]);
+    files.insert("d_b/b.bin".to_owned(), std::fs::read(source_folder.join("y.dat")).unwrap());
+    assert_eq!(read_folder(&output_folder), files);
+}
+
+
+#[test]
+fn test_init_round_trip() {
+    let temp = tempfile::tempdir().unwrap();
+    let input_folder = temp.path().join("input");
+    let pak_file = temp.path().join("test.pak");
+    let project_folder = temp.path().join("project");
+    let rebuilt_file = temp.path().join("rebuilt.pak");
+
+    // (one compressible asset and one that isn't, which packing with
+    // compression leaves uncompressed)
+    std::fs::create_dir(&input_folder).unwrap();
+    std::fs::write(input_folder.join("a.bin"), vec![1; 0x1000]).unwrap();
+    std::fs::write(input_folder.join("b.bin"), (0..0x1000).map(|i| (i * 7) as u8).collect::<Vec<_>>()).unwrap();
+    let options = PackOptions {
+        timestamp: 1234,
+        compress_files: true,
+        ..PackOptions::default()
+    };
+    pack(&input_folder, &pak_file, &TEST_KEY, &options, Verbosity::Quiet).unwrap();
+
+    init(&pak_file, &project_folder, Some(&TEST_KEY), Verbosity::Silent).unwrap();
+    let manifest_file = project_folder.join(MANIFEST_NAME);
+    let manifest = Manifest::load(&manifest_file).unwrap();
+    let options = PackOptions {
+        timestamp: manifest.timestamp.as_deref().unwrap().parse().unwrap(),
+        ..PackOptions::default()
+    };
+    pack_manifest(&manifest_file, &manifest, &rebuilt_file, &TEST_KEY, &options, Verbosity::Quiet).unwrap();
+    assert_eq!(std::fs::read(&rebuilt_file).unwrap(), std::fs::read(&pak_file).unwrap());
+}
+
+
+#[test]
+fn test_assets_list_round_trip() {
+    let temp = tempfile::tempdir().unwrap();
+    let input_folder = temp.path().join("input");
+    let pak_file = temp.path().join("test.pak");
+    let rebuilt_file = temp.path().join("rebuilt.pak");
+
+    std::fs::create_dir(&input_folder).unwrap();
+    std::fs::write(input_folder.join("a.bin"), vec![1; 0x1000]).unwrap();
+    std::fs::write(input_folder.join("b.bin"), (0..0x2003).map(|i| (i * 7) as u8).collect::<Vec<_>>()).unwrap();
+    let options = PackOptions {
+        timestamp: 1234,
+        compress_files: true,
+        ..PackOptions::default()
+    };
+    pack(&input_folder, &pak_file, &TEST_KEY, &options, Verbosity::Quiet).unwrap();
+
+    // An unedited dump gives back the same .pak, in either format
+    for (file_name, format) in [("dump.json", AssetsListFormat::Json), ("dump.toml", AssetsListFormat::Toml)] {
+        let dump_file = temp.path().join(file_name);
+        export(&pak_file, &TEST_KEY, format, Some(&dump_file)).unwrap();
+        import(&pak_file, &dump_file, &rebuilt_file, &TEST_KEY, true).unwrap();
+        assert_eq!(std::fs::read(&rebuilt_file).unwrap(), std::fs::read(&pak_file).unwrap());
+    }
+
+    // (extended offsets can't be written)
+    let dump_file = temp.path().join("dump.json");
+    let dump = std::fs::read_to_string(&dump_file).unwrap();
+    let dump = dump.replacen(&format!("\"version\": {FILE_VERSION}"), &format!("\"version\": {EXTENDED_FILE_VERSION}"), 1);
+    std::fs::write(&dump_file, dump).unwrap();
+    assert!(import(&pak_file, &dump_file, &rebuilt_file, &TEST_KEY, true).is_err());
+}
+
+
+#[test]
+fn test_other_key() {
+    let temp = tempfile::tempdir().unwrap();
+    let input_folder = temp.path().join("input");
+    let pak_file = temp.path().join("test.pak");
+    let output_folder = temp.path().join("output");
+    let key_file = temp.path().join("key.bin");
+
+    // (not the known key, so `get_key` can't find it)
+    let key = *b"some other key!!";
+    std::fs::write(&key_file, key).unwrap();
+    assert!(get_key(&key_file).is_err());
+
+    std::fs::create_dir(&input_folder).unwrap();
+    std::fs::write(input_folder.join("a.bin"), vec![1; 0x100]).unwrap();
+    pack(&input_folder, &pak_file, &key, &PackOptions::default(), Verbosity::Quiet).unwrap();
+    assert_eq!(*get_key_for_pak(&key_file, &pak_file).unwrap(), key);
+
+    // The unpacked folder's sidecar says which key to pack it with again
+    unpack(&pak_file, &output_folder, &key, &UnpackOptions::default(), Verbosity::Quiet).unwrap();
+    assert_eq!(*get_key_for_folder(&key_file, &output_folder).unwrap(), key);
+    assert!(get_key_for_folder(&key_file, &input_folder).is_err());
+}
+
+
+#[test]
+fn test_detect_game() {
+    let temp = tempfile::tempdir().unwrap();
+    let input_folder = temp.path().join("input");
+    let pak_file = temp.path().join("test.pak");
+    let key_file = temp.path().join("key.bin");
+    std::fs::write(&key_file, TEST_KEY).unwrap();
+
+    // (a key.bin file could be from any game, so only the asset names
+    // can show which)
+    let mut files = BTreeMap::new();
+    files.insert("Stage/01-01.arc".to_owned(), vec![1; 0x10]);
+    files.insert("Object/block.arc".to_owned(), vec![2; 0x10]);
+    files.insert("other.bin".to_owned(), vec![3; 0x10]);
+    std::fs::create_dir(&input_folder).unwrap();
+    write_folder(&input_folder, &files);
+    pack(&input_folder, &pak_file, &TEST_KEY, &PackOptions::default(), Verbosity::Quiet).unwrap();
+    assert_eq!(detect_game(&key_file, &pak_file, &TEST_KEY).unwrap(), Some(GamePreset::Nsmb));
+
+    std::fs::remove_dir_all(&input_folder).unwrap();
+    std::fs::create_dir(&input_folder).unwrap();
+    std::fs::write(input_folder.join("other.bin"), vec![3; 0x10]).unwrap();
+    let options = PackOptions {
+        force: true,
+        ..PackOptions::default()
+    };
+    pack(&input_folder, &pak_file, &TEST_KEY, &options, Verbosity::Quiet).unwrap();
+    assert_eq!(detect_game(&key_file, &pak_file, &TEST_KEY).unwrap(), None);
+}
+
+
+#[test]
+fn test_other_key_commands() {
+    let temp = tempfile::tempdir().unwrap();
+    let input_folder = temp.path().join("input");
+    let romfs_folder = temp.path().join("romfs");
+    let key_file = temp.path().join("key.bin");
+
+    let key = *b"some other key!!";
+    std::fs::write(&key_file, key).unwrap();
+
+    std::fs::create_dir(&input_folder).unwrap();
+    std::fs::create_dir(&romfs_folder).unwrap();
+    std::fs::write(input_folder.join("a.bin"), vec![1; 0x100]).unwrap();
+    pack(&input_folder, &romfs_folder.join("old.pak"), &key, &PackOptions::default(), Verbosity::Quiet).unwrap();
+    std::fs::write(input_folder.join("b.bin"), vec![2; 0x100]).unwrap();
+    pack(&input_folder, &romfs_folder.join("new.pak"), &key, &PackOptions::default(), Verbosity::Quiet).unwrap();
+
+    // Commands that take a .pak find its key the same way as unpacking
+    // it does
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_packling"))
+        .arg("diff")
+        .args([&key_file, &romfs_folder.join("old.pak"), &romfs_folder.join("new.pak")])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "+ b.bin\n1 added, 0 removed, 0 changed, 1 unchanged\n");
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_packling"))
+        .args(["unpack-all", "-q"])
+        .args([&key_file, &romfs_folder])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(read_folder(&romfs_folder.join("old")), BTreeMap::from([("a.bin".to_owned(), vec![1; 0x100])]));
+    assert_eq!(read_folder(&romfs_folder.join("new")).len(), 2);
+}
+
+
+#[test]
+fn test_pack_raw_compressed() {
+    let temp = tempfile::tempdir().unwrap();
+    let input_folder = temp.path().join("input");
+    let pak_file = temp.path().join("test.pak");
+    let output_folder = temp.path().join("output");
+    let rebuilt_file = temp.path().join("rebuilt.pak");
+
+    std::fs::create_dir(&input_folder).unwrap();
+    std::fs::write(input_folder.join("a.bin"), vec![1; 0x1000]).unwrap();
+    let options = PackOptions {
+        timestamp: 1234,
+        compress_files: true,
+        ..PackOptions::default()
+    };
+    pack(&input_folder, &pak_file, &TEST_KEY, &options, Verbosity::Quiet).unwrap();
+    let options = UnpackOptions {
+        raw: Some(RawForm::Compressed),
+        ..UnpackOptions::default()
+    };
+    unpack(&pak_file, &output_folder, &TEST_KEY, &options, Verbosity::Quiet).unwrap();
+
+    let options = PackOptions {
+        timestamp: 1234,
+        ..PackOptions::default()
+    };
+    pack(&output_folder, &rebuilt_file, &TEST_KEY, &options, Verbosity::Quiet).unwrap();
+    assert_eq!(std::fs::read(&rebuilt_file).unwrap(), std::fs::read(&pak_file).unwrap());
+
+    // An edit that keeps the size has to be caught by the CRC
+    let mut data = std::fs::read(output_folder.join("a.bin")).unwrap();
+    *data.last_mut().unwrap() ^= 1;
+    std::fs::write(output_folder.join("a.bin"), data).unwrap();
+    let options = PackOptions {
+        force: true,
+        ..PackOptions::default()
+    };
+    let err = pack(&output_folder, &rebuilt_file, &TEST_KEY, &options, Verbosity::Quiet).unwrap_err();
+    assert!(format!("{err:#}").contains("plaintext CRC32 mismatch"));
+    // (rather than being left at the size it was preallocated at)
+    assert!(!rebuilt_file.exists());
+}
+
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn test_round_trip(
+        files in prop::collection::btree_map(asset_name(), asset_data(), 0..8),
+        compress_files: bool,
+    ) {
+        assert_round_trip(&files, &[], compress_files);
+    }
+}
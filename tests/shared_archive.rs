@@ -0,0 +1,74 @@
+use packling::{
+    archive::{PakArchive, SharedPakArchive},
+    flow_pack::{PackOptions, pack},
+    shared::Verbosity,
+};
+
+
+/// Any key works, since nothing checks it when packing or unpacking.
+const TEST_KEY: [u8; 16] = *b"packling testing";
+
+
+#[test]
+fn test_shared_archive_concurrent_reads() {
+    let temp = tempfile::tempdir().unwrap();
+    let input_folder = temp.path().join("input");
+    let pak_file = temp.path().join("test.pak");
+
+    std::fs::create_dir(&input_folder).unwrap();
+    let files: Vec<(String, Vec<u8>)> = (0..32_u8)
+        .map(|i| (format!("file_{i}.bin"), vec![i; 0x100 * usize::from(i) + 3]))
+        .collect();
+    for (name, data) in &files {
+        std::fs::write(input_folder.join(name), data).unwrap();
+    }
+
+    pack(&input_folder, &pak_file, &TEST_KEY, &PackOptions { compress_files: true, ..PackOptions::default() }, Verbosity::Quiet).unwrap();
+
+    for cache_budget in [None, Some(0x4000)] {
+        let mut archive = SharedPakArchive::open(&pak_file, &TEST_KEY).unwrap();
+        if let Some(budget) = cache_budget {
+            archive = archive.with_cache(budget);
+        }
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for (name, data) in &files {
+                        let asset = archive.find_asset(name).unwrap();
+                        assert_eq!(archive.read_asset(&asset).unwrap(), *data);
+                    }
+                });
+            }
+        });
+    }
+}
+
+
+#[test]
+fn test_shared_archive_matches_archive() {
+    let temp = tempfile::tempdir().unwrap();
+    let input_folder = temp.path().join("input");
+    let pak_file = temp.path().join("test.pak");
+
+    std::fs::create_dir(&input_folder).unwrap();
+    std::fs::write(input_folder.join("a.bin"), vec![1; 0x1000]).unwrap();
+    std::fs::write(input_folder.join("b.bin"), (0..0x2003).map(|i| (i * 7) as u8).collect::<Vec<_>>()).unwrap();
+    pack(&input_folder, &pak_file, &TEST_KEY, &PackOptions { compress_files: true, ..PackOptions::default() }, Verbosity::Quiet).unwrap();
+
+    // (cut short, so that the last asset can't be read)
+    let pak = std::fs::read(&pak_file).unwrap();
+    std::fs::write(&pak_file, &pak[..pak.len() - 1]).unwrap();
+
+    let mut archive = PakArchive::open(&pak_file, &TEST_KEY).unwrap();
+    let shared = SharedPakArchive::open(&pak_file, &TEST_KEY).unwrap();
+    for name in ["a.bin", "b.bin", "c.bin"] {
+        let result = archive.find_asset(name).and_then(|asset| archive.read_asset(&asset));
+        let shared_result = shared.find_asset(name).and_then(|asset| shared.read_asset(&asset));
+        match (result, shared_result) {
+            (Ok(data), Ok(shared_data)) => assert_eq!(data, shared_data),
+            (Err(e), Err(shared_e)) => assert_eq!(format!("{e:#}"), format!("{shared_e:#}")),
+            (result, shared_result) => panic!("{name}: {result:?} vs {shared_result:?}"),
+        }
+    }
+}